@@ -0,0 +1,69 @@
+// streams a frame's worth of tile data into vram over and over, the way games
+// that decompress graphics every frame do, and times it two ways: decoding each
+// tile row as it's written (what GPU::write_vram used to do) and leaving the
+// 2bpp data in vram for the scanline renderer to read (what it does now)
+//
+//     cargo bench --bench vram_streaming
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use gb_emulator::gpu::GPU;
+
+const TILE_DATA_SIZE: usize = 0x1800;
+const FRAMES: usize = 2_000;
+// the best of a few runs, to keep the scheduler out of it
+const RUNS: usize = 5;
+
+// the decoded tile cache write_vram kept up to date, a colour index per pixel
+struct DecodedTiles {
+    tiles: Vec<[[u8; 8]; 8]>,
+}
+
+impl DecodedTiles {
+    fn new() -> DecodedTiles {
+        DecodedTiles { tiles: vec![[[0; 8]; 8]; TILE_DATA_SIZE / 16] }
+    }
+    // both bytes of the row `index` is in
+    fn decode_row(&mut self, gpu: &GPU, index: usize) {
+        let index = index & !1;
+        let (low, high) = (gpu.read_vram(index), gpu.read_vram(index + 1));
+        let row = &mut self.tiles[index / 16][index % 16 / 2];
+        for (pixel, value) in row.iter_mut().enumerate() {
+            let mask = 0x80 >> pixel;
+            *value = ((high & mask != 0) as u8) << 1 | (low & mask != 0) as u8;
+        }
+    }
+}
+
+fn stream(mut decoded: Option<&mut DecodedTiles>) -> Duration {
+    let mut gpu = GPU::new();
+    let start = Instant::now();
+    for frame in 0..FRAMES {
+        for index in 0..TILE_DATA_SIZE {
+            gpu.write_vram(index, black_box((index + frame) as u8));
+            if let Some(tiles) = decoded.as_deref_mut() {
+                tiles.decode_row(&gpu, index);
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    black_box(&gpu);
+    elapsed
+}
+
+fn best(mut run: impl FnMut() -> Duration) -> Duration {
+    (0..RUNS).map(|_| run()).min().unwrap()
+}
+
+fn main() {
+    let writes = (FRAMES * TILE_DATA_SIZE) as f64;
+    let mut tiles = DecodedTiles::new();
+    let eager = best(|| stream(Some(&mut tiles)));
+    black_box(&tiles.tiles);
+    let lazy = best(|| stream(None));
+    let per_write = |time: Duration| time.as_nanos() as f64 / writes;
+    println!("eager decoding: {:.2} ns per write", per_write(eager));
+    println!("lazy decoding:  {:.2} ns per write", per_write(lazy));
+    println!("{:.1}x faster", eager.as_secs_f64() / lazy.as_secs_f64());
+}
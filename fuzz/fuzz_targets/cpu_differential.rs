@@ -0,0 +1,10 @@
+#![no_main]
+
+use gb_emulator_fuzz::{differential, Program};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|program: Program| {
+    if let Err(divergence) = differential(&program) {
+        panic!("cpu diverged from the reference\n{}", divergence);
+    }
+});
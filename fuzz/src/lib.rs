@@ -0,0 +1,172 @@
+// differential fuzzing of the emulator's cpu against reference::Reference, an
+// interpreter written separately from it. the fuzzer picks the registers, some
+// memory and a run of code at pc, both cpus execute it side by side and the
+// first instruction they disagree on is reported with the state before it.
+// flag slips in SBC/DAA/rotates are what this is after.
+//
+// needs cargo-fuzz and a nightly toolchain, from this directory:
+//     cargo +nightly fuzz run cpu_differential
+// and to see what a saved crash does:
+//     cargo +nightly fuzz run cpu_differential artifacts/cpu_differential/crash-...
+
+use std::fmt;
+
+use arbitrary::Arbitrary;
+use gb_emulator::cpu::{Bus, CpuSnapshot, CPU};
+use gb_emulator::disassembler::disassemble;
+use gb_emulator::registers::{FlagsRegister, Registers};
+
+pub mod reference;
+
+use reference::{Outcome, Reference, State};
+
+// instructions run per input, enough for a few to feed into each other
+const MAX_STEPS: usize = 32;
+
+#[derive(Arbitrary, Debug)]
+pub struct Program {
+    pub state: State,
+    // poked in before the code, so (hl), the stack and the like hold something
+    pub memory: Vec<(u16, u8)>,
+    pub code: Vec<u8>,
+}
+
+impl Program {
+    fn memory(&self) -> Box<[u8; 0x10000]> {
+        let mut memory = Box::new([0; 0x10000]);
+        for &(address, value) in &self.memory {
+            memory[address as usize] = value;
+        }
+        for (offset, &byte) in self.code.iter().enumerate() {
+            memory[self.state.pc.wrapping_add(offset as u16) as usize] = byte;
+        }
+        memory
+    }
+}
+
+// flat memory with nothing mapped in it, and so no interrupts either
+pub struct FlatBus {
+    pub memory: Box<[u8; 0x10000]>,
+}
+
+impl Bus for FlatBus {
+    fn read_byte(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+    fn interrupt_flags(&self) -> u8 {
+        0
+    }
+    fn set_interrupt_flags(&mut self, _value: u8) {}
+    fn interrupt_enable(&self) -> u8 {
+        0
+    }
+}
+
+fn snapshot(state: &State) -> CpuSnapshot {
+    let State { a, f, b, c, d, e, h, l, sp, pc } = *state;
+    let registers = Registers { a, f: FlagsRegister::from(f), b, c, d, e, h, l };
+    CpuSnapshot { registers, pc, sp }
+}
+
+fn state(snapshot: &CpuSnapshot) -> State {
+    let Registers { a, f, b, c, d, e, h, l } = snapshot.registers;
+    State { a, f: f.into(), b, c, d, e, h, l, sp: snapshot.sp, pc: snapshot.pc }
+}
+
+// where the two first disagreed
+#[derive(Debug)]
+pub struct Divergence {
+    pub step: usize,
+    pub instruction: String,
+    pub before: State,
+    pub expected: State,
+    pub actual: State,
+    pub expected_cycles: u32,
+    pub actual_cycles: u32,
+    // address, expected, actual
+    pub memory: Option<(u16, u8, u8)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "step {}: {}", self.step, self.instruction)?;
+        writeln!(f, "  before:   {}", snapshot(&self.before))?;
+        writeln!(f, "  expected: {} in {} cycles", snapshot(&self.expected), self.expected_cycles)?;
+        write!(f, "  actual:   {} in {} cycles", snapshot(&self.actual), self.actual_cycles)?;
+        if let Some((address, expected, actual)) = self.memory {
+            write!(f, "\n  [{:04X}]: expected {:02X}, got {:02X}", address, expected, actual)?;
+        }
+        Ok(())
+    }
+}
+
+// runs the program on both until one of them stops or they disagree,
+// returning how many instructions matched
+pub fn differential(program: &Program) -> Result<usize, Box<Divergence>> {
+    let mut reference = Reference::new(program.state, program.memory());
+    let mut cpu = CPU::new(FlatBus { memory: program.memory() });
+    cpu.restore(&snapshot(&reference.state));
+    for step in 0..MAX_STEPS {
+        let before = reference.state;
+        let instruction = disassemble(cpu.bus(), before.pc, None).to_string();
+        let (expected_cycles, locks_up) = match reference.step() {
+            Outcome::Executed(cycles) => (cycles, false),
+            Outcome::NotRun => return Ok(step),
+            // the opcode fetch, then the emulator should hang there too
+            Outcome::LockedUp => (4, true),
+        };
+        let actual_cycles = cpu.step();
+        let mut actual = state(&cpu.snapshot());
+        if locks_up && cpu.locked_up().is_none() {
+            // whatever it did instead, it didn't stay put
+            actual.pc = actual.pc.wrapping_add(1);
+        }
+        let memory = reference
+            .memory
+            .iter()
+            .zip(cpu.bus().memory.iter())
+            .position(|(expected, actual)| expected != actual)
+            .map(|address| (address as u16, reference.memory[address], cpu.bus().memory[address]));
+        if actual != reference.state || actual_cycles != expected_cycles || memory.is_some() {
+            let expected = reference.state;
+            return Err(Box::new(Divergence {
+                step,
+                instruction,
+                before,
+                expected,
+                actual,
+                expected_cycles,
+                actual_cycles,
+                memory,
+            }));
+        }
+        if locks_up { return Ok(step + 1) }
+    }
+    Ok(MAX_STEPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_runs_stop_at_halt() {
+        // ld a, $45; add $38; daa; ld [hl+], a; push af; pop bc; halt
+        let code = vec![0x3E, 0x45, 0xC6, 0x38, 0x27, 0x22, 0xF5, 0xC1, 0x76];
+        let state = State { h: 0xC0, sp: 0xD000, pc: 0x0100, ..State::default() };
+        let program = Program { state, memory: Vec::new(), code };
+        assert_eq!(differential(&program).map_err(|divergence| divergence.to_string()), Ok(6));
+    }
+
+    #[test]
+    fn unused_opcodes_lock_both_up() {
+        // nop; inc a; <unused>
+        for opcode in [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD] {
+            let program = Program { state: State::default(), memory: Vec::new(), code: vec![0x00, 0x3C, opcode] };
+            assert_eq!(differential(&program).map_err(|divergence| divergence.to_string()), Ok(3));
+        }
+    }
+}
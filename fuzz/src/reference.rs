@@ -0,0 +1,558 @@
+// a plain SM83 interpreter to hold the emulator's cpu up against. it shares no
+// code with src/cpu.rs: opcodes are split into their x/y/z bit fields (the SM83
+// keeps most of the Z80's layout) and those index the register, condition and
+// alu tables below, with the flags worked out the long way. it's checked
+// against the SM83 single step tests, see the test at the bottom.
+//
+// HALT and STOP aren't run, step() leaves them to the caller. the unused opcodes hang it.
+// there are no interrupts, IME is only tracked.
+
+use arbitrary::Arbitrary;
+
+pub const ZERO: u8 = 0x80;
+pub const SUBTRACT: u8 = 0x40;
+pub const HALF_CARRY: u8 = 0x20;
+pub const CARRY: u8 = 0x10;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Arbitrary)]
+pub struct State {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    // with the clock cycles it took
+    Executed(u32),
+    // HALT or STOP, pc is left on it
+    NotRun,
+    // an unused opcode hangs the cpu, pc is left on it and nothing runs again
+    LockedUp,
+}
+
+// machine cycles of the unprefixed instructions from 0x00 and 0xC0, branches
+// not taken. 0x40-0xBF are worked out in cycles()
+const LOW_CYCLES: [u32; 0x40] = [
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, //
+    1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, //
+    2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, //
+    2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1, //
+];
+const HIGH_CYCLES: [u32; 0x40] = [
+    2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4, //
+    2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4, //
+    3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4, //
+    3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4, //
+];
+const UNUSED: [u8; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+fn cycles(opcode: u8) -> u32 {
+    match opcode {
+        0x00..=0x3F => LOW_CYCLES[opcode as usize],
+        // (hl) as either operand costs a memory access
+        0x40..=0xBF => if opcode & 0x07 == 6 || opcode & 0xF8 == 0x70 { 2 } else { 1 },
+        _ => HIGH_CYCLES[opcode as usize - 0xC0],
+    }
+}
+
+pub struct Reference {
+    pub state: State,
+    pub memory: Box<[u8; 0x10000]>,
+    pub ime: bool,
+}
+
+impl Reference {
+    pub fn new(state: State, memory: Box<[u8; 0x10000]>) -> Reference {
+        let state = State { f: state.f & 0xF0, ..state };
+        Reference { state, memory, ime: false }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+    fn fetch(&mut self) -> u8 {
+        let value = self.read(self.state.pc);
+        self.state.pc = self.state.pc.wrapping_add(1);
+        value
+    }
+    fn fetch_word(&mut self) -> u16 {
+        let low = self.fetch() as u16;
+        (self.fetch() as u16) << 8 | low
+    }
+    fn push(&mut self, value: u16) {
+        self.state.sp = self.state.sp.wrapping_sub(1);
+        self.write(self.state.sp, (value >> 8) as u8);
+        self.state.sp = self.state.sp.wrapping_sub(1);
+        self.write(self.state.sp, value as u8);
+    }
+    fn pop(&mut self) -> u16 {
+        let low = self.read(self.state.sp) as u16;
+        self.state.sp = self.state.sp.wrapping_add(1);
+        let high = self.read(self.state.sp) as u16;
+        self.state.sp = self.state.sp.wrapping_add(1);
+        high << 8 | low
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.state.f & flag != 0
+    }
+    fn set_flags(&mut self, zero: bool, subtract: bool, half_carry: bool, carry: bool) {
+        self.state.f = (zero as u8) << 7 | (subtract as u8) << 6 | (half_carry as u8) << 5 | (carry as u8) << 4;
+    }
+
+    fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.state.h, self.state.l])
+    }
+    fn set_hl(&mut self, value: u16) {
+        [self.state.h, self.state.l] = value.to_be_bytes();
+    }
+
+    // r: b, c, d, e, h, l, (hl), a
+    fn r(&self, index: u8) -> u8 {
+        match index {
+            0 => self.state.b,
+            1 => self.state.c,
+            2 => self.state.d,
+            3 => self.state.e,
+            4 => self.state.h,
+            5 => self.state.l,
+            6 => self.read(self.hl()),
+            _ => self.state.a,
+        }
+    }
+    fn set_r(&mut self, index: u8, value: u8) {
+        match index {
+            0 => self.state.b = value,
+            1 => self.state.c = value,
+            2 => self.state.d = value,
+            3 => self.state.e = value,
+            4 => self.state.h = value,
+            5 => self.state.l = value,
+            6 => self.write(self.hl(), value),
+            _ => self.state.a = value,
+        }
+    }
+    // rp: bc, de, hl, sp. rp2 has af in place of sp
+    fn rp(&self, index: u8) -> u16 {
+        match index {
+            0 => u16::from_be_bytes([self.state.b, self.state.c]),
+            1 => u16::from_be_bytes([self.state.d, self.state.e]),
+            2 => self.hl(),
+            _ => self.state.sp,
+        }
+    }
+    fn set_rp(&mut self, index: u8, value: u16) {
+        match index {
+            0 => [self.state.b, self.state.c] = value.to_be_bytes(),
+            1 => [self.state.d, self.state.e] = value.to_be_bytes(),
+            2 => self.set_hl(value),
+            _ => self.state.sp = value,
+        }
+    }
+    fn rp2(&self, index: u8) -> u16 {
+        if index == 3 { u16::from_be_bytes([self.state.a, self.state.f]) } else { self.rp(index) }
+    }
+    fn set_rp2(&mut self, index: u8, value: u16) {
+        if index == 3 {
+            [self.state.a, self.state.f] = value.to_be_bytes();
+            self.state.f &= 0xF0;
+        } else {
+            self.set_rp(index, value);
+        }
+    }
+    // cc: nz, z, nc, c
+    fn condition(&self, index: u8) -> bool {
+        match index {
+            0 => !self.flag(ZERO),
+            1 => self.flag(ZERO),
+            2 => !self.flag(CARRY),
+            _ => self.flag(CARRY),
+        }
+    }
+
+    // alu: add, adc, sub, sbc, and, xor, or, cp
+    fn alu(&mut self, index: u8, value: u8) {
+        let a = self.state.a;
+        let carry = self.flag(CARRY) as u8;
+        match index {
+            0 | 1 => {
+                let carry = if index == 1 { carry } else { 0 };
+                let result = a as u16 + value as u16 + carry as u16;
+                self.set_flags(result as u8 == 0, false, (a & 0xF) + (value & 0xF) + carry > 0xF, result > 0xFF);
+                self.state.a = result as u8;
+            }
+            2 | 3 | 7 => {
+                let carry = if index == 3 { carry } else { 0 };
+                let result = (a as i16) - (value as i16) - (carry as i16);
+                let half = (a & 0xF) as i16 - (value & 0xF) as i16 - carry as i16;
+                self.set_flags(result as u8 == 0, true, half < 0, result < 0);
+                if index != 7 { self.state.a = result as u8 }
+            }
+            4 => {
+                self.state.a = a & value;
+                self.set_flags(self.state.a == 0, false, true, false);
+            }
+            5 => {
+                self.state.a = a ^ value;
+                self.set_flags(self.state.a == 0, false, false, false);
+            }
+            _ => {
+                self.state.a = a | value;
+                self.set_flags(self.state.a == 0, false, false, false);
+            }
+        }
+    }
+
+    // rot: rlc, rrc, rl, rr, sla, sra, swap, srl. the accumulator versions
+    // (rlca...) clear Z afterwards
+    fn rotate(&mut self, index: u8, value: u8) -> u8 {
+        let carry = self.flag(CARRY) as u8;
+        let (result, carry_out) = match index {
+            0 => (value.rotate_left(1), value & 0x80 != 0),
+            1 => (value.rotate_right(1), value & 0x01 != 0),
+            2 => (value << 1 | carry, value & 0x80 != 0),
+            3 => (value >> 1 | carry << 7, value & 0x01 != 0),
+            4 => (value << 1, value & 0x80 != 0),
+            5 => (value >> 1 | value & 0x80, value & 0x01 != 0),
+            6 => (value.rotate_left(4), false),
+            _ => (value >> 1, value & 0x01 != 0),
+        };
+        self.set_flags(result == 0, false, false, carry_out);
+        result
+    }
+
+    fn daa(&mut self) {
+        let mut a = self.state.a;
+        let mut carry = self.flag(CARRY);
+        if self.flag(SUBTRACT) {
+            if carry { a = a.wrapping_sub(0x60) }
+            if self.flag(HALF_CARRY) { a = a.wrapping_sub(0x06) }
+        } else {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.flag(HALF_CARRY) || a & 0x0F > 0x09 { a = a.wrapping_add(0x06) }
+        }
+        self.state.a = a;
+        self.set_flags(a == 0, self.flag(SUBTRACT), false, carry);
+    }
+
+    // sp plus a signed byte, for add sp,e and ld hl,sp+e. flags from the low byte
+    fn sp_plus_offset(&mut self) -> u16 {
+        let offset = self.fetch();
+        let sp = self.state.sp;
+        self.set_flags(false, false, (sp & 0xF) + (offset as u16 & 0xF) > 0xF, (sp & 0xFF) + offset as u16 > 0xFF);
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    fn jump_relative(&mut self) {
+        let offset = self.fetch() as i8;
+        self.state.pc = self.state.pc.wrapping_add(offset as u16);
+    }
+
+    fn prefixed(&mut self) -> u32 {
+        let opcode = self.fetch();
+        let (x, y, z) = (opcode >> 6, opcode >> 3 & 7, opcode & 7);
+        let value = self.r(z);
+        match x {
+            0 => {
+                let result = self.rotate(y, value);
+                self.set_r(z, result);
+            }
+            1 => {
+                let carry = self.flag(CARRY);
+                self.set_flags(value & 1 << y == 0, false, true, carry);
+            }
+            2 => self.set_r(z, value & !(1 << y)),
+            _ => self.set_r(z, value | 1 << y),
+        }
+        match (z, x) {
+            (6, 1) => 3,
+            (6, _) => 4,
+            _ => 2,
+        }
+    }
+
+    pub fn step(&mut self) -> Outcome {
+        let opcode = self.read(self.state.pc);
+        if opcode == 0x10 || opcode == 0x76 { return Outcome::NotRun }
+        if UNUSED.contains(&opcode) { return Outcome::LockedUp }
+        self.state.pc = self.state.pc.wrapping_add(1);
+        let (x, y, z) = (opcode >> 6, opcode >> 3 & 7, opcode & 7);
+        let (p, q) = (y >> 1, y & 1);
+        let mut cycles = cycles(opcode);
+        match (x, z) {
+            (0, 0) => match y {
+                0 => {}
+                1 => {
+                    let address = self.fetch_word();
+                    let [high, low] = self.state.sp.to_be_bytes();
+                    self.write(address, low);
+                    self.write(address.wrapping_add(1), high);
+                }
+                3 => self.jump_relative(),
+                _ => {
+                    if self.condition(y - 4) {
+                        self.jump_relative();
+                        cycles += 1;
+                    } else {
+                        self.fetch();
+                    }
+                }
+            },
+            (0, 1) if q == 0 => {
+                let value = self.fetch_word();
+                self.set_rp(p, value);
+            }
+            (0, 1) => {
+                let (hl, value) = (self.hl(), self.rp(p));
+                let zero = self.flag(ZERO);
+                self.set_flags(zero, false, (hl & 0xFFF) + (value & 0xFFF) > 0xFFF, hl as u32 + value as u32 > 0xFFFF);
+                self.set_hl(hl.wrapping_add(value));
+            }
+            (0, 2) => {
+                // (bc), (de), (hl+), (hl-)
+                let address = match p {
+                    0 | 1 => self.rp(p),
+                    _ => self.hl(),
+                };
+                match p {
+                    2 => self.set_hl(address.wrapping_add(1)),
+                    3 => self.set_hl(address.wrapping_sub(1)),
+                    _ => {}
+                }
+                if q == 0 { self.write(address, self.state.a) } else { self.state.a = self.read(address) }
+            }
+            (0, 3) => {
+                let value = self.rp(p);
+                self.set_rp(p, if q == 0 { value.wrapping_add(1) } else { value.wrapping_sub(1) });
+            }
+            (0, 4) | (0, 5) => {
+                let value = self.r(y);
+                let carry = self.flag(CARRY);
+                let result = if z == 4 {
+                    self.set_flags(value == 0xFF, false, value & 0xF == 0xF, carry);
+                    value.wrapping_add(1)
+                } else {
+                    self.set_flags(value == 0x01, true, value & 0xF == 0, carry);
+                    value.wrapping_sub(1)
+                };
+                self.set_r(y, result);
+            }
+            (0, 6) => {
+                let value = self.fetch();
+                self.set_r(y, value);
+            }
+            (0, _) => match y {
+                0..=3 => {
+                    let result = self.rotate(y, self.state.a);
+                    self.state.a = result;
+                    self.state.f &= !ZERO;
+                }
+                4 => self.daa(),
+                5 => {
+                    self.state.a = !self.state.a;
+                    self.state.f |= SUBTRACT | HALF_CARRY;
+                }
+                6 => self.state.f = self.state.f & ZERO | CARRY,
+                _ => self.state.f = self.state.f & (ZERO | CARRY) ^ CARRY,
+            },
+            (1, _) => {
+                let value = self.r(z);
+                self.set_r(y, value);
+            }
+            (2, _) => {
+                let value = self.r(z);
+                self.alu(y, value);
+            }
+            (_, 0) => match y {
+                0..=3 => {
+                    if self.condition(y) {
+                        self.state.pc = self.pop();
+                        cycles += 3;
+                    }
+                }
+                4 => {
+                    let offset = self.fetch();
+                    self.write(0xFF00 | offset as u16, self.state.a);
+                }
+                5 => self.state.sp = self.sp_plus_offset(),
+                6 => {
+                    let offset = self.fetch();
+                    self.state.a = self.read(0xFF00 | offset as u16);
+                }
+                _ => {
+                    let value = self.sp_plus_offset();
+                    self.set_hl(value);
+                }
+            },
+            (_, 1) if q == 0 => {
+                let value = self.pop();
+                self.set_rp2(p, value);
+            }
+            (_, 1) => match p {
+                0 | 1 => {
+                    self.state.pc = self.pop();
+                    if p == 1 { self.ime = true }
+                }
+                2 => self.state.pc = self.hl(),
+                _ => self.state.sp = self.hl(),
+            },
+            (_, 2) => match y {
+                0..=3 => {
+                    let address = self.fetch_word();
+                    if self.condition(y) {
+                        self.state.pc = address;
+                        cycles += 1;
+                    }
+                }
+                4 => self.write(0xFF00 | self.state.c as u16, self.state.a),
+                5 => {
+                    let address = self.fetch_word();
+                    self.write(address, self.state.a);
+                }
+                6 => self.state.a = self.read(0xFF00 | self.state.c as u16),
+                _ => {
+                    let address = self.fetch_word();
+                    self.state.a = self.read(address);
+                }
+            },
+            (_, 3) => match y {
+                0 => self.state.pc = self.fetch_word(),
+                1 => cycles = self.prefixed(),
+                6 => self.ime = false,
+                // the emulator delays it by an instruction, with no interrupts
+                // that makes no difference here
+                _ => self.ime = true,
+            },
+            (_, 4) => {
+                let address = self.fetch_word();
+                if self.condition(y) {
+                    self.push(self.state.pc);
+                    self.state.pc = address;
+                    cycles += 3;
+                }
+            }
+            (_, 5) if q == 0 => self.push(self.rp2(p)),
+            (_, 5) => {
+                let address = self.fetch_word();
+                self.push(self.state.pc);
+                self.state.pc = address;
+            }
+            (_, 6) => {
+                let value = self.fetch();
+                self.alu(y, value);
+            }
+            _ => {
+                self.push(self.state.pc);
+                self.state.pc = y as u16 * 8;
+            }
+        }
+        Outcome::Executed(cycles * 4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // the SM83 single step tests (https://github.com/SingleStepTests/sm83) run
+    // on the reference, so a divergence is the emulator's to explain. point
+    // SM83_TESTS_DIR at a checkout's `v1` directory:
+    //     SM83_TESTS_DIR=../../sm83/v1 cargo test
+    // without it the test is skipped.
+    use std::fs;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    const TESTS_DIR_VAR: &str = "SM83_TESTS_DIR";
+
+    #[derive(Deserialize)]
+    struct TestCase {
+        name: String,
+        initial: CpuState,
+        #[serde(rename = "final")]
+        expected: CpuState,
+        cycles: Vec<serde::de::IgnoredAny>,
+    }
+
+    #[derive(Deserialize)]
+    struct CpuState {
+        pc: u16,
+        sp: u16,
+        a: u8,
+        b: u8,
+        c: u8,
+        d: u8,
+        e: u8,
+        f: u8,
+        h: u8,
+        l: u8,
+        ram: Vec<(u16, u8)>,
+    }
+
+    impl CpuState {
+        fn state(&self) -> State {
+            let CpuState { pc, sp, a, b, c, d, e, f, h, l, .. } = *self;
+            State { a, f, b, c, d, e, h, l, sp, pc }
+        }
+    }
+
+    #[test]
+    fn daa_corrects_additions_and_subtractions() {
+        let mut memory = Box::new([0; 0x10000]);
+        memory[..2].copy_from_slice(&[0x27, 0x27]);
+        // 0x45 + 0x38 = 0x7D -> 0x83
+        let mut reference = Reference::new(State { a: 0x7D, ..State::default() }, memory);
+        assert_eq!(reference.step(), Outcome::Executed(4));
+        assert_eq!((reference.state.a, reference.state.f), (0x83, 0));
+        // 0x10 - 0x01 = 0x0F with a half borrow -> 0x09
+        reference.state.a = 0x0F;
+        reference.state.f = SUBTRACT | HALF_CARRY;
+        reference.step();
+        assert_eq!((reference.state.a, reference.state.f), (0x09, SUBTRACT));
+    }
+
+    #[test]
+    fn sm83_single_step_tests() {
+        let Ok(dir) = std::env::var(TESTS_DIR_VAR) else {
+            eprintln!("{} not set, skipping sm83 single step tests", TESTS_DIR_VAR);
+            return;
+        };
+        let mut failures = Vec::new();
+        for entry in fs::read_dir(&dir).expect("unreadable SM83_TESTS_DIR") {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "json") { continue }
+            let tests: Vec<TestCase> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            for test in &tests {
+                let mut memory = Box::new([0; 0x10000]);
+                for &(address, value) in &test.initial.ram {
+                    memory[address as usize] = value;
+                }
+                let mut reference = Reference::new(test.initial.state(), memory);
+                let outcome = reference.step();
+                // halt and stop have files of their own, the emulator's tests cover them
+                if outcome == Outcome::NotRun { continue }
+                let ram_matches =
+                    test.expected.ram.iter().all(|&(address, value)| reference.memory[address as usize] == value);
+                let cycles_match = outcome == Outcome::Executed(test.cycles.len() as u32 * 4);
+                if reference.state != test.expected.state() || !ram_matches || !cycles_match {
+                    failures.push(test.name.clone());
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{} failing cases, first {:?}", failures.len(), &failures[..failures.len().min(5)]);
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::cpu::Bus;
+
+// an address and the bank mapped there, where that's known. a bare address in
+// a switchable area could be any of several places, so the tooling shows and
+// takes bank:address (05:4123) wherever there's a bank to go with it
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BankedAddress {
+    pub bank: Option<usize>,
+    pub address: u16,
+}
+
+impl BankedAddress {
+    pub fn new(address: u16) -> BankedAddress {
+        BankedAddress { bank: None, address }
+    }
+    pub fn banked(bank: usize, address: u16) -> BankedAddress {
+        BankedAddress { bank: Some(bank), address }
+    }
+    // the address with whatever bank the bus has there right now
+    pub fn mapped<B: Bus + ?Sized>(bus: &B, address: u16) -> BankedAddress {
+        BankedAddress { bank: bus.bank_at(address), address }
+    }
+    // bank:address or just an address, in hex. $ or 0x in front of the
+    // address is fine
+    pub fn parse(text: &str) -> Option<BankedAddress> {
+        let hex = |text: &str| {
+            let digits = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")).unwrap_or(text);
+            u16::from_str_radix(digits, 16).ok()
+        };
+        match text.split_once(':') {
+            Some((bank, address)) => Some(BankedAddress::banked(usize::from_str_radix(bank, 16).ok()?, hex(address)?)),
+            None => hex(text).map(BankedAddress::new),
+        }
+    }
+    // whether this is `address` with `bank` mapped there. without a bank of
+    // its own any bank will do
+    pub fn matches(&self, address: u16, bank: Option<usize>) -> bool {
+        self.address == address && (self.bank.is_none() || self.bank == bank)
+    }
+    // where a rom address is in the rom file. without a bank it's taken to be
+    // what's there at power on, bank 0 then bank 1
+    pub fn rom_offset(&self) -> Option<usize> {
+        if self.address >= 0x8000 { return None }
+        let power_on = if self.address < 0x4000 { 0 } else { 1 };
+        let bank = self.bank.unwrap_or(power_on);
+        Some(bank * ROM_BANK_SIZE + self.address as usize % ROM_BANK_SIZE)
+    }
+}
+
+impl From<u16> for BankedAddress {
+    fn from(address: u16) -> BankedAddress {
+        BankedAddress::new(address)
+    }
+}
+
+// 05:4123, or 4123 with no bank. widths pad the whole thing, so columns of
+// them line up
+impl fmt::Display for BankedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self.bank {
+            Some(bank) => format!("{:02X}:{:04X}", bank, self.address),
+            None => format!("{:04X}", self.address),
+        };
+        f.pad(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_read_and_print_with_their_bank() {
+        let address = BankedAddress::parse("05:4123").unwrap();
+        assert_eq!(address, BankedAddress::banked(5, 0x4123));
+        assert_eq!(address.to_string(), "05:4123");
+        assert_eq!(address.rom_offset(), Some(5 * 0x4000 + 0x0123));
+        assert_eq!(BankedAddress::parse("$C000"), Some(BankedAddress::new(0xC000)));
+        assert_eq!(BankedAddress::new(0x4000).rom_offset(), Some(0x4000));
+        assert_eq!(BankedAddress::new(0xC000).rom_offset(), None);
+        assert!(address.matches(0x4123, Some(5)));
+        assert!(!address.matches(0x4123, Some(6)));
+        assert!(BankedAddress::new(0x4123).matches(0x4123, Some(6)));
+        assert_eq!(BankedAddress::parse("xx:4000"), None);
+        assert_eq!(format!("{:>7}|{:>7}", BankedAddress::new(0xC000), address), "   C000|05:4123");
+    }
+}
@@ -0,0 +1,564 @@
+// the audio processing unit. each channel turns its registers into a digital
+// level from 0 to 15, advanced by the same clock cycles as the rest of the machine.
+// a frame sequencer running off that clock at 512 Hz drives the length counters,
+// channel 1's sweep and the envelopes. with a sample rate set, the channels are
+// mixed down to stereo for the host
+mod envelope;
+mod length;
+mod mixer;
+mod noise;
+mod square;
+mod wav;
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+use mixer::Mixer;
+use noise::Noise;
+use square::Square;
+use wav::WavWriter;
+
+pub const NR10: u16 = 0xFF10;
+pub const NR14: u16 = 0xFF14;
+pub const NR21: u16 = 0xFF16;
+pub const NR24: u16 = 0xFF19;
+pub const NR41: u16 = 0xFF20;
+pub const NR44: u16 = 0xFF23;
+pub const NR50: u16 = 0xFF24;
+pub const NR51: u16 = 0xFF25;
+pub const NR52: u16 = 0xFF26;
+// CGB only, the digital outputs of channels 1 and 2 (PCM12) and 3 and 4 (PCM34)
+pub const PCM12: u16 = 0xFF76;
+pub const PCM34: u16 = 0xFF77;
+// clock cycles per second
+pub const CLOCK_RATE: u32 = 4_194_304;
+// what recordings use when no frontend has picked a sample rate
+const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+// clock cycles per frame sequencer step
+const SEQUENCER_PERIOD: u32 = 8192;
+
+// in NR51/NR52 bit order
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Channel {
+    Square1,
+    Square2,
+    // not emulated, always silent
+    Wave,
+    Noise,
+}
+
+pub struct Apu {
+    square1: Square,
+    square2: Square,
+    noise: Noise,
+    // NR52 bit 7, with the power off every other register is cleared and ignores writes
+    powered: bool,
+    // master volume and VIN mixing
+    nr50: u8,
+    // which channels go to which side
+    nr51: u8,
+    sequencer_step: u8,
+    sequencer_timer: u32,
+    // only there once a frontend asks for samples
+    mixer: Option<Mixer>,
+    // host side controls by Channel, muting wins over soloing. while any channel is
+    // soloed only soloed channels are heard
+    muted: [bool; 4],
+    soloed: [bool; 4],
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            square1: Square::new(true),
+            square2: Square::new(false),
+            noise: Noise::new(),
+            powered: false,
+            nr50: 0,
+            nr51: 0,
+            sequencer_step: 0,
+            sequencer_timer: SEQUENCER_PERIOD,
+            mixer: None,
+            muted: [false; 4],
+            soloed: [false; 4],
+        }
+    }
+    // the host's settings survive a reset, the buffered audio doesn't
+    pub fn reset(&mut self) {
+        if let Some(mixer) = &mut self.mixer {
+            mixer.clear();
+        }
+        self.restart(false);
+    }
+    // back to the power on state, keeping what the host has set up
+    fn restart(&mut self, powered: bool) {
+        let (mixer, muted, soloed) = (self.mixer.take(), self.muted, self.soloed);
+        *self = Apu { powered, mixer, muted, soloed, ..Apu::new() };
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            NR10..=NR14 => self.square1.read((address - NR10) as usize),
+            // NR21-NR24, FF15 is where NR20 would be
+            0xFF15..=NR24 => self.square2.read((address - 0xFF15) as usize),
+            NR41..=NR44 => self.noise.read((address - NR41) as usize),
+            NR50 => self.nr50,
+            NR51 => self.nr51,
+            // bit 2 is the wave channel's, which isn't emulated
+            NR52 => (self.powered as u8) << 7
+                | 0x70
+                | (self.noise.enabled() as u8) << 3
+                | (self.square2.enabled() as u8) << 1
+                | self.square1.enabled() as u8,
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        if address == NR52 {
+            self.set_power(value & 0x80 != 0);
+            return;
+        }
+        if !self.powered { return }
+        match address {
+            NR10..=NR14 => self.square1.write((address - NR10) as usize, value),
+            NR21..=NR24 => self.square2.write((address - 0xFF15) as usize, value),
+            NR41..=NR44 => self.noise.write((address - NR41) as usize, value),
+            NR50 => self.nr50 = value,
+            NR51 => self.nr51 = value,
+            _ => {}
+        }
+    }
+    fn set_power(&mut self, powered: bool) {
+        if powered == self.powered { return }
+        self.restart(powered);
+    }
+    // the channels and sequencer. the host's side (sample rate, buffered audio,
+    // muting) is left alone
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        self.square1.save_state(state);
+        self.square2.save_state(state);
+        self.noise.save_state(state);
+        state.bytes(&[self.powered as u8, self.nr50, self.nr51, self.sequencer_step]);
+        state.u32(self.sequencer_timer);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.square1.load_state(state)?;
+        self.square2.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.powered = state.bool()?;
+        self.nr50 = state.u8()?;
+        self.nr51 = state.u8()?;
+        self.sequencer_step = state.u8()? % 8;
+        self.sequencer_timer = state.u32()?.clamp(1, SEQUENCER_PERIOD);
+        if let Some(mixer) = &mut self.mixer {
+            mixer.clear();
+        }
+        Ok(())
+    }
+    pub fn powered(&self) -> bool {
+        self.powered
+    }
+    // NR50 bits 0-2 and 4-6, 0 is the quietest setting
+    pub fn master_volume(&self) -> (u8, u8) {
+        (self.nr50 >> 4 & 0x07, self.nr50 & 0x07)
+    }
+    // NR51, bit n routes channel n + 1 to the right, bit n + 4 to the left
+    pub fn panning(&self) -> u8 {
+        self.nr51
+    }
+    // start producing interleaved stereo samples at `hz`, or stop with None. a
+    // recording can't change rate halfway, so one in progress is finished first
+    pub fn set_sample_rate(&mut self, hz: Option<u32>) {
+        let _ = self.stop_recording();
+        self.mixer = hz.map(Mixer::new);
+    }
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.mixer.as_ref().map(Mixer::sample_rate)
+    }
+    // how many interleaved values fill() can hand out right now
+    pub fn samples_available(&self) -> usize {
+        self.mixer.as_ref().map_or(0, Mixer::available)
+    }
+    // see Mixer::fill, without a sample rate this is all silence
+    pub fn fill(&mut self, out: &mut [f32]) -> usize {
+        match &mut self.mixer {
+            Some(mixer) => mixer.fill(out),
+            None => {
+                out.fill(0.0);
+                0
+            }
+        }
+    }
+    // write everything the APU plays from now on to a 16 bit stereo WAV file, at
+    // the frontend's sample rate. a recording already running is finished first
+    pub fn start_recording(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.stop_recording()?;
+        let mixer = self.mixer.get_or_insert_with(|| Mixer::new(DEFAULT_SAMPLE_RATE));
+        let writer = WavWriter::new(BufWriter::new(File::create(path)?), mixer.sample_rate())?;
+        mixer.set_recording(Some(writer));
+        Ok(())
+    }
+    // finishes the file, reporting the first error the recording ran into if any
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        let recording = self.mixer.as_mut().and_then(|mixer| mixer.set_recording(None));
+        match recording {
+            Some(recording) => recording.finish().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+    pub fn recording(&self) -> bool {
+        self.mixer.as_ref().is_some_and(Mixer::recording)
+    }
+    // each channel's DAC turns 0-15 into 1.0 down to -1.0. a DAC that's switched
+    // off (NRx2 & 0xF8 == 0) outputs nothing, one that's on keeps its level even
+    // while the channel itself is disabled
+    fn analog_outputs(&self) -> [f32; 4] {
+        let digital = self.channel_outputs();
+        let dacs = [self.square1.dac_enabled(), self.square2.dac_enabled(), false, self.noise.dac_enabled()];
+        [0, 1, 2, 3].map(|channel| if dacs[channel] { 1.0 - digital[channel] as f32 / 7.5 } else { 0.0 })
+    }
+    // left and right from -1.0 to 1.0. each channel adds a quarter of its level to the
+    // sides NR51 routes it to, then NR50 scales each side in eighths
+    fn mix(&self) -> [f32; 2] {
+        let levels = self.analog_outputs();
+        let (left_volume, right_volume) = self.master_volume();
+        let any_soloed = self.soloed.contains(&true);
+        let audible = |channel: usize| !self.muted[channel] && (!any_soloed || self.soloed[channel]);
+        let side = |shift: u8, volume: u8| {
+            let sum: f32 = (0..4)
+                .filter(|&channel| audible(channel as usize) && self.nr51 >> (channel + shift) & 1 != 0)
+                .map(|channel| levels[channel as usize])
+                .sum();
+            sum / 4.0 * (volume + 1) as f32 / 8.0
+        };
+        [side(4, left_volume), side(0, right_volume)]
+    }
+    pub fn tick(&mut self, cycles: u32) {
+        if self.powered {
+            self.advance(cycles);
+        }
+        // an unpowered APU is silence, but time still passes
+        let level = if self.powered { self.mix() } else { [0.0; 2] };
+        if let Some(mixer) = &mut self.mixer {
+            mixer.add(level, cycles);
+        }
+    }
+    fn advance(&mut self, cycles: u32) {
+        self.square1.tick(cycles);
+        self.square2.tick(cycles);
+        self.noise.tick(cycles);
+        let mut cycles = cycles;
+        while cycles >= self.sequencer_timer {
+            cycles -= self.sequencer_timer;
+            self.sequencer_timer = SEQUENCER_PERIOD;
+            self.step_sequencer();
+        }
+        self.sequencer_timer -= cycles;
+    }
+    // lengths on every even step, the sweep on 2 and 6, envelopes on 7
+    fn step_sequencer(&mut self) {
+        if self.sequencer_step.is_multiple_of(2) {
+            self.clock_lengths();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.clock_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.clock_envelopes();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+    // 256 Hz
+    fn clock_lengths(&mut self) {
+        self.square1.clock_length();
+        self.square2.clock_length();
+        self.noise.clock_length();
+    }
+    // 128 Hz
+    fn clock_sweep(&mut self) {
+        self.square1.clock_sweep();
+    }
+    // 64 Hz
+    fn clock_envelopes(&mut self) {
+        self.square1.clock_envelope();
+        self.square2.clock_envelope();
+        self.noise.clock_envelope();
+    }
+    // each channel's digital output right now, 0-15 by Channel. this is before
+    // muting, panning and volume, for visualizers
+    pub fn channel_outputs(&self) -> [u8; 4] {
+        [self.square1.output(), self.square2.output(), 0, self.noise.output()]
+    }
+    // PCM12/PCM34, the low nibble is the lower numbered channel
+    pub fn read_pcm(&self, address: u16) -> u8 {
+        let outputs = self.channel_outputs();
+        let first = if address == PCM12 { 0 } else { 2 };
+        outputs[first + 1] << 4 | outputs[first]
+    }
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+    pub fn set_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.soloed[channel as usize] = soloed;
+    }
+    pub fn soloed(&self, channel: Channel) -> bool {
+        self.soloed[channel as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_follows_its_duty_cycle() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        // 50% duty, full volume, period 0x700 so a duty step takes 1024 cycles
+        apu.write(0xFF16, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF18, 0x00);
+        apu.write(NR24, 0x87);
+        let mut levels = Vec::new();
+        for _ in 0..8 {
+            apu.tick(1024);
+            levels.push(apu.channel_outputs()[1]);
+        }
+        assert_eq!(levels, [0, 0, 0, 0, 15, 15, 15, 15]);
+        // reads only see the duty and length enable
+        assert_eq!(apu.read(0xFF16), 0xBF);
+        assert_eq!(apu.read(NR24), 0xBF);
+    }
+
+    #[test]
+    fn length_and_dac_switch_channels_off() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 0x3E);
+        apu.write(NR14, 0xC0);
+        apu.clock_lengths();
+        assert!(apu.square1.enabled());
+        apu.clock_lengths();
+        assert!(!apu.square1.enabled());
+        // a trigger without a DAC doesn't start the channel
+        apu.write(0xFF12, 0x00);
+        apu.write(NR14, 0x80);
+        assert!(!apu.square1.enabled());
+    }
+
+    #[test]
+    fn sweep_overflow_stops_channel_one() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        apu.write(0xFF12, 0xF0);
+        // pace 1, adding period >> 1
+        apu.write(NR10, 0x11);
+        apu.write(0xFF13, 0x00);
+        apu.write(NR14, 0x85);
+        assert!(apu.square1.enabled());
+        // 0x500 -> 0x780, whose next step would pass 0x7FF
+        apu.clock_sweep();
+        assert!(!apu.square1.enabled());
+    }
+
+    #[test]
+    fn envelope_steps_once_per_pace() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        // 75% duty, volume 7 going up every 2 ticks
+        apu.write(0xFF11, 0xC0);
+        apu.write(0xFF12, 0x7A);
+        apu.write(0xFF13, 0x00);
+        apu.write(NR14, 0x87);
+        apu.tick(1024);
+        assert_eq!(apu.channel_outputs()[0], 7);
+        apu.clock_envelopes();
+        assert_eq!(apu.channel_outputs()[0], 7);
+        apu.clock_envelopes();
+        assert_eq!(apu.channel_outputs()[0], 8);
+    }
+
+    #[test]
+    fn noise_lfsr_repeats_every_127_steps_in_short_mode() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        apu.write(0xFF21, 0xF0);
+        // 7 bit mode, divisor 8, no shift
+        apu.write(0xFF22, 0x08);
+        apu.write(NR44, 0x80);
+        let mut levels = Vec::new();
+        for _ in 0..254 {
+            apu.tick(8);
+            levels.push(apu.channel_outputs()[3]);
+        }
+        assert_eq!(levels[..127], levels[127..]);
+        assert!(levels.contains(&0) && levels.contains(&15));
+        assert_eq!(apu.read(0xFF22), 0x08);
+        assert_eq!(apu.read(NR41), 0xFF);
+    }
+
+    #[test]
+    fn frame_sequencer_clocks_lengths_at_256_hz() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        apu.write(0xFF17, 0xF0);
+        // 4 ticks of length left
+        apu.write(0xFF16, 0x3C);
+        apu.write(NR24, 0xC0);
+        // steps 0, 2 and 4 have clocked it
+        apu.tick(SEQUENCER_PERIOD * 6);
+        assert_eq!(apu.read(NR52), 0xF2);
+        apu.tick(SEQUENCER_PERIOD);
+        assert_eq!(apu.read(NR52), 0xF0);
+    }
+
+    #[test]
+    fn power_off_clears_registers_and_ignores_writes() {
+        let mut apu = Apu::new();
+        apu.write(NR50, 0x77);
+        assert_eq!(apu.read(NR50), 0x00);
+        apu.write(NR52, 0x80);
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0xF3);
+        apu.write(0xFF12, 0xF0);
+        apu.write(NR14, 0x80);
+        assert_eq!(apu.read(NR52), 0xF1);
+        apu.write(NR52, 0x00);
+        assert_eq!(apu.read(NR52), 0x70);
+        assert_eq!(apu.read(NR50), 0x00);
+        assert_eq!(apu.read(NR51), 0x00);
+        assert_eq!(apu.read(0xFF12), 0x00);
+    }
+
+    #[test]
+    fn samples_come_out_at_the_host_rate() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(Some(32768));
+        apu.write(NR52, 0x80);
+        apu.write(NR50, 0x77);
+        // channel 2 on the left only, 50% duty at full volume
+        apu.write(NR51, 0x20);
+        apu.write(0xFF16, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(NR24, 0x80);
+        // a tenth of a second in instruction sized steps
+        for _ in 0..CLOCK_RATE / 10 / 4 {
+            apu.tick(4);
+        }
+        assert_eq!(apu.samples_available(), 3276 * 2);
+        let mut out = vec![1.0; 3277 * 2];
+        assert_eq!(apu.fill(&mut out), 3276 * 2);
+        // the high pass filter centres the square wave around 0
+        let right_silent = out.chunks(2).all(|sample| sample[1] == 0.0);
+        let left = out.chunks(2).map(|sample| sample[0]);
+        let (low, high) = left.fold((0.0, 0.0), |(low, high), value: f32| (value.min(low), value.max(high)));
+        assert!(right_silent);
+        assert!(low < -0.1 && high > 0.1, "{} {}", low, high);
+        // the underrun repeats the last sample
+        assert_eq!(out[out.len() - 2..], out[out.len() - 4..out.len() - 2]);
+    }
+
+    #[test]
+    fn recordings_are_wav_files_with_every_sample() {
+        let path = std::env::temp_dir().join(format!("gb-emulator-apu-{}.wav", std::process::id()));
+        let mut apu = Apu::new();
+        apu.start_recording(&path).unwrap();
+        assert!(apu.recording());
+        assert_eq!(apu.sample_rate(), Some(DEFAULT_SAMPLE_RATE));
+        apu.tick(CLOCK_RATE / 100);
+        apu.stop_recording().unwrap();
+        assert!(!apu.recording());
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(&file[8..16], b"WAVEfmt ");
+        let data_bytes = u32::from_le_bytes(file[40..44].try_into().unwrap()) as usize;
+        // a hundredth of a second at 44.1 kHz, the 441st sample isn't quite done
+        assert_eq!(data_bytes, 440 * 4);
+        assert_eq!(file.len(), 44 + data_bytes);
+        assert_eq!(u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize, file.len() - 8);
+    }
+
+    #[test]
+    fn muting_and_soloing_only_change_the_mix() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0xFF);
+        // channel 1 and 2 both high: 75% duty, one step in
+        for (nrx1, nrx2, nrx4) in [(0xFF11, 0xFF12, NR14), (0xFF16, 0xFF17, NR24)] {
+            apu.write(nrx1, 0xC0);
+            apu.write(nrx2, 0xF0);
+            apu.write(nrx4, 0x87);
+        }
+        apu.tick(1024);
+        assert_eq!(apu.mix(), [-0.5; 2]);
+        apu.set_muted(Channel::Square1, true);
+        assert_eq!(apu.mix(), [-0.25; 2]);
+        assert_eq!(apu.channel_outputs(), [15, 15, 0, 0]);
+        assert_eq!(apu.read_pcm(PCM12), 0xFF);
+        assert_eq!(apu.read_pcm(PCM34), 0x00);
+        // soloing channel 1 while it's muted leaves nothing
+        apu.set_soloed(Channel::Square1, true);
+        assert_eq!(apu.mix(), [0.0; 2]);
+        apu.set_muted(Channel::Square1, false);
+        assert_eq!(apu.mix(), [-0.25; 2]);
+        // settings outlive a power cycle
+        apu.write(NR52, 0x00);
+        apu.write(NR52, 0x80);
+        assert!(apu.soloed(Channel::Square1));
+    }
+
+    #[test]
+    fn pcm_registers_put_each_channel_in_its_own_nibble() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        // square 2 at volume 10, high one duty step in
+        apu.write(NR21, 0xC0);
+        apu.write(0xFF17, 0xA0);
+        apu.write(NR24, 0x87);
+        apu.tick(1024 - 120);
+        // noise at volume 6. its LFSR starts all ones, which is low, and bit 0
+        // first clears on the 15th shift
+        apu.write(0xFF21, 0x60);
+        apu.write(0xFF22, 0x00);
+        apu.write(NR44, 0x80);
+        apu.tick(112);
+        assert_eq!(apu.read_pcm(PCM12), 0x00);
+        assert_eq!(apu.read_pcm(PCM34), 0x00);
+        apu.tick(8);
+        assert_eq!(apu.channel_outputs(), [0, 10, 0, 6]);
+        assert_eq!(apu.read_pcm(PCM12), 0xA0);
+        assert_eq!(apu.read_pcm(PCM34), 0x60);
+    }
+
+    #[test]
+    fn dacs_hold_their_level_until_switched_off() {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0x11);
+        // DAC on but the channel never triggered: digital 0, analog 1.0
+        apu.write(0xFF12, 0x08);
+        assert_eq!(apu.read(NR52), 0xF0);
+        assert_eq!(apu.mix(), [0.25; 2]);
+        apu.write(0xFF12, 0x00);
+        assert_eq!(apu.mix(), [0.0; 2]);
+        // through the high pass filter a constant level fades out
+        apu.set_sample_rate(Some(48000));
+        apu.write(0xFF12, 0x08);
+        let mut out = vec![0.0; 2];
+        apu.tick(CLOCK_RATE / 1000);
+        apu.fill(&mut out);
+        assert!(out[0] > 0.2);
+        apu.tick(CLOCK_RATE / 2);
+        let mut rest = vec![0.0; apu.samples_available()];
+        apu.fill(&mut rest);
+        assert!(rest[rest.len() - 2].abs() < 0.001);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+// the volume envelope of the square and noise channels (NRx2). every `pace`
+// ticks of the 64 Hz clock the volume moves one step up or down, stopping at 0 and 15
+#[derive(Copy, Clone, Default)]
+pub struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.bytes(&[self.register, self.volume, self.timer]);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.register = state.u8()?;
+        self.volume = state.u8()? & 0x0F;
+        self.timer = state.u8()?;
+        Ok(())
+    }
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+    }
+    // the upper 5 bits being zero switches the channel's DAC off
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+    fn initial_volume(&self) -> u8 {
+        self.register >> 4
+    }
+    fn increasing(&self) -> bool {
+        self.register & 0x08 != 0
+    }
+    fn pace(&self) -> u8 {
+        self.register & 0x07
+    }
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume();
+        self.timer = self.pace();
+    }
+    // a pace of 0 leaves the volume where it is
+    pub fn clock(&mut self) {
+        if self.pace() == 0 || self.timer == 0 { return }
+        self.timer -= 1;
+        if self.timer > 0 { return }
+        self.timer = self.pace();
+        if self.increasing() && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increasing() && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
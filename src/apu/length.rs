@@ -0,0 +1,41 @@
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+// counts down at 256 Hz while enabled (bit 6 of NRx4) and switches its channel
+// off when it reaches zero. `max` is 64, or 256 for the wave channel
+#[derive(Copy, Clone)]
+pub struct LengthCounter {
+    max: u16,
+    counter: u16,
+    pub enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.u16(self.counter);
+        state.bool(self.enabled);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.counter = state.u16()?.min(self.max);
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter { max, counter: 0, enabled: false }
+    }
+    // the length timer registers hold how many ticks have already passed
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+    // a trigger with the counter run out starts it again from the top
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+    // false once the channel has to be switched off
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 { return true }
+        self.counter -= 1;
+        self.counter > 0
+    }
+}
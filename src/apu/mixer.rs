@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+
+use super::wav::WavWriter;
+use super::CLOCK_RATE;
+
+// how much audio is kept for a frontend that falls behind, older samples are dropped
+const BUFFERED_SECONDS: f64 = 0.25;
+// how much charge the output capacitor keeps per clock cycle
+const CAPACITOR_CHARGE: f64 = 0.999958;
+
+// turns the per-cycle stereo level into samples at the host's rate. each output
+// sample is the average level over the clock cycles it covers, which works as a
+// simple low pass filter against the aliasing of the square waves.
+//
+// the output then goes through the high pass filter formed by the capacitors on
+// the real board. it takes out the DC offset of the DACs, so a channel whose level
+// stops changing decays back to silence, which games writing the volume rapidly
+// rely on to play samples
+pub struct Mixer {
+    sample_rate: u32,
+    cycles_per_sample: f64,
+    // clock cycles already averaged into the next sample
+    elapsed: f64,
+    sum: [f64; 2],
+    // interleaved left/right
+    samples: VecDeque<f32>,
+    capacity: usize,
+    last: [f32; 2],
+    capacitor: [f32; 2],
+    // CAPACITOR_CHARGE over one sample
+    charge_factor: f32,
+    // every sample also goes here while a recording runs
+    recording: Option<WavWriter<BufWriter<File>>>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Mixer {
+        let sample_rate = sample_rate.max(1);
+        let capacity = (sample_rate as f64 * BUFFERED_SECONDS) as usize * 2;
+        Mixer {
+            sample_rate,
+            cycles_per_sample: CLOCK_RATE as f64 / sample_rate as f64,
+            elapsed: 0.0,
+            sum: [0.0; 2],
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            last: [0.0; 2],
+            capacitor: [0.0; 2],
+            charge_factor: CAPACITOR_CHARGE.powf(CLOCK_RATE as f64 / sample_rate as f64) as f32,
+            recording: None,
+        }
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    // the level held for `cycles` clock cycles
+    pub fn add(&mut self, level: [f32; 2], cycles: u32) {
+        let mut cycles = cycles as f64;
+        while self.elapsed + cycles >= self.cycles_per_sample {
+            let part = self.cycles_per_sample - self.elapsed;
+            let sample = [0, 1].map(|side| ((self.sum[side] + level[side] as f64 * part) / self.cycles_per_sample) as f32);
+            self.push(sample);
+            cycles -= part;
+            self.elapsed = 0.0;
+            self.sum = [0.0; 2];
+        }
+        self.elapsed += cycles;
+        for (sum, level) in self.sum.iter_mut().zip(level) {
+            *sum += level as f64 * cycles;
+        }
+    }
+    fn high_pass(&mut self, sample: [f32; 2]) -> [f32; 2] {
+        [0, 1].map(|side| {
+            let out = sample[side] - self.capacitor[side];
+            self.capacitor[side] = sample[side] - out * self.charge_factor;
+            out
+        })
+    }
+    fn push(&mut self, sample: [f32; 2]) {
+        let sample = self.high_pass(sample);
+        if self.samples.len() + 2 > self.capacity {
+            self.samples.drain(..2);
+        }
+        self.samples.extend(sample);
+        self.last = sample;
+        if let Some(recording) = &mut self.recording {
+            recording.write_sample(sample);
+        }
+    }
+    // interleaved values waiting to be taken
+    pub fn available(&self) -> usize {
+        self.samples.len()
+    }
+    // fills `out` with interleaved left/right samples and returns how many came
+    // from the emulation. if there aren't enough, the rest repeats the last sample
+    // so an underrun doesn't click
+    pub fn fill(&mut self, out: &mut [f32]) -> usize {
+        let count = self.samples.len().min(out.len()) & !1;
+        for (slot, sample) in out.iter_mut().zip(self.samples.drain(..count)) {
+            *slot = sample;
+        }
+        for (index, slot) in out[count..].iter_mut().enumerate() {
+            *slot = self.last[index % 2];
+        }
+        count
+    }
+    pub fn set_recording(&mut self, recording: Option<WavWriter<BufWriter<File>>>) -> Option<WavWriter<BufWriter<File>>> {
+        std::mem::replace(&mut self.recording, recording)
+    }
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.elapsed = 0.0;
+        self.sum = [0.0; 2];
+        self.last = [0.0; 2];
+        self.capacitor = [0.0; 2];
+    }
+}
@@ -0,0 +1,132 @@
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+// NR43's divisor code picks the base period in clock cycles, code 0 counts as half of 16
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+// shifts of 14 and 15 leave the LFSR without a clock
+const MAX_SHIFT: u8 = 13;
+
+// channel 4. a 15 bit linear feedback shift register clocked at
+// divisor << shift cycles; in 7 bit mode the feedback also goes into bit 6,
+// making a short, more tonal loop
+#[derive(Copy, Clone)]
+pub struct Noise {
+    // NR43
+    polynomial: u8,
+    lfsr: u16,
+    timer: u32,
+    envelope: Envelope,
+    length: LengthCounter,
+    enabled: bool,
+}
+
+impl Noise {
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.u8(self.polynomial);
+        state.u16(self.lfsr);
+        state.u32(self.timer);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+        state.bool(self.enabled);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.polynomial = state.u8()?;
+        self.lfsr = state.u16()? & 0x7FFF;
+        self.timer = state.u32()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)?;
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+    pub fn new() -> Noise {
+        Noise {
+            polynomial: 0,
+            lfsr: 0x7FFF,
+            timer: DIVISORS[0],
+            envelope: Envelope::default(),
+            length: LengthCounter::new(64),
+            enabled: false,
+        }
+    }
+    fn shift(&self) -> u8 {
+        self.polynomial >> 4
+    }
+    fn short_mode(&self) -> bool {
+        self.polynomial & 0x08 != 0
+    }
+    fn timer_period(&self) -> u32 {
+        DIVISORS[(self.polynomial & 0x07) as usize] << self.shift()
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+    // registers are numbered from NR41
+    pub fn read(&self, register: usize) -> u8 {
+        match register {
+            1 => self.envelope.read(),
+            2 => self.polynomial,
+            3 => (self.length.enabled as u8) << 6 | 0xBF,
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => self.length.load(value & 0x3F),
+            1 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            2 => self.polynomial = value,
+            3 => {
+                self.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.lfsr = 0x7FFF;
+        self.timer = self.timer_period();
+        self.envelope.trigger();
+        self.length.trigger();
+    }
+    fn step_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ self.lfsr >> 1) & 1;
+        self.lfsr = self.lfsr >> 1 | feedback << 14;
+        if self.short_mode() {
+            self.lfsr = self.lfsr & !0x40 | feedback << 6;
+        }
+    }
+    pub fn tick(&mut self, cycles: u32) {
+        if self.shift() > MAX_SHIFT { return }
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.timer_period();
+            self.step_lfsr();
+        }
+        self.timer -= cycles;
+    }
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    // the digital output, 0-15. the channel is high while bit 0 is clear
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 != 0 { return 0 }
+        self.envelope.volume()
+    }
+}
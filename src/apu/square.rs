@@ -0,0 +1,215 @@
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+// the waveform of each duty setting (NRx1 bits 6-7), one entry per 8th of a period
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+const MAX_PERIOD: u16 = 0x7FF;
+
+// channel 1's frequency sweep (NR10). every `pace` ticks of the 128 Hz clock the
+// period moves by period >> shift, and going past 0x7FF switches the channel off
+#[derive(Copy, Clone, Default)]
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    timer: u8,
+    // the period sweep calculations work from, set on trigger
+    shadow: u16,
+    // a subtraction has happened since the last trigger
+    negated: bool,
+}
+
+impl Sweep {
+    fn pace(&self) -> u8 {
+        self.register >> 4 & 0x07
+    }
+    fn negate(&self) -> bool {
+        self.register & 0x08 != 0
+    }
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+    // a pace of 0 counts as 8 for the timer
+    fn reload_timer(&mut self) {
+        self.timer = if self.pace() == 0 { 8 } else { self.pace() };
+    }
+    fn next_period(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift();
+        if self.negate() {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+// channels 1 and 2. only channel 1 has a sweep unit, channel 2 has no NR20
+#[derive(Copy, Clone)]
+pub struct Square {
+    sweep: Option<Sweep>,
+    duty: u8,
+    duty_step: usize,
+    // 11 bits from NRx3 and NRx4, the tone is 131072 / (2048 - period) Hz
+    period: u16,
+    // clock cycles until the next duty step
+    timer: u32,
+    envelope: Envelope,
+    length: LengthCounter,
+    enabled: bool,
+}
+
+impl Square {
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        if let Some(sweep) = &self.sweep {
+            state.bytes(&[sweep.register, sweep.enabled as u8, sweep.timer, sweep.negated as u8]);
+            state.u16(sweep.shadow);
+        }
+        state.bytes(&[self.duty, self.duty_step as u8]);
+        state.u16(self.period);
+        state.u32(self.timer);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+        state.bool(self.enabled);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        if let Some(sweep) = &mut self.sweep {
+            sweep.register = state.u8()?;
+            sweep.enabled = state.bool()?;
+            sweep.timer = state.u8()?;
+            sweep.negated = state.bool()?;
+            sweep.shadow = state.u16()?;
+        }
+        self.duty = state.u8()? & 0x03;
+        self.duty_step = state.u8()? as usize % 8;
+        self.period = state.u16()? & MAX_PERIOD;
+        self.timer = state.u32()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)?;
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+    pub fn new(with_sweep: bool) -> Square {
+        Square {
+            sweep: with_sweep.then(Sweep::default),
+            duty: 0,
+            duty_step: 0,
+            period: 0,
+            timer: Square::timer_period(0),
+            envelope: Envelope::default(),
+            length: LengthCounter::new(64),
+            enabled: false,
+        }
+    }
+    fn timer_period(period: u16) -> u32 {
+        (2048 - period as u32) * 4
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+    // registers are numbered from NRx0, reads have unused and write only bits set
+    pub fn read(&self, register: usize) -> u8 {
+        match register {
+            0 => self.sweep.map_or(0xFF, |sweep| 0x80 | sweep.register),
+            1 => self.duty << 6 | 0x3F,
+            2 => self.envelope.read(),
+            4 => (self.length.enabled as u8) << 6 | 0xBF,
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => if let Some(sweep) = &mut self.sweep {
+                sweep.register = value & 0x7F;
+                // leaving negate mode after a subtraction was used stops the channel
+                if sweep.negated && !sweep.negate() {
+                    self.enabled = false;
+                }
+            },
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.period = self.period & 0x700 | value as u16,
+            4 => {
+                self.period = self.period & 0xFF | (value as u16 & 0x07) << 8;
+                self.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = Square::timer_period(self.period);
+        self.envelope.trigger();
+        self.length.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.period;
+            sweep.negated = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.pace() != 0 || sweep.shift() != 0;
+            // the overflow check runs straight away when there's a shift
+            if sweep.shift() != 0 && sweep.next_period() > MAX_PERIOD {
+                self.enabled = false;
+            }
+        }
+    }
+    // advance the duty position by the clock cycles that passed
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = Square::timer_period(self.period);
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else { return };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 { return }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.pace() == 0 { return }
+        let period = sweep.next_period();
+        if period > MAX_PERIOD {
+            self.enabled = false;
+        } else if sweep.shift() != 0 {
+            sweep.shadow = period;
+            self.period = period;
+            // and once more with the new period, only to check for overflow
+            if sweep.next_period() > MAX_PERIOD {
+                self.enabled = false;
+            }
+        }
+    }
+    // the digital output, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.enabled { return 0 }
+        DUTY_PATTERNS[self.duty as usize][self.duty_step] * self.envelope.volume()
+    }
+}
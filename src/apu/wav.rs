@@ -0,0 +1,55 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 2;
+const BYTES_PER_SAMPLE: u16 = 2;
+
+// writes 16 bit stereo PCM as a RIFF WAVE file. the sizes in the header are only
+// known at the end, finish() goes back and fills them in
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    data_bytes: u32,
+    // the first write error stops the recording, the emulator carries on
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        let block_align = CHANNELS * BYTES_PER_SAMPLE;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&CHANNELS.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { writer, data_bytes: 0, error: None })
+    }
+    // one left/right pair, -1.0 to 1.0
+    pub fn write_sample(&mut self, sample: [f32; 2]) {
+        if self.error.is_some() { return }
+        let [left, right] = sample.map(|value| ((value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+        match self.writer.write_all(&[left[0], left[1], right[0], right[1]]) {
+            Ok(()) => self.data_bytes += (CHANNELS * BYTES_PER_SAMPLE) as u32,
+            Err(error) => self.error = Some(error),
+        }
+    }
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(HEADER_SIZE - 8 + self.data_bytes).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.writer.write_all(&self.data_bytes.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
@@ -0,0 +1,32 @@
+use std::io::{self, Cursor, Read};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ROM_EXTENSIONS: [&str; 2] = [".gb", ".gbc"];
+
+// unpacks a rom from a .zip (the first .gb/.gbc entry) or .gz file, anything
+// else is assumed to be a plain rom and handed back untouched. Ok(None) means a
+// zip without any rom in it
+pub fn extract_rom(data: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+    if data.starts_with(ZIP_MAGIC) {
+        let mut archive = ZipArchive::new(Cursor::new(data))?;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            let name = entry.name().to_ascii_lowercase();
+            if !entry.is_file() || !ROM_EXTENSIONS.iter().any(|extension| name.ends_with(extension)) { continue }
+            let mut rom = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut rom)?;
+            return Ok(Some(rom));
+        }
+        Ok(None)
+    } else if data.starts_with(GZIP_MAGIC) {
+        let mut rom = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut rom)?;
+        Ok(Some(rom))
+    } else {
+        Ok(Some(data))
+    }
+}
@@ -0,0 +1,391 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::mbc::{self, CameraSource, Mapper, RumbleSink};
+use crate::patch::{self, PatchError};
+use crate::reset::ResetKind;
+
+const HEADER_TITLE: usize = 0x0134;
+const HEADER_CGB_FLAG: usize = 0x0143;
+const HEADER_NEW_LICENSEE: usize = 0x0144;
+const HEADER_SGB_FLAG: usize = 0x0146;
+const HEADER_CARTRIDGE_TYPE: usize = 0x0147;
+const HEADER_ROM_SIZE: usize = 0x0148;
+const HEADER_RAM_SIZE: usize = 0x0149;
+const HEADER_OLD_LICENSEE: usize = 0x014B;
+const HEADER_VERSION: usize = 0x014C;
+const HEADER_CHECKSUM: usize = 0x014D;
+const HEADER_GLOBAL_CHECKSUM: usize = 0x014E;
+const HEADER_END: usize = 0x0150;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    // the image is too short to even hold a header
+    TooSmall(usize),
+    HeaderChecksum { expected: u8, actual: u8 },
+    Patch(PatchError),
+    // a zip file without any .gb or .gbc entry
+    NoRomInArchive,
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(error) => write!(f, "couldn't read rom: {}", error),
+            CartridgeError::TooSmall(length) => write!(f, "rom is only {} bytes, too small for a cartridge header", length),
+            CartridgeError::HeaderChecksum { expected, actual } =>
+                write!(f, "header checksum mismatch: header says {:02X}, computed {:02X}", expected, actual),
+            CartridgeError::Patch(error) => write!(f, "couldn't apply patch: {}", error),
+            CartridgeError::NoRomInArchive => write!(f, "archive doesn't contain a .gb or .gbc file"),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(error: io::Error) -> Self {
+        CartridgeError::Io(error)
+    }
+}
+
+impl From<PatchError> for CartridgeError {
+    fn from(error: PatchError) -> Self {
+        CartridgeError::Patch(error)
+    }
+}
+
+// the hardware a cartridge carries next to its rom, from header byte 0x0147
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MapperKind {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+    Unknown(u8),
+}
+
+impl MapperKind {
+    pub fn from_cartridge_type(cartridge_type: u8) -> MapperKind {
+        match cartridge_type {
+            0x00 | 0x08 | 0x09 => MapperKind::RomOnly,
+            0x01..=0x03 => MapperKind::Mbc1,
+            0x05 | 0x06 => MapperKind::Mbc2,
+            0x0B..=0x0D => MapperKind::Mmm01,
+            0x0F..=0x13 => MapperKind::Mbc3,
+            0x19..=0x1E => MapperKind::Mbc5,
+            0x20 => MapperKind::Mbc6,
+            0x22 => MapperKind::Mbc7,
+            0xFC => MapperKind::PocketCamera,
+            0xFD => MapperKind::Tama5,
+            0xFE => MapperKind::HuC3,
+            0xFF => MapperKind::HuC1,
+            other => MapperKind::Unknown(other),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CgbSupport {
+    // plain DMG game
+    None,
+    // runs on both, with color on CGB
+    Enhanced,
+    Only,
+}
+
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    // two character code when the old licensee byte defers to it (0x33), otherwise the old code in hex
+    pub licensee: String,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < HEADER_END { return Err(CartridgeError::TooSmall(rom.len())) }
+        let cgb = match rom[HEADER_CGB_FLAG] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+        // cgb games took the last title byte for the cgb flag
+        let title_end = if cgb == CgbSupport::None { HEADER_CGB_FLAG + 1 } else { HEADER_CGB_FLAG };
+        let title = rom[HEADER_TITLE..title_end].iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+        let licensee = match rom[HEADER_OLD_LICENSEE] {
+            0x33 => String::from_utf8_lossy(&rom[HEADER_NEW_LICENSEE..HEADER_NEW_LICENSEE + 2]).into_owned(),
+            code => format!("{:02X}", code),
+        };
+        let ram_size = match rom[HEADER_RAM_SIZE] {
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        };
+        Ok(CartridgeHeader {
+            title,
+            cgb,
+            sgb: rom[HEADER_SGB_FLAG] == 0x03,
+            cartridge_type: rom[HEADER_CARTRIDGE_TYPE],
+            // 32 KiB shifted by the size code, anything absurd is clamped
+            rom_size: (0x8000usize) << rom[HEADER_ROM_SIZE].min(8),
+            ram_size,
+            licensee,
+            version: rom[HEADER_VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: u16::from_be_bytes([rom[HEADER_GLOBAL_CHECKSUM], rom[HEADER_GLOBAL_CHECKSUM + 1]]),
+        })
+    }
+    // the checksum the boot rom verifies before starting the game
+    pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+        rom[HEADER_TITLE..HEADER_CHECKSUM].iter()
+            .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1))
+    }
+    pub fn mapper(&self) -> MapperKind {
+        MapperKind::from_cartridge_type(self.cartridge_type)
+    }
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFC | 0xFE | 0xFF)
+    }
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type, 0x0F | 0x10 | 0xFE)
+    }
+    pub fn has_rumble(&self) -> bool {
+        matches!(self.cartridge_type, 0x1C..=0x1E)
+    }
+}
+
+// told when writing the save file fails as the cartridge is dropped, with nobody
+// left to return the error to
+pub type SaveErrorHook = Box<dyn FnMut(&io::Error)>;
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    mapper: Box<dyn Mapper>,
+    // where battery backed ram is persisted, flushed when the cartridge is dropped
+    save_path: Option<PathBuf>,
+    save_error_hook: Option<SaveErrorHook>,
+}
+
+impl Cartridge {
+    // battery backed carts also pick up the .sav next to the rom, if there is one.
+    // with the archives feature the rom can also be inside a .zip or .gz file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Cartridge, CartridgeError> {
+        let rom = fs::read(&path)?;
+        #[cfg(feature = "archives")]
+        let rom = crate::archive::extract_rom(rom)?.ok_or(CartridgeError::NoRomInArchive)?;
+        let mut cartridge = Cartridge::from_bytes(rom)?;
+        if cartridge.header.has_battery() {
+            let save_path = path.as_ref().with_extension("sav");
+            match fs::read(&save_path) {
+                Ok(ram) => cartridge.load_ram(&ram),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error.into()),
+            }
+            cartridge.save_path = Some(save_path);
+        }
+        Ok(cartridge)
+    }
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let cartridge = Cartridge::from_bytes_unchecked(rom)?;
+        let actual = CartridgeHeader::compute_header_checksum(cartridge.rom());
+        if actual != cartridge.header.header_checksum {
+            return Err(CartridgeError::HeaderChecksum { expected: cartridge.header.header_checksum, actual });
+        }
+        Ok(cartridge)
+    }
+    // apply an IPS or BPS patch (told apart by their headers) before loading,
+    // so hacks and translations don't need a pre-patched copy of the rom
+    pub fn load_with_patch(rom: Vec<u8>, patch: &[u8]) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_bytes(patch::apply(&rom, patch)?)
+    }
+    // skips the header checksum, which real hardware refuses to boot without but
+    // some homebrew and test images never bother filling in
+    pub fn from_bytes_unchecked(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let mapper = mbc::for_header(&header, rom);
+        Ok(Cartridge { header, mapper, save_path: None, save_error_hook: None })
+    }
+    // a cartridge driven by mapper hardware this crate doesn't know about. the header
+    // is still parsed from the mapper's rom, but its cartridge type is ignored
+    pub fn from_mapper(mapper: Box<dyn Mapper>) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(mapper.rom())?;
+        Ok(Cartridge { header, mapper, save_path: None, save_error_hook: None })
+    }
+    pub fn mapper(&self) -> &dyn Mapper {
+        self.mapper.as_ref()
+    }
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.mapper.as_mut()
+    }
+    pub fn rom(&self) -> &[u8] {
+        self.mapper.rom()
+    }
+    // 0x0000-0x7FFF, past the end of the image reads as open bus
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mapper.read_rom(address)
+    }
+    // writes to rom are how mappers get their commands, a plain rom ignores them
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        self.mapper.write_rom(address, value);
+    }
+    // 0xA000-0xBFFF, address is relative to 0xA000
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mapper.read_ram(address)
+    }
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.mapper.write_ram(address, value);
+    }
+    pub fn set_rumble_sink(&mut self, sink: Box<dyn RumbleSink>) {
+        self.mapper.set_rumble_sink(sink);
+    }
+    // feeds an accelerometer cart (MBC7), ignored by everything else
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mapper.set_tilt(x, y);
+    }
+    // feeds a camera cart, ignored by everything else
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.mapper.set_camera_source(source);
+    }
+    pub fn tick(&mut self, cycles: u32) {
+        self.mapper.tick(cycles);
+    }
+    pub fn set_emulated_clock(&mut self, emulated: bool) {
+        self.mapper.set_emulated_clock(emulated);
+    }
+    // contents of the external ram, for hosts that manage save storage themselves
+    pub fn save_ram(&self) -> Vec<u8> {
+        self.mapper.ram().to_vec()
+    }
+    // a save that's shorter or longer than the cart's ram only fills what overlaps
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let ram = self.mapper.ram_mut();
+        let length = ram.len().min(data.len());
+        ram[..length].copy_from_slice(&data[..length]);
+    }
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+    // None stops the cartridge writing its ram out on its own
+    pub fn set_save_path(&mut self, path: Option<PathBuf>) {
+        self.save_path = path;
+    }
+    // without a hook a failed write on drop goes unreported, hosts that care about
+    // it can also call flush_save themselves first
+    pub fn set_save_error_hook(&mut self, hook: SaveErrorHook) {
+        self.save_error_hook = Some(hook);
+    }
+    // write battery backed ram to the save path now rather than waiting for drop
+    pub fn flush_save(&self) -> io::Result<()> {
+        match &self.save_path {
+            Some(path) if self.header.has_battery() && !self.mapper.ram().is_empty() => fs::write(path, self.mapper.ram()),
+            _ => Ok(()),
+        }
+    }
+    pub fn save_state(&self) -> Vec<u8> {
+        self.mapper.save_state()
+    }
+    pub fn load_state(&mut self, data: &[u8]) {
+        self.mapper.load_state(data);
+    }
+    // ram without a battery doesn't survive losing power
+    pub fn reset(&mut self, kind: ResetKind) {
+        self.mapper.reset(kind);
+        if kind == ResetKind::PowerCycle && !self.header.has_battery() {
+            self.mapper.ram_mut().fill(0);
+        }
+    }
+    // wipes external ram, battery or not
+    pub fn clear_ram(&mut self) {
+        self.mapper.ram_mut().fill(0);
+    }
+}
+
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush_save()
+            && let Some(hook) = &mut self.save_error_hook {
+            hook(&error);
+        }
+    }
+}
+
+// a rom with `program` at the entry point and a header that passes the checksum,
+// for tests. anything with a mapper gets four banks so there's something to switch to
+#[cfg(test)]
+pub(crate) fn test_rom(program: &[u8], cartridge_type: u8, ram_size: u8) -> Vec<u8> {
+    let banks = if cartridge_type == 0x00 { 2 } else { 4 };
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom[HEADER_CARTRIDGE_TYPE] = cartridge_type;
+    rom[HEADER_ROM_SIZE] = if banks == 4 { 0x01 } else { 0x00 };
+    rom[HEADER_RAM_SIZE] = ram_size;
+    rom[HEADER_CHECKSUM] = CartridgeHeader::compute_header_checksum(&rom);
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn battery_ram_round_trips_through_the_sav_file() {
+        let dir = std::env::temp_dir().join(format!("gb-emulator-sav-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.gb");
+        // MBC1+RAM+BATTERY with 8 KiB of ram
+        fs::write(&rom_path, test_rom(&[], 0x03, 0x02)).unwrap();
+        // no .sav yet, the ram starts out blank
+        let mut cartridge = Cartridge::from_file(&rom_path).unwrap();
+        assert_eq!(cartridge.save_path(), Some(dir.join("game.sav").as_path()));
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0x0123, 0x42);
+        drop(cartridge);
+        let save = fs::read(dir.join("game.sav")).unwrap();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0x0123], 0x42);
+        // and it's picked up again next time
+        let mut cartridge = Cartridge::from_file(&rom_path).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        assert_eq!(cartridge.read_ram(0x0123), 0x42);
+        // a save path that can't be written reports through flush_save and the hook
+        let errors = Rc::new(RefCell::new(0));
+        let counter = errors.clone();
+        cartridge.set_save_path(Some(dir.clone()));
+        cartridge.set_save_error_hook(Box::new(move |_| *counter.borrow_mut() += 1));
+        assert!(cartridge.flush_save().is_err());
+        drop(cartridge);
+        assert_eq!(*errors.borrow(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::address::BankedAddress;
+
+// flag bits per rom byte. code and data are the low two bits, as in every
+// CDL, the branch targets above them are what Mesen adds for the Game Boy
+pub const CDL_CODE: u8 = 0x01;
+pub const CDL_DATA: u8 = 0x02;
+pub const CDL_JUMP_TARGET: u8 = 0x04;
+pub const CDL_SUB_ENTRY_POINT: u8 = 0x08;
+
+const RST_OPCODES: [u8; 8] = [0xC7, 0xCF, 0xD7, 0xDF, 0xE7, 0xEF, 0xF7, 0xFF];
+
+// bytes in the instruction an opcode starts, counting the 0xCB prefix
+pub fn instruction_length(opcode: u8) -> u16 {
+    match opcode {
+        0x01 | 0x08 | 0x11 | 0x21 | 0x31 | 0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCC | 0xCD | 0xD2 | 0xD4 | 0xDA | 0xDC
+        | 0xEA | 0xFA => 3,
+        0x06 | 0x0E | 0x10 | 0x16 | 0x18 | 0x1E | 0x20 | 0x26 | 0x28 | 0x2E | 0x30 | 0x36 | 0x38 | 0x3E | 0xC6
+        | 0xCB | 0xCE | 0xD6 | 0xDE | 0xE0 | 0xE6 | 0xE8 | 0xEE | 0xF0 | 0xF6 | 0xF8 | 0xFE => 2,
+        _ => 1,
+    }
+}
+
+fn is_call(opcode: u8) -> bool {
+    matches!(opcode, 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC) || RST_OPCODES.contains(&opcode)
+}
+
+fn is_jump(opcode: u8) -> bool {
+    matches!(opcode, 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xE9)
+}
+
+// the instruction the cpu is in the middle of
+#[derive(Copy, Clone)]
+struct Fetch {
+    address: u16,
+    opcode: u8,
+    length: u16,
+}
+
+// which rom bytes ran as code and which were read as data, one flag byte per
+// rom byte. the file is those bytes as they are, what disassemblers that take
+// a CDL expect. turned on with Mmu::enable_code_data_log
+pub struct CodeDataLog {
+    flags: Vec<Cell<u8>>,
+    current: Cell<Option<Fetch>>,
+}
+
+impl CodeDataLog {
+    pub fn new(rom_size: usize) -> CodeDataLog {
+        CodeDataLog { flags: vec![Cell::new(0); rom_size], current: Cell::new(None) }
+    }
+    // carries on from a log saved earlier, as long as it's for a rom this size
+    pub fn from_bytes(bytes: &[u8], rom_size: usize) -> Option<CodeDataLog> {
+        if bytes.len() != rom_size { return None }
+        let log = CodeDataLog::new(rom_size);
+        for (flag, &byte) in log.flags.iter().zip(bytes) {
+            flag.set(byte);
+        }
+        Some(log)
+    }
+    pub fn load(path: impl AsRef<Path>, rom_size: usize) -> io::Result<CodeDataLog> {
+        let bytes = fs::read(path)?;
+        CodeDataLog::from_bytes(&bytes, rom_size)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the log is for a rom of another size"))
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.flags.iter().map(Cell::get).collect()
+    }
+    pub fn flags(&self, offset: usize) -> u8 {
+        self.flags.get(offset).map_or(0, Cell::get)
+    }
+    // the flags for a rom address in a given bank, 0 outside the rom
+    pub fn flags_at(&self, location: BankedAddress) -> u8 {
+        location.rom_offset().map_or(0, |offset| self.flags(offset))
+    }
+    fn mark(&self, offset: Option<usize>, flag: u8) {
+        if let Some(cell) = offset.and_then(|offset| self.flags.get(offset)) {
+            cell.set(cell.get() | flag);
+        }
+    }
+    // an instruction starting at `address`, with `offset` giving the rom offset
+    // a cpu address is mapped to (None outside the rom). the instruction's bytes
+    // are code, and if the one before it branched here this is a jump target or
+    // the start of a subroutine
+    pub fn instruction_started(&self, address: u16, opcode: u8, offset: impl Fn(u16) -> Option<usize>) {
+        if let Some(previous) = self.current.get() {
+            let fell_through = previous.address.wrapping_add(previous.length) == address;
+            if is_call(previous.opcode) && !fell_through {
+                self.mark(offset(address), CDL_SUB_ENTRY_POINT);
+            } else if is_jump(previous.opcode) && !fell_through {
+                self.mark(offset(address), CDL_JUMP_TARGET);
+            }
+        }
+        let length = instruction_length(opcode);
+        for byte in 0..length {
+            self.mark(offset(address.wrapping_add(byte)), CDL_CODE);
+        }
+        self.current.set(Some(Fetch { address, opcode, length }));
+    }
+    // a read of the rom, data unless it's the cpu fetching the current instruction
+    pub fn read(&self, address: u16, offset: Option<usize>) {
+        let fetching = self
+            .current
+            .get()
+            .is_some_and(|fetch| address.wrapping_sub(fetch.address) < fetch.length);
+        if !fetching {
+            self.mark(offset, CDL_DATA);
+        }
+    }
+    pub fn clear(&self) {
+        for flag in &self.flags {
+            flag.set(0);
+        }
+    }
+    // rom bytes seen as code and as data, for a quick idea of the coverage
+    pub fn counts(&self) -> (usize, usize) {
+        let code = self.flags.iter().filter(|flag| flag.get() & CDL_CODE != 0).count();
+        let data = self.flags.iter().filter(|flag| flag.get() & CDL_DATA != 0).count();
+        (code, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+    use crate::cpu::Bus;
+    use crate::disassembler::disassemble;
+    use crate::gameboy::GameBoy;
+    use crate::mmu::Mmu;
+
+    #[test]
+    fn lengths_agree_with_the_disassembler() {
+        let mut mmu = Mmu::new();
+        for opcode in 0..=0xFF {
+            mmu.write_byte(0xC000, opcode);
+            assert_eq!(instruction_length(opcode) as usize, disassemble(&mmu, 0xC000, None).bytes.len(), "{:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn rom_bytes_are_marked_as_code_or_data() {
+        // ld hl, 0x0200; ld a, [hl]; call 0x0150; jr -2. 0x0150 is jp 0x0160, 0x0160 ret
+        let mut rom = test_rom(&[0x21, 0x00, 0x02, 0x7E, 0xCD, 0x50, 0x01, 0x18, 0xFE, 0x00], 0x00, 0);
+        rom[0x150..0x153].copy_from_slice(&[0xC3, 0x60, 0x01]);
+        rom[0x160] = 0xC9;
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        gameboy.mmu_mut().enable_code_data_log();
+        for _ in 0..6 {
+            gameboy.step();
+        }
+        let log = gameboy.mmu().code_data_log().unwrap();
+        assert_eq!((0x100..0x109).map(|offset| log.flags(offset) & CDL_CODE).sum::<u8>(), 9);
+        assert_eq!(log.flags(0x0200), CDL_DATA);
+        assert_eq!(log.flags(0x0150), CDL_CODE | CDL_SUB_ENTRY_POINT);
+        assert_eq!(log.flags(0x0160), CDL_CODE | CDL_JUMP_TARGET);
+        assert_eq!(log.flags_at(BankedAddress::banked(0, 0x0150)), CDL_CODE | CDL_SUB_ENTRY_POINT);
+        // the instruction after the call is where the ret comes back to, not a jump target
+        assert_eq!(log.flags(0x0107), CDL_CODE);
+        assert_eq!(log.flags(0x0109), 0);
+        let bytes = log.to_bytes();
+        assert_eq!(bytes.len(), 0x8000);
+        assert!(CodeDataLog::from_bytes(&bytes[..0x4000], 0x8000).is_none());
+        assert_eq!(CodeDataLog::from_bytes(&bytes, 0x8000).unwrap().counts(), log.counts());
+    }
+}
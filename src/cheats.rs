@@ -0,0 +1,183 @@
+use std::fmt;
+
+mod cht;
+mod search;
+
+pub use search::{gameshark_code, CheatSearch, Comparison};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheatError(pub String);
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Cheat {
+    // ABC-DEF or ABC-DEF-GHI: replaces what the cpu reads from a rom address,
+    // only where the rom holds `compare` if there is one, so a single bank is hit
+    GameGenie { address: u16, value: u8, compare: Option<u8> },
+    // TTVVAAAA: writes a value to ram once a frame, at the start of vblank.
+    // TT picks the bank: 0x80-0x8F an external ram bank, 0x90-0x97 a CGB work
+    // ram bank, anything else (usually 0x01) whatever is mapped at the time
+    GameShark { bank: u8, address: u16, value: u8 },
+}
+
+fn hex_digits(code: &str) -> Option<Vec<u8>> {
+    code.chars().map(|digit| digit.to_digit(16).map(|digit| digit as u8)).collect()
+}
+
+impl Cheat {
+    // the kind is told apart by the format, upper or lower case
+    pub fn parse(code: &str) -> Result<Cheat, CheatError> {
+        let code = code.trim();
+        let invalid = || CheatError(format!("{:?} isn't a Game Genie or GameShark code", code));
+        let groups: Vec<&str> = code.split('-').collect();
+        let game_genie = groups.len() >= 2 && groups.len() <= 3 && groups.iter().all(|group| group.len() == 3);
+        // GameShark codes are 8 digits with no dashes
+        if !game_genie && (code.len() != 8 || groups.len() != 1) {
+            return Err(invalid());
+        }
+        let digits = hex_digits(&groups.concat()).ok_or_else(invalid)?;
+        let byte = |index: usize| digits[index] << 4 | digits[index + 1];
+        if !game_genie {
+            let address = u16::from_be_bytes([byte(6), byte(4)]);
+            return Ok(Cheat::GameShark { bank: byte(0), address, value: byte(2) });
+        }
+        // the address is scrambled with its top digit inverted, and the compare
+        // byte is rotated and xored. H is only there to check the code was typed right
+        let address = ((digits[5] ^ 0x0F) as u16) << 12 | (digits[2] as u16) << 8 | (digits[3] as u16) << 4 | digits[4] as u16;
+        let compare = (digits.len() == 9).then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA);
+        if address > 0x7FFF {
+            return Err(CheatError(format!("{:?} doesn't patch rom", code)));
+        }
+        Ok(Cheat::GameGenie { address, value: byte(0), compare })
+    }
+}
+
+pub struct CheatEntry {
+    pub description: String,
+    // as it was entered, codes that only work together joined with '+'
+    pub code: String,
+    pub cheats: Vec<Cheat>,
+    pub enabled: bool,
+}
+
+// the cheats in effect, of either kind. the MMU applies them: Game Genie codes
+// on every rom read, GameShark codes whenever vblank starts
+pub struct CheatEngine {
+    entries: Vec<CheatEntry>,
+}
+
+impl CheatEngine {
+    pub fn new() -> CheatEngine {
+        CheatEngine { entries: Vec::new() }
+    }
+    // enabled straight away, returning its index
+    pub fn add(&mut self, code: &str) -> Result<usize, CheatError> {
+        self.add_described("", code)
+    }
+    pub fn add_described(&mut self, description: &str, code: &str) -> Result<usize, CheatError> {
+        let cheats = code.split('+').map(Cheat::parse).collect::<Result<_, _>>()?;
+        let code = code.split('+').map(|part| part.trim().to_uppercase()).collect::<Vec<_>>().join("+");
+        self.entries.push(CheatEntry { description: description.to_string(), code, cheats, enabled: true });
+        Ok(self.entries.len() - 1)
+    }
+    // keeps a ram address at a value, e.g. one found with a CheatSearch
+    pub fn freeze(&mut self, address: u16, value: u8) -> usize {
+        self.add(&gameshark_code(address, value)).expect("generated codes are valid")
+    }
+    pub fn remove(&mut self, index: usize) -> Option<CheatEntry> {
+        (index < self.entries.len()).then(|| self.entries.remove(index))
+    }
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(entry) = self.entries.get_mut(index) {
+            entry.enabled = enabled;
+        }
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+    pub fn entries(&self) -> &[CheatEntry] {
+        &self.entries
+    }
+    fn enabled(&self) -> impl Iterator<Item = Cheat> + '_ {
+        self.entries.iter().filter(|entry| entry.enabled).flat_map(|entry| entry.cheats.iter().copied())
+    }
+    // what the cpu sees at a rom address that holds `value`
+    pub fn patch_rom(&self, address: u16, value: u8) -> u8 {
+        self.enabled()
+            .find_map(|cheat| match cheat {
+                Cheat::GameGenie { address: patched, value: new, compare }
+                    if patched == address && compare.is_none_or(|compare| compare == value) => Some(new),
+                _ => None,
+            })
+            .unwrap_or(value)
+    }
+    // (bank, address, value) of every GameShark code to write this frame
+    pub fn ram_writes(&self) -> Vec<(u8, u16, u8)> {
+        self.enabled()
+            .filter_map(|cheat| match cheat {
+                Cheat::GameShark { bank, address, value } => Some((bank, address, value)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+    use crate::cpu::Bus;
+    use crate::gameboy::GameBoy;
+
+    #[test]
+    fn codes_decode_by_format() {
+        assert_eq!(
+            Cheat::parse("01FF34c2"),
+            Ok(Cheat::GameShark { bank: 0x01, address: 0xC234, value: 0xFF })
+        );
+        assert_eq!(
+            Cheat::parse("00A-17B-C49"),
+            Ok(Cheat::GameGenie { address: 0x4A17, value: 0x00, compare: Some(0xC8) })
+        );
+        assert_eq!(Cheat::parse("3E1-5AF"), Ok(Cheat::GameGenie { address: 0x015A, value: 0x3E, compare: None }));
+        assert!(Cheat::parse("01FF34C").is_err());
+        assert!(Cheat::parse("0100-C0D").is_err());
+        assert!(Cheat::parse("XYZ-123").is_err());
+        // would land in vram
+        assert!(Cheat::parse("00A-171").is_err());
+    }
+
+    #[test]
+    fn gameshark_codes_write_at_vblank() {
+        // MBC5 with 4 banks of ram; ld a, 0x91; ldh (0x40), a; jr -2
+        let rom = test_rom(&[0x3E, 0x91, 0xE0, 0x40, 0x18, 0xFE], 0x1B, 0x03);
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        let mmu = gameboy.mmu_mut();
+        mmu.cheats.add("014200C0").unwrap();
+        mmu.cheats.add("827710A0").unwrap();
+        let disabled = mmu.cheats.add("019901C0").unwrap();
+        mmu.cheats.set_enabled(disabled, false);
+        mmu.cheats.add("111-50F").unwrap();
+        assert_eq!(mmu.read_byte(0x0150), 0x11);
+        mmu.enable_heatmap(None);
+        gameboy.run_frame();
+        let mmu = gameboy.mmu_mut();
+        // the writes don't show up as the game's own
+        let heatmap = mmu.disable_heatmap().unwrap().snapshot();
+        assert_eq!(heatmap.writes[0xC000], 0);
+        assert_eq!(mmu.read_byte(0xC000), 0x42);
+        assert_ne!(mmu.read_byte(0xC001), 0x99);
+        // bank 2 gets it, whichever bank is mapped
+        mmu.write_byte(0x0000, 0x0A);
+        assert_ne!(mmu.read_byte(0xA010), 0x77);
+        mmu.write_byte(0x4000, 0x02);
+        assert_eq!(mmu.read_byte(0xA010), 0x77);
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cheats::{CheatEngine, CheatError};
+
+// cheat lists as RetroArch and other libretro frontends share them:
+//   cheats = 1
+//
+//   cheat0_desc = "Infinite lives"
+//   cheat0_code = "010A23D1+010A24D1"
+//   cheat0_enable = true
+// other keys (RetroArch's own memory search cheats, for one) are ignored, and
+// so are entries without a code
+impl CheatEngine {
+    pub fn from_cht(text: &str) -> Result<CheatEngine, CheatError> {
+        let mut values = HashMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| CheatError(format!("line {}: expected key = value", index + 1)))?;
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+            values.insert(key.trim().to_lowercase(), value.to_string());
+        }
+        let count: usize = match values.get("cheats") {
+            Some(count) => count.parse().map_err(|_| CheatError(format!("bad cheat count {:?}", count)))?,
+            None => 0,
+        };
+        let mut engine = CheatEngine::new();
+        for index in 0..count {
+            let value = |key: &str| values.get(&format!("cheat{}_{}", index, key));
+            let Some(code) = value("code").filter(|code| !code.is_empty()) else { continue };
+            let description = value("desc").map_or("", String::as_str);
+            let added = engine
+                .add_described(description, code)
+                .map_err(|error| CheatError(format!("cheat{}: {}", index, error)))?;
+            engine.set_enabled(added, value("enable").is_some_and(|enable| enable == "true"));
+        }
+        Ok(engine)
+    }
+    pub fn to_cht(&self) -> String {
+        let mut text = format!("cheats = {}\n", self.entries.len());
+        for (index, entry) in self.entries.iter().enumerate() {
+            text += &format!("\ncheat{}_desc = {:?}\n", index, entry.description);
+            text += &format!("cheat{}_code = \"{}\"\n", index, entry.code);
+            text += &format!("cheat{}_enable = {}\n", index, entry.enabled);
+        }
+        text
+    }
+    // a missing file is an error, like Keymap::load
+    pub fn load_cht(path: impl AsRef<Path>) -> Result<CheatEngine, CheatError> {
+        let text = fs::read_to_string(path).map_err(|error| CheatError(error.to_string()))?;
+        CheatEngine::from_cht(&text)
+    }
+    pub fn save_cht(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_cht())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheats::Cheat;
+
+    #[test]
+    fn cheat_lists_round_trip_through_cht() {
+        let text = "cheats = 3\n\n\
+            cheat0_desc = \"Infinite lives\"\n\
+            cheat0_code = \"010A23D1+010A24D1\"\n\
+            cheat0_enable = true\n\n\
+            cheat1_desc = \"Found with a search\"\n\
+            cheat1_address = 53539\n\n\
+            cheat2_desc = \"Moon jump\"\n\
+            cheat2_code = \"00A-17B-C49\"\n\
+            cheat2_enable = false\n";
+        let engine = CheatEngine::from_cht(text).unwrap();
+        let entries = engine.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].description, "Infinite lives");
+        assert_eq!(entries[0].cheats.len(), 2);
+        assert_eq!(entries[0].cheats[1], Cheat::GameShark { bank: 0x01, address: 0xD124, value: 0x0A });
+        assert!(entries[0].enabled);
+        assert!(!entries[1].enabled);
+        let reloaded = CheatEngine::from_cht(&engine.to_cht()).unwrap();
+        assert_eq!(reloaded.to_cht(), engine.to_cht());
+        assert!(CheatEngine::from_cht("cheats = 1\ncheat0_code = \"nope\"\n").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::cpu::Bus;
+use crate::mmu::{HRAM_BEGIN, HRAM_END, WRAM_BEGIN, WRAM_END};
+
+// how a value has to compare with the one seen at the previous search step
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    // wrapping, so a counter going from 0xFF to 0x01 increased by 2
+    IncreasedBy(u8),
+    DecreasedBy(u8),
+    Exactly(u8),
+}
+
+impl Comparison {
+    fn matches(self, previous: u8, current: u8) -> bool {
+        match self {
+            Comparison::Unchanged => current == previous,
+            Comparison::Changed => current != previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+            Comparison::IncreasedBy(amount) => current == previous.wrapping_add(amount),
+            Comparison::DecreasedBy(amount) => current == previous.wrapping_sub(amount),
+            Comparison::Exactly(value) => current == value,
+        }
+    }
+}
+
+// narrows work ram and high ram down to the addresses behaving the way a value
+// in the game does, e.g. lives going down by one each time one is lost. start a
+// search, play a little, filter, and repeat until few enough are left to freeze
+// or try out as GameShark codes. only the work ram bank mapped at D000 is seen
+pub struct CheatSearch {
+    // (address, value at the last step) of everything still in the running
+    candidates: Vec<(u16, u8)>,
+}
+
+impl CheatSearch {
+    // every address is a candidate to begin with
+    pub fn new<B: Bus>(bus: &B) -> CheatSearch {
+        let addresses = (WRAM_BEGIN..=WRAM_END).chain(HRAM_BEGIN..=HRAM_END);
+        CheatSearch { candidates: addresses.map(|address| (address as u16, bus.peek_byte(address as u16))).collect() }
+    }
+    // keeps the addresses whose value now compares as asked with the last step,
+    // returning how many are left
+    pub fn filter<B: Bus>(&mut self, bus: &B, comparison: Comparison) -> usize {
+        self.candidates.retain_mut(|(address, previous)| {
+            let current = bus.peek_byte(*address);
+            let keep = comparison.matches(*previous, current);
+            *previous = current;
+            keep
+        });
+        self.candidates.len()
+    }
+    pub fn candidates(&self) -> &[(u16, u8)] {
+        &self.candidates
+    }
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+// a code that keeps `address` at `value`, written once a frame
+pub fn gameshark_code(address: u16, value: u8) -> String {
+    let [low, high] = address.to_le_bytes();
+    format!("01{:02X}{:02X}{:02X}", value, low, high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheats::{Cheat, CheatEngine};
+    use crate::mmu::Mmu;
+
+    #[test]
+    fn filters_narrow_down_to_the_changing_value() {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xC100, 3);
+        mmu.write_byte(0xFF90, 3);
+        let mut search = CheatSearch::new(&mmu);
+        mmu.write_byte(0xC100, 2);
+        mmu.write_byte(0xFF90, 5);
+        search.filter(&mmu, Comparison::Changed);
+        assert_eq!(search.candidates(), [(0xC100, 2), (0xFF90, 5)]);
+        mmu.write_byte(0xC100, 1);
+        mmu.write_byte(0xFF90, 4);
+        assert_eq!(search.filter(&mmu, Comparison::DecreasedBy(1)), 2);
+        assert_eq!(search.filter(&mmu, Comparison::Exactly(1)), 1);
+        let (address, _) = search.candidates()[0];
+        let code = gameshark_code(address, 9);
+        assert_eq!(Cheat::parse(&code), Ok(Cheat::GameShark { bank: 0x01, address: 0xC100, value: 9 }));
+        let mut cheats = CheatEngine::new();
+        let index = cheats.freeze(address, 9);
+        assert_eq!(cheats.entries()[index].code, code);
+    }
+}
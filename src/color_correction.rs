@@ -0,0 +1,55 @@
+use std::sync::OnceLock;
+
+use crate::config::ColorCorrection;
+
+const COLORS: usize = 0x8000;
+// the GBC screen has a much steeper response than a PC monitor
+const LCD_GAMMA: f64 = 4.0;
+const DISPLAY_GAMMA: f64 = 2.2;
+// how much of each channel bleeds into the others, rows are the output red,
+// green and blue, columns the input red, green and blue (out of 255)
+const MIX: [[f64; 3]; 3] = [
+    [255.0, 50.0, 0.0],
+    [10.0, 230.0, 30.0],
+    [50.0, 10.0, 220.0],
+];
+
+// 5 bits per channel widened to 8 by repeating the top bits
+pub fn rgb555_to_rgba(color: u16) -> [u8; 4] {
+    let channel = |shift: u16| {
+        let value = (color >> shift & 0x1F) as u8;
+        value << 3 | value >> 2
+    };
+    [channel(0), channel(5), channel(10), 0xFF]
+}
+
+// approximates how the GBC screen shows a colour: darker midtones and less
+// saturation than the raw values give on a modern display
+fn gbc_lcd(color: u16) -> [u8; 4] {
+    let linear = [0, 5, 10].map(|shift| ((color >> shift & 0x1F) as f64 / 31.0).powf(LCD_GAMMA));
+    let mixed = MIX.map(|row| {
+        let value = (row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]) / 255.0;
+        (value.min(1.0).powf(1.0 / DISPLAY_GAMMA) * 255.0).round() as u8
+    });
+    [mixed[0], mixed[1], mixed[2], 0xFF]
+}
+
+// every RGB555 colour run through gbc_lcd, built the first time it's needed
+fn gbc_lcd_table() -> &'static [[u8; 4]; COLORS] {
+    static TABLE: OnceLock<Box<[[u8; 4]; COLORS]>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = Box::new([[0; 4]; COLORS]);
+        for (color, rgba) in table.iter_mut().enumerate() {
+            *rgba = gbc_lcd(color as u16);
+        }
+        table
+    })
+}
+
+// a CGB colour as it goes into the frame
+pub fn to_rgba(color: u16, correction: ColorCorrection) -> [u8; 4] {
+    match correction {
+        ColorCorrection::Off => rgb555_to_rgba(color),
+        ColorCorrection::GbcLcd => gbc_lcd_table()[(color & 0x7FFF) as usize],
+    }
+}
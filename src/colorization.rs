@@ -0,0 +1,264 @@
+use crate::cartridge::Cartridge;
+
+const HEADER_TITLE: usize = 0x0134;
+const TITLE_LENGTH: usize = 16;
+// several titles share a checksum, the boot rom tells those apart by this letter
+const DISAMBIGUATION_LETTER: usize = HEADER_TITLE + 3;
+
+// the colours a CGB gives a DMG game in place of the four shades, as RGB555.
+// each layer maps shade 0-3 (after BGP/OBP0/OBP1) to its own colour
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DmgColors {
+    pub background: [u16; 4],
+    pub objects: [[u16; 4]; 2],
+}
+
+// the boot rom's raw palettes, four colours each
+const PALETTES: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// where OBJ0, OBJ1 and BG start in PALETTES, counted in colours. most start on a
+// palette, a few start one colour early and borrow the last colour of the one before
+const fn palettes(obj0: usize, obj1: usize, background: usize) -> [usize; 3] {
+    [obj0 * 4, obj1 * 4, background * 4]
+}
+
+const COMBINATIONS: [[usize; 3]; 51] = [
+    palettes(4, 4, 29),
+    palettes(18, 18, 18),
+    palettes(20, 20, 20),
+    palettes(24, 24, 24),
+    palettes(9, 9, 9),
+    palettes(0, 0, 0),
+    palettes(27, 27, 27),
+    palettes(5, 5, 5),
+    palettes(12, 12, 12),
+    palettes(26, 26, 26),
+    palettes(16, 8, 8),
+    palettes(4, 28, 28),
+    palettes(4, 2, 2),
+    palettes(3, 4, 4),
+    palettes(4, 29, 29),
+    palettes(28, 4, 28),
+    palettes(2, 17, 2),
+    palettes(16, 16, 8),
+    palettes(4, 4, 7),
+    palettes(4, 4, 18),
+    palettes(4, 4, 20),
+    palettes(19, 19, 9),
+    [4 * 4 - 1, 4 * 4 - 1, 11 * 4],
+    palettes(17, 17, 2),
+    palettes(4, 4, 2),
+    palettes(4, 4, 3),
+    palettes(28, 28, 0),
+    palettes(3, 3, 0),
+    palettes(0, 0, 1),
+    palettes(18, 22, 18),
+    palettes(20, 22, 20),
+    palettes(24, 22, 24),
+    palettes(16, 22, 8),
+    palettes(17, 4, 13),
+    [28 * 4 - 1, 0, 14 * 4],
+    [28 * 4 - 1, 4 * 4, 15 * 4],
+    palettes(19, 22, 9),
+    palettes(16, 28, 10),
+    palettes(4, 23, 28),
+    palettes(17, 22, 2),
+    palettes(4, 0, 2),
+    palettes(4, 28, 3),
+    palettes(28, 3, 0),
+    palettes(3, 28, 4),
+    palettes(21, 28, 4),
+    palettes(3, 28, 0),
+    palettes(25, 3, 28),
+    palettes(0, 28, 8),
+    palettes(4, 3, 28),
+    palettes(28, 3, 6),
+    palettes(4, 28, 29),
+];
+
+const fn colors_at(start: usize) -> [u16; 4] {
+    [PALETTES[start], PALETTES[start + 1], PALETTES[start + 2], PALETTES[start + 3]]
+}
+
+const fn combination(index: usize) -> DmgColors {
+    let [obj0, obj1, background] = COMBINATIONS[index];
+    DmgColors { background: colors_at(background), objects: [colors_at(obj0), colors_at(obj1)] }
+}
+
+// the combinations that can be picked by holding a direction (and A or B) while
+// the CGB logo is on screen
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ManualPalette {
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
+}
+
+impl ManualPalette {
+    pub fn colors(&self) -> DmgColors {
+        let index = match self {
+            ManualPalette::Up => 5,
+            ManualPalette::UpA => 43,
+            ManualPalette::UpB => 28,
+            ManualPalette::Left => 48,
+            ManualPalette::LeftA => 40,
+            ManualPalette::LeftB => 7,
+            ManualPalette::Down => 8,
+            ManualPalette::DownA => 3,
+            ManualPalette::DownB => 49,
+            ManualPalette::Right => 1,
+            ManualPalette::RightA => 0,
+            ManualPalette::RightB => 6,
+        };
+        combination(index)
+    }
+}
+
+// what unknown games and games from other licensees get
+pub const DEFAULT_PALETTE: ManualPalette = ManualPalette::RightA;
+
+// sums of the title bytes the boot rom knows. the last 14 are shared by more than
+// one title and are told apart by the fourth letter of the title
+const TITLE_CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B,
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+const FIRST_SHARED_CHECKSUM: usize = 65;
+const SHARED_CHECKSUMS: usize = TITLE_CHECKSUMS.len() - FIRST_SHARED_CHECKSUM;
+
+// the fourth letters, a row for each time a shared checksum comes round again:
+// SUPER MARIOLAND and POKEMON BLUE in the first, VEGAS STAKES in the second,
+// TETRIS ATTACK alone in the third
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// the combination for each checksum, then for each fourth letter
+const TITLE_COMBINATIONS: [u8; FIRST_SHARED_CHECKSUM + FOURTH_LETTERS.len()] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50,
+    17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 34, 23, 18, 29,
+    28,
+];
+
+// the combination the CGB boot rom picks for a title checksum and fourth letter
+fn title_combination(checksum: u8, letter: u8) -> usize {
+    let Some(index) = TITLE_CHECKSUMS.iter().position(|&sum| sum == checksum) else { return 0 };
+    if index < FIRST_SHARED_CHECKSUM { return TITLE_COMBINATIONS[index] as usize }
+    (index - FIRST_SHARED_CHECKSUM..FOURTH_LETTERS.len())
+        .step_by(SHARED_CHECKSUMS)
+        .find(|&row| FOURTH_LETTERS[row] == letter)
+        .map_or(0, |row| TITLE_COMBINATIONS[FIRST_SHARED_CHECKSUM + row] as usize)
+}
+
+// the colours the CGB boot rom picks for a DMG cartridge. only games licensed
+// by Nintendo are looked up by the sum of their title bytes
+pub fn boot_palette(cartridge: &Cartridge) -> DmgColors {
+    let default = DEFAULT_PALETTE.colors();
+    if cartridge.header.licensee != "01" { return default }
+    let rom = cartridge.rom();
+    let Some(title) = rom.get(HEADER_TITLE..HEADER_TITLE + TITLE_LENGTH) else { return default };
+    let checksum = title.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    combination(title_combination(checksum, rom[DISAMBIGUATION_LETTER]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_rom, CartridgeHeader};
+
+    fn cartridge(title: &str, licensee: u8) -> Cartridge {
+        let mut rom = test_rom(&[], 0x00, 0x00);
+        rom[HEADER_TITLE..HEADER_TITLE + title.len()].copy_from_slice(title.as_bytes());
+        rom[0x14B] = licensee;
+        rom[0x14D] = CartridgeHeader::compute_header_checksum(&rom);
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    #[test]
+    fn titles_are_looked_up_by_checksum() {
+        // red background with green sprites
+        let red = boot_palette(&cartridge("POKEMON RED", 0x01));
+        assert_eq!(red, combination(13));
+        assert_eq!(red.background, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!(red.objects[0], [0x7FFF, 0x1BEF, 0x0200, 0x0000]);
+        assert_eq!(boot_palette(&cartridge("POKEMON GREEN", 0x01)), combination(14));
+        assert_eq!(boot_palette(&cartridge("ZELDA", 0x01)), combination(44));
+        // only Nintendo's own titles, everything else gets the default
+        assert_eq!(boot_palette(&cartridge("POKEMON RED", 0x02)), DEFAULT_PALETTE.colors());
+        assert_eq!(boot_palette(&cartridge("NOT A REAL GAME", 0x01)), DEFAULT_PALETTE.colors());
+    }
+
+    #[test]
+    fn shared_checksums_fall_back_on_the_fourth_letter() {
+        // both sum to 0x61
+        assert_eq!(boot_palette(&cartridge("POKEMON BLUE", 0x01)), combination(11));
+        assert_eq!(boot_palette(&cartridge("VEGAS STAKES", 0x01)), combination(41));
+        // and so do MOGURANYA and TETRIS ATTACK, the latter from the third row
+        assert_eq!(title_combination(0xB3, b'U'), 17);
+        assert_eq!(title_combination(0xB3, b'R'), 28);
+        // a shared checksum with none of its letters is an unknown game
+        assert_eq!(title_combination(0x61, b'X'), 0);
+        assert_eq!(title_combination(0x46, b'R'), 46);
+        assert_eq!(title_combination(0x46, b'U'), 0);
+    }
+
+    #[test]
+    fn manual_palettes_match_the_boot_rom() {
+        let up = ManualPalette::Up.colors();
+        assert_eq!(up.background, [0x7FFF, 0x32BF, 0x00D0, 0x0000]);
+        assert_eq!(up.objects, [up.background; 2]);
+        // blue background, red and green sprites
+        let left = ManualPalette::Left.colors();
+        assert_eq!(left.background[1], 0x7E8C);
+        assert_eq!([left.objects[0][1], left.objects[1][1]], [0x421F, 0x1BEF]);
+        // the combinations that start a colour early pick up the palette before
+        assert_eq!(combination(22).objects[0], [0x0000, 0x7FFF, 0x421F, 0x1CF2]);
+    }
+}
@@ -0,0 +1,50 @@
+// how closely the emulator follows hardware timing. Fast takes shortcuts that
+// almost no game notices (and may use extra threads), CycleAccurate models the
+// quirks test roms check for
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Accuracy {
+    Fast,
+    #[default]
+    CycleAccurate,
+}
+
+// the four colours DMG shades are drawn with, lightest (shade 0) first
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum DmgPalette {
+    #[default]
+    Grayscale,
+    // the green tint of the original screen
+    ClassicGreen,
+    // RGBA for each shade
+    Custom([[u8; 4]; 4]),
+}
+
+impl DmgPalette {
+    pub fn colors(&self) -> [[u8; 4]; 4] {
+        match self {
+            DmgPalette::Grayscale => [
+                [0xFF, 0xFF, 0xFF, 0xFF],
+                [0xAA, 0xAA, 0xAA, 0xFF],
+                [0x55, 0x55, 0x55, 0xFF],
+                [0x00, 0x00, 0x00, 0xFF],
+            ],
+            DmgPalette::ClassicGreen => [
+                [0x9B, 0xBC, 0x0F, 0xFF],
+                [0x8B, 0xAC, 0x0F, 0xFF],
+                [0x30, 0x62, 0x30, 0xFF],
+                [0x0F, 0x38, 0x0F, 0xFF],
+            ],
+            DmgPalette::Custom(colors) => *colors,
+        }
+    }
+}
+
+// what CGB colours go through on their way to the frame. raw RGB555 values
+// look oversaturated on a modern display
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum ColorCorrection {
+    #[default]
+    Off,
+    // gamma and channel mixing approximating the GBC screen
+    GbcLcd,
+}
@@ -0,0 +1,134 @@
+use std::time::Instant;
+
+use crate::timing::{instruction_cycles, CYCLES_PER_FRAME};
+
+// opcodes are keyed 0x000-0x0FF, CB prefixed ones 0x100-0x1FF
+const OPCODE_SLOTS: usize = 0x200;
+// weight of each new measurement in the running averages
+const SMOOTHING: f64 = 0.05;
+
+#[derive(Copy, Clone, Debug)]
+pub struct OpcodeCost {
+    // emulated clock cycles, not counting taken branch extras
+    pub cycles: u8,
+    // running average of host time spent executing it, None until it has run
+    pub host_ns: Option<f64>,
+    pub samples: u64,
+}
+
+// what running one frame is expected to cost on this host
+#[derive(Copy, Clone, Debug)]
+pub struct FrameBudget {
+    pub estimated_ns: f64,
+    pub budget_ns: f64,
+    // skipping rendering would help keep up with real time
+    pub should_skip: bool,
+}
+
+// when an instruction started, for timing it. Instant panics in the browser, so
+// nothing is timed there and the model only knows the cycle costs
+pub fn host_now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) { return None }
+    Some(Instant::now())
+}
+
+// combines the static cycle cost of each opcode with how long it actually takes
+// on the host, so adaptive frontends (battery constrained handhelds, wasm) can
+// budget frames and decide when to frame skip
+pub struct CostModel {
+    costs: Vec<OpcodeCost>,
+    // running average host time per emulated cycle over everything executed
+    ns_per_cycle: Option<f64>,
+}
+
+impl CostModel {
+    pub fn new() -> CostModel {
+        let costs = (0..OPCODE_SLOTS).map(|slot| OpcodeCost {
+            cycles: instruction_cycles(slot as u8, slot >= 0x100),
+            host_ns: None,
+            samples: 0,
+        }).collect();
+        CostModel { costs, ns_per_cycle: None }
+    }
+    fn slot(opcode: u8, prefixed: bool) -> usize {
+        opcode as usize | if prefixed { 0x100 } else { 0 }
+    }
+    pub fn record(&mut self, opcode: u8, prefixed: bool, elapsed_ns: f64) {
+        let cost = &mut self.costs[CostModel::slot(opcode, prefixed)];
+        cost.host_ns = Some(match cost.host_ns {
+            Some(average) => average + (elapsed_ns - average) * SMOOTHING,
+            None => elapsed_ns,
+        });
+        cost.samples += 1;
+        if cost.cycles > 0 {
+            let per_cycle = elapsed_ns / cost.cycles as f64;
+            self.ns_per_cycle = Some(match self.ns_per_cycle {
+                Some(average) => average + (per_cycle - average) * SMOOTHING,
+                None => per_cycle,
+            });
+        }
+    }
+    pub fn cost(&self, opcode: u8, prefixed: bool) -> OpcodeCost {
+        self.costs[CostModel::slot(opcode, prefixed)]
+    }
+    pub fn ns_per_cycle(&self) -> Option<f64> {
+        self.ns_per_cycle
+    }
+    // host time one full frame of emulation is expected to take
+    pub fn estimate_frame_ns(&self) -> Option<f64> {
+        self.ns_per_cycle.map(|ns| ns * CYCLES_PER_FRAME as f64)
+    }
+    // compare the estimate against the time the frontend can spend per frame,
+    // e.g. 16_742_706.0 to keep up with the real 59.73 Hz
+    pub fn frame_budget(&self, budget_ns: f64) -> Option<FrameBudget> {
+        self.estimate_frame_ns().map(|estimated_ns| FrameBudget {
+            estimated_ns,
+            budget_ns,
+            should_skip: estimated_ns > budget_ns,
+        })
+    }
+    pub fn reset(&mut self) {
+        *self = CostModel::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn costs_start_from_the_cycle_table() {
+        let model = CostModel::new();
+        assert_eq!(model.cost(0x00, false).cycles, 4);
+        // CALL nn, and JR cc at its not taken time
+        assert_eq!(model.cost(0xCD, false).cycles, 24);
+        assert_eq!(model.cost(0x20, false).cycles, 8);
+        // prefixed: registers, BIT n,(HL) and the other (HL) ones
+        assert_eq!(model.cost(0x11, true).cycles, 8);
+        assert_eq!(model.cost(0x46, true).cycles, 12);
+        assert_eq!(model.cost(0x06, true).cycles, 16);
+        assert_eq!(model.cost(0xD3, false).cycles, 0);
+        assert!(model.cost(0x00, false).host_ns.is_none());
+        assert!(model.frame_budget(16_742_706.0).is_none());
+    }
+
+    #[test]
+    fn measurements_feed_the_frame_budget() {
+        let mut model = CostModel::new();
+        // 1ns a cycle, then an 8ns NOP nudges the average up by SMOOTHING
+        model.record(0x00, false, 4.0);
+        model.record(0x00, false, 8.0);
+        let nop = model.cost(0x00, false);
+        assert_eq!((nop.host_ns, nop.samples), (Some(4.2), 2));
+        assert_eq!(model.ns_per_cycle(), Some(1.05));
+        // opcodes without a cycle cost don't count towards it
+        model.record(0xD3, false, 1000.0);
+        assert_eq!(model.ns_per_cycle(), Some(1.05));
+        let estimated = 1.05 * CYCLES_PER_FRAME as f64;
+        assert_eq!(model.estimate_frame_ns(), Some(estimated));
+        assert!(!model.frame_budget(estimated + 1.0).unwrap().should_skip);
+        assert!(model.frame_budget(estimated - 1.0).unwrap().should_skip);
+        model.reset();
+        assert_eq!(model.ns_per_cycle(), None);
+    }
+}
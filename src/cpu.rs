@@ -1,22 +1,30 @@
+use std::fmt;
+
+use crate::cost_model::{host_now, CostModel};
 use crate::registers::Registers;
 use crate::instructions::*;
-use crate::gpu::*;
+use crate::mmu::Mmu;
+use crate::model::Model;
+use crate::reset::ResetKind;
+use crate::savestate::{find_section, read_sections, CpuState, Section, StateError, StateWriter, CPU_SECTION, VERSION};
+use crate::timing::{branch_taken_extra, instruction_cycles};
+use crate::trace::DoctorTracer;
 
-struct MemoryBus {
-    memory: [u8; 0xFFFF],
-    gpu: GPU,
-}
+#[cfg(test)]
+mod sm83_tests;
 
-impl MemoryBus {
-    fn read_byte(&self, address: u16) -> u8 {
-        let address = address as usize;
-        match address {
-            VRAM_BEGIN..=VRAM_END => {
-                self.gpu.read_vram(address - VRAM_BEGIN)
-            }
-            _ => self.memory[address],
-        }
-        // TODO: support other areas of memory
+// everything the cpu can see through its address pins. Mmu is the real
+// machine, other implementations exist for tests and tooling
+pub trait Bus {
+    fn read_byte(&self, address: u16) -> u8;
+    fn write_byte(&mut self, address: u16, value: u8);
+    // read without side effects, for tracers and debuggers
+    fn peek_byte(&self, address: u16) -> u8 {
+        self.read_byte(address)
+    }
+    // which bank is mapped at an address, for debuggers. None where nothing is banked
+    fn bank_at(&self, _address: u16) -> Option<usize> {
+        None
     }
     // pass in address to first byte of u16
     fn read_word(&self, address: u16) -> u16 {
@@ -24,46 +32,371 @@ impl MemoryBus {
         let most_significant_byte = self.read_byte(address.wrapping_add(1)) as u16;
         (most_significant_byte << 8) | least_significant_byte
     }
-    fn write_byte(&mut self, address: u16, value: u8) {
-        let address = address as usize;
-        match address {
-            VRAM_BEGIN..=VRAM_END => {
-                self.gpu.write_vram(address - VRAM_BEGIN, value);
-            }
-            _ => self.memory[address] = value,
-        }
-        // TODO: support other areas of memory
-    }
     fn write_word(&mut self, address: u16, value: u16) {
         let least_significant_byte = (value & 0xFF) as u8;
         let most_significant_byte = ((value & 0xFF00) >> 8) as u8;
         self.write_byte(address, least_significant_byte);
         self.write_byte(address.wrapping_add(1), most_significant_byte);
     }
+    // called with the address of every instruction before it executes
+    fn instruction_started(&self, _address: u16) {}
+    // called after every instruction has finished executing
+    fn instruction_finished(&mut self) {}
+    // called when the cpu jumps to an interrupt's handler, with its IF bit
+    fn interrupt_dispatched(&mut self, _interrupt: u8) {}
+    // bring memory and peripherals back to their reset state
+    fn reset(&mut self, _kind: ResetKind) {}
+    // advance the rest of the machine by the clock cycles the cpu just spent
+    fn tick(&mut self, _cycles: u32) {}
+    // extra save state sections for whatever sits on the bus, as (tag, data)
+    fn state_sections(&self) -> Vec<([u8; 4], Vec<u8>)> {
+        Vec::new()
+    }
+    // takes back what state_sections() wrote, refusing states that don't fit
+    fn load_state_sections(&mut self, _sections: &[Section]) -> Result<(), StateError> {
+        Ok(())
+    }
+    // IF and IE, without the side effects of going through memory
+    fn interrupt_flags(&self) -> u8 {
+        self.peek_byte(INTERRUPT_FLAGS)
+    }
+    fn set_interrupt_flags(&mut self, value: u8) {
+        self.write_byte(INTERRUPT_FLAGS, value);
+    }
+    fn interrupt_enable(&self) -> u8 {
+        self.peek_byte(INTERRUPT_ENABLE)
+    }
+    // whether a boot rom will run from 0x0000 instead of the cartridge
+    fn boot_rom_mapped(&self) -> bool {
+        false
+    }
+    // the timer's whole 16 bit counter, of which DIV is only the upper byte
+    fn set_divider(&mut self, _counter: u16) {}
+    // STOP ran. true if it carried out a CGB speed switch, which the cpu runs on
+    // from, rather than stopping
+    fn stop(&mut self) -> bool {
+        false
+    }
+    // whether the cpu is running at twice the clock the rest of the machine uses
+    fn double_speed(&self) -> bool {
+        false
+    }
+}
+
+pub const INTERRUPT_FLAGS: u16 = 0xFF0F;
+pub const INTERRUPT_ENABLE: u16 = 0xFFFF;
+// bits of IF/IE
+pub const VBLANK_INTERRUPT: u8 = 0x01;
+pub const STAT_INTERRUPT: u8 = 0x02;
+pub const TIMER_INTERRUPT: u8 = 0x04;
+pub const SERIAL_INTERRUPT: u8 = 0x08;
+pub const JOYPAD_INTERRUPT: u8 = 0x10;
+// vblank, stat, timer, serial, joypad in priority order, handlers are 8 bytes apart
+const INTERRUPT_VECTOR_BASE: u16 = 0x0040;
+const INTERRUPT_DISPATCH_CYCLES: u32 = 20;
+const HALTED_CYCLES: u32 = 4;
+
+// copy of the programmer visible cpu state, handed to execution hooks
+#[derive(Copy, Clone)]
+pub struct CpuSnapshot {
+    pub registers: Registers,
+    pub pc: u16,
+    pub sp: u16,
+}
+
+// A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE PC:0100
+impl fmt::Display for CpuSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = &self.registers;
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+        write!(
+            f,
+            "A:{:02X} F:{}{}{}{} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} PC:{:04X}",
+            r.a,
+            flag(r.f.zero, 'Z'),
+            flag(r.f.subtract, 'N'),
+            flag(r.f.half_carry, 'H'),
+            flag(r.f.carry, 'C'),
+            r.get_bc(),
+            r.get_de(),
+            r.get_hl(),
+            self.sp,
+            self.pc
+        )
+    }
 }
 
-struct CPU {
+// called with the cpu state and the opcode byte at pc (0xCB for prefixed instructions)
+pub type ExecHook = Box<dyn FnMut(&CpuSnapshot, u8)>;
+pub type ResetListener = Box<dyn FnMut(ResetKind)>;
+
+pub struct CPU<B: Bus = Mmu> {
     registers: Registers,
     pc: u16,
     sp: u16,
-    bus: MemoryBus,
+    bus: B,
+    tracer: Option<DoctorTracer>,
+    exec_hook: Option<ExecHook>,
+    post_exec_hook: Option<ExecHook>,
+    // model used to recreate the post boot state on reset
+    model: Model,
+    reset_listener: Option<ResetListener>,
+    // measures host time per opcode when enabled
+    cost_model: Option<CostModel>,
+    // whether the last conditional jump/call/return went ahead, it costs extra cycles
+    branch_taken: bool,
+    // interrupt master enable, EI only sets it after the following instruction
+    ime: bool,
+    ime_pending: bool,
+    // HALT/STOP, the cpu idles until an interrupt is requested
+    halted: bool,
+    // the illegal opcode that hung the cpu. like hardware it never fetches
+    // again, only a reset gets it going
+    locked_up: Option<u8>,
 }
 
-impl CPU {
-    fn step(&mut self) {
+impl<B: Bus> CPU<B> {
+    pub fn new(bus: B) -> CPU<B> {
+        CPU {
+            registers: Registers::new(),
+            pc: 0,
+            sp: 0,
+            bus,
+            tracer: None,
+            exec_hook: None,
+            post_exec_hook: None,
+            model: Model::default(),
+            reset_listener: None,
+            cost_model: None,
+            branch_taken: false,
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            locked_up: None,
+        }
+    }
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+    pub fn enable_cost_model(&mut self) {
+        self.cost_model.get_or_insert_with(CostModel::new);
+    }
+    pub fn disable_cost_model(&mut self) -> Option<CostModel> {
+        self.cost_model.take()
+    }
+    pub fn cost_model(&self) -> Option<&CostModel> {
+        self.cost_model.as_ref()
+    }
+    // put the machine in the state the boot rom would have left it in, for
+    // running without a boot rom. games read A at 0x0100 to detect the hardware
+    pub fn skip_boot_rom(&mut self, model: Model) {
+        self.model = model;
+        let header_checksum = self.bus.peek_byte(0x014D);
+        let state = model.post_boot_state(header_checksum);
+        self.registers = state.registers;
+        self.sp = state.sp;
+        self.pc = state.pc;
+        self.clear_execution_state();
+        for (address, value) in model.post_boot_io() {
+            self.bus.write_byte(address, value);
+        }
+        self.bus.set_divider(model.post_boot_divider());
+    }
+    // the model reset puts back the post boot state of, when there's no boot rom
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+    // restart the machine. the listener hears about it first so frontends and
+    // movie recorders can note the reset at the right point in the input stream
+    pub fn reset(&mut self, kind: ResetKind) {
+        if let Some(listener) = &mut self.reset_listener {
+            listener(kind);
+        }
+        self.bus.reset(kind);
+        if self.bus.boot_rom_mapped() {
+            self.start_boot_rom();
+        } else {
+            self.skip_boot_rom(self.model);
+        }
+    }
+    // the boot rom starts from a blank cpu and sets everything up itself
+    pub fn start_boot_rom(&mut self) {
+        self.registers = Registers::new();
+        self.sp = 0;
+        self.pc = 0;
+        self.clear_execution_state();
+    }
+    fn clear_execution_state(&mut self) {
+        self.ime = false;
+        self.ime_pending = false;
+        self.halted = false;
+        self.locked_up = None;
+    }
+    // the opcode the cpu locked up on, if it ran into one that doesn't exist
+    pub fn locked_up(&self) -> Option<u8> {
+        self.locked_up
+    }
+    pub fn set_reset_listener<F: FnMut(ResetKind) + 'static>(&mut self, listener: F) {
+        self.reset_listener = Some(Box::new(listener));
+    }
+    pub fn save_state<W: std::io::Write>(&self, writer: W) -> std::io::Result<W> {
+        let mut writer = StateWriter::new(writer)?;
+        self.write_state_sections(&mut writer)?;
+        writer.finish()
+    }
+    // the sections save_state() writes, for callers adding sections of their own
+    pub fn write_state_sections<W: std::io::Write>(&self, writer: &mut StateWriter<W>) -> std::io::Result<()> {
+        let state = CpuState {
+            registers: self.registers,
+            sp: self.sp,
+            pc: self.pc,
+            ime: self.ime,
+            ime_pending: self.ime_pending,
+            halted: self.halted,
+            locked_up: self.locked_up,
+        };
+        writer.section(CPU_SECTION, &state.encode())?;
+        for (tag, data) in self.bus.state_sections() {
+            writer.section(tag, &data)?;
+        }
+        Ok(())
+    }
+    // the whole machine as save_state() left it. a state that can't be loaded
+    // leaves the machine as it was
+    pub fn load_state<R: std::io::Read>(&mut self, reader: R) -> Result<(), StateError> {
+        let (version, sections) = read_sections(reader)?;
+        if version != VERSION {
+            return Err(StateError::Version(version));
+        }
+        let state = CpuState::decode(&find_section(&sections, CPU_SECTION)?.data)?;
+        let backup = self.save_state(Vec::new())?;
+        if let Err(error) = self.bus.load_state_sections(&sections) {
+            let (_, sections) = read_sections(backup.as_slice())?;
+            self.bus.load_state_sections(&sections)?;
+            return Err(error);
+        }
+        self.registers = state.registers;
+        self.sp = state.sp;
+        self.pc = state.pc;
+        self.ime = state.ime;
+        self.ime_pending = state.ime_pending;
+        self.halted = state.halted;
+        self.locked_up = state.locked_up;
+        self.branch_taken = false;
+        Ok(())
+    }
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            registers: self.registers,
+            pc: self.pc,
+            sp: self.sp,
+        }
+    }
+    // the other way around, for tools that set the registers up themselves
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        self.registers = snapshot.registers;
+        self.pc = snapshot.pc;
+        self.sp = snapshot.sp;
+    }
+    // called before every instruction executes
+    pub fn set_exec_hook<F: FnMut(&CpuSnapshot, u8) + 'static>(&mut self, hook: F) {
+        self.exec_hook = Some(Box::new(hook));
+    }
+    // called after every instruction, with the opcode that just ran and the resulting state
+    pub fn set_post_exec_hook<F: FnMut(&CpuSnapshot, u8) + 'static>(&mut self, hook: F) {
+        self.post_exec_hook = Some(Box::new(hook));
+    }
+    pub fn clear_exec_hooks(&mut self) {
+        self.exec_hook = None;
+        self.post_exec_hook = None;
+    }
+    // log every instruction to the tracer, None turns tracing off
+    pub fn set_tracer(&mut self, tracer: Option<DoctorTracer>) {
+        self.tracer = tracer;
+    }
+    fn trace(&mut self) {
+        if let Some(tracer) = &mut self.tracer {
+            let pcmem = [0, 1, 2, 3].map(|offset| self.bus.peek_byte(self.pc.wrapping_add(offset)));
+            // a broken writer shouldn't take the emulator down with it, just stop tracing
+            if tracer.trace(&self.registers, self.sp, self.pc, pcmem).is_err() {
+                self.tracer = None;
+            }
+        }
+    }
+    // jump to the highest priority interrupt that's both requested and enabled.
+    // also wakes a halted cpu, whether or not interrupts are enabled
+    fn service_interrupts(&mut self) -> Option<u32> {
+        let pending = self.bus.interrupt_flags() & self.bus.interrupt_enable() & 0x1F;
+        if pending != 0 { self.halted = false }
+        if self.halted {
+            self.bus.tick(HALTED_CYCLES);
+            return Some(HALTED_CYCLES);
+        }
+        if !self.ime || pending == 0 { return None }
+        self.ime = false;
+        let interrupt = pending.trailing_zeros() as u16;
+        let flags = self.bus.interrupt_flags();
+        self.bus.set_interrupt_flags(flags & !(1 << interrupt));
+        self.bus.interrupt_dispatched(1 << interrupt);
+        self.PUSH(self.pc);
+        self.pc = INTERRUPT_VECTOR_BASE + interrupt * 8;
+        self.bus.tick(INTERRUPT_DISPATCH_CYCLES);
+        Some(INTERRUPT_DISPATCH_CYCLES)
+    }
+    // execute one instruction (or dispatch an interrupt), returning the clock cycles it took
+    pub fn step(&mut self) -> u32 {
+        if self.locked_up.is_some() {
+            // interrupts can't get it out either, but the rest of the machine runs on
+            self.bus.tick(HALTED_CYCLES);
+            return HALTED_CYCLES;
+        }
+        if let Some(cycles) = self.service_interrupts() { return cycles }
+        // an EI before this instruction takes effect after it, unless it's a DI
+        let enable_interrupts = self.ime_pending;
+        self.trace();
+        self.bus.instruction_started(self.pc);
+        let opcode = self.bus.peek_byte(self.pc);
+        if self.exec_hook.is_some() {
+            let snapshot = self.snapshot();
+            if let Some(hook) = &mut self.exec_hook { hook(&snapshot, opcode) }
+        }
         let mut instruction_byte = self.bus.read_byte( self.pc);
         let prefixed = instruction_byte == 0xCB;
         if prefixed { 
             instruction_byte = self.bus.read_byte(self.pc.wrapping_add(1));
         }
-        let next_pc = 
-            if let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed) {
-            self.execute(instruction)
-        }   else {
-            let description = format!("0x{}{:x}", if prefixed { "CB" } else { "" }, instruction_byte);
-            panic!("Unkown instruction found for: {}", description)
+        // every prefixed opcode exists, so only unprefixed ones end up here
+        let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed) else {
+            self.locked_up = Some(instruction_byte);
+            self.bus.tick(HALTED_CYCLES);
+            self.bus.instruction_finished();
+            return HALTED_CYCLES;
         };
+        let started = self.cost_model.as_ref().and_then(|_| host_now());
+        self.branch_taken = false;
+        let next_pc = self.execute(instruction);
+        if let (Some(cost_model), Some(started)) = (&mut self.cost_model, started) {
+            cost_model.record(instruction_byte, prefixed, started.elapsed().as_nanos() as f64);
+        }
         self.pc = next_pc;
+        let mut cycles = instruction_cycles(instruction_byte, prefixed) as u32;
+        if self.branch_taken && !prefixed {
+            cycles += branch_taken_extra(instruction_byte) as u32;
+        }
+        self.bus.tick(cycles);
+        if enable_interrupts && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+        self.bus.instruction_finished();
+        if self.post_exec_hook.is_some() {
+            let snapshot = self.snapshot();
+            if let Some(hook) = &mut self.post_exec_hook { hook(&snapshot, opcode) }
+        }
+        cycles
     }
     // increments pc and returns byte at new pc
     fn get_immediate_byte(&mut self) -> u8 {
@@ -77,7 +410,7 @@ impl CPU {
         value
     }
     fn read_arithmetic_byte_target(&mut self, target: ArithmeticByteTarget) -> u8 {
-        let value = match target {
+        match target {
             ArithmeticByteTarget::B => self.registers.b,
             ArithmeticByteTarget::C => self.registers.c,
             ArithmeticByteTarget::D => self.registers.d,
@@ -87,17 +420,15 @@ impl CPU {
             ArithmeticByteTarget::HL => self.bus.read_byte(self.registers.get_hl()),
             ArithmeticByteTarget::A => self.registers.a,
             ArithmeticByteTarget::N8 => self.get_immediate_byte(),
-        };
-        value
+        }
     }
     fn read_arithmetic_word_target(&self, target: ArithmeticWordTarget) -> u16 {
-        let value = match target {
+        match target {
             ArithmeticWordTarget::BC => self.registers.get_bc(),
             ArithmeticWordTarget::DE => self.registers.get_de(),
             ArithmeticWordTarget::HL => self.registers.get_hl(),
             ArithmeticWordTarget::SP => self.sp,
-        };
-        value
+        }
     }
     fn write_arithmetic_word_target(&mut self, target: ArithmeticWordTarget, value: u16) {
         match target {
@@ -108,7 +439,7 @@ impl CPU {
         }
     }
     fn read_prefixed_target(&self, target: PrefixedTarget) -> u8 {
-        let r = match target {
+        match target {
             PrefixedTarget::B => self.registers.b,
             PrefixedTarget::C => self.registers.c,
             PrefixedTarget::D => self.registers.d,
@@ -117,8 +448,7 @@ impl CPU {
             PrefixedTarget::L => self.registers.l,
             PrefixedTarget::HL => self.bus.read_byte(self.registers.get_hl()),
             PrefixedTarget::A => self.registers.a,
-        };
-        r
+        }
     }
     fn write_prefixed_target(&mut self, target: PrefixedTarget, new_r: u8) {
         match target {
@@ -133,26 +463,79 @@ impl CPU {
         }
     }
 
+    fn check_jump_test(&self, test: JumpTest) -> bool {
+        match test {
+            JumpTest::NotZero => !self.registers.f.zero,
+            JumpTest::Zero => self.registers.f.zero,
+            JumpTest::NotCarry => !self.registers.f.carry,
+            JumpTest::Carry => self.registers.f.carry,
+            JumpTest::Always => true,
+        }
+    }
+
     fn execute(&mut self, instruction: Instruction) -> u16 {
         match instruction {
+            Instruction::NOP() => self.pc.wrapping_add(1),
+            Instruction::HALT() => {
+                // with interrupts off and one already pending HALT doesn't wait
+                // (the hardware's halt bug isn't emulated)
+                let pending = self.bus.interrupt_flags() & self.bus.interrupt_enable() & 0x1F;
+                self.halted = self.ime || pending == 0;
+                self.pc.wrapping_add(1)
+            }
+            Instruction::STOP() => {
+                // treated as a halt that ignores its second byte, unless it switches speed
+                self.halted = !self.bus.stop();
+                self.pc.wrapping_add(2)
+            }
+            Instruction::DI() => {
+                self.ime = false;
+                self.ime_pending = false;
+                self.pc.wrapping_add(1)
+            }
+            Instruction::EI() => {
+                self.ime_pending = true;
+                self.pc.wrapping_add(1)
+            }
+            Instruction::CALL(test) => {
+                let jump_condition = self.check_jump_test(test);
+                self.branch_taken = jump_condition;
+                self.CALL(jump_condition)
+            }
+            Instruction::RET(test) => {
+                let jump_condition = self.check_jump_test(test);
+                self.branch_taken = jump_condition;
+                self.RET(jump_condition)
+            }
+            Instruction::RETI() => {
+                self.ime = true;
+                self.RET(true)
+            }
+            Instruction::RST(address) => {
+                self.PUSH(self.pc.wrapping_add(1));
+                address as u16
+            }
+            Instruction::LDHLSP() => {
+                let value = self.add_sp_offset();
+                self.registers.set_hl(value);
+                self.pc.wrapping_add(1)
+            }
+            Instruction::ADDSP() => {
+                self.sp = self.add_sp_offset();
+                self.pc.wrapping_add(1)
+            }
+            Instruction::DAA() => {
+                self.DAA();
+                self.pc.wrapping_add(1)
+            }
             Instruction::JP(test) => {
-                let jump_condition = match test {
-                    JumpTest::NotZero => !self.registers.f.zero,
-                    JumpTest::Zero => self.registers.f.zero,
-                    JumpTest::NotCarry => !self.registers.f.carry,
-                    JumpTest::Carry => self.registers.f.carry,
-                    JumpTest::Always => true,
-                };
+                let jump_condition = self.check_jump_test(test);
+                self.branch_taken = jump_condition;
                 self.JP(jump_condition)
             }
             Instruction::JR(test) => {
-                let jump_condition = match test {
-                    JumpTest::NotZero => !self.registers.f.zero,
-                    JumpTest::Zero => self.registers.f.zero,
-                    JumpTest::NotCarry => !self.registers.f.carry,
-                    JumpTest::Carry => self.registers.f.carry,
-                    JumpTest::Always => true,
-                };
+                let jump_condition = self.check_jump_test(test);
+                self.branch_taken = jump_condition;
                 self.JR(jump_condition)
             }
             Instruction::JPHL() => {
@@ -175,6 +558,15 @@ impl CPU {
                             LoadByteSource::DE => self.bus.read_byte(self.registers.get_de()),
                             LoadByteSource::HL => self.bus.read_byte(self.registers.get_hl()),
                             LoadByteSource::N8 => self.get_immediate_byte(),
+                            LoadByteSource::A16 => {
+                                let address = self.get_immediate_word();
+                                self.bus.read_byte(address)
+                            }
+                            LoadByteSource::HighC => self.bus.read_byte(0xFF00 | self.registers.c as u16),
+                            LoadByteSource::HighN8 => {
+                                let offset = self.get_immediate_byte();
+                                self.bus.read_byte(0xFF00 | offset as u16)
+                            }
                             // _ => panic!("Invalid LD LoadType::Byte source"),
                         };
                         match target {
@@ -188,6 +580,15 @@ impl CPU {
                             LoadByteTarget::BC => self.bus.write_byte(self.registers.get_bc(), source_value),
                             LoadByteTarget::DE => self.bus.write_byte(self.registers.get_de(), source_value),
                             LoadByteTarget::HL => self.bus.write_byte(self.registers.get_hl(), source_value),
+                            LoadByteTarget::A16 => {
+                                let address = self.get_immediate_word();
+                                self.bus.write_byte(address, source_value)
+                            }
+                            LoadByteTarget::HighC => self.bus.write_byte(0xFF00 | self.registers.c as u16, source_value),
+                            LoadByteTarget::HighN8 => {
+                                let offset = self.get_immediate_byte();
+                                self.bus.write_byte(0xFF00 | offset as u16, source_value)
+                            }
                             // _ => panic!("Invalid LD LoadType::Byte target"),
                         }
                         self.pc.wrapping_add(1)
@@ -197,6 +598,7 @@ impl CPU {
                         match source {
                             LoadWordSource::N16 => self.get_immediate_word(),
                             LoadWordSource::SP => self.sp,
+                            LoadWordSource::HL => self.registers.get_hl(),
                             // _ => panic!("Invalid LD LoadType::Word source"),
                         };
                         match target {
@@ -320,7 +722,7 @@ impl CPU {
             }
             Instruction::CP(target) => {
                 let value = self.read_arithmetic_byte_target(target);
-                let _new_value = self.CP(value);
+                self.CP(value);
                 self.pc.wrapping_add(1)
             }
             Instruction::RLCA() => {
@@ -351,7 +753,6 @@ impl CPU {
                 self.CCF();
                 self.pc.wrapping_add(1)
             }
-            // TODO: support for more instructions
 
             // Prefixed Instructions
             Instruction::RLC(target) => {
@@ -428,14 +829,39 @@ impl CPU {
         if should_jump { self.bus.read_word(self.pc.wrapping_add(1)) }
         else { self.pc.wrapping_add(3) }
     }
-    // jump based on i8 offset stored after instruction
+    // jump based on i8 offset stored after instruction, relative to the next instruction
     fn JR(&self, should_jump: bool) -> u16 {
+        let next_pc = self.pc.wrapping_add(2);
         if should_jump {
             let offset = self.bus.read_byte(self.pc.wrapping_add(1)) as i8;
             // compiler demands i16 for wrapping_add_signed here
-            self.pc.wrapping_add_signed(offset as i16)
+            next_pc.wrapping_add_signed(offset as i16)
+        }
+        else { next_pc }
+    }
+    // push the address of the next instruction and jump to the 16 bit address after the instruction
+    fn CALL(&mut self, should_jump: bool) -> u16 {
+        let next_pc = self.pc.wrapping_add(3);
+        if should_jump {
+            let address = self.bus.read_word(self.pc.wrapping_add(1));
+            self.PUSH(next_pc);
+            address
         }
-        else { self.pc.wrapping_add(2) }
+        else { next_pc }
+    }
+    // jump to the address popped off the stack
+    fn RET(&mut self, should_jump: bool) -> u16 {
+        if should_jump { self.POP() }
+        else { self.pc.wrapping_add(1) }
+    }
+    // SP plus the signed immediate, for ADD SP and LD HL,SP. carries come from the low byte
+    fn add_sp_offset(&mut self) -> u16 {
+        let offset = self.get_immediate_byte() as i8 as i16 as u16;
+        self.registers.f.zero = false;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = (self.sp & 0xF) + (offset & 0xF) > 0xF;
+        self.registers.f.carry = (self.sp & 0xFF) + (offset & 0xFF) > 0xFF;
+        self.sp.wrapping_add(offset)
     }
     // return u16 at current stack pointer (to be stored in 16 bit reg) and increment it
     fn POP(&mut self) -> u16 {
@@ -609,6 +1035,26 @@ impl CPU {
         self.registers.f.carry = old_a & 0x1 != 0;
         self.registers.a = new_value;
     }
+    // adjust A back into binary coded decimal after an addition or subtraction
+    fn DAA(&mut self) {
+        let mut correction = 0;
+        let mut carry = self.registers.f.carry;
+        let subtract = self.registers.f.subtract;
+        if self.registers.f.half_carry || (!subtract && self.registers.a & 0xF > 0x9) {
+            correction |= 0x06;
+        }
+        if carry || (!subtract && self.registers.a > 0x99) {
+            correction |= 0x60;
+            carry = true;
+        }
+        let new_a = if subtract { self.registers.a.wrapping_sub(correction) }
+            else { self.registers.a.wrapping_add(correction) };
+        self.registers.f.zero = new_a == 0;
+        // don't touch subtract flag
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = carry;
+        self.registers.a = new_a;
+    }
     // toggle every bit in A
     fn CPL(&mut self) {
         let new_a = self.registers.a ^ 0xFF;
@@ -732,4 +1178,158 @@ impl CPU {
         // don't touch any flags
         new_r
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 64 KiB of plain memory, IF and IE included
+    struct FlatBus {
+        memory: Vec<u8>,
+    }
+
+    impl Bus for FlatBus {
+        fn read_byte(&self, address: u16) -> u8 {
+            self.memory[address as usize]
+        }
+        fn write_byte(&mut self, address: u16, value: u8) {
+            self.memory[address as usize] = value;
+        }
+    }
+
+    // a cpu about to run `program` from 0x0000
+    fn cpu_running(program: &[u8]) -> CPU<FlatBus> {
+        let mut memory = vec![0; 0x10000];
+        memory[..program.len()].copy_from_slice(program);
+        let mut cpu = CPU::new(FlatBus { memory });
+        cpu.sp = 0xFFFE;
+        cpu
+    }
+
+    fn run(cpu: &mut CPU<FlatBus>, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.step();
+        }
+    }
+
+    #[test]
+    fn daa_adjusts_after_add_and_sub() {
+        // ld a, $45; add $38; daa
+        let mut cpu = cpu_running(&[0x3E, 0x45, 0xC6, 0x38, 0x27]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.a, 0x83);
+        assert!(!cpu.registers.f.carry && !cpu.registers.f.zero);
+        // ld a, $99; add $01; daa carries out of the top digit
+        let mut cpu = cpu_running(&[0x3E, 0x99, 0xC6, 0x01, 0x27]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.a, 0x00);
+        assert!(cpu.registers.f.carry && cpu.registers.f.zero);
+        // ld a, $42; sub $15; daa borrows from the low digit
+        let mut cpu = cpu_running(&[0x3E, 0x42, 0xD6, 0x15, 0x27]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.a, 0x27);
+        assert!(cpu.registers.f.subtract && !cpu.registers.f.carry);
+        // ld a, $10; sub $20; daa keeps the borrow out of the top digit
+        let mut cpu = cpu_running(&[0x3E, 0x10, 0xD6, 0x20, 0x27]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.a, 0x90);
+        assert!(cpu.registers.f.carry);
+    }
+
+    #[test]
+    fn interrupts_dispatch_in_priority_order() {
+        // ei; nop
+        let mut cpu = cpu_running(&[0xFB, 0x00]);
+        cpu.bus_mut().write_byte(INTERRUPT_ENABLE, 0x1F);
+        cpu.bus_mut().write_byte(INTERRUPT_FLAGS, TIMER_INTERRUPT | STAT_INTERRUPT | JOYPAD_INTERRUPT);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.step(), INTERRUPT_DISPATCH_CYCLES);
+        // STAT comes before the timer and the joypad, and only its flag is taken
+        assert_eq!(cpu.pc, 0x0048);
+        assert_eq!(cpu.bus().read_byte(INTERRUPT_FLAGS), TIMER_INTERRUPT | JOYPAD_INTERRUPT);
+        assert_eq!(cpu.bus().read_word(cpu.sp), 0x0002);
+        assert!(!cpu.ime);
+        // requested but not enabled is passed over
+        cpu.ime = true;
+        cpu.bus_mut().write_byte(INTERRUPT_ENABLE, JOYPAD_INTERRUPT);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0060);
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // ei; nop; nop
+        let mut cpu = cpu_running(&[0xFB, 0x00, 0x00]);
+        cpu.bus_mut().write_byte(INTERRUPT_ENABLE, VBLANK_INTERRUPT);
+        cpu.bus_mut().write_byte(INTERRUPT_FLAGS, VBLANK_INTERRUPT);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0001);
+        // the instruction after EI still runs before the interrupt is taken
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0002);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0040);
+        // ei; di never lets it through
+        let mut cpu = cpu_running(&[0xFB, 0xF3, 0x00]);
+        cpu.bus_mut().write_byte(INTERRUPT_ENABLE, VBLANK_INTERRUPT);
+        cpu.bus_mut().write_byte(INTERRUPT_FLAGS, VBLANK_INTERRUPT);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x0003);
+    }
+
+    #[test]
+    fn jr_is_relative_to_the_next_instruction() {
+        // jr +2; nop; nop; jr -2
+        let mut cpu = cpu_running(&[0x18, 0x02, 0x00, 0x00, 0x18, 0xFE]);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0004);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0004);
+        // xor a; jr nz, +5 falls through, jr z, -4 goes back to the start
+        let mut cpu = cpu_running(&[0xAF, 0x20, 0x05, 0x28, 0xFB]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x0003);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0000);
+    }
+
+    #[test]
+    fn the_cost_model_times_each_instruction_run() {
+        // nop; nop; bit 0, (hl)
+        let mut cpu = cpu_running(&[0x00, 0x00, 0xCB, 0x46]);
+        cpu.step();
+        cpu.enable_cost_model();
+        run(&mut cpu, 2);
+        let model = cpu.cost_model().unwrap();
+        assert_eq!(model.cost(0x00, false).samples, 1);
+        assert_eq!(model.cost(0x46, true).samples, 1);
+        assert!(model.cost(0x46, true).host_ns.is_some());
+        assert!(model.ns_per_cycle().is_some());
+        assert!(cpu.disable_cost_model().is_some());
+    }
+
+    #[test]
+    fn illegal_opcodes_lock_the_cpu_up() {
+        for opcode in [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD] {
+            // ei; <opcode>
+            let mut cpu = cpu_running(&[0xFB, opcode]);
+            cpu.step();
+            assert_eq!(cpu.step(), HALTED_CYCLES);
+            assert_eq!(cpu.locked_up(), Some(opcode));
+            // not even an interrupt gets it going again
+            cpu.bus_mut().write_byte(INTERRUPT_ENABLE, VBLANK_INTERRUPT);
+            cpu.bus_mut().write_byte(INTERRUPT_FLAGS, VBLANK_INTERRUPT);
+            for _ in 0..4 {
+                assert_eq!(cpu.step(), HALTED_CYCLES);
+            }
+            assert_eq!(cpu.pc, 0x0001);
+            // a state keeps it locked up, a reset doesn't
+            let state = cpu.save_state(Vec::new()).unwrap();
+            cpu.reset(ResetKind::Soft);
+            assert_eq!(cpu.locked_up(), None);
+            cpu.load_state(state.as_slice()).unwrap();
+            assert_eq!(cpu.locked_up(), Some(opcode));
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// runs the community SM83 single step tests (https://github.com/SingleStepTests/sm83)
+// against the cpu. each json file holds the test vectors for one opcode: an initial
+// register/memory state, the state expected after executing one instruction and the
+// bus activity of every machine cycle.
+//
+// the vectors aren't vendored, point SM83_TESTS_DIR at a checkout's `v1` directory:
+//     SM83_TESTS_DIR=../sm83/v1 cargo test sm83
+// without it the test is skipped.
+
+use std::cell::RefCell;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::*;
+use crate::registers::FlagsRegister;
+
+const TESTS_DIR_VAR: &str = "SM83_TESTS_DIR";
+
+#[derive(Deserialize)]
+struct TestCase {
+    name: String,
+    initial: CpuState,
+    #[serde(rename = "final")]
+    expected: CpuState,
+    // [address, data, "r-m"] per machine cycle, null for cycles with no bus activity
+    cycles: Vec<Option<(u16, Option<u8>, String)>>,
+}
+
+#[derive(Deserialize)]
+struct CpuState {
+    pc: u16,
+    sp: u16,
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+// plain 64 KiB of memory with no mapped hardware, recording every write
+struct FlatBus {
+    memory: Box<[u8; 0x10000]>,
+    writes: RefCell<Vec<(u16, u8)>>,
+}
+
+impl FlatBus {
+    fn new() -> FlatBus {
+        FlatBus { memory: Box::new([0; 0x10000]), writes: RefCell::new(Vec::new()) }
+    }
+}
+
+impl Bus for FlatBus {
+    fn read_byte(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.writes.borrow_mut().push((address, value));
+        self.memory[address as usize] = value;
+    }
+}
+
+fn load_state(cpu: &mut CPU<FlatBus>, state: &CpuState) {
+    cpu.pc = state.pc;
+    cpu.sp = state.sp;
+    cpu.registers.a = state.a;
+    cpu.registers.f = FlagsRegister::from(state.f);
+    cpu.registers.b = state.b;
+    cpu.registers.c = state.c;
+    cpu.registers.d = state.d;
+    cpu.registers.e = state.e;
+    cpu.registers.h = state.h;
+    cpu.registers.l = state.l;
+    for &(address, value) in &state.ram {
+        cpu.bus.memory[address as usize] = value;
+    }
+}
+
+// returns a description of every mismatch, empty when the test passed
+fn compare_state(cpu: &CPU<FlatBus>, test: &TestCase) -> Vec<String> {
+    let expected = &test.expected;
+    let mut mismatches = Vec::new();
+    let f: u8 = cpu.registers.f.into();
+    let registers = [
+        ("a", cpu.registers.a as u16, expected.a as u16),
+        ("f", f as u16, expected.f as u16),
+        ("b", cpu.registers.b as u16, expected.b as u16),
+        ("c", cpu.registers.c as u16, expected.c as u16),
+        ("d", cpu.registers.d as u16, expected.d as u16),
+        ("e", cpu.registers.e as u16, expected.e as u16),
+        ("h", cpu.registers.h as u16, expected.h as u16),
+        ("l", cpu.registers.l as u16, expected.l as u16),
+        ("pc", cpu.pc, expected.pc),
+        ("sp", cpu.sp, expected.sp),
+    ];
+    for (name, actual, wanted) in registers {
+        if actual != wanted {
+            mismatches.push(format!("{}: got {:04X}, expected {:04X}", name, actual, wanted));
+        }
+    }
+    for &(address, wanted) in &expected.ram {
+        let actual = cpu.bus.memory[address as usize];
+        if actual != wanted {
+            mismatches.push(format!("[{:04X}]: got {:02X}, expected {:02X}", address, actual, wanted));
+        }
+    }
+    let expected_writes: Vec<(u16, u8)> = test.cycles.iter().flatten()
+        .filter(|(_, _, pins)| pins.contains('w'))
+        .filter_map(|&(address, value, _)| value.map(|value| (address, value)))
+        .collect();
+    let writes = cpu.bus.writes.borrow();
+    if *writes != expected_writes {
+        mismatches.push(format!("bus writes: got {:02X?}, expected {:02X?}", *writes, expected_writes));
+    }
+    mismatches
+}
+
+// runs every case in one opcode file, returning (cases run, first failure if any, failure count)
+fn run_file(path: &Path) -> (usize, Option<String>, usize) {
+    let contents = fs::read_to_string(path).expect("unreadable test file");
+    let tests: Vec<TestCase> = serde_json::from_str(&contents).expect("malformed test file");
+    let mut first_failure = None;
+    let mut failures = 0;
+    for test in &tests {
+        let mut cpu = CPU::new(FlatBus::new());
+        load_state(&mut cpu, &test.initial);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| { cpu.step(); }));
+        let mismatches = match result {
+            Ok(()) => compare_state(&cpu, test),
+            Err(_) => vec!["cpu panicked".to_string()],
+        };
+        if !mismatches.is_empty() {
+            failures += 1;
+            first_failure.get_or_insert_with(|| format!("{}: {}", test.name, mismatches.join("; ")));
+        }
+    }
+    (tests.len(), first_failure, failures)
+}
+
+#[test]
+fn sm83_single_step_tests() {
+    let Ok(dir) = std::env::var(TESTS_DIR_VAR) else {
+        eprintln!("{} not set, skipping sm83 single step tests", TESTS_DIR_VAR);
+        return;
+    };
+    let mut paths: Vec<_> = fs::read_dir(&dir).expect("unreadable SM83_TESTS_DIR")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .collect();
+    paths.sort();
+
+    // unimplemented opcodes panic, keep the output readable while they're caught
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut failed_opcodes = Vec::new();
+    let mut total = 0;
+    for path in &paths {
+        let opcode = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let (count, first_failure, failures) = run_file(path);
+        total += count;
+        if let Some(first_failure) = first_failure {
+            eprintln!("{}: {}/{} failed, first: {}", opcode, failures, count, first_failure);
+            failed_opcodes.push(opcode);
+        }
+    }
+    panic::set_hook(default_hook);
+
+    eprintln!("ran {} cases across {} opcodes", total, paths.len());
+    assert!(failed_opcodes.is_empty(), "failing opcodes: {}", failed_opcodes.join(", "));
+}
@@ -0,0 +1,414 @@
+use std::fmt;
+
+use crate::address::BankedAddress;
+use crate::cpu::{Bus, CpuSnapshot};
+use crate::mmu::Mmu;
+use crate::symbols::Symbols;
+use crate::trace::TraceLog;
+
+mod profiler;
+mod repl;
+mod watch;
+
+pub use profiler::{AddressProfile, Profiler, SymbolProfile};
+pub use repl::Repl;
+pub use watch::{MemoryAccess, Watchpoint};
+
+// stops before the instruction at `address` runs. with a bank it only stops
+// while that bank is mapped there (see Bus::bank_at), so code in one rom bank
+// can be told apart from whatever else shares its addresses
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub bank: Option<usize>,
+}
+
+impl Breakpoint {
+    pub fn new(address: u16) -> Breakpoint {
+        Breakpoint { address, bank: None }
+    }
+    pub fn banked(bank: usize, address: u16) -> Breakpoint {
+        Breakpoint { address, bank: Some(bank) }
+    }
+    pub fn matches(&self, pc: u16, bank: Option<usize>) -> bool {
+        self.location().matches(pc, bank)
+    }
+    pub fn location(&self) -> BankedAddress {
+        BankedAddress { bank: self.bank, address: self.address }
+    }
+}
+
+impl From<BankedAddress> for Breakpoint {
+    fn from(location: BankedAddress) -> Breakpoint {
+        Breakpoint { address: location.address, bank: location.bank }
+    }
+}
+
+// bank:address in hex, like rgbds symbol files
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.location().fmt(f)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    Breakpoint(Breakpoint),
+    // after the instruction that made the first access a watchpoint caught,
+    // see Debugger::watch_hits for the rest
+    Watchpoint(MemoryAccess),
+    // a step_into, step_over or step_out finished
+    Step,
+    // pause() was called
+    Requested,
+    // the cpu ran into an opcode that doesn't exist and hung
+    LockedUp(u8),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(breakpoint) => write!(f, "breakpoint at {}", breakpoint),
+            StopReason::Watchpoint(access) => write!(f, "watchpoint: {}", access),
+            StopReason::Step => write!(f, "step"),
+            StopReason::Requested => write!(f, "paused"),
+            StopReason::LockedUp(opcode) => write!(f, "cpu locked up on illegal opcode ${:02X}", opcode),
+        }
+    }
+}
+
+// what the debugger is running towards
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Goal {
+    // nothing but breakpoints
+    Run,
+    // instructions left to run
+    Steps(u32),
+    // made into ReturnTo or a single step at the first instruction
+    StepOver,
+    // back at `pc` with the stack no deeper than `sp`, so a call has returned
+    // (and not just a recursive one)
+    ReturnTo { pc: u16, sp: u16 },
+    // a return that left the stack above `sp`, the stack pointer is taken at
+    // the first instruction
+    StepOut(Option<u16>),
+}
+
+// length of the instruction if it's a CALL or RST, the ones step_over skips
+fn call_length(opcode: u8) -> Option<u16> {
+    match opcode {
+        0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => Some(3),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some(1),
+        _ => None,
+    }
+}
+
+fn is_return(opcode: u8) -> bool {
+    matches!(opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8)
+}
+
+// breakpoints and run control, attached to a GameBoy with attach_debugger().
+// the GameBoy asks before every instruction whether to go on, so run() and the
+// step functions only say what should happen: the frontend keeps calling
+// run_frame() or run_cycles() as usual and the machine stops where told.
+// GameBoy::step() goes around the debugger
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    paused: bool,
+    stop_reason: Option<StopReason>,
+    goal: Goal,
+    // don't stop at a breakpoint on the instruction we're resuming from
+    resuming: bool,
+    // the opcode at pc when the last instruction was let through
+    last_opcode: u8,
+    symbols: Symbols,
+    trace_log: Option<TraceLog>,
+    profiler: Option<Profiler>,
+    // where the instruction let through by before_instruction is
+    running: Option<(Option<usize>, u16)>,
+    // what the watchpoints caught in the instruction that last stopped us
+    watch_hits: Vec<MemoryAccess>,
+}
+
+impl Debugger {
+    // starts out running, so attaching doesn't stop the game
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            paused: false,
+            stop_reason: None,
+            goal: Goal::Run,
+            resuming: false,
+            last_opcode: 0,
+            symbols: Symbols::new(),
+            trace_log: None,
+            profiler: None,
+            running: None,
+            watch_hits: Vec::new(),
+        }
+    }
+    // false if it was already there
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        if self.breakpoints.contains(&breakpoint) { return false }
+        self.breakpoints.push(breakpoint);
+        true
+    }
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|&other| other != breakpoint);
+        self.breakpoints.len() != count
+    }
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+    // labels for everything that shows addresses
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+    // logs every instruction that runs from now on, giving back the log there
+    // was so it can be finished
+    pub fn set_trace_log(&mut self, log: Option<TraceLog>) -> Option<TraceLog> {
+        std::mem::replace(&mut self.trace_log, log)
+    }
+    pub fn enable_profiler(&mut self) {
+        self.profiler.get_or_insert_with(Profiler::new);
+    }
+    pub fn disable_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+    // the accesses behind a StopReason::Watchpoint. watchpoints themselves are
+    // set on the Mmu, which sees the accesses
+    pub fn watch_hits(&self) -> &[MemoryAccess] {
+        &self.watch_hits
+    }
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+    // why the machine last stopped, None while running
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+    // stop before the next instruction
+    pub fn pause(&mut self) {
+        self.stop(StopReason::Requested);
+    }
+    // go on until a breakpoint is hit or pause() is called
+    pub fn run(&mut self) {
+        self.resume(Goal::Run);
+    }
+    // run one instruction (or interrupt dispatch) and stop again
+    pub fn step_into(&mut self) {
+        self.resume(Goal::Steps(1));
+    }
+    // like step_into, but a CALL or RST runs until the routine returns
+    pub fn step_over(&mut self) {
+        self.resume(Goal::StepOver);
+    }
+    // run until the current routine returns to its caller
+    pub fn step_out(&mut self) {
+        self.resume(Goal::StepOut(None));
+    }
+    fn resume(&mut self, goal: Goal) {
+        self.paused = false;
+        self.stop_reason = None;
+        self.goal = goal;
+        self.resuming = true;
+    }
+    fn stop(&mut self, reason: StopReason) {
+        self.paused = true;
+        self.stop_reason = Some(reason);
+        self.goal = Goal::Run;
+    }
+    // whether a step has reached its goal, moving towards it otherwise
+    fn reached_goal(&mut self, cpu: &CpuSnapshot, opcode: u8) -> bool {
+        match self.goal {
+            Goal::Run => {}
+            Goal::Steps(0) => return true,
+            Goal::Steps(steps) => self.goal = Goal::Steps(steps - 1),
+            Goal::StepOver => {
+                self.goal = match call_length(opcode) {
+                    Some(length) => Goal::ReturnTo { pc: cpu.pc.wrapping_add(length), sp: cpu.sp },
+                    None => Goal::Steps(0),
+                };
+            }
+            Goal::ReturnTo { pc, sp } => return cpu.pc == pc && cpu.sp >= sp,
+            Goal::StepOut(None) => self.goal = Goal::StepOut(Some(cpu.sp)),
+            Goal::StepOut(Some(sp)) => return is_return(self.last_opcode) && cpu.sp > sp,
+        }
+        false
+    }
+    // called by the GameBoy before each instruction, true to stop before it
+    // runs. anything watching instructions go by sees it here if it does run
+    pub fn before_instruction(&mut self, cpu: &CpuSnapshot, mmu: &Mmu) -> bool {
+        let bank = mmu.bank_at(cpu.pc);
+        if self.should_stop(cpu, mmu.peek_byte(cpu.pc), bank) { return true }
+        self.running = Some((bank, cpu.pc));
+        // like the cpu's tracer, a log that can't be written stops logging
+        if let Some(log) = &mut self.trace_log && log.trace(cpu, mmu, &self.symbols).is_err() {
+            self.trace_log = None;
+        }
+        false
+    }
+    // called by the GameBoy when the cpu locks up, it won't run anything after this
+    pub fn locked_up(&mut self, opcode: u8) {
+        self.stop(StopReason::LockedUp(opcode));
+    }
+    // and after it, with the clock cycles it took
+    pub fn after_instruction(&mut self, cycles: u32, mmu: &Mmu) {
+        if let (Some(profiler), Some((bank, address))) = (&mut self.profiler, self.running.take()) {
+            profiler.record(bank, address, cycles);
+        }
+        let hits = mmu.take_watch_hits();
+        if let Some(&first) = hits.first() {
+            self.watch_hits = hits;
+            self.stop(StopReason::Watchpoint(first));
+        }
+    }
+    // asked before each instruction with the cpu state, the opcode at pc and
+    // the bank mapped there, true to stop before it runs
+    pub fn should_stop(&mut self, cpu: &CpuSnapshot, opcode: u8, bank: Option<usize>) -> bool {
+        if self.paused { return true }
+        let first = std::mem::take(&mut self.resuming);
+        if self.reached_goal(cpu, opcode) {
+            self.stop(StopReason::Step);
+            return true;
+        }
+        self.last_opcode = opcode;
+        if first { return false }
+        let pc = cpu.pc;
+        let hit = self.breakpoints.iter().find(|breakpoint| breakpoint.matches(pc, bank)).copied();
+        if let Some(breakpoint) = hit {
+            self.stop(StopReason::Breakpoint(breakpoint));
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+    use crate::disassembler::disassemble;
+    use crate::gameboy::GameBoy;
+    use crate::heatmap::AccessKind;
+
+    #[test]
+    fn a_cpu_lockup_stops_the_machine_once() {
+        // nop; an opcode that doesn't exist
+        let mut gameboy = GameBoy::new(test_rom(&[0x00, 0xDD], 0x00, 0)).unwrap();
+        gameboy.attach_debugger();
+        gameboy.run_frame();
+        assert_eq!(gameboy.locked_up(), Some(0xDD));
+        assert_eq!(gameboy.debugger().unwrap().stop_reason(), Some(StopReason::LockedUp(0xDD)));
+        assert_eq!(gameboy.cpu().snapshot().pc, 0x0101);
+        // going on runs the rest of the machine without stopping again
+        gameboy.debugger_mut().unwrap().run();
+        assert!(gameboy.run_cycles(1000) >= 1000);
+        assert_eq!(gameboy.debugger().unwrap().stop_reason(), None);
+    }
+
+    #[test]
+    fn breakpoints_stop_the_machine_before_the_instruction() {
+        // MBC5. nop; nop; ld a, 2; ld (0x2000), a; call 0x4000; jr -2
+        let program = [0x00, 0x00, 0x3E, 0x02, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x18, 0xFE, 0x00];
+        let mut rom = test_rom(&program, 0x19, 0);
+        // ret in banks 1 and 2
+        rom[0x4000] = 0xC9;
+        rom[0x8000] = 0xC9;
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        // bank 2 can be looked at before the game maps it
+        let view = gameboy.mmu().bank_view(2);
+        assert_eq!(disassemble(&view, 0x4000, None).to_string(), "02:4000: C9        ret");
+        let debugger = gameboy.attach_debugger();
+        assert!(debugger.add_breakpoint(Breakpoint::new(0x0101)));
+        assert!(!debugger.add_breakpoint(Breakpoint::new(0x0101)));
+        debugger.add_breakpoint(Breakpoint::banked(1, 0x4000));
+        debugger.add_breakpoint(Breakpoint::banked(2, 0x4000));
+
+        gameboy.run_frame();
+        let pc = |gameboy: &GameBoy| gameboy.cpu().snapshot().pc;
+        let debugger = gameboy.debugger().unwrap();
+        assert!(debugger.paused());
+        assert_eq!(debugger.stop_reason(), Some(StopReason::Breakpoint(Breakpoint::new(0x0101))));
+        assert_eq!(pc(&gameboy), 0x0101);
+        // nothing runs while paused
+        gameboy.run_frame();
+        assert_eq!(gameboy.run_cycles(1000), 0);
+        assert_eq!(pc(&gameboy), 0x0101);
+
+        gameboy.debugger_mut().unwrap().step_into();
+        gameboy.run_frame();
+        assert_eq!(gameboy.debugger().unwrap().stop_reason(), Some(StopReason::Step));
+        assert_eq!(pc(&gameboy), 0x0102);
+
+        // the bank 1 breakpoint doesn't fire with bank 2 mapped
+        gameboy.debugger_mut().unwrap().run();
+        gameboy.run_frame();
+        let stop = gameboy.debugger().unwrap().stop_reason();
+        assert_eq!(stop, Some(StopReason::Breakpoint(Breakpoint::banked(2, 0x4000))));
+        assert_eq!(stop.unwrap().to_string(), "breakpoint at 02:4000");
+
+        gameboy.debugger_mut().unwrap().remove_breakpoint(Breakpoint::banked(2, 0x4000));
+        gameboy.debugger_mut().unwrap().run();
+        gameboy.run_frame();
+        assert!(!gameboy.debugger().unwrap().paused());
+        assert_eq!(pc(&gameboy), 0x010A);
+        assert!(gameboy.detach_debugger().is_some());
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_access() {
+        // ld hl, 0xC000; ld a, [hl]; ld [hl], 3; jr -2
+        let rom = test_rom(&[0x21, 0x00, 0xC0, 0x7E, 0x36, 0x03, 0x18, 0xFE], 0x00, 0);
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        gameboy.attach_debugger();
+        gameboy.mmu_mut().add_watchpoint(Watchpoint::write(0xC000));
+        gameboy.run_frame();
+        let debugger = gameboy.debugger().unwrap();
+        let write = MemoryAccess { kind: AccessKind::Write, address: 0xC000, bank: None, value: 3 };
+        assert_eq!(debugger.stop_reason(), Some(StopReason::Watchpoint(write)));
+        assert_eq!(debugger.stop_reason().unwrap().to_string(), "watchpoint: wrote $03 to C000");
+        assert_eq!(debugger.watch_hits(), [write]);
+        assert_eq!(gameboy.cpu().snapshot().pc, 0x0106);
+        gameboy.mmu_mut().add_watchpoint(Watchpoint::read(0xC000));
+        gameboy.mmu_mut().remove_watchpoint(Watchpoint::write(0xC000));
+        gameboy.debugger_mut().unwrap().run();
+        gameboy.run_frame();
+        assert!(!gameboy.debugger().unwrap().paused());
+    }
+
+    #[test]
+    fn steps_go_over_and_out_of_calls() {
+        // call 0x0150 twice and start over. 0x0150 calls 0x0160 and returns, 0x0160 is nop; ret
+        let mut rom = test_rom(&[0xCD, 0x50, 0x01, 0xCD, 0x50, 0x01, 0x18, 0xF8], 0x00, 0);
+        rom[0x150..0x154].copy_from_slice(&[0xCD, 0x60, 0x01, 0xC9]);
+        rom[0x160..0x162].copy_from_slice(&[0x00, 0xC9]);
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        gameboy.attach_debugger().pause();
+        let mut expect = |command: fn(&mut Debugger), pc: u16| {
+            command(gameboy.debugger_mut().unwrap());
+            gameboy.run_frame();
+            assert_eq!(gameboy.debugger().unwrap().stop_reason(), Some(StopReason::Step));
+            assert_eq!(gameboy.cpu().snapshot().pc, pc);
+        };
+        expect(Debugger::step_over, 0x0103);
+        expect(Debugger::step_into, 0x0150);
+        expect(Debugger::step_over, 0x0153);
+        expect(Debugger::step_into, 0x0106);
+        expect(Debugger::step_into, 0x0100);
+        expect(Debugger::step_into, 0x0150);
+        expect(Debugger::step_into, 0x0160);
+        expect(Debugger::step_out, 0x0153);
+        expect(Debugger::step_out, 0x0103);
+    }
+}
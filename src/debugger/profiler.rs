@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use crate::address::BankedAddress;
+use crate::symbols::Symbols;
+
+// time spent at one address, in clock cycles. halted time and interrupt
+// dispatches count towards where the cpu was at the time
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AddressProfile {
+    pub bank: Option<usize>,
+    pub address: u16,
+    pub cycles: u64,
+    pub executions: u64,
+}
+
+impl AddressProfile {
+    pub fn location(&self) -> BankedAddress {
+        BankedAddress { bank: self.bank, address: self.address }
+    }
+}
+
+// everything spent in a routine, see Symbols::routine
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SymbolProfile {
+    pub name: String,
+    pub cycles: u64,
+    pub executions: u64,
+}
+
+// adds up the clock cycles spent at each pc while the debugger lets the game
+// run, to find where frame time goes. turned on with Debugger::enable_profiler
+pub struct Profiler {
+    counts: HashMap<(Option<usize>, u16), (u64, u64)>,
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler { counts: HashMap::new(), total: 0 }
+    }
+    pub fn record(&mut self, bank: Option<usize>, address: u16, cycles: u32) {
+        let (total, executions) = self.counts.entry((bank, address)).or_default();
+        *total += cycles as u64;
+        *executions += 1;
+        self.total += cycles as u64;
+    }
+    pub fn total_cycles(&self) -> u64 {
+        self.total
+    }
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.total = 0;
+    }
+    // the hottest addresses first
+    pub fn by_address(&self) -> Vec<AddressProfile> {
+        let mut profile: Vec<AddressProfile> = self
+            .counts
+            .iter()
+            .map(|(&(bank, address), &(cycles, executions))| AddressProfile { bank, address, cycles, executions })
+            .collect();
+        profile.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.location().cmp(&b.location())));
+        profile
+    }
+    // the hottest routines first. time outside any routine is put down as "?"
+    pub fn by_symbol(&self, symbols: &Symbols) -> Vec<SymbolProfile> {
+        let mut totals: HashMap<&str, (u64, u64)> = HashMap::new();
+        for (&(bank, address), &(cycles, executions)) in &self.counts {
+            let name = symbols.routine(address, bank).map_or("?", |symbol| symbol.name.as_str());
+            let (total, count) = totals.entry(name).or_default();
+            *total += cycles;
+            *count += executions;
+        }
+        let mut profile: Vec<SymbolProfile> = totals
+            .into_iter()
+            .map(|(name, (cycles, executions))| SymbolProfile { name: name.to_string(), cycles, executions })
+            .collect();
+        profile.sort_by(|a, b| b.cycles.cmp(&a.cycles).then_with(|| a.name.cmp(&b.name)));
+        profile
+    }
+    // the top `count` routines, or addresses without symbols, as a table
+    pub fn report(&self, symbols: &Symbols, count: usize) -> String {
+        let percent = |cycles: u64| cycles as f64 * 100.0 / self.total.max(1) as f64;
+        let rows: Vec<String> = if symbols.is_empty() {
+            self.by_address()
+                .iter()
+                .take(count)
+                .map(|row| {
+                    format!("{:6.2}% {:>12} {:>10}  {}", percent(row.cycles), row.cycles, row.executions, row.location())
+                })
+                .collect()
+        } else {
+            self.by_symbol(symbols)
+                .iter()
+                .take(count)
+                .map(|row| format!("{:6.2}% {:>12} {:>10}  {}", percent(row.cycles), row.cycles, row.executions, row.name))
+                .collect()
+        };
+        format!("{:>7} {:>12} {:>10}  where\n{}", "time", "cycles", "runs", rows.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+    use crate::gameboy::GameBoy;
+
+    #[test]
+    fn cycles_add_up_per_address_and_routine() {
+        // Main: call Wait; jr Main. Wait: ld b, 10; .loop: dec b; jr nz, .loop; ret
+        let mut rom = test_rom(&[0xCD, 0x50, 0x01, 0x18, 0xFB], 0x00, 0);
+        rom[0x150..0x156].copy_from_slice(&[0x06, 0x0A, 0x05, 0x20, 0xFD, 0xC9]);
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        let debugger = gameboy.attach_debugger();
+        debugger.set_symbols(Symbols::parse("00:0100 Main\n00:0150 Wait\n00:0152 Wait.loop\n").unwrap());
+        debugger.enable_profiler();
+        gameboy.run_frame();
+        let debugger = gameboy.debugger().unwrap();
+        let profiler = debugger.profiler().unwrap();
+        let addresses = profiler.by_address();
+        // the jr nz back round the loop is taken most times, at 12 cycles
+        assert_eq!(addresses[0].address, 0x0153);
+        let dec = addresses.iter().find(|row| row.address == 0x0152).unwrap();
+        assert_eq!(dec.cycles, dec.executions * 4);
+        let routines = profiler.by_symbol(debugger.symbols());
+        assert_eq!(routines.len(), 2);
+        assert_eq!(routines[0].name, "Wait");
+        assert_eq!(routines.iter().map(|routine| routine.cycles).sum::<u64>(), profiler.total_cycles());
+        assert!(profiler.report(debugger.symbols(), 5).lines().nth(1).unwrap().ends_with("Wait"));
+    }
+}
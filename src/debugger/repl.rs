@@ -0,0 +1,270 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+
+use crate::address::BankedAddress;
+use crate::debugger::{Breakpoint, Debugger, Watchpoint};
+use crate::disassembler::{disassemble, disassemble_range};
+use crate::gameboy::GameBoy;
+use crate::symbols::Symbols;
+use crate::trace::TraceLog;
+
+const HELP: &str = "\
+b <address>        add a breakpoint, the address can be bank:address or a label
+d <address>        delete a breakpoint
+watch <address>    stop after a write to the address, rwatch for reads, awatch for both
+unwatch <address>  delete the watchpoints on an address
+bl                 list breakpoints and watchpoints
+c                  continue until a breakpoint
+s                  step into
+n                  step over calls
+fin                run until the current routine returns
+regs               show the registers
+x/<n> <address>    dump n bytes of memory (16 by default)
+trace <file>|off   log every instruction that runs to a file, or stop
+profile on|off     count the cycles spent at each address
+profile [n]        the n hottest routines (addresses without symbols), 10 by default
+cdl on|off        mark rom bytes as code or data as they're used
+cdl <file>         save the code/data log for a disassembler
+dis [address] [n]  disassemble n instructions (10 by default) from pc or address
+q                  quit
+an empty line repeats the last command. addresses are hex, bank:address (05:4123), a label,
+or pc, sp, bc, de, hl. dis and x read the bank given whether it's mapped in or not";
+
+const DEFAULT_DUMP_BYTES: usize = 16;
+const DEFAULT_DISASSEMBLY_LINES: usize = 10;
+const DEFAULT_PROFILE_LINES: usize = 10;
+
+// the debugger's, the Repl keeps one attached
+fn symbols(gameboy: &GameBoy) -> &Symbols {
+    gameboy.debugger().expect("attached by the repl").symbols()
+}
+
+// a hex address or bank:address, a label or a register holding an address. a
+// label in a switchable bank comes with its bank
+fn parse_address(gameboy: &GameBoy, text: &str) -> Result<BankedAddress, String> {
+    let cpu = gameboy.cpu().snapshot();
+    let register = match text.to_lowercase().as_str() {
+        "pc" => Some(cpu.pc),
+        "sp" => Some(cpu.sp),
+        "bc" => Some(cpu.registers.get_bc()),
+        "de" => Some(cpu.registers.get_de()),
+        "hl" => Some(cpu.registers.get_hl()),
+        _ => None,
+    };
+    register
+        .map(BankedAddress::new)
+        .or_else(|| symbols(gameboy).resolve(text))
+        .ok_or_else(|| format!("bad address {:?}", text))
+}
+
+fn parse_count(text: Option<&str>, default: usize) -> Result<usize, String> {
+    text.map_or(Ok(default), |text| text.parse().map_err(|_| format!("bad count {:?}", text)))
+}
+
+// gdb-like commands on a GameBoy with a debugger attached, for a quick look
+// without setting up a remote debugger
+// labels come from the debugger's symbols
+pub struct Repl {
+    // what an empty line repeats
+    last: String,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl { last: String::new() }
+    }
+    // runs one command, returning what to print. None once asked to quit
+    pub fn execute(&mut self, gameboy: &mut GameBoy, line: &str) -> Option<String> {
+        gameboy.attach_debugger();
+        let line = line.trim();
+        let line = if line.is_empty() { self.last.clone() } else { line.to_string() };
+        self.last = line.clone();
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let arguments: Vec<&str> = words.collect();
+        if matches!(command, "q" | "quit") { return None }
+        let result = match (command, arguments.as_slice()) {
+            ("", _) => Ok(String::new()),
+            ("h" | "help", _) => Ok(HELP.to_string()),
+            ("b" | "break", [address]) => parse_address(gameboy, address).map(Breakpoint::from).map(|breakpoint| {
+                gameboy.attach_debugger().add_breakpoint(breakpoint);
+                format!("breakpoint at {}", breakpoint)
+            }),
+            ("d" | "delete", [address]) => parse_address(gameboy, address).map(Breakpoint::from).and_then(|breakpoint| {
+                if gameboy.attach_debugger().remove_breakpoint(breakpoint) {
+                    Ok(format!("deleted breakpoint at {}", breakpoint))
+                } else {
+                    Err(format!("no breakpoint at {}", breakpoint))
+                }
+            }),
+            (watch @ ("watch" | "rwatch" | "awatch"), [address]) => parse_address(gameboy, address).map(|address| {
+                let watchpoint = match watch {
+                    "watch" => Watchpoint::write(address),
+                    "rwatch" => Watchpoint::read(address),
+                    _ => Watchpoint::access(address),
+                };
+                gameboy.mmu_mut().add_watchpoint(watchpoint);
+                format!("watchpoint at {}", watchpoint)
+            }),
+            ("unwatch", [address]) => parse_address(gameboy, address).and_then(|location| {
+                let watchpoints: Vec<Watchpoint> = gameboy.mmu().watchpoints().to_vec();
+                let removed = watchpoints
+                    .into_iter()
+                    .filter(|watchpoint| watchpoint.location() == location)
+                    .filter(|&watchpoint| gameboy.mmu_mut().remove_watchpoint(watchpoint))
+                    .count();
+                if removed == 0 { return Err(format!("no watchpoint at {}", location)) }
+                Ok(format!("deleted watchpoints at {}", location))
+            }),
+            ("bl", []) => {
+                let mut lines: Vec<String> =
+                    gameboy.attach_debugger().breakpoints().iter().map(Breakpoint::to_string).collect();
+                lines.extend(gameboy.mmu().watchpoints().iter().map(|watchpoint| format!("watch {}", watchpoint)));
+                Ok(lines.join("\n"))
+            }
+            ("c" | "continue", []) => Ok(resume(gameboy, Debugger::run)),
+            ("s" | "step", []) => Ok(resume(gameboy, Debugger::step_into)),
+            ("n" | "next", []) => Ok(resume(gameboy, Debugger::step_over)),
+            ("fin" | "finish", []) => Ok(resume(gameboy, Debugger::step_out)),
+            ("trace", ["off"]) => match gameboy.attach_debugger().set_trace_log(None) {
+                Some(log) => log.finish().map(|()| "tracing stopped".to_string()).map_err(|error| error.to_string()),
+                None => Err("not tracing".to_string()),
+            },
+            ("trace", [path]) => File::create(path).map_err(|error| format!("{}: {}", path, error)).and_then(|file| {
+                let previous = gameboy.attach_debugger().set_trace_log(Some(TraceLog::new(BufWriter::new(file))));
+                previous.map_or(Ok(()), TraceLog::finish).map_err(|error| error.to_string())?;
+                Ok(format!("tracing to {}", path))
+            }),
+            ("profile", ["on"]) => {
+                gameboy.attach_debugger().enable_profiler();
+                Ok("profiling".to_string())
+            }
+            ("profile", ["off"]) => {
+                gameboy.attach_debugger().disable_profiler();
+                Ok("profiling stopped".to_string())
+            }
+            ("profile", arguments) if arguments.len() <= 1 => {
+                let count = parse_count(arguments.first().copied(), DEFAULT_PROFILE_LINES);
+                let debugger = gameboy.attach_debugger();
+                count.and_then(|count| {
+                    let profiler = debugger.profiler().ok_or("not profiling, profile on first")?;
+                    Ok(profiler.report(debugger.symbols(), count))
+                })
+            }
+            ("cdl", ["on"]) => {
+                gameboy.mmu_mut().enable_code_data_log();
+                Ok("logging code and data".to_string())
+            }
+            ("cdl", ["off"]) => match gameboy.mmu_mut().set_code_data_log(None) {
+                Some(_) => Ok("code/data log stopped".to_string()),
+                None => Err("no code/data log, cdl on first".to_string()),
+            },
+            ("cdl", [path]) => match gameboy.mmu().code_data_log() {
+                Some(log) => log.save(path).map_err(|error| format!("{}: {}", path, error)).map(|()| {
+                    let (code, data) = log.counts();
+                    format!("saved {}: {} bytes of code, {} of data", path, code, data)
+                }),
+                None => Err("no code/data log, cdl on first".to_string()),
+            },
+            ("regs", []) => Ok(gameboy.cpu().snapshot().to_string()),
+            ("dis", arguments) if arguments.len() <= 2 => {
+                let pc = BankedAddress::new(gameboy.cpu().snapshot().pc);
+                let location = arguments.first().map_or(Ok(pc), |text| parse_address(gameboy, text));
+                location.and_then(|location| {
+                    let count = parse_count(arguments.get(1).copied(), DEFAULT_DISASSEMBLY_LINES)?;
+                    let symbols = Some(symbols(gameboy));
+                    let lines = match location.bank {
+                        Some(bank) => disassemble_range(&gameboy.mmu().bank_view(bank), location.address, count, symbols),
+                        None => disassemble_range(gameboy.mmu(), location.address, count, symbols),
+                    };
+                    Ok(lines.iter().map(|line| line.to_string()).collect::<Vec<_>>().join("\n"))
+                })
+            }
+            (dump, [address]) if dump == "x" || dump.starts_with("x/") => {
+                let count = parse_count(dump.strip_prefix("x/"), DEFAULT_DUMP_BYTES);
+                count.and_then(|count| Ok(memory(gameboy, parse_address(gameboy, address)?, count)))
+            }
+            _ => Err(format!("can't do {:?}, try help", line)),
+        };
+        Some(result.unwrap_or_else(|error| error))
+    }
+    // reads commands until quit or the end of the input
+    pub fn run<R: BufRead, W: Write>(&mut self, gameboy: &mut GameBoy, input: R, mut output: W) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(gb) ")?;
+            output.flush()?;
+            let Some(line) = lines.next().transpose()? else { return Ok(()) };
+            match self.execute(gameboy, &line) {
+                Some(text) if text.is_empty() => {}
+                Some(text) => writeln!(output, "{}", text)?,
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+// runs frames until the debugger stops again, then says where and why. with
+// no breakpoint in the way a continue goes on for good
+fn resume(gameboy: &mut GameBoy, command: fn(&mut Debugger)) -> String {
+    command(gameboy.attach_debugger());
+    while !gameboy.attach_debugger().paused() {
+        gameboy.advance_frame();
+    }
+    let reason = gameboy.attach_debugger().stop_reason().map(|reason| reason.to_string()).unwrap_or_default();
+    let here = disassemble(gameboy.mmu(), gameboy.cpu().snapshot().pc, Some(symbols(gameboy)));
+    format!("{}\n{}", reason, here)
+}
+
+// 16 bytes to a line, read without side effects from the bank given, if any
+fn memory(gameboy: &GameBoy, location: BankedAddress, count: usize) -> String {
+    let at = |offset: usize| BankedAddress { address: location.address.wrapping_add(offset as u16), ..location };
+    let bytes: Vec<u8> = (0..count).map(|offset| gameboy.mmu().peek_banked(at(offset))).collect();
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("{}: {}", at(row * 16), hex.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    #[test]
+    fn commands_drive_the_debugger() {
+        // ld hl, 0xC000; ld (hl), 0x42; call 0x0150; jr -2. 0x0150 is ret
+        let mut rom = test_rom(&[0x21, 0x00, 0xC0, 0x36, 0x42, 0xCD, 0x50, 0x01, 0x18, 0xFE], 0x00, 0);
+        rom[0x150] = 0xC9;
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        gameboy.attach_debugger().pause();
+        let symbols = Symbols::parse("00:0150 Update\n00:C000 wCounter\n").unwrap();
+        gameboy.attach_debugger().set_symbols(symbols);
+        let mut repl = Repl::new();
+        let mut run = |line: &str| repl.execute(&mut gameboy, line).unwrap();
+        assert_eq!(run("dis pc 2"), "00:0100: 21 00 C0  ld hl, $C000\n00:0103: 36 42     ld [hl], $42");
+        assert_eq!(run("b Update"), "breakpoint at 0150");
+        assert_eq!(run("s"), "step\n00:0103: 36 42     ld [hl], $42");
+        // repeats the step
+        assert_eq!(run(""), "step\n00:0105: CD 50 01  call Update");
+        assert_eq!(run("x/4 hl"), "C000: 42 00 00 00");
+        assert_eq!(run("x/2 wCounter"), "C000: 42 00");
+        assert_eq!(run("c"), "breakpoint at 0150\nUpdate:\n00:0150: C9        ret");
+        assert!(run("regs").ends_with("HL:C000 SP:FFFC PC:0150"));
+        assert_eq!(run("d 0150"), "deleted breakpoint at 0150");
+        assert_eq!(run("fin"), "step\n00:0108: 18 FE     jr $0108");
+        assert_eq!(run("awatch wCounter"), "watchpoint at C000 (access)");
+        assert_eq!(run("bl"), "watch C000 (access)");
+        assert_eq!(run("unwatch C000"), "deleted watchpoints at C000");
+        assert_eq!(run("unwatch C000"), "no watchpoint at C000");
+        assert_eq!(run("b nowhere"), "bad address \"nowhere\"");
+        assert_eq!(run("b 01:4000"), "breakpoint at 01:4000");
+        assert_eq!(run("x/2 02:D000"), "02:D000: 00 00");
+        assert!(repl.execute(&mut gameboy, "q").is_none());
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+
+use crate::address::BankedAddress;
+use crate::heatmap::AccessKind;
+
+// stops after an instruction that reads or writes `address`, see
+// Mmu::add_watchpoint. with a bank only while that bank is mapped there
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Watchpoint {
+    pub address: u16,
+    pub bank: Option<usize>,
+    pub reads: bool,
+    pub writes: bool,
+}
+
+impl Watchpoint {
+    pub fn read(location: impl Into<BankedAddress>) -> Watchpoint {
+        Watchpoint::watching(location.into(), true, false)
+    }
+    pub fn write(location: impl Into<BankedAddress>) -> Watchpoint {
+        Watchpoint::watching(location.into(), false, true)
+    }
+    pub fn access(location: impl Into<BankedAddress>) -> Watchpoint {
+        Watchpoint::watching(location.into(), true, true)
+    }
+    fn watching(location: BankedAddress, reads: bool, writes: bool) -> Watchpoint {
+        Watchpoint { address: location.address, bank: location.bank, reads, writes }
+    }
+    pub fn location(&self) -> BankedAddress {
+        BankedAddress { bank: self.bank, address: self.address }
+    }
+    pub fn matches(&self, access: &MemoryAccess) -> bool {
+        self.location().matches(access.address, access.bank)
+            && match access.kind {
+                AccessKind::Read => self.reads,
+                AccessKind::Write => self.writes,
+                AccessKind::Execute => false,
+            }
+    }
+}
+
+// 0150 (read), 02:D000 (write) or C000 (access)
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match (self.reads, self.writes) {
+            (true, false) => "read",
+            (false, true) => "write",
+            _ => "access",
+        };
+        write!(f, "{} ({})", self.location(), kind)
+    }
+}
+
+// a read or write the cpu made that a watchpoint caught, with the bank that
+// was mapped there. a write's value is what was written, whether or not
+// anything took it
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub bank: Option<usize>,
+    pub value: u8,
+}
+
+impl MemoryAccess {
+    pub fn location(&self) -> BankedAddress {
+        BankedAddress { bank: self.bank, address: self.address }
+    }
+}
+
+impl fmt::Display for MemoryAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            AccessKind::Write => write!(f, "wrote ${:02X} to {}", self.value, self.location()),
+            _ => write!(f, "read ${:02X} from {}", self.value, self.location()),
+        }
+    }
+}
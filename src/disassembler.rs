@@ -0,0 +1,351 @@
+use std::fmt;
+
+use crate::address::BankedAddress;
+use crate::cpu::Bus;
+use crate::instructions::*;
+use crate::symbols::Symbols;
+
+// one decoded instruction, in RGBDS syntax
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Disassembly {
+    pub address: u16,
+    // what the bus had mapped at the address, see Bus::bank_at
+    pub bank: Option<usize>,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    // from the symbols, if there's one for the address
+    pub label: Option<String>,
+}
+
+// 00:0150: C3 00 40  jp $4000, just the address where nothing is banked,
+// with a label on a line of its own before it
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+        write!(f, "{}: {:<9} {}", self.location(), self.hex(), self.text)
+    }
+}
+
+impl Disassembly {
+    pub fn location(&self) -> BankedAddress {
+        BankedAddress { bank: self.bank, address: self.address }
+    }
+    // the instruction's bytes, C3 00 40
+    pub fn hex(&self) -> String {
+        self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn condition(test: &JumpTest) -> &'static str {
+    match test {
+        JumpTest::NotZero => "nz, ",
+        JumpTest::Zero => "z, ",
+        JumpTest::NotCarry => "nc, ",
+        JumpTest::Carry => "c, ",
+        JumpTest::Always => "",
+    }
+}
+
+fn register(target: &PrefixedTarget) -> &'static str {
+    match target {
+        PrefixedTarget::B => "b",
+        PrefixedTarget::C => "c",
+        PrefixedTarget::D => "d",
+        PrefixedTarget::E => "e",
+        PrefixedTarget::H => "h",
+        PrefixedTarget::L => "l",
+        PrefixedTarget::HL => "[hl]",
+        PrefixedTarget::A => "a",
+    }
+}
+
+fn register_pair(target: ArithmeticWordTarget) -> &'static str {
+    match target {
+        ArithmeticWordTarget::BC => "bc",
+        ArithmeticWordTarget::DE => "de",
+        ArithmeticWordTarget::HL => "hl",
+        ArithmeticWordTarget::SP => "sp",
+    }
+}
+
+// reads operands following the opcode, counting how many there were
+struct Operands<'a, B: Bus + ?Sized> {
+    bus: &'a B,
+    symbols: Option<&'a Symbols>,
+    address: u16,
+    length: u16,
+}
+
+// the label for an address with whatever bank is mapped there now
+fn label<'a, B: Bus + ?Sized>(bus: &B, symbols: Option<&'a Symbols>, address: u16) -> Option<&'a str> {
+    symbols?.label(address, bus.bank_at(address))
+}
+
+impl<B: Bus + ?Sized> Operands<'_, B> {
+    fn byte(&mut self) -> u8 {
+        let value = self.bus.peek_byte(self.address.wrapping_add(self.length));
+        self.length += 1;
+        value
+    }
+    fn word(&mut self) -> u16 {
+        let low = self.byte();
+        u16::from_le_bytes([low, self.byte()])
+    }
+    fn n8(&mut self) -> String {
+        format!("${:02X}", self.byte())
+    }
+    fn a16(&mut self) -> String {
+        format!("${:04X}", self.word())
+    }
+    fn high(&mut self) -> String {
+        let address = 0xFF00 | self.byte() as u16;
+        format!("[{}]", self.location(address))
+    }
+    // an address the instruction jumps to or accesses, by name if it has one
+    fn location(&self, address: u16) -> String {
+        label(self.bus, self.symbols, address).map_or_else(|| format!("${:04X}", address), str::to_string)
+    }
+    fn target(&mut self) -> String {
+        let address = self.word();
+        self.location(address)
+    }
+    fn signed(&mut self) -> i8 {
+        self.byte() as i8
+    }
+    fn arithmetic(&mut self, target: &ArithmeticByteTarget) -> String {
+        match target {
+            ArithmeticByteTarget::B => "b".into(),
+            ArithmeticByteTarget::C => "c".into(),
+            ArithmeticByteTarget::D => "d".into(),
+            ArithmeticByteTarget::E => "e".into(),
+            ArithmeticByteTarget::H => "h".into(),
+            ArithmeticByteTarget::L => "l".into(),
+            ArithmeticByteTarget::HL => "[hl]".into(),
+            ArithmeticByteTarget::A => "a".into(),
+            ArithmeticByteTarget::N8 => self.n8(),
+        }
+    }
+    fn load_target(&mut self, target: &LoadByteTarget) -> String {
+        match target {
+            LoadByteTarget::A => "a".into(),
+            LoadByteTarget::B => "b".into(),
+            LoadByteTarget::C => "c".into(),
+            LoadByteTarget::D => "d".into(),
+            LoadByteTarget::E => "e".into(),
+            LoadByteTarget::H => "h".into(),
+            LoadByteTarget::L => "l".into(),
+            LoadByteTarget::BC => "[bc]".into(),
+            LoadByteTarget::DE => "[de]".into(),
+            LoadByteTarget::HL => "[hl]".into(),
+            LoadByteTarget::A16 => format!("[{}]", self.target()),
+            LoadByteTarget::HighC => "[c]".into(),
+            LoadByteTarget::HighN8 => self.high(),
+        }
+    }
+    fn load_source(&mut self, source: &LoadByteSource) -> String {
+        match source {
+            LoadByteSource::A => "a".into(),
+            LoadByteSource::B => "b".into(),
+            LoadByteSource::C => "c".into(),
+            LoadByteSource::D => "d".into(),
+            LoadByteSource::E => "e".into(),
+            LoadByteSource::H => "h".into(),
+            LoadByteSource::L => "l".into(),
+            LoadByteSource::BC => "[bc]".into(),
+            LoadByteSource::DE => "[de]".into(),
+            LoadByteSource::HL => "[hl]".into(),
+            LoadByteSource::N8 => self.n8(),
+            LoadByteSource::A16 => format!("[{}]", self.target()),
+            LoadByteSource::HighC => "[c]".into(),
+            LoadByteSource::HighN8 => self.high(),
+        }
+    }
+    fn load(&mut self, load: &LoadType) -> String {
+        match load {
+            LoadType::Byte(target, source) => {
+                let high = matches!(target, LoadByteTarget::HighC | LoadByteTarget::HighN8)
+                    || matches!(source, LoadByteSource::HighC | LoadByteSource::HighN8);
+                let target = self.load_target(target);
+                let source = self.load_source(source);
+                format!("{} {}, {}", if high { "ldh" } else { "ld" }, target, source)
+            }
+            LoadType::Word(target, source) => {
+                let target = match target {
+                    LoadWordTarget::BC => "bc".into(),
+                    LoadWordTarget::DE => "de".into(),
+                    LoadWordTarget::HL => "hl".into(),
+                    LoadWordTarget::SP => "sp".into(),
+                    LoadWordTarget::A16 => format!("[{}]", self.target()),
+                };
+                let source = match source {
+                    LoadWordSource::N16 => self.a16(),
+                    LoadWordSource::SP => "sp".into(),
+                    LoadWordSource::HL => "hl".into(),
+                };
+                format!("ld {}, {}", target, source)
+            }
+            LoadType::AddressIncDec(target, _, mode) => {
+                let hl = match mode { AddressMode::Inc => "[hl+]", AddressMode::Dec => "[hl-]" };
+                match target {
+                    LoadIncDecTarget::HL => format!("ld {}, a", hl),
+                    LoadIncDecTarget::A => format!("ld a, {}", hl),
+                }
+            }
+        }
+    }
+    fn text(&mut self, instruction: &Instruction) -> String {
+        match instruction {
+            Instruction::NOP() => "nop".into(),
+            Instruction::HALT() => "halt".into(),
+            Instruction::STOP() => {
+                self.byte();
+                "stop".into()
+            }
+            Instruction::DI() => "di".into(),
+            Instruction::EI() => "ei".into(),
+            Instruction::JP(test) => format!("jp {}{}", condition(test), self.target()),
+            Instruction::JR(test) => {
+                let offset = self.signed();
+                let target = self.address.wrapping_add(self.length).wrapping_add(offset as u16);
+                format!("jr {}{}", condition(test), self.location(target))
+            }
+            Instruction::JPHL() => "jp hl".into(),
+            Instruction::CALL(test) => format!("call {}{}", condition(test), self.target()),
+            Instruction::RET(test) => format!("ret {}", condition(test)).trim_end_matches([',', ' ']).into(),
+            Instruction::RETI() => "reti".into(),
+            Instruction::RST(vector) => format!("rst ${:02X}", vector),
+            Instruction::LD(load) => self.load(load),
+            Instruction::LDHLSP() => format!("ld hl, sp{:+}", self.signed()),
+            Instruction::POP(target) | Instruction::PUSH(target) => {
+                let pair = match target {
+                    StackTarget::BC => "bc",
+                    StackTarget::DE => "de",
+                    StackTarget::HL => "hl",
+                    StackTarget::AF => "af",
+                };
+                let mnemonic = if matches!(instruction, Instruction::POP(_)) { "pop" } else { "push" };
+                format!("{} {}", mnemonic, pair)
+            }
+            Instruction::INC(target) => format!("inc {}", register(target)),
+            Instruction::DEC(target) => format!("dec {}", register(target)),
+            Instruction::INC16(target) => format!("inc {}", register_pair(*target)),
+            Instruction::DEC16(target) => format!("dec {}", register_pair(*target)),
+            Instruction::ADDHL(target) => format!("add hl, {}", register_pair(*target)),
+            Instruction::ADDSP() => format!("add sp, {}", self.signed()),
+            Instruction::ADD(target) => format!("add a, {}", self.arithmetic(target)),
+            Instruction::ADC(target) => format!("adc a, {}", self.arithmetic(target)),
+            Instruction::SUB(target) => format!("sub a, {}", self.arithmetic(target)),
+            Instruction::SBC(target) => format!("sbc a, {}", self.arithmetic(target)),
+            Instruction::AND(target) => format!("and a, {}", self.arithmetic(target)),
+            Instruction::OR(target) => format!("or a, {}", self.arithmetic(target)),
+            Instruction::XOR(target) => format!("xor a, {}", self.arithmetic(target)),
+            Instruction::CP(target) => format!("cp a, {}", self.arithmetic(target)),
+            Instruction::RLCA() => "rlca".into(),
+            Instruction::RRCA() => "rrca".into(),
+            Instruction::RLA() => "rla".into(),
+            Instruction::RRA() => "rra".into(),
+            Instruction::DAA() => "daa".into(),
+            Instruction::CPL() => "cpl".into(),
+            Instruction::SCF() => "scf".into(),
+            Instruction::CCF() => "ccf".into(),
+            Instruction::RLC(target) => format!("rlc {}", register(target)),
+            Instruction::RRC(target) => format!("rrc {}", register(target)),
+            Instruction::RL(target) => format!("rl {}", register(target)),
+            Instruction::RR(target) => format!("rr {}", register(target)),
+            Instruction::SLA(target) => format!("sla {}", register(target)),
+            Instruction::SRA(target) => format!("sra {}", register(target)),
+            Instruction::SWAP(target) => format!("swap {}", register(target)),
+            Instruction::SRL(target) => format!("srl {}", register(target)),
+            Instruction::BIT(bit, target) => format!("bit {}, {}", bit, register(target)),
+            Instruction::RES(bit, target) => format!("res {}, {}", bit, register(target)),
+            Instruction::SET(bit, target) => format!("set {}, {}", bit, register(target)),
+        }
+    }
+}
+
+// the instruction at `address`, read without side effects. opcodes the cpu
+// doesn't have come out as a single `db` byte. with symbols, addresses that
+// have a label are shown by name
+pub fn disassemble<B: Bus + ?Sized>(bus: &B, address: u16, symbols: Option<&Symbols>) -> Disassembly {
+    let mut operands = Operands { bus, symbols, address, length: 0 };
+    let mut opcode = operands.byte();
+    let prefixed = opcode == 0xCB;
+    if prefixed {
+        opcode = operands.byte();
+    }
+    let text = match Instruction::from_byte(opcode, prefixed) {
+        Some(instruction) => operands.text(&instruction),
+        None => format!("db ${:02X}", opcode),
+    };
+    let bytes = (0..operands.length).map(|offset| bus.peek_byte(address.wrapping_add(offset))).collect();
+    let label = label(bus, symbols, address).map(str::to_string);
+    Disassembly { address, bank: bus.bank_at(address), bytes, text, label }
+}
+
+// `count` instructions one after the other
+pub fn disassemble_range<B: Bus + ?Sized>(
+    bus: &B,
+    address: u16,
+    count: usize,
+    symbols: Option<&Symbols>,
+) -> Vec<Disassembly> {
+    let mut address = address;
+    (0..count)
+        .map(|_| {
+            let disassembly = disassemble(bus, address, symbols);
+            address = address.wrapping_add(disassembly.bytes.len() as u16);
+            disassembly
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::Mmu;
+
+    #[test]
+    fn instructions_read_back_as_rgbds_syntax() {
+        let mut mmu = Mmu::new();
+        let code = [
+            0x31, 0xFE, 0xFF, 0xE0, 0x40, 0x2A, 0x20, 0xFC, 0xCB, 0x7C, 0xCD, 0x50, 0x01, 0xF8, 0xFE, 0xC8, 0xD3, 0x10,
+            0x00, 0xFE, 0x12,
+        ];
+        for (offset, &byte) in code.iter().enumerate() {
+            mmu.write_byte(0xC000 + offset as u16, byte);
+        }
+        let text: Vec<String> = disassemble_range(&mmu, 0xC000, 10, None).iter().map(|line| line.text.clone()).collect();
+        assert_eq!(
+            text,
+            [
+                "ld sp, $FFFE",
+                "ldh [$FF40], a",
+                "ld a, [hl+]",
+                "jr nz, $C004",
+                "bit 7, h",
+                "call $0150",
+                "ld hl, sp-2",
+                "ret z",
+                "db $D3",
+                "stop",
+            ]
+        );
+        let last = disassemble(&mmu, 0xC013, None);
+        assert_eq!(last.to_string(), "C013: FE 12     cp a, $12");
+        let symbols = Symbols::parse("00:C003 Loop\n00:0150 Init\n00:FF40 rLCDC\n").unwrap();
+        let lines: Vec<String> =
+            disassemble_range(&mmu, 0xC003, 4, Some(&symbols)).iter().map(|line| line.to_string()).collect();
+        assert_eq!(
+            lines,
+            [
+                "Loop:\nC003: E0 40     ldh [rLCDC], a",
+                "C005: 2A        ld a, [hl+]",
+                "C006: 20 FC     jr nz, $C004",
+                "C008: CB 7C     bit 7, h",
+            ]
+        );
+        assert_eq!(disassemble(&mmu, 0xC00A, Some(&symbols)).text, "call Init");
+    }
+}
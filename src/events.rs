@@ -0,0 +1,135 @@
+use std::fmt;
+
+use crate::cpu::{JOYPAD_INTERRUPT, SERIAL_INTERRUPT, STAT_INTERRUPT, TIMER_INTERRUPT, VBLANK_INTERRUPT};
+use crate::gpu::Mode;
+
+pub type EventSubscriber = Box<dyn FnMut(&HardwareEvent)>;
+
+// the interrupt one IF/IE bit stands for
+pub fn interrupt_name(interrupt: u8) -> &'static str {
+    match interrupt {
+        VBLANK_INTERRUPT => "vblank",
+        STAT_INTERRUPT => "stat",
+        TIMER_INTERRUPT => "timer",
+        SERIAL_INTERRUPT => "serial",
+        JOYPAD_INTERRUPT => "joypad",
+        _ => "?",
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EventKind {
+    // interrupts are their IF bit
+    InterruptRequested(u8),
+    InterruptDispatched(u8),
+    // OAM DMA, or CGB HDMA as a whole general purpose transfer or one hblank block
+    DmaStarted { source: u16, destination: u16, length: u16 },
+    DmaFinished,
+    LcdModeChanged(Mode),
+    TimerOverflow,
+    SerialTransferStarted { outgoing: u8 },
+    SerialTransferFinished { incoming: u8 },
+    RomBankSwitched(usize),
+    WramBankSwitched(u8),
+}
+
+// something the hardware did, at the Mmu's cycle count
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HardwareEvent {
+    pub cycle: u64,
+    pub kind: EventKind,
+}
+
+// 1234567 interrupt requested: stat
+impl fmt::Display for HardwareEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>12} ", self.cycle)?;
+        match self.kind {
+            EventKind::InterruptRequested(interrupt) => write!(f, "interrupt requested: {}", interrupt_name(interrupt)),
+            EventKind::InterruptDispatched(interrupt) => write!(f, "interrupt dispatched: {}", interrupt_name(interrupt)),
+            EventKind::DmaStarted { source, destination, length } => {
+                write!(f, "dma started: {} bytes {:04X} -> {:04X}", length, source, destination)
+            }
+            EventKind::DmaFinished => write!(f, "dma finished"),
+            EventKind::LcdModeChanged(mode) => write!(f, "lcd mode: {:?}", mode),
+            EventKind::TimerOverflow => write!(f, "timer overflow"),
+            EventKind::SerialTransferStarted { outgoing } => write!(f, "serial transfer started: sent {:02X}", outgoing),
+            EventKind::SerialTransferFinished { incoming } => {
+                write!(f, "serial transfer finished: received {:02X}", incoming)
+            }
+            EventKind::RomBankSwitched(bank) => write!(f, "rom bank {:02X}", bank),
+            EventKind::WramBankSwitched(bank) => write!(f, "wram bank {}", bank),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cartridge::test_rom;
+    use crate::cpu::Bus;
+    use crate::gameboy::GameBoy;
+    use crate::mmu::Mmu;
+    use crate::oam_dma::{DMA, OAM_DMA_LENGTH};
+
+    #[test]
+    fn subscribers_hear_what_the_hardware_does() {
+        // MBC1. ld a, 2; ld [$2000], a; ld a, $04; ldh [rIE], a; ld a, $05; ldh [rTAC], a;
+        // ld a, $FF; ldh [rTIMA], a; ei; halt; nop; jr -2. 0x0050 is reti
+        let code = [
+            0x3E, 0x02, 0xEA, 0x00, 0x20, 0x3E, 0x04, 0xE0, 0xFF, 0x3E, 0x05, 0xE0, 0x07, 0x3E, 0xFF, 0xE0, 0x05, 0xFB,
+            0x76, 0x00, 0x18, 0xFE,
+        ];
+        let mut rom = test_rom(&code, 0x01, 0);
+        rom[0x50] = 0xD9;
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let heard = events.clone();
+        gameboy.mmu_mut().set_event_subscriber(move |event| heard.borrow_mut().push(*event));
+        for _ in 0..20 {
+            gameboy.step();
+        }
+        let events = events.borrow();
+        let kinds: Vec<EventKind> = events
+            .iter()
+            .map(|event| event.kind)
+            .filter(|kind| !matches!(kind, EventKind::LcdModeChanged(_)))
+            .collect();
+        assert_eq!(
+            kinds[..4],
+            [
+                EventKind::RomBankSwitched(2),
+                EventKind::TimerOverflow,
+                EventKind::InterruptRequested(TIMER_INTERRUPT),
+                EventKind::InterruptDispatched(TIMER_INTERRUPT),
+            ]
+        );
+        assert!(events.windows(2).all(|pair| pair[0].cycle <= pair[1].cycle));
+        assert!(events.iter().any(|event| event.kind == EventKind::LcdModeChanged(Mode::Drawing)));
+        let requested = events.iter().find(|event| event.kind == EventKind::InterruptRequested(TIMER_INTERRUPT));
+        assert!(requested.unwrap().to_string().ends_with(" interrupt requested: timer"));
+    }
+
+    #[test]
+    fn oam_dma_is_logged_from_the_write_to_the_last_byte() {
+        let mut mmu = Mmu::new();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let heard = events.clone();
+        mmu.set_event_subscriber(move |event| heard.borrow_mut().push(*event));
+        mmu.write_byte(DMA, 0xC0);
+        mmu.tick(4 * OAM_DMA_LENGTH as u32 - 4);
+        mmu.tick(4);
+        let dma: Vec<HardwareEvent> =
+            events.borrow().iter().copied().filter(|event| !matches!(event.kind, EventKind::LcdModeChanged(_))).collect();
+        assert_eq!(
+            dma,
+            [
+                HardwareEvent { cycle: 0, kind: EventKind::DmaStarted { source: 0xC000, destination: 0xFE00, length: 0xA0 } },
+                HardwareEvent { cycle: 4 * OAM_DMA_LENGTH as u64, kind: EventKind::DmaFinished },
+            ]
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+pub const SCREEN_PIXELS: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+pub const FRAME_BYTES: usize = SCREEN_PIXELS * 4;
+
+// encodes RGBA pixels, width x height, as a png
+#[cfg(feature = "png")]
+pub fn write_png<W: Write>(writer: W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    let invalid = |error: png::EncodingError| io::Error::new(io::ErrorKind::InvalidData, error.to_string());
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(invalid)?;
+    writer.write_image_data(rgba).map_err(invalid)
+}
+
+// a finished 160x144 picture, stored as RGBA with 4 bytes per pixel. alongside
+// it is the same picture as DMG shade indices (0 lightest to 3 darkest, one per
+// byte) for hosts that apply their own colours
+#[derive(Clone)]
+pub struct Frame {
+    pub pixels: Box<[u8; FRAME_BYTES]>,
+    pub shades: Box<[u8; SCREEN_PIXELS]>,
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame { pixels: Box::new([0xFF; FRAME_BYTES]), shades: Box::new([0; SCREEN_PIXELS]) }
+    }
+    // every pixel the same colour, shade 0
+    pub fn blank(rgba: [u8; 4]) -> Frame {
+        let mut frame = Frame::new();
+        for pixel in frame.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+        frame
+    }
+    // mix `weight` of another frame's colours into this one, 0.0 leaves it alone
+    pub fn blend(&mut self, other: &Frame, weight: f32) {
+        let weight = weight.clamp(0.0, 1.0);
+        for (byte, &other) in self.pixels.iter_mut().zip(other.pixels.iter()) {
+            *byte = (*byte as f32 * (1.0 - weight) + other as f32 * weight).round() as u8;
+        }
+    }
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+    }
+    pub fn set_shade(&mut self, x: usize, y: usize, shade: u8) {
+        self.shades[y * SCREEN_WIDTH + x] = shade;
+    }
+    pub fn shade(&self, x: usize, y: usize) -> u8 {
+        self.shades[y * SCREEN_WIDTH + x]
+    }
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        [self.pixels[offset], self.pixels[offset + 1], self.pixels[offset + 2], self.pixels[offset + 3]]
+    }
+    // 64 bit FNV-1a over the pixel data, cheap enough to run every frame
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+        for &byte in self.pixels.iter() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+        hash
+    }
+}
+
+// receives every completed frame from the PPU. several sinks can be attached at
+// once so e.g. a window, a recorder and a regression test can all watch the same run
+pub trait FrameSink {
+    fn push_frame(&mut self, frame: &Frame);
+}
+
+// shared sinks let the frontend keep a handle (possibly on another thread)
+// to a sink that has been handed to the PPU
+impl<T: FrameSink> FrameSink for Arc<Mutex<T>> {
+    fn push_frame(&mut self, frame: &Frame) {
+        // a poisoned lock means the other side panicked, frames aren't worth propagating that
+        if let Ok(mut sink) = self.lock() {
+            sink.push_frame(frame);
+        }
+    }
+}
+
+// keeps the most recent `capacity` frames around for the consumer to pick up
+pub struct BufferSink {
+    frames: VecDeque<Frame>,
+    capacity: usize,
+}
+
+impl BufferSink {
+    pub fn new(capacity: usize) -> BufferSink {
+        BufferSink { frames: VecDeque::with_capacity(capacity), capacity: capacity.max(1) }
+    }
+    pub fn latest(&self) -> Option<&Frame> {
+        self.frames.back()
+    }
+    pub fn pop_oldest(&mut self) -> Option<Frame> {
+        self.frames.pop_front()
+    }
+    pub fn drain(&mut self) -> impl Iterator<Item = Frame> + '_ {
+        self.frames.drain(..)
+    }
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl FrameSink for BufferSink {
+    fn push_frame(&mut self, frame: &Frame) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame.clone());
+    }
+}
+
+// streams raw RGBA frames to a writer, e.g. a file or the stdin of
+// `ffmpeg -f rawvideo -pix_fmt rgba -s 160x144 -r 59.73 -i - out.mp4`
+pub struct RecordingSink<W: Write> {
+    writer: W,
+    frames_written: u64,
+    // the first write error stops the recording, the rest of the emulator carries on
+    error: Option<io::Error>,
+}
+
+impl<W: Write> RecordingSink<W> {
+    pub fn new(writer: W) -> RecordingSink<W> {
+        RecordingSink { writer, frames_written: 0, error: None }
+    }
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written
+    }
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> FrameSink for RecordingSink<W> {
+    fn push_frame(&mut self, frame: &Frame) {
+        if self.error.is_some() { return }
+        match self.writer.write_all(&frame.pixels[..]) {
+            Ok(()) => self.frames_written += 1,
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+// only remembers a hash per frame, for headless runs that compare against known output
+pub struct HashSink {
+    hashes: Vec<u64>,
+}
+
+impl HashSink {
+    pub fn new() -> HashSink {
+        HashSink { hashes: Vec::new() }
+    }
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+    pub fn last(&self) -> Option<u64> {
+        self.hashes.last().copied()
+    }
+}
+
+impl FrameSink for HashSink {
+    fn push_frame(&mut self, frame: &Frame) {
+        self.hashes.push(frame.hash());
+    }
+}
@@ -0,0 +1,622 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::apu::CLOCK_RATE;
+use crate::cartridge::MapperKind;
+use crate::frame::FRAME_BYTES;
+use crate::gameboy::{GameBoy, UNCAPPED};
+use crate::joypad::Button;
+use crate::movie::{Movie, MovieError, MovieMode, MovieSession};
+use crate::recorder::RecordingFormat;
+use crate::savestate::{StateSlots, STATE_SLOTS};
+use crate::timing::CYCLES_PER_FRAME;
+
+#[cfg(feature = "audio")]
+pub mod audio;
+#[cfg(feature = "desktop")]
+pub mod desktop;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+mod keymap;
+
+pub use keymap::{Binding, Keymap};
+#[cfg(feature = "toml")]
+pub use keymap::ConfigError;
+
+// how long a frame lasts on real hardware, about 59.73 per second
+pub const FRAME_DURATION: Duration = Duration::from_nanos(CYCLES_PER_FRAME as u64 * 1_000_000_000 / CLOCK_RATE as u64);
+// the speed while fast forward is held, see GameBoy::set_speed
+pub const FAST_FORWARD_SPEED: f32 = 4.0;
+// an uncapped batch where there's no clock to stop it, i.e. in the browser
+const UNCAPPED_FRAMES: u32 = 16;
+// battery ram is written out this often, about every ten seconds
+const SAVE_FLUSH_FRAMES: u32 = 600;
+// a turbo button is held for this many frames, then released for as many
+pub const TURBO_FRAMES: u32 = 2;
+// how far a stick has to be pushed to press a direction, and how far back it
+// has to come to let go
+const STICK_PRESS: f32 = 0.5;
+const STICK_RELEASE: f32 = 0.3;
+
+// something a key can be bound to
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    Button(Button),
+    // held down
+    FastForward,
+    // saves the screen to the next free screenshot-NNN.png
+    Screenshot,
+    // starts or stops recording to the next free recording-NNN file
+    Record,
+    // reads the keymap file given to load_keymap() again
+    ReloadKeymap,
+    // pauses or resumes
+    Pause,
+    // pauses if needed and runs a single frame
+    FrameAdvance,
+    // to and from the current slot
+    SaveState,
+    LoadState,
+    NextSlot,
+    PreviousSlot,
+    // switches a running movie between read-only and read-write
+    MovieReadOnly,
+    Reset,
+    Quit,
+}
+
+impl Action {
+    // as written in keymap files: button names, fast_forward, screenshot...
+    pub fn name(&self) -> String {
+        match self {
+            Action::Button(button) => button.name().to_string(),
+            Action::FastForward => "fast_forward".to_string(),
+            Action::Screenshot => "screenshot".to_string(),
+            Action::Record => "record".to_string(),
+            Action::ReloadKeymap => "reload_keymap".to_string(),
+            Action::Pause => "pause".to_string(),
+            Action::FrameAdvance => "frame_advance".to_string(),
+            Action::SaveState => "save_state".to_string(),
+            Action::LoadState => "load_state".to_string(),
+            Action::NextSlot => "next_slot".to_string(),
+            Action::PreviousSlot => "previous_slot".to_string(),
+            Action::MovieReadOnly => "movie_read_only".to_string(),
+            Action::Reset => "reset".to_string(),
+            Action::Quit => "quit".to_string(),
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Action> {
+        let name = name.to_lowercase();
+        if let Some(button) = Button::from_name(&name) {
+            return Some(Action::Button(button));
+        }
+        [
+            Action::FastForward,
+            Action::Screenshot,
+            Action::Record,
+            Action::ReloadKeymap,
+            Action::Pause,
+            Action::FrameAdvance,
+            Action::SaveState,
+            Action::LoadState,
+            Action::NextSlot,
+            Action::PreviousSlot,
+            Action::MovieReadOnly,
+            Action::Reset,
+            Action::Quit,
+        ]
+        .into_iter()
+            .find(|action| action.name() == name)
+    }
+}
+
+// what every frontend does the same way, whatever draws the window: key handling,
+// hotkeys, fast forward and collecting audio. a frontend feeds it key events, calls
+// run_frame() once per FRAME_DURATION and shows the result
+pub struct Frontend {
+    gameboy: GameBoy,
+    keymap: Keymap,
+    #[cfg(feature = "toml")]
+    keymap_path: Option<PathBuf>,
+    // buttons held through turbo bindings
+    turbo: Vec<Button>,
+    frames: u32,
+    // directions the stick is pressing
+    stick: Vec<Button>,
+    // fractions of a frame owed at speeds that aren't whole numbers
+    pending: f32,
+    // the speed to go back to when fast forward is let go
+    fast_forward: Option<f32>,
+    quit: bool,
+    // where screenshots and recordings go
+    output_dir: PathBuf,
+    recording_format: RecordingFormat,
+    state_slots: StateSlots,
+    slot: u8,
+    // with the file it's written to when recording
+    movie: Option<(MovieSession, Option<PathBuf>)>,
+    // interleaved stereo samples from the frames run since the last take_audio()
+    audio: Vec<f32>,
+}
+
+impl Frontend {
+    pub fn new(gameboy: GameBoy) -> Frontend {
+        Frontend {
+            gameboy,
+            keymap: Keymap::new(),
+            #[cfg(feature = "toml")]
+            keymap_path: None,
+            turbo: Vec::new(),
+            frames: 0,
+            stick: Vec::new(),
+            pending: 0.0,
+            fast_forward: None,
+            quit: false,
+            output_dir: PathBuf::from("."),
+            #[cfg(feature = "gif")]
+            recording_format: RecordingFormat::Gif,
+            #[cfg(not(feature = "gif"))]
+            recording_format: RecordingFormat::Ffmpeg { audio: true },
+            state_slots: StateSlots::new(".", "state"),
+            slot: 0,
+            movie: None,
+            audio: Vec::new(),
+        }
+    }
+    pub fn gameboy(&self) -> &GameBoy {
+        &self.gameboy
+    }
+    pub fn gameboy_mut(&mut self) -> &mut GameBoy {
+        &mut self.gameboy
+    }
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.keymap
+    }
+    // replaces the keymap with the file's, and remembers the file for ReloadKeymap
+    #[cfg(feature = "toml")]
+    pub fn load_keymap(&mut self, path: PathBuf) -> Result<(), ConfigError> {
+        self.keymap = Keymap::load(&path)?;
+        self.keymap_path = Some(path);
+        Ok(())
+    }
+    #[cfg(feature = "toml")]
+    fn reload_keymap(&mut self) {
+        let Some(path) = self.keymap_path.clone() else { return };
+        if let Err(error) = self.load_keymap(path) {
+            eprintln!("couldn't reload the keymap: {}", error);
+        }
+    }
+    #[cfg(not(feature = "toml"))]
+    fn reload_keymap(&mut self) {}
+    pub fn set_output_dir(&mut self, dir: PathBuf) {
+        self.output_dir = dir;
+    }
+    pub fn set_recording_format(&mut self, format: RecordingFormat) {
+        self.recording_format = format;
+    }
+    // where SaveState and LoadState go, by default state.ss0-9 in the working directory
+    pub fn set_state_slots(&mut self, slots: StateSlots) {
+        self.state_slots = slots;
+    }
+    pub fn state_slots(&self) -> &StateSlots {
+        &self.state_slots
+    }
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+    // power cycles and records every frame's input until stop_movie(), which
+    // writes it to `path`
+    pub fn record_movie(&mut self, path: PathBuf) -> std::io::Result<()> {
+        self.stop_movie()?;
+        self.movie = Some((MovieSession::record(&mut self.gameboy), Some(path)));
+        Ok(())
+    }
+    // plays a movie back read-only, MovieReadOnly switches to recording over it
+    pub fn play_movie(&mut self, movie: Movie, path: Option<PathBuf>) -> Result<(), MovieError> {
+        self.stop_movie()?;
+        self.movie = Some((MovieSession::play(&mut self.gameboy, movie)?, path));
+        Ok(())
+    }
+    pub fn movie(&self) -> Option<&MovieSession> {
+        self.movie.as_ref().map(|(session, _)| session)
+    }
+    // ends the movie, saving it if anything was recorded
+    pub fn stop_movie(&mut self) -> std::io::Result<()> {
+        let Some((session, path)) = self.movie.take() else { return Ok(()) };
+        let recorded = session.movie().rerecords > 0 || session.mode() == MovieMode::Recording;
+        match path {
+            Some(path) if recorded => session.into_movie().save(std::io::BufWriter::new(std::fs::File::create(path)?)),
+            _ => Ok(()),
+        }
+    }
+    // finishes what has to be written out before exiting: recordings, movies and
+    // battery ram
+    pub fn shutdown(&mut self) {
+        if let Err(error) = self.gameboy.flush_save() {
+            eprintln!("couldn't write the save file: {}", error);
+        }
+        if let Err(error) = self.gameboy.stop_recording() {
+            eprintln!("couldn't finish the recording: {}", error);
+        }
+        if let Err(error) = self.stop_movie() {
+            eprintln!("couldn't save the movie: {}", error);
+        }
+    }
+    // the first `<name>-NNN.<extension>` that doesn't exist yet
+    fn next_output_path(&self, name: &str, extension: &str) -> PathBuf {
+        (0..)
+            .map(|index| self.output_dir.join(format!("{}-{:03}.{}", name, index, extension)))
+            .find(|path| !path.exists())
+            .unwrap()
+    }
+    // keys and gamepad buttons without a binding are ignored
+    pub fn key(&mut self, key: &str, pressed: bool) {
+        if let Some(binding) = self.keymap.key(key) {
+            self.handle_binding(binding, pressed);
+        }
+    }
+    pub fn pad_button(&mut self, button: &str, pressed: bool) {
+        if let Some(binding) = self.keymap.pad(button) {
+            self.handle_binding(binding, pressed);
+        }
+    }
+    // a stick from -1.0 to 1.0, y up. it tilts accelerometer carts and works the
+    // d-pad for everything else
+    pub fn stick(&mut self, x: f32, y: f32) {
+        let tilt = self.gameboy.mmu().cartridge().is_some_and(|cartridge| cartridge.header.mapper() == MapperKind::Mbc7);
+        if tilt {
+            self.gameboy.set_tilt(x, -y);
+            return;
+        }
+        for (button, value) in [(Button::Right, x), (Button::Left, -x), (Button::Up, y), (Button::Down, -y)] {
+            let held = self.stick.contains(&button);
+            let pressed = if held { value > STICK_RELEASE } else { value > STICK_PRESS };
+            if pressed != held {
+                self.stick.retain(|&other| other != button);
+                if pressed {
+                    self.stick.push(button);
+                }
+                self.gameboy.set_button(button, pressed);
+            }
+        }
+    }
+    // lets go of everything a gamepad was holding, e.g. when it's unplugged
+    pub fn release_pad(&mut self) {
+        self.stick(0.0, 0.0);
+        let buttons = Button::ALL.into_iter().filter(|&button| {
+            self.keymap.pad_bindings().any(|binding| binding.action == Action::Button(button))
+        });
+        for button in buttons.collect::<Vec<_>>() {
+            self.turbo.retain(|&held| held != button);
+            self.gameboy.set_button(button, false);
+        }
+    }
+    fn handle_binding(&mut self, binding: Binding, pressed: bool) {
+        match binding.action {
+            Action::Button(button) if binding.turbo => {
+                self.turbo.retain(|&held| held != button);
+                if pressed {
+                    self.turbo.push(button);
+                } else {
+                    self.gameboy.set_button(button, false);
+                }
+            }
+            action => self.handle(action, pressed),
+        }
+    }
+    pub fn handle(&mut self, action: Action, pressed: bool) {
+        match action {
+            Action::Button(button) => self.gameboy.set_button(button, pressed),
+            Action::FastForward => self.set_fast_forward(pressed),
+            Action::Screenshot if pressed => {
+                if let Err(error) = self.save_screenshot() {
+                    eprintln!("couldn't save a screenshot: {}", error);
+                }
+            }
+            Action::Record if pressed => {
+                if let Err(error) = self.toggle_recording() {
+                    eprintln!("couldn't record: {}", error);
+                }
+            }
+            Action::ReloadKeymap if pressed => self.reload_keymap(),
+            Action::Pause if pressed => {
+                if self.gameboy.paused() {
+                    self.gameboy.resume();
+                } else {
+                    self.gameboy.pause();
+                }
+            }
+            Action::FrameAdvance if pressed => {
+                self.gameboy.pause();
+                self.run_one_frame(false);
+            }
+            Action::SaveState if pressed => self.save_state(),
+            Action::LoadState if pressed => self.load_state(),
+            Action::MovieReadOnly if pressed => {
+                if let Some((session, _)) = &mut self.movie {
+                    let read_only = !session.read_only();
+                    session.set_read_only(read_only);
+                    self.show_message(if read_only { "Movie read-only" } else { "Movie read-write" });
+                }
+            }
+            Action::Reset if pressed && self.movie.is_some() => {
+                if let Some((session, _)) = &mut self.movie {
+                    session.request_reset();
+                }
+            }
+            Action::NextSlot if pressed => self.select_slot((self.slot + 1) % STATE_SLOTS),
+            Action::PreviousSlot if pressed => self.select_slot((self.slot + STATE_SLOTS - 1) % STATE_SLOTS),
+            Action::Reset if pressed => self.gameboy.reset(),
+            Action::Quit if pressed => self.quit = true,
+            _ => {}
+        }
+    }
+    // the slots announce how it went themselves. states made during a movie carry
+    // their place in it, so those are written and announced here
+    fn save_state(&mut self) {
+        let Some((session, _)) = &self.movie else {
+            if let Err(error) = self.state_slots.save(&mut self.gameboy, self.slot) {
+                eprintln!("couldn't save state {}: {}", self.slot, error);
+            }
+            return;
+        };
+        let path = self.state_slots.path(self.slot);
+        let message = match session.save_state(&self.gameboy).and_then(|state| std::fs::write(path, state)) {
+            Ok(()) => format!("State {} saved", self.slot),
+            Err(error) => format!("Couldn't save state {}: {}", self.slot, error),
+        };
+        self.show_message(&message);
+    }
+    fn load_state(&mut self) {
+        let Some((session, _)) = &mut self.movie else {
+            if let Err(error) = self.state_slots.load(&mut self.gameboy, self.slot) {
+                eprintln!("couldn't load state {}: {}", self.slot, error);
+            }
+            return;
+        };
+        let result: Result<(), Box<dyn std::error::Error>> = std::fs::read(self.state_slots.path(self.slot))
+            .map_err(Into::into)
+            .and_then(|data| Ok(session.load_state(&mut self.gameboy, &data)?));
+        let message = match result {
+            Ok(()) => format!("State {} loaded", self.slot),
+            Err(error) => format!("Couldn't load state {}: {}", self.slot, error),
+        };
+        self.show_message(&message);
+    }
+    pub fn select_slot(&mut self, slot: u8) {
+        self.slot = slot % STATE_SLOTS;
+        let used = if self.state_slots.path(self.slot).exists() { "" } else { " (empty)" };
+        self.show_message(&format!("Slot {}{}", self.slot, used));
+    }
+    // on screen, and on stderr for frontends that don't show the OSD
+    fn show_message(&mut self, message: &str) {
+        eprintln!("{}", message);
+        self.gameboy.show_message(message);
+    }
+    #[cfg(feature = "png")]
+    fn save_screenshot(&self) -> std::io::Result<()> {
+        let path = self.next_output_path("screenshot", "png");
+        self.gameboy.screenshot(path, crate::scaler::Filter::Nearest(1))
+    }
+    #[cfg(not(feature = "png"))]
+    fn save_screenshot(&self) -> std::io::Result<()> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "screenshots need the png feature"))
+    }
+    fn toggle_recording(&mut self) -> std::io::Result<()> {
+        if self.gameboy.recording() {
+            return self.gameboy.stop_recording();
+        }
+        let extension = match self.recording_format {
+            #[cfg(feature = "gif")]
+            RecordingFormat::Gif => "gif",
+            RecordingFormat::Ffmpeg { .. } => "mp4",
+        };
+        let path = self.next_output_path("recording", extension);
+        self.gameboy.start_recording(path, self.recording_format)
+    }
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+    pub fn fast_forwarding(&self) -> bool {
+        self.fast_forward.is_some()
+    }
+    fn set_fast_forward(&mut self, on: bool) {
+        if on && self.fast_forward.is_none() {
+            let speed = self.gameboy.speed();
+            self.fast_forward = Some(speed);
+            self.gameboy.set_speed(speed.max(FAST_FORWARD_SPEED));
+        } else if !on && let Some(speed) = self.fast_forward.take() {
+            self.gameboy.set_speed(speed);
+        }
+    }
+    // audio is only collected with a sample rate set
+    pub fn set_sample_rate(&mut self, rate: Option<u32>) {
+        self.gameboy.set_sample_rate(rate);
+    }
+    // the frame to show next, running as many as the game's speed calls for in
+    // one FRAME_DURATION: none some of the time when slowed down, several when sped
+    // up, keeping the audio of the last one only so the sound stays at its normal
+    // pitch. uncapped runs frames for a FRAME_DURATION of real time, and paused none
+    pub fn run_frame(&mut self) -> &[u8; FRAME_BYTES] {
+        if self.gameboy.paused() {
+            return self.gameboy.frame();
+        }
+        let speed = self.gameboy.speed();
+        if speed == UNCAPPED {
+            self.pending = 0.0;
+            return self.run_uncapped();
+        }
+        self.pending += speed;
+        let frames = self.pending as u32;
+        self.pending -= frames as f32;
+        for frame in 0..frames {
+            self.run_one_frame(frame + 1 == frames);
+        }
+        self.gameboy.frame()
+    }
+    fn run_uncapped(&mut self) -> &[u8; FRAME_BYTES] {
+        // std has no clock in the browser
+        if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            for _ in 0..UNCAPPED_FRAMES {
+                self.run_one_frame(false);
+            }
+        } else {
+            let start = Instant::now();
+            while start.elapsed() < FRAME_DURATION {
+                self.run_one_frame(false);
+            }
+        }
+        self.gameboy.frame()
+    }
+    fn run_one_frame(&mut self, keep_audio: bool) {
+        let turbo_down = (self.frames / TURBO_FRAMES).is_multiple_of(2);
+        for &button in &self.turbo {
+            self.gameboy.set_button(button, turbo_down);
+        }
+        self.frames = self.frames.wrapping_add(1);
+        if let Some((session, _)) = &mut self.movie {
+            session.before_frame(&mut self.gameboy);
+        }
+        self.gameboy.advance_frame();
+        self.collect_audio(keep_audio);
+        // a failure is already on the OSD
+        if self.frames.is_multiple_of(SAVE_FLUSH_FRAMES)
+            && let Err(error) = self.gameboy.flush_save() {
+            eprintln!("couldn't write the save file: {}", error);
+        }
+    }
+    fn collect_audio(&mut self, keep: bool) {
+        let mut buffer = [0.0; 1024];
+        loop {
+            let count = self.gameboy.fill_audio(&mut buffer);
+            if count == 0 { break }
+            if keep {
+                self.audio.extend_from_slice(&buffer[..count]);
+            }
+        }
+    }
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+    use crate::gameboy::MIN_SPEED;
+
+    // jr -2
+    const SPIN: [u8; 2] = [0x18, 0xFE];
+
+    #[test]
+    fn keys_drive_buttons_and_hotkeys() {
+        let rom = test_rom(&SPIN, 0x00, 0);
+        let mut frontend = Frontend::new(GameBoy::new(rom).unwrap());
+        frontend.key("Z", true);
+        assert!(frontend.gameboy().mmu().joypad.pressed(Button::A));
+        frontend.key("z", false);
+        assert!(!frontend.gameboy().mmu().joypad.pressed(Button::A));
+        frontend.keymap_mut().bind("space", Action::FastForward);
+        frontend.key("space", true);
+        assert!(frontend.fast_forwarding());
+        frontend.key("p", true);
+        assert!(frontend.gameboy().paused());
+        let frames = frontend.frames;
+        frontend.run_frame();
+        frontend.key("n", true);
+        assert_eq!(frontend.frames, frames + 1);
+        frontend.key("p", true);
+        assert!(!frontend.gameboy().paused());
+        frontend.key("q", true);
+        assert!(!frontend.quit_requested());
+        frontend.key("escape", true);
+        assert!(frontend.quit_requested());
+    }
+
+    #[test]
+    fn hotkeys_save_and_load_the_current_slot() {
+        let rom = test_rom(&SPIN, 0x00, 0);
+        let dir = std::env::temp_dir().join(format!("gb-emulator-frontend-slots-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut frontend = Frontend::new(GameBoy::new(rom).unwrap());
+        frontend.set_state_slots(StateSlots::new(&dir, "game"));
+        frontend.key("f6", true);
+        assert_eq!(frontend.slot(), STATE_SLOTS - 1);
+        frontend.key("f7", true);
+        frontend.key("f2", true);
+        assert!(frontend.state_slots().path(0).exists());
+        frontend.run_frame();
+        frontend.key("f4", true);
+        assert_eq!(frontend.gameboy().mmu().gpu.osd.messages().last(), Some("State 0 loaded"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn turbo_bindings_pulse_the_button() {
+        let rom = test_rom(&SPIN, 0x00, 0);
+        let mut frontend = Frontend::new(GameBoy::new(rom).unwrap());
+        frontend.keymap_mut().bind_key("k", Binding::turbo(Action::Button(Button::B)));
+        frontend.key("k", true);
+        let mut held = Vec::new();
+        for _ in 0..6 {
+            frontend.run_frame();
+            held.push(frontend.gameboy().mmu().joypad.pressed(Button::B));
+        }
+        assert_eq!(held, [true, true, false, false, true, true]);
+        frontend.key("k", false);
+        assert!(!frontend.gameboy().mmu().joypad.pressed(Button::B));
+    }
+
+    #[test]
+    fn speed_sets_the_frames_run_per_frame_shown() {
+        let rom = test_rom(&SPIN, 0x00, 0);
+        let mut frontend = Frontend::new(GameBoy::new(rom).unwrap());
+        frontend.set_sample_rate(Some(48000));
+        let run = |frontend: &mut Frontend| {
+            let before = frontend.frames;
+            frontend.run_frame();
+            frontend.frames - before
+        };
+        frontend.gameboy_mut().set_speed(0.5);
+        assert_eq!(frontend.gameboy().mmu().apu.sample_rate(), Some(96000));
+        assert_eq!([run(&mut frontend), run(&mut frontend), run(&mut frontend)], [0, 1, 0]);
+        frontend.gameboy_mut().set_speed(2.5);
+        assert_eq!(frontend.gameboy().mmu().apu.sample_rate(), Some(48000));
+        assert_eq!([run(&mut frontend), run(&mut frontend)], [3, 2]);
+        // fast forward goes back to whatever the speed was
+        frontend.key("tab", true);
+        assert_eq!(frontend.gameboy().speed(), FAST_FORWARD_SPEED);
+        frontend.key("tab", false);
+        assert_eq!(frontend.gameboy().speed(), 2.5);
+        frontend.gameboy_mut().set_speed(UNCAPPED);
+        assert_eq!(frontend.gameboy().mmu().apu.sample_rate(), None);
+        assert!(run(&mut frontend) > 0);
+        // whatever a frontend passes in
+        frontend.gameboy_mut().set_speed(0.0);
+        assert_eq!(frontend.gameboy().speed(), MIN_SPEED);
+        frontend.gameboy_mut().set_speed(f32::NAN);
+        assert_eq!(frontend.gameboy().speed(), 1.0);
+    }
+
+    #[test]
+    fn the_stick_works_the_dpad() {
+        let rom = test_rom(&SPIN, 0x00, 0);
+        let mut frontend = Frontend::new(GameBoy::new(rom).unwrap());
+        let pressed = |frontend: &Frontend, button| frontend.gameboy().mmu().joypad.pressed(button);
+        frontend.stick(0.4, 0.9);
+        assert!(pressed(&frontend, Button::Up) && !pressed(&frontend, Button::Right));
+        // past the press threshold, then held until it's well back
+        frontend.stick(0.6, 0.4);
+        assert!(pressed(&frontend, Button::Up) && pressed(&frontend, Button::Right));
+        frontend.stick(0.6, 0.2);
+        assert!(!pressed(&frontend, Button::Up));
+        frontend.pad_button("south", true);
+        assert!(pressed(&frontend, Button::B));
+        frontend.release_pad();
+        assert!(!pressed(&frontend, Button::Right) && !pressed(&frontend, Button::B));
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+// queued samples past this much (interleaved, so about 100ms at 48kHz) are
+// dropped rather than let the sound lag further behind the picture
+const MAX_QUEUED: usize = 48_000 * 2 / 10;
+
+#[derive(Debug)]
+pub struct AudioError(pub String);
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't open audio output: {}", self.0)
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+// the default output device playing interleaved stereo samples as they're queued,
+// with silence whenever the emulator falls behind
+pub struct AudioOutput {
+    // playback stops when this is dropped
+    _stream: cpal::Stream,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+}
+
+impl AudioOutput {
+    pub fn open() -> Result<AudioOutput, AudioError> {
+        let error = |error: &dyn fmt::Display| AudioError(error.to_string());
+        let device = cpal::default_host().default_output_device().ok_or_else(|| AudioError("no output device".to_string()))?;
+        let sample_rate = device.default_output_config().map_err(|e| error(&e))?.sample_rate().0;
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let playing = queue.clone();
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |out: &mut [f32], _| {
+                    let mut queue = playing.lock().unwrap();
+                    for sample in out.iter_mut() {
+                        *sample = queue.pop_front().unwrap_or(0.0);
+                    }
+                },
+                |error| eprintln!("audio output error: {}", error),
+                None,
+            )
+            .map_err(|e| error(&e))?;
+        stream.play().map_err(|e| error(&e))?;
+        Ok(AudioOutput { _stream: stream, queue, sample_rate })
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    pub fn queue(&self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+        let excess = queue.len().saturating_sub(MAX_QUEUED);
+        queue.drain(..excess);
+    }
+}
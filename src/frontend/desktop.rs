@@ -0,0 +1,124 @@
+use std::fmt;
+use std::time::Instant;
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::dpi::LogicalSize;
+use winit::error::OsError;
+use winit::event::{ElementState, Event, KeyboardInput, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::WindowBuilder;
+
+use crate::frame::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::frontend::{Frontend, FRAME_DURATION};
+#[cfg(feature = "audio")]
+use crate::frontend::audio::AudioOutput;
+#[cfg(feature = "gamepad")]
+use crate::frontend::gamepad::Gamepads;
+
+// the window starts out at this multiple of the screen size
+const SCALE: u32 = 3;
+
+#[derive(Debug)]
+pub enum DesktopError {
+    Window(OsError),
+    Pixels(pixels::Error),
+}
+
+impl fmt::Display for DesktopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DesktopError::Window(error) => write!(f, "couldn't open a window: {}", error),
+            DesktopError::Pixels(error) => write!(f, "couldn't set up rendering: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for DesktopError {}
+
+// a window drawn through pixels (wgpu) with no system libraries beyond the
+// graphics driver. only returns if setting up fails, the process exits when
+// the window is closed or the frontend asks to quit
+pub fn run(mut frontend: Frontend) -> Result<(), DesktopError> {
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("gb-emulator")
+        .with_inner_size(LogicalSize::new(SCREEN_WIDTH as u32 * SCALE, SCREEN_HEIGHT as u32 * SCALE))
+        .with_min_inner_size(LogicalSize::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32))
+        .build(&event_loop)
+        .map_err(DesktopError::Window)?;
+    let size = window.inner_size();
+    let surface = SurfaceTexture::new(size.width, size.height, &window);
+    let mut pixels = Pixels::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, surface).map_err(DesktopError::Pixels)?;
+
+    #[cfg(feature = "audio")]
+    let audio = match AudioOutput::open() {
+        Ok(audio) => {
+            frontend.set_sample_rate(Some(audio.sample_rate()));
+            Some(audio)
+        }
+        Err(error) => {
+            eprintln!("{}, running without sound", error);
+            None
+        }
+    };
+
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = Gamepads::new()
+        .inspect_err(|error| eprintln!("{}", error))
+        .ok();
+
+    let mut next_frame = Instant::now();
+    event_loop.run(move |event, _, control_flow| {
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(size) => {
+                    if let Err(error) = pixels.resize_surface(size.width, size.height) {
+                        eprintln!("couldn't resize: {}", error);
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+                WindowEvent::KeyboardInput { input: KeyboardInput { virtual_keycode: Some(key), state, .. }, .. } => {
+                    let name = format!("{:?}", key).to_lowercase();
+                    frontend.key(&name, state == ElementState::Pressed);
+                    if frontend.quit_requested() {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+                _ => {}
+            },
+            Event::MainEventsCleared => {
+                #[cfg(feature = "gamepad")]
+                if let Some(gamepads) = &mut gamepads {
+                    gamepads.poll(&mut frontend);
+                }
+                let now = Instant::now();
+                if now >= next_frame {
+                    let frame = frontend.run_frame();
+                    pixels.frame_mut().copy_from_slice(frame);
+                    #[cfg(feature = "audio")]
+                    if let Some(audio) = &audio {
+                        audio.queue(&frontend.take_audio());
+                    }
+                    window.request_redraw();
+                    next_frame += FRAME_DURATION;
+                    // after a stall (a dragged window, a breakpoint) don't race to catch up
+                    if next_frame < now {
+                        next_frame = now + FRAME_DURATION;
+                    }
+                }
+                if *control_flow != ControlFlow::Exit {
+                    *control_flow = ControlFlow::WaitUntil(next_frame);
+                }
+            }
+            Event::LoopDestroyed => frontend.shutdown(),
+            Event::RedrawRequested(_) => {
+                if let Err(error) = pixels.render() {
+                    eprintln!("couldn't draw: {}", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => {}
+        }
+    });
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+use gilrs::{Axis, EventType, Gilrs};
+
+use crate::frontend::Frontend;
+
+#[derive(Debug)]
+pub struct GamepadError(pub String);
+
+impl fmt::Display for GamepadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't look for gamepads: {}", self.0)
+    }
+}
+
+impl std::error::Error for GamepadError {}
+
+// every connected controller, plugged in before or after starting. buttons go
+// through the keymap's gamepad bindings under gilrs' names (south, dpadup...),
+// the left stick through Frontend::stick
+pub struct Gamepads {
+    gilrs: Gilrs,
+    // last left stick position, axes arrive one at a time
+    stick: (f32, f32),
+}
+
+impl Gamepads {
+    pub fn new() -> Result<Gamepads, GamepadError> {
+        let gilrs = Gilrs::new().map_err(|error| GamepadError(error.to_string()))?;
+        Ok(Gamepads { gilrs, stick: (0.0, 0.0) })
+    }
+    // hands everything that happened since the last call to the frontend
+    pub fn poll(&mut self, frontend: &mut Frontend) {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => frontend.pad_button(&button_name(button), true),
+                EventType::ButtonReleased(button, _) => frontend.pad_button(&button_name(button), false),
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => {
+                    self.stick.0 = value;
+                    frontend.stick(self.stick.0, self.stick.1);
+                }
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => {
+                    self.stick.1 = value;
+                    frontend.stick(self.stick.0, self.stick.1);
+                }
+                EventType::Connected => {
+                    eprintln!("gamepad connected: {}", self.gilrs.gamepad(event.id).name());
+                }
+                EventType::Disconnected => {
+                    self.stick = (0.0, 0.0);
+                    frontend.release_pad();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn button_name(button: gilrs::Button) -> String {
+    format!("{:?}", button).to_lowercase()
+}
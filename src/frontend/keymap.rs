@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use crate::frontend::Action;
+use crate::joypad::Button;
+
+// an action plus whether holding it fires it repeatedly. only buttons can turbo
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Binding {
+    pub action: Action,
+    pub turbo: bool,
+}
+
+impl Binding {
+    pub fn new(action: Action) -> Binding {
+        Binding { action, turbo: false }
+    }
+    pub fn turbo(action: Action) -> Binding {
+        Binding { action, turbo: true }
+    }
+}
+
+// keyboard keys and gamepad buttons to actions, any number of either per action.
+// key names are lowercase and spelled as winit spells them (z, return, back, up),
+// gamepad buttons as gilrs does (south, east, dpadup, start). other frontends
+// translate theirs
+pub struct Keymap {
+    keys: HashMap<String, Binding>,
+    pad: HashMap<String, Binding>,
+}
+
+impl Keymap {
+    // arrows for the d-pad, z/x for A/B, return/back for start/select, and on a
+    // gamepad A and B where a Game Boy has them (right and bottom)
+    pub fn new() -> Keymap {
+        let mut keymap = Keymap::empty();
+        for (key, action) in [
+            ("up", Action::Button(Button::Up)),
+            ("down", Action::Button(Button::Down)),
+            ("left", Action::Button(Button::Left)),
+            ("right", Action::Button(Button::Right)),
+            ("z", Action::Button(Button::A)),
+            ("x", Action::Button(Button::B)),
+            ("return", Action::Button(Button::Start)),
+            ("back", Action::Button(Button::Select)),
+            ("tab", Action::FastForward),
+            ("f1", Action::Reset),
+            ("f2", Action::SaveState),
+            ("f4", Action::LoadState),
+            ("f6", Action::PreviousSlot),
+            ("f7", Action::NextSlot),
+            ("f8", Action::MovieReadOnly),
+            ("f5", Action::ReloadKeymap),
+            ("pause", Action::Pause),
+            ("p", Action::Pause),
+            ("n", Action::FrameAdvance),
+            ("f10", Action::Record),
+            ("f12", Action::Screenshot),
+            ("escape", Action::Quit),
+        ] {
+            keymap.bind(key, action);
+        }
+        for (button, action) in [
+            ("dpadup", Action::Button(Button::Up)),
+            ("dpaddown", Action::Button(Button::Down)),
+            ("dpadleft", Action::Button(Button::Left)),
+            ("dpadright", Action::Button(Button::Right)),
+            ("east", Action::Button(Button::A)),
+            ("south", Action::Button(Button::B)),
+            ("start", Action::Button(Button::Start)),
+            ("select", Action::Button(Button::Select)),
+            ("righttrigger", Action::FastForward),
+        ] {
+            keymap.bind_pad(button, Binding::new(action));
+        }
+        keymap
+    }
+    pub fn empty() -> Keymap {
+        Keymap { keys: HashMap::new(), pad: HashMap::new() }
+    }
+    pub fn bind(&mut self, key: &str, action: Action) {
+        self.bind_key(key, Binding::new(action));
+    }
+    pub fn bind_key(&mut self, key: &str, binding: Binding) {
+        self.keys.insert(key.to_lowercase(), binding);
+    }
+    pub fn bind_pad(&mut self, button: &str, binding: Binding) {
+        self.pad.insert(button.to_lowercase(), binding);
+    }
+    pub fn unbind(&mut self, key: &str) {
+        self.keys.remove(&key.to_lowercase());
+    }
+    pub fn unbind_pad(&mut self, button: &str) {
+        self.pad.remove(&button.to_lowercase());
+    }
+    pub fn key(&self, key: &str) -> Option<Binding> {
+        self.keys.get(&key.to_lowercase()).copied()
+    }
+    pub fn pad(&self, button: &str) -> Option<Binding> {
+        self.pad.get(&button.to_lowercase()).copied()
+    }
+    pub fn pad_bindings(&self) -> impl Iterator<Item = &Binding> {
+        self.pad.values()
+    }
+    pub fn action(&self, key: &str) -> Option<Action> {
+        self.key(key).map(|binding| binding.action)
+    }
+}
+
+#[cfg(feature = "toml")]
+pub use config::ConfigError;
+
+// keymaps as toml, one table per device. a binding is an action name or a table
+// with turbo set:
+//   [keyboard]
+//   z = "a"
+//   a = { action = "a", turbo = true }
+//   tab = "fast_forward"
+//   [gamepad]
+//   east = "a"
+// a missing table keeps the default bindings for that device
+#[cfg(feature = "toml")]
+mod config {
+    use std::collections::{BTreeMap, HashMap};
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{Binding, Keymap};
+    use crate::frontend::Action;
+
+    #[derive(Debug)]
+    pub enum ConfigError {
+        Io(io::Error),
+        Parse(String),
+    }
+
+    impl fmt::Display for ConfigError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                ConfigError::Io(error) => write!(f, "{}", error),
+                ConfigError::Parse(message) => write!(f, "{}", message),
+            }
+        }
+    }
+
+    impl std::error::Error for ConfigError {}
+
+    impl From<io::Error> for ConfigError {
+        fn from(error: io::Error) -> Self {
+            ConfigError::Io(error)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Entry {
+        Action(String),
+        Full { action: String, #[serde(default)] turbo: bool },
+    }
+
+    // BTreeMaps so saved files list the bindings in a stable order
+    #[derive(Serialize, Deserialize)]
+    struct File {
+        keyboard: Option<BTreeMap<String, Entry>>,
+        gamepad: Option<BTreeMap<String, Entry>>,
+    }
+
+    fn parse_bindings(entries: BTreeMap<String, Entry>) -> Result<HashMap<String, Binding>, ConfigError> {
+        entries
+            .into_iter()
+            .map(|(name, entry)| {
+                let (action, turbo) = match entry {
+                    Entry::Action(action) => (action, false),
+                    Entry::Full { action, turbo } => (action, turbo),
+                };
+                let action = Action::from_name(&action)
+                    .ok_or_else(|| ConfigError::Parse(format!("{}: unknown action {:?}", name, action)))?;
+                Ok((name.to_lowercase(), Binding { action, turbo }))
+            })
+            .collect()
+    }
+
+    fn entries(bindings: &HashMap<String, Binding>) -> BTreeMap<String, Entry> {
+        bindings
+            .iter()
+            .map(|(name, binding)| {
+                let action = binding.action.name();
+                let entry = if binding.turbo { Entry::Full { action, turbo: true } } else { Entry::Action(action) };
+                (name.clone(), entry)
+            })
+            .collect()
+    }
+
+    impl Keymap {
+        pub fn from_toml(text: &str) -> Result<Keymap, ConfigError> {
+            let file: File = toml::from_str(text).map_err(|error| ConfigError::Parse(error.to_string()))?;
+            let mut keymap = Keymap::new();
+            if let Some(keyboard) = file.keyboard {
+                keymap.keys = parse_bindings(keyboard)?;
+            }
+            if let Some(gamepad) = file.gamepad {
+                keymap.pad = parse_bindings(gamepad)?;
+            }
+            Ok(keymap)
+        }
+        pub fn to_toml(&self) -> String {
+            let file = File { keyboard: Some(entries(&self.keys)), gamepad: Some(entries(&self.pad)) };
+            toml::to_string(&file).expect("keymaps always serialize")
+        }
+        pub fn load(path: impl AsRef<Path>) -> Result<Keymap, ConfigError> {
+            Keymap::from_toml(&fs::read_to_string(path)?)
+        }
+        pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+            fs::write(path, self.to_toml())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::joypad::Button;
+
+        #[test]
+        fn bindings_round_trip_through_toml() {
+            let keymap = Keymap::from_toml(
+                "[keyboard]\nz = \"a\"\nj = \"a\"\nk = { action = \"b\", turbo = true }\nTab = \"fast_forward\"\n",
+            )
+            .unwrap();
+            assert_eq!(keymap.action("z"), Some(Action::Button(Button::A)));
+            assert_eq!(keymap.action("j"), Some(Action::Button(Button::A)));
+            assert_eq!(keymap.key("k"), Some(Binding::turbo(Action::Button(Button::B))));
+            assert_eq!(keymap.action("tab"), Some(Action::FastForward));
+            // only what the file lists
+            assert_eq!(keymap.action("x"), None);
+            // gamepad defaults stay
+            assert_eq!(keymap.pad("east"), Some(Binding::new(Action::Button(Button::A))));
+            let reloaded = Keymap::from_toml(&keymap.to_toml()).unwrap();
+            assert_eq!(reloaded.key("k"), keymap.key("k"));
+            assert_eq!(reloaded.pad("south"), keymap.pad("south"));
+            assert!(Keymap::from_toml("[keyboard]\nz = \"jump\"").is_err());
+        }
+    }
+}
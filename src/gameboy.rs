@@ -0,0 +1,705 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cpu::{Bus, CPU};
+use crate::debugger::Debugger;
+use crate::frame::FRAME_BYTES;
+use crate::joypad::Button;
+use crate::mmu::{BootRomSizeError, Mmu, CGB_BOOT_ROM_SIZE, DMG_BOOT_ROM_SIZE};
+use crate::model::Model;
+use crate::recorder::{Recorder, RecordingFormat};
+use crate::reset::ResetKind;
+use crate::savestate::{StateError, StateInfo, StateWriter, INFO_SECTION};
+#[cfg(feature = "png")]
+use crate::frame::write_png;
+#[cfg(feature = "png")]
+use crate::scaler::{Filter, Scaler};
+use crate::sgb::Sgb;
+use crate::timing::{unix_now, CYCLES_PER_FRAME};
+
+// for set_speed(): as many frames as the host can manage, without sound
+pub const UNCAPPED: f32 = f32::INFINITY;
+// the slowest set_speed() goes, lower speeds (including 0) are raised to it
+pub const MIN_SPEED: f32 = 0.1;
+
+#[derive(Debug)]
+pub enum GameBoyError {
+    Cartridge(CartridgeError),
+    BootRom(BootRomSizeError),
+}
+
+impl fmt::Display for GameBoyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameBoyError::Cartridge(error) => write!(f, "{}", error),
+            GameBoyError::BootRom(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for GameBoyError {}
+
+impl From<CartridgeError> for GameBoyError {
+    fn from(error: CartridgeError) -> Self {
+        GameBoyError::Cartridge(error)
+    }
+}
+
+impl From<BootRomSizeError> for GameBoyError {
+    fn from(error: BootRomSizeError) -> Self {
+        GameBoyError::BootRom(error)
+    }
+}
+
+// sets up a GameBoy. without a model the cartridge's CGB flag picks one, and
+// without a boot rom for that model the machine starts where the boot rom would
+// have left it
+pub struct GameBoyBuilder {
+    // a rom that doesn't parse is reported by build()
+    cartridge: Result<Cartridge, CartridgeError>,
+    model: Option<Model>,
+    boot_roms: Vec<Vec<u8>>,
+    // the work ram seed when nothing may depend on the host, see deterministic()
+    deterministic: Option<u32>,
+}
+
+impl GameBoyBuilder {
+    pub fn new(rom: Vec<u8>) -> GameBoyBuilder {
+        GameBoyBuilder::with_cartridge(Cartridge::from_bytes(rom))
+    }
+    fn with_cartridge(cartridge: Result<Cartridge, CartridgeError>) -> GameBoyBuilder {
+        GameBoyBuilder { cartridge, model: None, boot_roms: Vec::new(), deterministic: None }
+    }
+    pub fn model(mut self, model: Model) -> GameBoyBuilder {
+        self.model = Some(model);
+        self
+    }
+    // go back to picking the model from the cartridge
+    pub fn auto_model(mut self) -> GameBoyBuilder {
+        self.model = None;
+        self
+    }
+    // can be given once for DMG and once for CGB, the one matching the model runs
+    pub fn boot_rom(mut self, rom: Vec<u8>) -> GameBoyBuilder {
+        self.boot_roms.push(rom);
+        self
+    }
+    // the same rom and inputs then always give the same frames: cartridge clocks
+    // count emulated cycles instead of host time and work ram starts out as the
+    // power-on noise for `seed`. host links such as a tcp serial cable are still
+    // up to the frontend
+    pub fn deterministic(mut self, seed: u32) -> GameBoyBuilder {
+        self.deterministic = Some(seed);
+        self
+    }
+    pub fn build(self) -> Result<GameBoy, GameBoyError> {
+        let mut cartridge = self.cartridge?;
+        if self.deterministic.is_some() {
+            cartridge.set_emulated_clock(true);
+        }
+        let model = self.model.unwrap_or_else(|| Model::for_cartridge(&cartridge.header));
+        let mut mmu = Mmu::new();
+        mmu.set_model(model);
+        mmu.insert_cartridge(cartridge);
+        if let Some(seed) = self.deterministic {
+            mmu.set_ram_seed(seed);
+        }
+        let mut boot_rom = None;
+        for rom in self.boot_roms {
+            if rom.len() != DMG_BOOT_ROM_SIZE && rom.len() != CGB_BOOT_ROM_SIZE {
+                return Err(BootRomSizeError(rom.len()).into());
+            }
+            if rom.len() == model.boot_rom_size() {
+                boot_rom = Some(rom);
+            }
+        }
+        let mut cpu = CPU::new(mmu);
+        cpu.set_model(model);
+        match boot_rom {
+            Some(rom) => {
+                cpu.bus_mut().load_boot_rom(rom)?;
+                cpu.start_boot_rom();
+            }
+            None => cpu.skip_boot_rom(model),
+        }
+        let recorder = Arc::new(Mutex::new(Recorder::new()));
+        cpu.bus_mut().gpu.add_frame_sink(Box::new(recorder.clone()));
+        Ok(GameBoy {
+            cpu,
+            overshoot: 0,
+            recorder,
+            paused: false,
+            speed: 1.0,
+            frame_skip: 0,
+            frames: 0,
+            sample_rate: None,
+            debugger: None,
+        })
+    }
+}
+
+// the whole machine with a cartridge in, started the way the boot rom would
+// leave it. this is what frontends drive; cpu() and mmu() reach the components
+// for anything not covered here
+pub struct GameBoy {
+    cpu: CPU<Mmu>,
+    // cycles run_cycles went past its target last time, taken off the next call
+    overshoot: u32,
+    // attached to the GPU as a frame sink for good
+    recorder: Arc<Mutex<Recorder>>,
+    paused: bool,
+    speed: f32,
+    frame_skip: u32,
+    // frames run, for frame skipping
+    frames: u32,
+    // the rate the frontend plays at, the APU's differs when slowed down
+    sample_rate: Option<u32>,
+    debugger: Option<Debugger>,
+}
+
+impl GameBoy {
+    // with the model picked from the cartridge and no boot rom, see GameBoyBuilder
+    pub fn new(rom: Vec<u8>) -> Result<GameBoy, GameBoyError> {
+        GameBoyBuilder::new(rom).build()
+    }
+    pub fn builder(rom: Vec<u8>) -> GameBoyBuilder {
+        GameBoyBuilder::new(rom)
+    }
+    pub fn with_cartridge(cartridge: Cartridge) -> GameBoyBuilder {
+        GameBoyBuilder::with_cartridge(Ok(cartridge))
+    }
+    pub fn model(&self) -> Model {
+        self.mmu().model()
+    }
+    pub fn cpu(&self) -> &CPU<Mmu> {
+        &self.cpu
+    }
+    pub fn cpu_mut(&mut self) -> &mut CPU<Mmu> {
+        &mut self.cpu
+    }
+    pub fn mmu(&self) -> &Mmu {
+        self.cpu.bus()
+    }
+    pub fn mmu_mut(&mut self) -> &mut Mmu {
+        self.cpu.bus_mut()
+    }
+    // one instruction, or an interrupt dispatch, returning the clock cycles it took
+    pub fn step(&mut self) -> u32 {
+        let cycles = self.cpu.step();
+        // counted in the PPU's clock, a CGB in double speed gets two cpu cycles to each
+        if self.cpu.bus().double_speed() { cycles / 2 } else { cycles }
+    }
+    // the illegal opcode the cpu hung on, if it did. the screen and sound keep
+    // going but no more instructions run until a reset
+    pub fn locked_up(&self) -> Option<u8> {
+        self.cpu.locked_up()
+    }
+    // run until the PPU finishes a frame and return it, see frame(). with the LCD
+    // off no frame ever comes, so this gives up after a frame's worth of cycles.
+    // does nothing while paused, and stops early where an attached debugger says
+    pub fn run_frame(&mut self) -> &[u8; FRAME_BYTES] {
+        if self.paused {
+            return self.frame();
+        }
+        self.advance_frame()
+    }
+    // run_frame() whether paused or not, for stepping through a paused game one
+    // frame at a time
+    pub fn advance_frame(&mut self) -> &[u8; FRAME_BYTES] {
+        let skipping = self.speed > 1.0 && self.frame_skip > 0;
+        let render = !skipping || self.frames.is_multiple_of(self.frame_skip + 1);
+        self.frames = self.frames.wrapping_add(1);
+        self.mmu_mut().gpu.set_rendering(render);
+        let mut cycles = 0;
+        while cycles < CYCLES_PER_FRAME {
+            let Some(ran) = self.debugged_step() else { break };
+            cycles += ran;
+            if self.mmu_mut().gpu.take_frame_ready() { break }
+        }
+        self.frame()
+    }
+    // run for about `cycles` clock cycles, e.g. to keep pace with an audio callback.
+    // instructions can't be split, so this returns how many actually ran and the
+    // excess is made up on the next call. nothing runs while paused
+    pub fn run_cycles(&mut self, cycles: u32) -> u32 {
+        if self.paused { return 0 }
+        let target = cycles.saturating_sub(self.overshoot);
+        self.overshoot -= cycles - target;
+        let mut ran = 0;
+        while ran < target {
+            let Some(cycles) = self.debugged_step() else {
+                // stopped by the debugger, there's nothing to make up for
+                self.overshoot = 0;
+                return ran;
+            };
+            ran += cycles;
+        }
+        self.overshoot += ran - target;
+        ran
+    }
+    // step() unless an attached debugger stops before the instruction
+    fn debugged_step(&mut self) -> Option<u32> {
+        if let Some(debugger) = &mut self.debugger
+            && debugger.before_instruction(&self.cpu.snapshot(), self.cpu.bus()) {
+            return None;
+        }
+        let was_locked_up = self.cpu.locked_up().is_some();
+        let cycles = self.step();
+        if let Some(debugger) = &mut self.debugger {
+            debugger.after_instruction(cycles, self.cpu.bus());
+            if let Some(opcode) = self.cpu.locked_up() && !was_locked_up {
+                debugger.locked_up(opcode);
+            }
+        }
+        Some(cycles)
+    }
+    // from now on run_frame() and run_cycles() go through the debugger, see
+    // Debugger. attaching again keeps the one there
+    pub fn attach_debugger(&mut self) -> &mut Debugger {
+        self.debugger.get_or_insert_with(Debugger::new)
+    }
+    pub fn detach_debugger(&mut self) -> Option<Debugger> {
+        self.debugger.take()
+    }
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+    // run_frame() and run_cycles() stop running anything until resume(). step()
+    // and advance_frame() still do, for debuggers and frame by frame stepping
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+    // back to the power-on state with the same cartridge, battery backed ram and
+    // clocks included, as when switching the console off and on again
+    pub fn reset(&mut self) {
+        self.overshoot = 0;
+        self.cpu.reset(ResetKind::PowerCycle);
+    }
+    // a reset that also wipes the cartridge's save ram
+    pub fn hard_reset(&mut self) {
+        if let Some(cartridge) = self.mmu_mut().cartridge_mut() {
+            cartridge.clear_ram();
+        }
+        self.reset();
+    }
+    // battery ram for hosts that keep saves themselves, empty without a cartridge
+    pub fn save_ram(&self) -> Vec<u8> {
+        self.mmu().save_ram()
+    }
+    pub fn load_ram(&mut self, data: &[u8]) {
+        self.mmu_mut().load_ram(data);
+    }
+    // the cartridge only writes its .sav when dropped, this gets it onto disk
+    // while the game is still running. a failure is also announced on the OSD
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        let result = self.mmu().flush_save();
+        if let Err(error) = &result {
+            self.show_message(&format!("Couldn't write the save file: {}", error));
+        }
+        result
+    }
+    // shown over the picture for a couple of seconds, see Osd
+    pub fn show_message(&mut self, message: &str) {
+        self.mmu_mut().gpu.osd.push(message);
+    }
+    // switches a cheat from mmu().cheats on or off and says so on the OSD
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) {
+        let cheats = &mut self.mmu_mut().cheats;
+        cheats.set_enabled(index, enabled);
+        let Some(entry) = cheats.entries().get(index) else { return };
+        let name = if entry.description.is_empty() { entry.code.clone() } else { entry.description.clone() };
+        let state = if enabled { "enabled" } else { "disabled" };
+        self.show_message(&format!("Cheat {}: {}", state, name));
+    }
+    // the whole machine in the versioned format of the savestate module, led by
+    // the time and a thumbnail of the screen. host side settings (speed, sample
+    // rate, buttons held) aren't part of it
+    pub fn save_state<W: io::Write>(&self, writer: W) -> io::Result<W> {
+        let info = StateInfo::new(unix_now(), self.frame());
+        let mut writer = StateWriter::new(writer)?;
+        writer.section(INFO_SECTION, &info.encode())?;
+        self.cpu.write_state_sections(&mut writer)?;
+        writer.finish()
+    }
+    // refuses states from another rom or version, leaving the game running as it was
+    pub fn load_state<R: io::Read>(&mut self, reader: R) -> Result<(), StateError> {
+        self.cpu.load_state(reader)?;
+        self.overshoot = 0;
+        Ok(())
+    }
+    // how far an accelerometer cart (MBC7) is tilted, in g. ignored by other carts
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mmu_mut().set_tilt(x, y);
+    }
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.mmu_mut().joypad.set_button(button, pressed);
+    }
+    pub fn press(&mut self, button: Button) {
+        self.set_button(button, true);
+    }
+    pub fn release(&mut self, button: Button) {
+        self.set_button(button, false);
+    }
+    // the last finished frame as RGBA, 160x144 rows top to bottom
+    pub fn frame(&self) -> &[u8; FRAME_BYTES] {
+        self.mmu().gpu.frame()
+    }
+    // the last finished frame as a png, upscaled by `filter` (Filter::Nearest(1)
+    // keeps it at 160x144)
+    #[cfg(feature = "png")]
+    pub fn screenshot_png(&self, filter: Filter) -> std::io::Result<Vec<u8>> {
+        let mut scaler = Scaler::new(filter);
+        let mut png = Vec::new();
+        write_png(&mut png, scaler.width(), scaler.height(), scaler.scale(self.mmu().gpu.finished_frame()))?;
+        Ok(png)
+    }
+    #[cfg(feature = "png")]
+    pub fn screenshot(&self, path: impl AsRef<std::path::Path>, filter: Filter) -> std::io::Result<()> {
+        std::fs::write(path, self.screenshot_png(filter)?)
+    }
+    // capture gameplay until stop_recording(). one running already is finished first
+    pub fn start_recording(&mut self, path: impl AsRef<Path>, format: RecordingFormat) -> io::Result<()> {
+        self.stop_recording()?;
+        let path = path.as_ref();
+        let audio_path = match format {
+            RecordingFormat::Ffmpeg { audio: true } => {
+                let wav = path.with_extension("audio.wav");
+                self.mmu_mut().apu.start_recording(&wav)?;
+                Some(wav)
+            }
+            _ => None,
+        };
+        let started = self.recorder.lock().unwrap().start(path, format, audio_path);
+        if started.is_err() {
+            let _ = self.mmu_mut().apu.stop_recording();
+        }
+        started
+    }
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        if !self.recording() { return Ok(()) }
+        // the sound has to be complete before it's muxed in
+        let audio = self.mmu_mut().apu.stop_recording();
+        self.recorder.lock().unwrap().stop()?;
+        audio
+    }
+    pub fn recording(&self) -> bool {
+        self.recorder.lock().unwrap().recording()
+    }
+    // with an SGB model and a game that supports it, the 256x224 RGBA picture with
+    // the border and the game coloured in
+    pub fn sgb_frame(&self) -> Option<Vec<u8>> {
+        self.mmu().sgb.as_ref().map(Sgb::render)
+    }
+    // stereo samples come out interleaved at this rate, None stops producing them
+    pub fn set_sample_rate(&mut self, rate: Option<u32>) {
+        self.sample_rate = rate;
+        self.update_sample_rate();
+    }
+    // how fast frontends run the game, 1.0 being real time and UNCAPPED as fast
+    // as they can. below 1.0 the sound is stretched to match, lower in pitch;
+    // above it frontends drop the sound of frames they don't show, and uncapped
+    // there's none at all. NaN means real time
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = if speed.is_nan() { 1.0 } else { speed.max(MIN_SPEED) };
+        self.update_sample_rate();
+    }
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+    // while running faster than real time, draw one frame then skip `frames`.
+    // 0 draws them all
+    pub fn set_frame_skip(&mut self, frames: u32) {
+        self.frame_skip = frames;
+    }
+    pub fn frame_skip(&self) -> u32 {
+        self.frame_skip
+    }
+    fn update_sample_rate(&mut self) {
+        let rate = match self.sample_rate {
+            _ if self.speed == UNCAPPED => None,
+            Some(rate) if self.speed < 1.0 => Some((rate as f32 / self.speed).round() as u32),
+            rate => rate,
+        };
+        // changing it restarts the mixer and ends wav recordings
+        if self.mmu().apu.sample_rate() != rate {
+            self.mmu_mut().apu.set_sample_rate(rate);
+        }
+    }
+    // moves as many buffered samples into `out` as fit, returning how many were written
+    pub fn fill_audio(&mut self, out: &mut [f32]) -> usize {
+        self.mmu_mut().apu.fill(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::CLOCK_RATE;
+    use crate::cartridge::{test_rom, CartridgeHeader};
+    use crate::config::Accuracy;
+    use crate::cpu::Bus;
+    use crate::frame::HashSink;
+    use crate::joypad::P1;
+    use crate::savestate::{StateSlots, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+
+    // ld a, 0x91; ldh (0x40), a; jr -2
+    const SPIN: [u8; 6] = [0x3E, 0x91, 0xE0, 0x40, 0x18, 0xFE];
+
+    // a rom that turns the LCD on and spins
+    fn spin_rom() -> Vec<u8> {
+        test_rom(&SPIN, 0x00, 0)
+    }
+
+    #[test]
+    fn runs_frames_and_takes_input() {
+        let mut gameboy = GameBoy::new(spin_rom()).unwrap();
+        gameboy.run_frame();
+        // each run stops as vblank starts
+        assert_eq!(gameboy.mmu().gpu.ly(), 144);
+        gameboy.press(Button::Start);
+        gameboy.mmu_mut().joypad.write(P1, 0x10);
+        assert_eq!(gameboy.mmu().joypad.read(P1) & 0x0F, 0x07);
+        assert!(GameBoy::new(vec![0; 0x100]).is_err());
+    }
+
+    #[test]
+    fn battery_ram_goes_in_and_out_through_the_facade() {
+        // MBC1+RAM+BATTERY with 8 KiB of ram
+        let mut gameboy = GameBoy::new(test_rom(&SPIN, 0x03, 0x02)).unwrap();
+        let mut save = vec![0; 0x2000];
+        save[0x10] = 0x42;
+        gameboy.load_ram(&save);
+        assert_eq!(gameboy.save_ram(), save);
+        // no save path, nothing to write
+        assert!(gameboy.flush_save().is_ok());
+        assert!(GameBoy::new(spin_rom()).unwrap().save_ram().is_empty());
+    }
+
+    #[test]
+    fn cheats_and_save_errors_are_announced() {
+        let mut gameboy = GameBoy::new(test_rom(&SPIN, 0x03, 0x02)).unwrap();
+        let index = gameboy.mmu_mut().cheats.add_described("Infinite lives", "01FFC0C0").unwrap();
+        gameboy.set_cheat_enabled(index, false);
+        assert!(!gameboy.mmu().cheats.entries()[index].enabled);
+        gameboy.set_cheat_enabled(index, true);
+        // a save path that's a directory can't be written
+        gameboy.mmu_mut().cartridge_mut().unwrap().set_save_path(Some(std::env::temp_dir()));
+        assert!(gameboy.flush_save().is_err());
+        let osd = &gameboy.mmu().gpu.osd;
+        let messages: Vec<&str> = osd.messages().collect();
+        assert_eq!(messages[..2], ["Cheat disabled: Infinite lives", "Cheat enabled: Infinite lives"]);
+        assert!(messages[2].starts_with("Couldn't write the save file"));
+        // don't leave it trying again on drop
+        gameboy.mmu_mut().cartridge_mut().unwrap().set_save_path(None);
+    }
+
+    #[test]
+    fn model_follows_the_cartridge_unless_chosen() {
+        let mut rom = spin_rom();
+        assert_eq!(GameBoy::new(rom.clone()).unwrap().model(), Model::Dmg);
+        rom[0x143] = 0x80;
+        rom[0x14D] = CartridgeHeader::compute_header_checksum(&rom);
+        let gameboy = GameBoy::new(rom.clone()).unwrap();
+        assert_eq!(gameboy.model(), Model::Cgb);
+        // the boot rom leaves A = 0x11 on CGB
+        assert_eq!(gameboy.cpu().snapshot().registers.a, 0x11);
+        let gameboy = GameBoy::builder(rom.clone()).model(Model::Mgb).build().unwrap();
+        assert_eq!(gameboy.model(), Model::Mgb);
+        assert_eq!(gameboy.cpu().snapshot().registers.a, 0xFF);
+        // only the boot rom for the chosen model runs
+        let gameboy = GameBoy::builder(rom.clone())
+            .boot_rom(vec![0; DMG_BOOT_ROM_SIZE])
+            .boot_rom(vec![0; CGB_BOOT_ROM_SIZE])
+            .build()
+            .unwrap();
+        assert_eq!(gameboy.cpu().snapshot().pc, 0);
+        assert!(gameboy.mmu().boot_rom_mapped());
+        let result = GameBoy::builder(rom).boot_rom(vec![0; 0x200]).build();
+        assert!(matches!(result, Err(GameBoyError::BootRom(BootRomSizeError(0x200)))));
+    }
+
+    #[test]
+    fn the_divider_starts_where_each_boot_rom_leaves_it() {
+        for (model, div) in [(Model::Dmg, 0xAB), (Model::Mgb, 0xAB), (Model::Sgb, 0x00), (Model::Cgb, 0x00)] {
+            let gameboy = GameBoy::builder(spin_rom()).model(model).build().unwrap();
+            assert_eq!(gameboy.mmu().read_byte(0xFF04), div, "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn stop_switches_a_cgb_to_double_speed() {
+        // ld a, 1; ldh [rKEY1], a; stop; nop; jr -2
+        let rom = test_rom(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x00, 0x18, 0xFE], 0x00, 0);
+        let mut gameboy = GameBoy::builder(rom.clone()).model(Model::Cgb).build().unwrap();
+        assert_eq!(gameboy.step() + gameboy.step(), 20);
+        gameboy.step();
+        assert!(gameboy.mmu().double_speed());
+        // the cpu carries on, at twice the rate
+        assert_eq!(gameboy.step(), 2);
+        assert_eq!(gameboy.cpu().snapshot().pc, 0x0107);
+        // a DMG only stops
+        let mut gameboy = GameBoy::builder(rom).model(Model::Dmg).build().unwrap();
+        for _ in 0..4 {
+            gameboy.step();
+        }
+        assert_eq!(gameboy.cpu().snapshot().pc, 0x0106);
+    }
+
+    #[test]
+    fn deterministic_runs_ignore_the_host() {
+        // HuC3, with its real time clock
+        let rom = test_rom(&SPIN, 0xFE, 0);
+        let build = || GameBoy::builder(rom.clone()).deterministic(7).build().unwrap();
+        let (mut first, second) = (build(), build());
+        assert_eq!(first.mmu().read_byte(0xC123), second.mmu().read_byte(0xC123));
+        let cartridge = first.mmu_mut().cartridge_mut().unwrap();
+        cartridge.tick(CLOCK_RATE * 61);
+        // select the clock, point it at the minutes and read the low nibble back
+        cartridge.write_rom(0x0000, 0x0B);
+        for command in [0x40, 0x50, 0x10] {
+            cartridge.write_ram(0x0000, command);
+        }
+        cartridge.write_rom(0x0000, 0x0C);
+        assert_eq!(cartridge.read_ram(0x0000), 0x81);
+    }
+
+    #[test]
+    fn resets_keep_the_cartridge() {
+        // MBC1 with battery backed ram
+        let rom = test_rom(&SPIN, 0x03, 0x02);
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        let a = gameboy.cpu().snapshot().registers.a;
+        gameboy.mmu_mut().write_byte(0x0000, 0x0A);
+        gameboy.mmu_mut().write_byte(0xA000, 0x42);
+        gameboy.mmu_mut().write_byte(0xC000, 0x42);
+        gameboy.run_frame();
+        gameboy.reset();
+        assert_eq!(gameboy.cpu().snapshot().pc, 0x100);
+        assert_eq!(gameboy.cpu().snapshot().registers.a, a);
+        assert_eq!(gameboy.mmu().gpu.ly(), 0);
+        assert_ne!(gameboy.mmu().read_byte(0xC000), 0x42);
+        gameboy.mmu_mut().write_byte(0x0000, 0x0A);
+        assert_eq!(gameboy.mmu().read_byte(0xA000), 0x42);
+        gameboy.hard_reset();
+        gameboy.mmu_mut().write_byte(0x0000, 0x0A);
+        assert_eq!(gameboy.mmu().read_byte(0xA000), 0x00);
+    }
+
+    #[test]
+    fn the_fast_preset_finishes_a_frame_from_the_first_vblank() {
+        let mut gameboy = GameBoy::new(spin_rom()).unwrap();
+        gameboy.mmu_mut().set_accuracy(Accuracy::Fast);
+        let hashes = Arc::new(Mutex::new(HashSink::new()));
+        gameboy.mmu_mut().gpu.add_frame_sink(Box::new(hashes.clone()));
+        for frame in 1..=3 {
+            gameboy.run_frame();
+            assert_eq!(gameboy.mmu().gpu.ly(), 144);
+            assert_eq!(hashes.lock().unwrap().hashes().len(), frame);
+        }
+    }
+
+    #[test]
+    fn paused_games_only_advance_a_frame_at_a_time() {
+        let mut gameboy = GameBoy::new(spin_rom()).unwrap();
+        gameboy.run_frame();
+        gameboy.pause();
+        let div = gameboy.mmu().read_byte(0xFF04);
+        gameboy.run_frame();
+        assert_eq!(gameboy.run_cycles(CYCLES_PER_FRAME), 0);
+        assert_eq!(gameboy.mmu().read_byte(0xFF04), div);
+        // exactly one frame, vblank to vblank
+        gameboy.advance_frame();
+        assert_eq!(gameboy.mmu().gpu.ly(), 144);
+        assert_ne!(gameboy.mmu().read_byte(0xFF04), div);
+        assert!(gameboy.paused());
+        gameboy.resume();
+        assert!(gameboy.run_cycles(4) > 0);
+    }
+
+    #[test]
+    fn states_bring_back_the_whole_machine() {
+        // MBC1 with ram
+        let mut rom = test_rom(&SPIN, 0x02, 0x02);
+        let mut gameboy = GameBoy::new(rom.clone()).unwrap();
+        gameboy.mmu_mut().write_byte(0x0000, 0x0A);
+        gameboy.mmu_mut().write_byte(0xA000, 0x42);
+        gameboy.run_frame();
+        gameboy.run_cycles(1000);
+        let state = gameboy.save_state(Vec::new()).unwrap();
+        let mut expected = GameBoy::new(rom.clone()).unwrap();
+        expected.load_state(state.as_slice()).unwrap();
+        gameboy.mmu_mut().write_byte(0xA000, 0x00);
+        gameboy.run_frame();
+        gameboy.load_state(state.as_slice()).unwrap();
+        assert_eq!(gameboy.cpu().save_state(Vec::new()).unwrap(), expected.cpu().save_state(Vec::new()).unwrap());
+        assert_eq!(gameboy.mmu().read_byte(0xA000), 0x42);
+        assert_eq!(gameboy.run_frame(), expected.run_frame());
+        assert_eq!(gameboy.cpu().snapshot().pc, expected.cpu().snapshot().pc);
+
+        rom[0x134] = b'X';
+        rom[0x14D] = CartridgeHeader::compute_header_checksum(&rom);
+        let mut other = GameBoy::new(rom).unwrap();
+        let before = other.cpu().save_state(Vec::new()).unwrap();
+        assert!(matches!(other.load_state(state.as_slice()), Err(StateError::WrongRom { .. })));
+        assert_eq!(other.cpu().save_state(Vec::new()).unwrap(), before);
+    }
+
+    #[test]
+    fn slots_keep_a_thumbnail_of_each_state() {
+        let dir = std::env::temp_dir().join(format!("gb-emulator-slots-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let slots = StateSlots::new(&dir, "spin");
+        let mut gameboy = GameBoy::new(spin_rom()).unwrap();
+        gameboy.run_frame();
+        slots.save(&mut gameboy, 3).unwrap();
+        let list = slots.list();
+        assert_eq!(list.len(), 1);
+        let (slot, info) = &list[0];
+        assert_eq!(*slot, 3);
+        assert!(info.timestamp > 0);
+        assert_eq!(info.thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+        // the screen is blank, so averaging leaves it as it was
+        assert_eq!(info.thumbnail[..4], gameboy.frame()[..4]);
+        assert!(slots.info(4).unwrap().is_none());
+        gameboy.run_frame();
+        slots.load(&mut gameboy, 3).unwrap();
+        assert!(slots.load(&mut gameboy, 4).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn screenshots_encode_the_scaled_frame() {
+        let mut gameboy = GameBoy::new(spin_rom()).unwrap();
+        gameboy.run_frame();
+        let png = gameboy.screenshot_png(Filter::Nearest(2)).unwrap();
+        let mut reader = png::Decoder::new(std::io::Cursor::new(png)).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (320, 288));
+        assert_eq!(pixels[..4], gameboy.frame()[..4]);
+    }
+
+    #[test]
+    fn run_cycles_evens_out_overshoot() {
+        let mut gameboy = GameBoy::new(spin_rom()).unwrap();
+        // every instruction here is longer than 1 cycle
+        let mut total: u32 = (0..1000).map(|_| gameboy.run_cycles(1)).sum();
+        assert!((1000..1000 + 24).contains(&total));
+        total += (0..10).map(|_| gameboy.run_cycles(CYCLES_PER_FRAME)).sum::<u32>();
+        let wanted = 1000 + CYCLES_PER_FRAME * 10;
+        assert!((wanted..wanted + 24).contains(&total));
+    }
+}
@@ -1,63 +1,1008 @@
+use crate::colorization::DmgColors;
+use crate::config::{Accuracy, ColorCorrection, DmgPalette};
+use crate::cpu::{STAT_INTERRUPT, VBLANK_INTERRUPT};
+use crate::frame::{Frame, FrameSink, FRAME_BYTES, SCREEN_PIXELS, SCREEN_WIDTH};
+use crate::osd::Osd;
+use crate::renderer::{
+    parallel_rendering_supported, render_line, scan_oam, CgbPalettes, LineRegisters, ParallelRenderer, WINDOW_X_OFFSET,
+};
+use crate::reset::ResetKind;
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+#[cfg(test)]
+mod acid2_tests;
+// tile sheet, tile map and OAM views for tooling
+pub mod debug;
+#[cfg(test)]
+pub mod golden;
+mod palette_ram;
+mod registers;
+
+pub use palette_ram::{PaletteRam, BCPD, BCPS, OCPD, OCPS, PALETTE_COUNT};
+pub use registers::{Lcdc, Mode, Palette, Stat};
+
 pub const VRAM_BEGIN: usize = 0x8000;
 pub const VRAM_END: usize = 0x9FFF;
 pub const VRAM_SIZE: usize = VRAM_END - VRAM_BEGIN + 1;
-const TILE_DATA_SIZE: usize = 0x1800;
-
-#[derive(Copy,Clone)]
-enum TilePixelValue {
-    Zero,   // Black
-    One,    // Dark Gray
-    Two,    // Light Gray
-    Three,  // White
-}
+// DMG only has the first, CGB's second bank holds more tile data and the
+// background attribute maps
+pub const VRAM_BANKS: usize = 2;
+pub type Vram = [[u8; VRAM_SIZE]; VRAM_BANKS];
+pub const OAM_BEGIN: usize = 0xFE00;
+pub const OAM_END: usize = 0xFE9F;
+pub const OAM_SIZE: usize = OAM_END - OAM_BEGIN + 1;
+// value seen by the cpu when reading memory nothing drives
+pub const OPEN_BUS: u8 = 0xFF;
+
+pub const DOTS_PER_LINE: u32 = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+pub const VBLANK_LINE: u8 = 144;
+const OAM_SCAN_DOTS: u32 = 80;
+// drawing takes at least this long, then longer for fine scrolling (the
+// discarded pixels of the first tile), the window and every sprite fetched
+const MIN_DRAWING_DOTS: u32 = 172;
+const WINDOW_FETCH_DOTS: u32 = 6;
+const SPRITE_FETCH_DOTS: u32 = 6;
+// on line 153 LY only reads 153 for the first few dots, then reads 0 for the rest
+// of the line and all of line 0. LYC compares against what LY reads, so LYC=0
+// matches early during line 153
+const LINE_153_LY_DOTS: u32 = 4;
 
-type Tile = [[TilePixelValue; 8]; 8];
-fn empty_tile() -> Tile {
-    [[TilePixelValue::Zero; 8]; 8]
+#[derive(Copy, Clone, Default)]
+pub struct GpuConfig {
+    pub accuracy: Accuracy,
+    pub palette: DmgPalette,
+    pub color_correction: ColorCorrection,
+    // how much of the previous frame shows through the new one, 0.0 is off.
+    // the DMG screen fades slowly, so games flicker sprites to fake transparency
+    pub ghosting: f32,
 }
 
 pub struct GPU {
-    vram: [u8; VRAM_SIZE],
-    tile_set: [Tile; 384],
+    vram: Vram,
+    // VBK, which bank the cpu sees
+    vram_bank: u8,
+    oam: [u8; OAM_SIZE],
+    pub lcdc: Lcdc,
+    // only the interrupt selects are stored, see stat()
+    stat: Stat,
+    pub bg_palette: Palette,
+    pub obj_palettes: [Palette; 2],
+    // CGB mode draws with the colour palette rams instead of BGP/OBP0/OBP1
+    cgb_mode: bool,
+    bg_palette_ram: PaletteRam,
+    obj_palette_ram: PaletteRam,
+    // colours for a DMG game running on a CGB
+    dmg_colors: Option<DmgColors>,
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+    pub window_x: u8,
+    pub window_y: u8,
+    pub lyc: u8,
+    // how many lines of the window have been drawn this frame. the window picks up
+    // where it left off when it's hidden for a few lines, it doesn't follow LY
+    window_line: u8,
+    // whether LY has matched WY at the start of a line this frame. the window can
+    // only appear from then on, moving WY afterwards doesn't take it away again
+    window_y_reached: bool,
+    // what the current line is being drawn with, captured when drawing starts.
+    // writes after that (usually in hblank) show up from the next line on
+    latched: LineRegisters,
+    // line being processed and how far into it the PPU is
+    line: u8,
+    dot: u32,
+    // set when hblank starts, for the HDMA to pick up
+    entered_hblank: bool,
+    // the dot drawing ends on for the current line
+    hblank_start: u32,
+    // the combined STAT interrupt line, requests fire on its rising edge
+    stat_line: bool,
+    // interrupts requested since the bus last collected them, as IF bits
+    interrupts: u8,
+    frame: Frame,
+    // the last frame as shown with ghosting, which the next one is blended with
+    ghost: Option<Frame>,
+    // set whenever a frame is finished, until the frontend takes it
+    frame_ready: bool,
+    // the first frame after the LCD is switched on isn't shown, the screen stays blank
+    skip_frame: bool,
+    // the frame the parallel renderer has in flight is one of those, it's
+    // blanked when it comes back
+    skip_in_flight: bool,
+    // false while frames are skipped to run faster: timing is unchanged but
+    // nothing is drawn, so frame() keeps the last picture drawn
+    rendering: bool,
+    frame_sinks: Vec<Box<dyn FrameSink>>,
+    pub osd: Osd,
+    config: GpuConfig,
+    // only present with the fast accuracy preset
+    parallel_renderer: Option<ParallelRenderer>,
+}
+
+// how long mode 3 lasts for a line, known once OAM scan is over
+fn drawing_dots(registers: &LineRegisters) -> u32 {
+    let sprites = if registers.lcdc.sprites_enabled { registers.sprites.count as u32 } else { 0 };
+    MIN_DRAWING_DOTS
+        + (registers.scx % 8) as u32
+        + if registers.window_line.is_some() { WINDOW_FETCH_DOTS } else { 0 }
+        + sprites * SPRITE_FETCH_DOTS
 }
 
 impl GPU {
-    pub fn read_vram(&self, address: usize) -> u8 {
-        self.vram[address]
+    pub fn new() -> GPU {
+        GPU {
+            vram: [[0; VRAM_SIZE]; VRAM_BANKS],
+            vram_bank: 0,
+            oam: [0; OAM_SIZE],
+            lcdc: Lcdc::default(),
+            stat: Stat::default(),
+            bg_palette: Palette::default(),
+            obj_palettes: [Palette::default(); 2],
+            cgb_mode: false,
+            bg_palette_ram: PaletteRam::new(),
+            obj_palette_ram: PaletteRam::new(),
+            dmg_colors: None,
+            scroll_x: 0,
+            scroll_y: 0,
+            window_x: 0,
+            window_y: 0,
+            lyc: 0,
+            window_line: 0,
+            window_y_reached: false,
+            latched: LineRegisters::default(),
+            line: 0,
+            dot: 0,
+            entered_hblank: false,
+            hblank_start: OAM_SCAN_DOTS + MIN_DRAWING_DOTS,
+            stat_line: false,
+            interrupts: 0,
+            frame: Frame::new(),
+            ghost: None,
+            frame_ready: false,
+            skip_frame: false,
+            skip_in_flight: false,
+            rendering: true,
+            frame_sinks: Vec::new(),
+            osd: Osd::new(),
+            config: GpuConfig::default(),
+            parallel_renderer: None,
+        }
+    }
+    // registers always reset, video memory only loses its contents when power is cut
+    pub fn reset(&mut self, kind: ResetKind) {
+        self.lcdc = Lcdc::default();
+        self.stat = Stat::default();
+        self.bg_palette = Palette::default();
+        self.obj_palettes = [Palette::default(); 2];
+        self.bg_palette_ram = PaletteRam::new();
+        self.obj_palette_ram = PaletteRam::new();
+        self.scroll_x = 0;
+        self.scroll_y = 0;
+        self.window_x = 0;
+        self.window_y = 0;
+        self.lyc = 0;
+        self.window_line = 0;
+        self.window_y_reached = false;
+        self.latched = LineRegisters::default();
+        self.line = 0;
+        self.dot = 0;
+        self.entered_hblank = false;
+        self.hblank_start = OAM_SCAN_DOTS + MIN_DRAWING_DOTS;
+        self.stat_line = false;
+        self.interrupts = 0;
+        self.vram_bank = 0;
+        if kind == ResetKind::PowerCycle {
+            self.vram = [[0; VRAM_SIZE]; VRAM_BANKS];
+            self.oam = [0; OAM_SIZE];
+        }
+        self.frame = Frame::new();
+        self.ghost = None;
+        self.frame_ready = false;
+        self.skip_frame = false;
+        self.skip_in_flight = false;
+        // restart the worker's copy of vram along with ours
+        if self.parallel_renderer.is_some() {
+            self.parallel_renderer = Some(ParallelRenderer::new(&self.vram));
+        }
+    }
+    // advance the PPU by some clock cycles (dots)
+    pub fn tick(&mut self, cycles: u32) {
+        // the PPU doesn't run at all while the LCD is off
+        if !self.lcdc.lcd_enabled { return }
+        for _ in 0..cycles {
+            self.dot += 1;
+            if self.line < VBLANK_LINE {
+                if self.dot == OAM_SCAN_DOTS {
+                    self.latched = self.line_registers(self.line as usize);
+                    self.hblank_start = OAM_SCAN_DOTS + drawing_dots(&self.latched);
+                }
+                if self.dot == self.hblank_start {
+                    self.hblank(self.line as usize);
+                    self.entered_hblank = true;
+                }
+            }
+            if self.dot == DOTS_PER_LINE {
+                self.dot = 0;
+                self.line = (self.line + 1) % LINES_PER_FRAME;
+                // WY only gets compared on visible lines, vblank can't set it up for the next frame
+                if self.line < VBLANK_LINE && self.line == self.window_y {
+                    self.window_y_reached = true;
+                }
+                if self.line == VBLANK_LINE {
+                    self.interrupts |= VBLANK_INTERRUPT;
+                    self.vblank();
+                }
+            }
+            self.update_stat_line();
+        }
+    }
+    fn update_stat_line(&mut self) {
+        let line = self.stat().interrupt_line();
+        if line && !self.stat_line {
+            self.interrupts |= STAT_INTERRUPT;
+        }
+        self.stat_line = line;
+    }
+    // the interrupts the PPU has requested since the last call, as IF bits
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupts)
+    }
+    pub fn take_entered_hblank(&mut self) -> bool {
+        std::mem::take(&mut self.entered_hblank)
+    }
+    // the value of the LY register, see LINE_153_LY_DOTS. the fast preset keeps
+    // reporting 153 for the whole line
+    pub fn ly(&self) -> u8 {
+        let early_rollover = self.config.accuracy == Accuracy::CycleAccurate
+            && self.line == LINES_PER_FRAME - 1
+            && self.dot >= LINE_153_LY_DOTS;
+        if early_rollover { 0 } else { self.line }
+    }
+    pub fn lyc_match(&self) -> bool {
+        self.ly() == self.lyc
+    }
+    pub fn mode(&self) -> Mode {
+        if !self.lcdc.lcd_enabled { Mode::HBlank }
+        else if self.line >= VBLANK_LINE { Mode::VBlank }
+        else if self.dot < OAM_SCAN_DOTS { Mode::OamScan }
+        else if self.dot < self.hblank_start { Mode::Drawing }
+        else { Mode::HBlank }
+    }
+    // the cpu is locked out of vram while the PPU draws and out of oam while it
+    // scans or draws, reads see open bus and writes are dropped. the fast preset
+    // lets every access through
+    pub fn vram_accessible(&self) -> bool {
+        self.config.accuracy == Accuracy::Fast || self.mode() != Mode::Drawing
+    }
+    pub fn oam_accessible(&self) -> bool {
+        self.config.accuracy == Accuracy::Fast || !matches!(self.mode(), Mode::OamScan | Mode::Drawing)
+    }
+    // STAT as the cpu reads it right now
+    pub fn stat(&self) -> Stat {
+        Stat { lyc_match: self.lyc_match(), mode: self.mode(), ..self.stat }
+    }
+    // turning the LCD off stops the PPU at the start of line 0 and leaves the
+    // screen blank until it's turned back on
+    pub fn write_lcdc(&mut self, value: u8) {
+        let lcdc = Lcdc::from(value);
+        if !self.lcdc.lcd_enabled && lcdc.lcd_enabled {
+            self.window_y_reached = self.window_y == 0;
+            self.skip_frame = true;
+        }
+        if self.lcdc.lcd_enabled && !lcdc.lcd_enabled {
+            self.line = 0;
+            self.dot = 0;
+            self.window_line = 0;
+            self.entered_hblank = false;
+            self.stat_line = false;
+            self.lcdc = lcdc;
+            // frontends don't get vblanks while the LCD is off, show them the blank screen now
+            self.frame = self.blank_frame();
+            self.ghost = None;
+            self.finish_frame();
+        }
+        self.lcdc = lcdc;
+    }
+    pub fn write_stat(&mut self, value: u8) {
+        self.stat.write(value);
+    }
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+    }
+    pub fn dmg_colors(&self) -> Option<DmgColors> {
+        self.dmg_colors
+    }
+    pub fn set_dmg_colors(&mut self, colors: Option<DmgColors>) {
+        self.dmg_colors = colors;
+    }
+    // BCPS/BCPD/OCPS/OCPD. palette ram can't be reached while the PPU draws, the
+    // same as vram: reads see open bus and writes only move the index on
+    pub fn read_palette_register(&self, address: u16) -> u8 {
+        match address {
+            BCPS => self.bg_palette_ram.spec(),
+            OCPS => self.obj_palette_ram.spec(),
+            _ if !self.vram_accessible() => OPEN_BUS,
+            BCPD => self.bg_palette_ram.data(),
+            OCPD => self.obj_palette_ram.data(),
+            _ => OPEN_BUS,
+        }
+    }
+    pub fn write_palette_register(&mut self, address: u16, value: u8) {
+        let accessible = self.vram_accessible();
+        match address {
+            BCPS => self.bg_palette_ram.write_spec(value),
+            OCPS => self.obj_palette_ram.write_spec(value),
+            BCPD if accessible => self.bg_palette_ram.write_data(value),
+            OCPD if accessible => self.obj_palette_ram.write_data(value),
+            BCPD => self.bg_palette_ram.advance(),
+            OCPD => self.obj_palette_ram.advance(),
+            _ => {}
+        }
+    }
+    // registers, video memory and where the PPU is in the frame. the picture
+    // itself goes separately, see save_screen()
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        for bank in &self.vram {
+            state.bytes(bank);
+        }
+        state.u8(self.vram_bank);
+        state.bytes(&self.oam);
+        state.bytes(&[
+            self.lcdc.into(),
+            self.stat.into(),
+            self.bg_palette.into(),
+            self.obj_palettes[0].into(),
+            self.obj_palettes[1].into(),
+        ]);
+        self.bg_palette_ram.save_state(state);
+        self.obj_palette_ram.save_state(state);
+        state.bytes(&[self.scroll_x, self.scroll_y, self.window_x, self.window_y, self.lyc]);
+        state.u8(self.window_line);
+        state.bool(self.window_y_reached);
+        // what the line in progress was latched with. the rest of the latch is
+        // rebuilt from the registers when loading
+        let latched = &self.latched;
+        state.bytes(&[latched.lcdc.into(), latched.scx, latched.scy, latched.wx]);
+        state.bool(latched.window_line.is_some());
+        state.u8(latched.window_line.unwrap_or(0));
+        state.bytes(&[latched.bg_palette.into(), latched.obj_palettes[0].into(), latched.obj_palettes[1].into()]);
+        state.u8(self.line);
+        state.u32(self.dot);
+        state.u32(self.hblank_start);
+        state.bytes(&[self.entered_hblank as u8, self.stat_line as u8, self.interrupts, self.skip_frame as u8]);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        for bank in self.vram.iter_mut() {
+            state.bytes(bank)?;
+        }
+        self.vram_bank = state.u8()? & 0x01;
+        state.bytes(&mut self.oam)?;
+        self.lcdc = state.u8()?.into();
+        self.stat = state.u8()?.into();
+        self.bg_palette = state.u8()?.into();
+        self.obj_palettes = [state.u8()?.into(), state.u8()?.into()];
+        self.bg_palette_ram.load_state(state)?;
+        self.obj_palette_ram.load_state(state)?;
+        self.scroll_x = state.u8()?;
+        self.scroll_y = state.u8()?;
+        self.window_x = state.u8()?;
+        self.window_y = state.u8()?;
+        self.lyc = state.u8()?;
+        self.window_line = state.u8()?;
+        self.window_y_reached = state.bool()?;
+        let (lcdc, scx, scy, wx) = (state.u8()?.into(), state.u8()?, state.u8()?, state.u8()?);
+        let on_window = state.bool()?;
+        let window_line = on_window.then_some(state.u8()?);
+        let palettes = (state.u8()?.into(), [state.u8()?.into(), state.u8()?.into()]);
+        self.line = state.u8()?;
+        self.dot = state.u32()?;
+        if self.line >= LINES_PER_FRAME || self.dot >= DOTS_PER_LINE {
+            return Err(state.invalid("PPU position"));
+        }
+        self.hblank_start = state.u32()?;
+        self.entered_hblank = state.bool()?;
+        self.stat_line = state.bool()?;
+        self.interrupts = state.u8()?;
+        self.skip_frame = state.bool()?;
+        self.latched = LineRegisters {
+            lcdc,
+            scx,
+            scy,
+            wx,
+            window_line,
+            bg_palette: palettes.0,
+            obj_palettes: palettes.1,
+            ..self.registers_for_line(self.line as usize, window_line)
+        };
+        self.latched.sprites = scan_oam(&self.oam, self.line as usize, lcdc.tall_sprites);
+        self.frame_ready = false;
+        self.ghost = None;
+        if self.parallel_renderer.is_some() {
+            self.parallel_renderer = Some(ParallelRenderer::new(&self.vram));
+            self.skip_in_flight = false;
+        }
+        Ok(())
+    }
+    // the picture as far as it's drawn
+    pub fn save_screen(&self, state: &mut StateEncoder) {
+        state.bytes(&self.frame.pixels[..]);
+        state.bytes(&self.frame.shades[..]);
+    }
+    pub fn load_screen(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        state.bytes(&mut self.frame.pixels[..])?;
+        state.bytes(&mut self.frame.shades[..])
+    }
+    // takes effect from the next line drawn
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.config.palette = palette;
+    }
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.config.color_correction = correction;
+    }
+    pub fn set_ghosting(&mut self, weight: f32) {
+        self.config.ghosting = weight;
+    }
+    // the parallel renderer of the fast preset keeps drawing regardless, it's off
+    // the emulation thread anyway
+    pub fn set_rendering(&mut self, rendering: bool) {
+        self.rendering = rendering;
+    }
+    pub fn config(&self) -> GpuConfig {
+        self.config
+    }
+    pub fn set_config(&mut self, config: GpuConfig) {
+        self.config = config;
+        match config.accuracy {
+            Accuracy::Fast if parallel_rendering_supported() => {
+                if self.parallel_renderer.is_none() {
+                    self.parallel_renderer = Some(ParallelRenderer::new(&self.vram));
+                }
+            }
+            _ => {
+                if let Some(frame) = self.parallel_renderer.take().and_then(ParallelRenderer::finish) {
+                    self.frame = if std::mem::take(&mut self.skip_in_flight) { self.blank_frame() } else { frame };
+                }
+            }
+        }
+    }
+    // the last finished frame as RGBA, rows top to bottom
+    pub fn frame(&self) -> &[u8; FRAME_BYTES] {
+        &self.frame.pixels
+    }
+    // both of the above together, e.g. for a Scaler
+    pub fn finished_frame(&self) -> &Frame {
+        &self.frame
+    }
+    // the same frame as shade indices 0-3, one byte per pixel
+    pub fn indexed_frame(&self) -> &[u8; SCREEN_PIXELS] {
+        &self.frame.shades
+    }
+    // see Frame::hash, for checking output against known values
+    pub fn frame_hash(&self) -> u64 {
+        self.frame.hash()
+    }
+    // whether a new frame was finished since the last call. frontends polling
+    // this after running the cpu know exactly when there's something to present,
+    // those wanting a callback can attach a FrameSink instead
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+    fn window_on_line(&self) -> bool {
+        self.lcdc.window_enabled
+            && self.window_y_reached
+            && (self.window_x as usize) < SCREEN_WIDTH + WINDOW_X_OFFSET
+    }
+    fn line_registers(&mut self, line: usize) -> LineRegisters {
+        let window_line = self.window_on_line().then(|| {
+            self.window_line += 1;
+            self.window_line - 1
+        });
+        self.registers_for_line(line, window_line)
+    }
+    fn registers_for_line(&self, line: usize, window_line: Option<u8>) -> LineRegisters {
+        LineRegisters {
+            lcdc: self.lcdc,
+            scx: self.scroll_x,
+            scy: self.scroll_y,
+            wx: self.window_x,
+            window_line,
+            cgb: self.cgb_mode,
+            bg_palette: self.bg_palette,
+            obj_palettes: self.obj_palettes,
+            cgb_palettes: self.cgb_mode.then(|| CgbPalettes {
+                background: self.bg_palette_ram.colors(),
+                objects: self.obj_palette_ram.colors(),
+            }),
+            dmg_colors: self.dmg_colors,
+            shades: self.config.palette.colors(),
+            color_correction: self.config.color_correction,
+            sprites: scan_oam(&self.oam, line, self.lcdc.tall_sprites),
+        }
+    }
+    // the PPU finished drawing `line` and entered hblank
+    fn hblank(&mut self, line: usize) {
+        let registers = self.latched;
+        match &mut self.parallel_renderer {
+            Some(renderer) => renderer.hblank(line, registers),
+            None if self.rendering => render_line(&self.vram, &registers, line, &mut self.frame),
+            None => {}
+        }
+    }
+    // the PPU entered vblank, the frame is complete
+    fn vblank(&mut self) {
+        self.window_line = 0;
+        self.window_y_reached = false;
+        let skip = std::mem::take(&mut self.skip_frame);
+        match &mut self.parallel_renderer {
+            // the worker hands back the frame before this one. until it has
+            // produced anything the previous frame goes out again
+            Some(renderer) => {
+                if let Some(frame) = renderer.vblank(&self.vram) {
+                    self.frame = frame;
+                }
+                if std::mem::replace(&mut self.skip_in_flight, skip) {
+                    self.frame = self.blank_frame();
+                }
+            }
+            None if skip => self.frame = self.blank_frame(),
+            None => {}
+        }
+        self.finish_frame();
+    }
+    fn blank_frame(&self) -> Frame {
+        Frame::blank(self.config.palette.colors()[0])
+    }
+    pub fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>) {
+        self.frame_sinks.push(sink);
+    }
+    pub fn take_frame_sinks(&mut self) -> Vec<Box<dyn FrameSink>> {
+        std::mem::take(&mut self.frame_sinks)
+    }
+    // hand the finished frame to every attached sink, called when the PPU enters vblank.
+    // ghosting is applied to frame() itself, on screen messages go on a copy so
+    // frame() always holds what the game drew
+    pub fn finish_frame(&mut self) {
+        self.frame_ready = true;
+        self.apply_ghosting();
+        if self.osd.is_visible() {
+            let mut frame = self.frame.clone();
+            self.osd.render(&mut frame);
+            for sink in self.frame_sinks.iter_mut() {
+                sink.push_frame(&frame);
+            }
+        } else {
+            for sink in self.frame_sinks.iter_mut() {
+                sink.push_frame(&self.frame);
+            }
+        }
+        self.osd.tick();
+    }
+    fn apply_ghosting(&mut self) {
+        if self.config.ghosting <= 0.0 {
+            self.ghost = None;
+            return;
+        }
+        if let Some(ghost) = &self.ghost {
+            self.frame.blend(ghost, self.config.ghosting);
+        }
+        self.ghost = Some(self.frame.clone());
+    }
+    // vram and oam accessors take offsets relative to the start of their region.
+    // offsets outside the region read as open bus and writes to them are dropped,
+    // so no address the cpu can produce is able to panic here
+    pub fn read_vram(&self, index: usize) -> u8 {
+        self.vram_bank_data(self.vram_bank).get(index).copied().unwrap_or(OPEN_BUS)
     }
     pub fn write_vram(&mut self, index: usize, value: u8) {
-        self.vram[index] = value;
-        // check bounds for tile decoding
-        if index >= TILE_DATA_SIZE { return }
-
-        // normalize index by setting lsb to 0
-        let index = index & 0xFFFE;
-        let byte1 = self.vram[index];
-        let byte2 = self.vram[index + 1];
-
-        // entire tile is 8 rows, therefore 16 bytes
-        let tile_index = index / 16;
-        // since every 2 bytes is a new row
-        let row_index = (index % 16) / 2;
-
-        for pixel_index in 0..8 {
-            /* bits representing pixels are as such:
-                1111_1111
-                0123 4567
-
-                meaning msb from each byte combine to represent the first pixel in the row
-                while lsb from each byte combine to represent the last pixel in the row
-            */ 
-            let mask = 1 << (7 - pixel_index);
-            let lsb = byte1 & mask;
-            let msb = byte2 & mask;
-            let value = match (msb != 0, lsb != 0) {
-                (true, true) => TilePixelValue::Three,
-                (true, false) => TilePixelValue::Two,
-                (false, true) => TilePixelValue::One,
-                (false, false) => TilePixelValue::Zero,
+        let bank = self.vram_bank as usize;
+        let Some(byte) = self.vram[bank].get_mut(index) else { return };
+        *byte = value;
+        if let Some(renderer) = &mut self.parallel_renderer {
+            renderer.vram_written(bank, index, value);
+        }
+    }
+    pub fn vram_bank(&self) -> u8 {
+        self.vram_bank
+    }
+    pub fn set_vram_bank(&mut self, bank: u8) {
+        self.vram_bank = bank & 0x01;
+    }
+    // either bank regardless of VBK, for the renderer and debug views
+    pub fn vram_bank_data(&self, bank: u8) -> &[u8; VRAM_SIZE] {
+        &self.vram[(bank & 0x01) as usize]
+    }
+    pub fn read_oam(&self, index: usize) -> u8 {
+        self.oam.get(index).copied().unwrap_or(OPEN_BUS)
+    }
+    pub fn write_oam(&mut self, index: usize, value: u8) {
+        if let Some(byte) = self.oam.get_mut(index) {
+            *byte = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::frame::{HashSink, SCREEN_HEIGHT};
+
+    const TILE_DATA_SIZE: usize = 0x1800;
+
+    // tiny xorshift so the fuzz run is reproducible without pulling in a rng crate
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn fuzz_vram_and_oam_accessors_never_panic() {
+        let mut gpu = GPU::new();
+        let mut state = 0x2545_F491_4F6C_DD1D;
+        for _ in 0..200_000 {
+            let roll = next(&mut state);
+            // mostly hit around the region edges, sometimes anywhere at all
+            let index = match roll % 4 {
+                0 => (roll >> 8) as usize % (VRAM_SIZE + 0x100),
+                1 => (roll >> 8) as usize % (OAM_SIZE + 0x100),
+                2 => TILE_DATA_SIZE - 2 + (roll >> 8) as usize % 4,
+                _ => (roll >> 8) as usize,
             };
-            self.tile_set[tile_index][row_index][pixel_index] = value;
+            let value = (roll >> 32) as u8;
+            gpu.write_vram(index, value);
+            gpu.read_vram(index);
+            gpu.write_oam(index, value);
+            gpu.read_oam(index);
+        }
+    }
+
+    #[test]
+    fn out_of_range_reads_are_open_bus() {
+        let mut gpu = GPU::new();
+        gpu.write_vram(VRAM_SIZE, 0x12);
+        gpu.write_oam(OAM_SIZE, 0x34);
+        assert_eq!(gpu.read_vram(VRAM_SIZE), OPEN_BUS);
+        assert_eq!(gpu.read_oam(OAM_SIZE), OPEN_BUS);
+        assert_eq!(gpu.read_vram(usize::MAX), OPEN_BUS);
+    }
+
+    // draws a few frames while changing vram and scroll mid frame, as a game would in hblank
+    fn render_frames(accuracy: Accuracy) -> Vec<u64> {
+        let hashes = Arc::new(Mutex::new(HashSink::new()));
+        let mut gpu = GPU::new();
+        gpu.set_config(GpuConfig { accuracy, ..GpuConfig::default() });
+        gpu.add_frame_sink(Box::new(hashes.clone()));
+        // background, window and sprites all on
+        gpu.write_lcdc(0xF3);
+        gpu.window_x = 47;
+        gpu.window_y = 60;
+        gpu.bg_palette = Palette::from(0xE4);
+        gpu.obj_palettes = [Palette::from(0x1B), Palette::from(0xD2)];
+        let mut state = 0x9E37_79B9_7F4A_7C15;
+        for _ in 0..6 {
+            for line in 0..SCREEN_HEIGHT {
+                for _ in 0..8 {
+                    let roll = next(&mut state);
+                    gpu.write_vram((roll >> 8) as usize % VRAM_SIZE, (roll >> 32) as u8);
+                }
+                if line % 16 == 0 {
+                    gpu.scroll_x = gpu.scroll_x.wrapping_add(3);
+                    gpu.scroll_y = gpu.scroll_y.wrapping_sub(1);
+                }
+                gpu.tick(DOTS_PER_LINE);
+            }
+            gpu.tick(DOTS_PER_LINE * (LINES_PER_FRAME - VBLANK_LINE) as u32);
         }
+        // flush the frame still on the worker
+        gpu.set_config(GpuConfig { accuracy: Accuracy::CycleAccurate, ..GpuConfig::default() });
+        gpu.finish_frame();
+        hashes.lock().unwrap().hashes().to_vec()
+    }
+
+    #[test]
+    fn parallel_rendering_matches_single_threaded() {
+        let single = render_frames(Accuracy::CycleAccurate);
+        let parallel = render_frames(Accuracy::Fast);
+        // the parallel renderer runs a frame behind, its first vblank pushes the blank screen
+        assert_eq!(single[..single.len() - 1], parallel[1..]);
+        assert_eq!((single.len(), parallel.len()), (7, 7));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn last_tile_row_decodes() {
+        let mut gpu = GPU::new();
+        gpu.write_vram(TILE_DATA_SIZE - 2, 0xFF);
+        gpu.write_vram(TILE_DATA_SIZE - 1, 0xFF);
+        let sheet = gpu.render_tile_sheet(0);
+        assert_eq!(sheet.pixel(sheet.width - 8, sheet.height - 1), [0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn oam_scan_keeps_first_ten_sprites_on_a_line() {
+        let mut gpu = GPU::new();
+        // twelve sprites on line 0, the first one off screen to the left
+        for sprite in 0..12 {
+            gpu.write_oam(sprite * 4, 16);
+            gpu.write_oam(sprite * 4 + 1, sprite as u8 * 8);
+        }
+        let selected = scan_oam(&gpu.oam, 0, false);
+        assert_eq!(selected.count, 10);
+        assert_eq!(selected.as_slice()[0].x, 0);
+        assert_eq!(selected.as_slice()[9].x, 72);
+        assert_eq!(scan_oam(&gpu.oam, 8, false).count, 0);
+        assert_eq!(scan_oam(&gpu.oam, 8, true).count, 10);
+    }
+
+    #[test]
+    fn turning_the_lcd_off_stops_the_ppu_and_blanks_the_screen() {
+        let mut gpu = GPU::new();
+        gpu.write_lcdc(0x91);
+        gpu.bg_palette = Palette::from(0xE4);
+        gpu.write_vram(0, 0xFF);
+        gpu.write_vram(1, 0xFF);
+        gpu.tick(DOTS_PER_LINE * 3);
+        assert_eq!(gpu.ly(), 3);
+        assert_eq!(gpu.frame()[..4], [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(gpu.indexed_frame()[0], 3);
+        gpu.write_lcdc(0x11);
+        gpu.tick(DOTS_PER_LINE * 3);
+        assert_eq!(gpu.ly(), 0);
+        assert_eq!(gpu.mode(), Mode::HBlank);
+        assert_eq!(gpu.frame()[..4], [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(gpu.indexed_frame()[0], 0);
+        assert_eq!(gpu.frame_hash(), Frame::blank([0xFF; 4]).hash());
+        assert!(gpu.take_frame_ready());
+        // the first frame after switching back on stays blank, the next one is drawn
+        gpu.write_lcdc(0x91);
+        gpu.tick(DOTS_PER_LINE * VBLANK_LINE as u32);
+        assert!(gpu.take_frame_ready());
+        assert_eq!(gpu.frame()[..4], [0xFF, 0xFF, 0xFF, 0xFF]);
+        gpu.tick(DOTS_PER_LINE * LINES_PER_FRAME as u32);
+        assert!(gpu.take_frame_ready());
+        assert_eq!(gpu.frame()[..4], [0x00, 0x00, 0x00, 0xFF]);
+        gpu.write_lcdc(0x11);
+        // blank means the lightest shade of the chosen palette
+        gpu.set_palette(DmgPalette::ClassicGreen);
+        gpu.write_lcdc(0x91);
+        gpu.write_lcdc(0x11);
+        assert_eq!(gpu.frame()[..4], DmgPalette::ClassicGreen.colors()[0]);
+    }
+
+    #[test]
+    fn stat_interrupt_fires_on_the_rising_edge_of_its_line() {
+        let mut gpu = GPU::new();
+        gpu.write_lcdc(0x91);
+        gpu.write_stat(0x08);
+        // drawing, then hblank
+        gpu.tick(OAM_SCAN_DOTS + 1);
+        assert_eq!(gpu.mode(), Mode::Drawing);
+        assert_eq!(gpu.take_interrupts(), 0);
+        gpu.tick(MIN_DRAWING_DOTS);
+        assert_eq!(gpu.mode(), Mode::HBlank);
+        assert_eq!(gpu.take_interrupts(), STAT_INTERRUPT);
+        // LYC matching while hblank already holds the line high doesn't fire again
+        gpu.write_stat(0x48);
+        gpu.lyc = 0;
+        gpu.tick(1);
+        assert_eq!(gpu.take_interrupts(), 0);
+        // fine scroll and sprites make drawing take longer
+        gpu.scroll_x = 3;
+        gpu.write_oam(0, 16 + 1);
+        gpu.write_oam(1, 8);
+        gpu.tick(DOTS_PER_LINE - gpu.dot + OAM_SCAN_DOTS + MIN_DRAWING_DOTS);
+        assert_eq!(gpu.ly(), 1);
+        assert_eq!(gpu.mode(), Mode::Drawing);
+        gpu.tick(3 + SPRITE_FETCH_DOTS);
+        assert_eq!(gpu.mode(), Mode::HBlank);
+    }
+
+    #[test]
+    fn vblank_interrupt_and_lyc_match_follow_ly() {
+        let mut gpu = GPU::new();
+        gpu.write_lcdc(0x91);
+        gpu.lyc = 100;
+        gpu.tick(DOTS_PER_LINE * 100);
+        assert_eq!(gpu.ly(), 100);
+        assert!(gpu.stat().lyc_match);
+        assert_eq!(gpu.take_interrupts(), 0);
+        gpu.tick(DOTS_PER_LINE * (VBLANK_LINE as u32 - 100));
+        assert_eq!(gpu.mode(), Mode::VBlank);
+        assert!(!gpu.stat().lyc_match);
+        assert_eq!(gpu.take_interrupts(), VBLANK_INTERRUPT);
+        assert!(gpu.take_frame_ready());
+        assert!(!gpu.take_frame_ready());
+        // once per frame
+        gpu.tick(DOTS_PER_LINE * (LINES_PER_FRAME as u32 - 1));
+        assert_eq!(gpu.take_interrupts(), 0);
+        gpu.tick(DOTS_PER_LINE);
+        assert_eq!(gpu.take_interrupts(), VBLANK_INTERRUPT);
+    }
+
+    #[test]
+    fn ly_rolls_over_early_on_line_153() {
+        let mut gpu = GPU::new();
+        gpu.write_lcdc(0x91);
+        gpu.write_stat(0x40);
+        gpu.lyc = 0;
+        gpu.tick(DOTS_PER_LINE * (LINES_PER_FRAME as u32 - 1));
+        gpu.take_interrupts();
+        for _ in 0..LINE_153_LY_DOTS {
+            assert_eq!(gpu.ly(), 153);
+            gpu.tick(1);
+        }
+        // LYC=0 matches, and fires, with most of line 153 still to go
+        assert_eq!(gpu.ly(), 0);
+        assert_eq!(gpu.take_interrupts(), STAT_INTERRUPT);
+        gpu.tick(DOTS_PER_LINE - LINE_153_LY_DOTS);
+        assert_eq!(gpu.ly(), 0);
+        assert_eq!(gpu.take_interrupts(), 0);
+
+        // the fast preset holds 153 for the whole line and matches on line 0
+        gpu.set_config(GpuConfig { accuracy: Accuracy::Fast, ..GpuConfig::default() });
+        gpu.tick(DOTS_PER_LINE * (LINES_PER_FRAME as u32 - 1) + LINE_153_LY_DOTS);
+        gpu.take_interrupts();
+        assert_eq!(gpu.ly(), 153);
+        gpu.tick(DOTS_PER_LINE - LINE_153_LY_DOTS - 1);
+        assert_eq!(gpu.ly(), 153);
+        assert_eq!(gpu.take_interrupts(), 0);
+        gpu.tick(1);
+        assert_eq!(gpu.ly(), 0);
+        assert_eq!(gpu.take_interrupts(), STAT_INTERRUPT);
+    }
+
+    #[test]
+    fn registers_are_latched_when_drawing_starts() {
+        let mut gpu = GPU::new();
+        gpu.window_x = WINDOW_X_OFFSET as u8;
+        gpu.window_y = 10;
+        gpu.write_lcdc(0xB1);
+        gpu.tick(DOTS_PER_LINE * 20 + 100);
+        assert_eq!(gpu.latched.window_line, Some(10));
+        // the window stays once WY has been matched
+        gpu.window_y = 100;
+        gpu.scroll_x = 5;
+        assert_eq!(gpu.latched.scx, 0);
+        gpu.tick(DOTS_PER_LINE);
+        assert_eq!(gpu.latched.window_line, Some(11));
+        assert_eq!(gpu.latched.scx, 5);
+        // until the next frame
+        gpu.tick(DOTS_PER_LINE * LINES_PER_FRAME as u32);
+        assert_eq!(gpu.ly(), 21);
+        assert_eq!(gpu.latched.window_line, None);
+    }
+
+    #[test]
+    fn wy_in_vblank_doesnt_bring_the_window_in_on_line_0() {
+        let mut gpu = GPU::new();
+        gpu.window_x = WINDOW_X_OFFSET as u8;
+        gpu.window_y = 150;
+        gpu.write_lcdc(0xB1);
+        gpu.tick(DOTS_PER_LINE * LINES_PER_FRAME as u32 + 100);
+        assert_eq!(gpu.ly(), 0);
+        assert_eq!(gpu.latched.window_line, None);
+        assert!(!gpu.window_y_reached);
+    }
+
+    #[test]
+    fn vram_and_oam_are_blocked_while_the_ppu_uses_them() {
+        let mut gpu = GPU::new();
+        gpu.write_lcdc(0x91);
+        gpu.tick(1);
+        assert!(gpu.vram_accessible() && !gpu.oam_accessible());
+        gpu.tick(OAM_SCAN_DOTS);
+        assert!(!gpu.vram_accessible() && !gpu.oam_accessible());
+        gpu.set_config(GpuConfig { accuracy: Accuracy::Fast, ..GpuConfig::default() });
+        assert!(gpu.vram_accessible() && gpu.oam_accessible());
+        gpu.set_config(GpuConfig { accuracy: Accuracy::CycleAccurate, ..GpuConfig::default() });
+        gpu.tick(MIN_DRAWING_DOTS);
+        assert!(gpu.vram_accessible() && gpu.oam_accessible());
+    }
+
+    #[test]
+    fn cgb_palette_ram_auto_increments_and_colours_the_frame() {
+        let mut gpu = GPU::new();
+        gpu.set_cgb_mode(true);
+        gpu.write_palette_register(BCPS, 0x80);
+        // background palette 0 colour 0 is pure red, colour 1 pure blue
+        for byte in [0x1F, 0x00, 0x00, 0x7C] {
+            gpu.write_palette_register(BCPD, byte);
+        }
+        assert_eq!(gpu.read_palette_register(BCPS), 0xC4);
+        gpu.write_palette_register(BCPS, 0x02);
+        assert_eq!(gpu.read_palette_register(BCPD), 0x00);
+        gpu.write_lcdc(0x91);
+        gpu.write_vram(0x10, 0xFF);
+        gpu.write_vram(0x1801, 1);
+        gpu.tick(DOTS_PER_LINE);
+        assert_eq!(gpu.frame.pixel(0, 0), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(gpu.frame.pixel(8, 0), [0x00, 0x00, 0xFF, 0xFF]);
+        // blocked while drawing, the index still moves on
+        gpu.write_palette_register(BCPS, 0x80);
+        gpu.tick(OAM_SCAN_DOTS);
+        gpu.write_palette_register(BCPD, 0x55);
+        assert_eq!(gpu.read_palette_register(BCPD), OPEN_BUS);
+        assert_eq!(gpu.read_palette_register(BCPS), 0xC1);
+        assert_eq!(gpu.bg_palette_ram.color(0, 0), 0x001F);
+    }
+
+    #[test]
+    fn cgb_attributes_pick_palette_bank_and_flip() {
+        let mut gpu = GPU::new();
+        gpu.set_cgb_mode(true);
+        // background palette 1: colour 0 black, colour 1 pure green
+        gpu.write_palette_register(BCPS, 0x88);
+        for byte in [0x00, 0x00, 0xE0, 0x03] {
+            gpu.write_palette_register(BCPD, byte);
+        }
+        // tile 0 in bank 1 has only its leftmost pixel set
+        gpu.set_vram_bank(1);
+        gpu.write_vram(0, 0x80);
+        // first map entry: palette 1, bank 1, then the same with x flip
+        gpu.write_vram(0x1800, 0x09);
+        gpu.write_vram(0x1801, 0x29);
+        gpu.set_vram_bank(0);
+        gpu.write_lcdc(0x91);
+        gpu.tick(DOTS_PER_LINE);
+        assert_eq!(gpu.frame.pixel(0, 0), [0x00, 0xFF, 0x00, 0xFF]);
+        assert_eq!(gpu.frame.pixel(1, 0), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(gpu.frame.pixel(15, 0), [0x00, 0xFF, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn ghosting_blends_with_the_previous_frame() {
+        let mut gpu = GPU::new();
+        gpu.set_ghosting(0.5);
+        gpu.frame = Frame::blank([0xFF, 0xFF, 0xFF, 0xFF]);
+        gpu.finish_frame();
+        gpu.frame = Frame::blank([0x00, 0x00, 0x00, 0xFF]);
+        gpu.finish_frame();
+        assert_eq!(gpu.frame()[..4], [0x80, 0x80, 0x80, 0xFF]);
+        gpu.set_ghosting(0.0);
+        gpu.frame = Frame::blank([0x00, 0x00, 0x00, 0xFF]);
+        gpu.finish_frame();
+        assert_eq!(gpu.frame()[..4], [0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn debug_views_show_tiles_maps_and_oam() {
+        let mut gpu = GPU::new();
+        gpu.write_lcdc(0x93);
+        gpu.bg_palette = Palette::from(0xE4);
+        // tile 1 is solid colour 3, and sits at the top left of the low map
+        for byte in 0x10..0x20 {
+            gpu.write_vram(byte, 0xFF);
+        }
+        gpu.write_vram(0x1800, 1);
+        let sheet = gpu.render_tile_sheet(0);
+        assert_eq!((sheet.width, sheet.height), (128, 192));
+        assert_eq!(sheet.pixel(8, 0), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(sheet.pixel(0, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        gpu.scroll_x = 4;
+        let map = gpu.render_tile_map(debug::TileMap::Low, true);
+        assert_eq!(map.pixel(1, 1), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(map.pixel(4, 1), [0xFF, 0x00, 0x00, 0xFF]);
+        gpu.write_oam(4, 20);
+        gpu.write_oam(5, 3);
+        gpu.write_oam(7, 0x30);
+        let entry = gpu.oam_entries()[1];
+        assert_eq!((entry.x, entry.y, entry.palette, entry.x_flip), (-5, 4, 1, true));
+    }
+
+    #[test]
+    fn registers_round_trip_through_bytes() {
+        for byte in 0..=0xFF {
+            assert_eq!(u8::from(Lcdc::from(byte)), byte);
+            assert_eq!(u8::from(Palette::from(byte)), byte);
+            assert_eq!(u8::from(Stat::from(byte)), byte | 0x80);
+        }
+        let mut stat = Stat::from(0x03);
+        stat.write(0xFF);
+        assert_eq!(u8::from(stat), 0xFB);
+    }
+}
@@ -0,0 +1,71 @@
+// runs Matt Currie's acid2 rendering tests (https://github.com/mattcurrie/dmg-acid2
+// and https://github.com/mattcurrie/cgb-acid2) and compares the screen against the
+// reference images from those repos. between them they exercise most of the ppu:
+// window and object priorities, 8x16 objects, flipping, the object limit per
+// line, LCDC bits changed mid frame and on CGB the attribute maps and palettes.
+//
+// the roms aren't vendored, point ACID2_ROMS_DIR at a directory holding
+//     dmg-acid2.gb   dmg-acid2.png   (img/reference-dmg.png in the dmg-acid2 repo)
+//     cgb-acid2.gbc  cgb-acid2.png   (img/reference.png in the cgb-acid2 repo)
+// and run
+//     ACID2_ROMS_DIR=../acid2 cargo test acid2
+// without it the tests are skipped, and so is either rom that's missing. the
+// default grayscale palette and uncorrected CGB colours are the ones the
+// references were made with, so the frames have to match exactly. on a
+// mismatch the frame and a diff image are left next to the reference.
+
+use std::path::Path;
+
+use super::golden;
+use crate::cartridge::Cartridge;
+use crate::frame::Frame;
+use crate::gameboy::GameBoy;
+use crate::model::Model;
+use crate::reset::DEFAULT_RAM_SEED;
+
+const ROMS_DIR_VAR: &str = "ACID2_ROMS_DIR";
+
+// both draw their picture within the first few frames and then sit on a halt
+const FRAMES: u32 = 60;
+
+fn run(rom: &str, reference: &str, model: Model) {
+    let Ok(dir) = std::env::var(ROMS_DIR_VAR) else {
+        eprintln!("{} not set, skipping {}", ROMS_DIR_VAR, rom);
+        return;
+    };
+    let (rom_path, reference_path) = (Path::new(&dir).join(rom), Path::new(&dir).join(reference));
+    if !rom_path.exists() || !reference_path.exists() {
+        eprintln!("{} or {} not found in {}, skipping", rom, reference, dir);
+        return;
+    }
+    let cartridge = Cartridge::from_file(&rom_path).unwrap();
+    let mut gameboy = GameBoy::with_cartridge(cartridge).deterministic(DEFAULT_RAM_SEED).model(model).build().unwrap();
+    for _ in 0..FRAMES {
+        gameboy.run_frame();
+    }
+    let golden = golden::load(&reference_path).unwrap();
+    let mut expected = Frame::new();
+    expected.pixels.copy_from_slice(&golden);
+    let frame = gameboy.mmu().gpu.finished_frame();
+    if frame.hash() != expected.hash() {
+        let diff = golden::compare(frame, &golden).map(|diff| diff.to_string()).unwrap_or_default();
+        panic!(
+            "{} frame hash {:016X}, expected {:016X}: {}, {}",
+            rom,
+            frame.hash(),
+            expected.hash(),
+            diff,
+            golden::save_failure(&reference_path, frame, &golden)
+        );
+    }
+}
+
+#[test]
+fn dmg_acid2() {
+    run("dmg-acid2.gb", "dmg-acid2.png", Model::Dmg);
+}
+
+#[test]
+fn cgb_acid2() {
+    run("cgb-acid2.gbc", "cgb-acid2.png", Model::Cgb);
+}
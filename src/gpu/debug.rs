@@ -0,0 +1,144 @@
+use super::{GPU, OAM_SIZE};
+use crate::frame::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::renderer::{
+    tile_data_address, tile_pixel, Sprite, BACKGROUND_SIDE, OAM_ENTRY_SIZE, SPRITE_X_OFFSET, SPRITE_Y_OFFSET,
+    TILE_MAP_0, TILE_MAP_1, TILE_MAP_SIDE, WINDOW_X_OFFSET,
+};
+
+// the tile data region holds 384 tiles, shown 16 to a row
+pub const TILE_SHEET_COLUMNS: usize = 16;
+pub const TILE_SHEET_ROWS: usize = 24;
+const TILE_SIDE: usize = 8;
+const SPRITE_COUNT: usize = OAM_SIZE / OAM_ENTRY_SIZE;
+// overlay outlines on tile map views
+const VIEWPORT_COLOR: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+const WINDOW_COLOR: [u8; 4] = [0x00, 0x80, 0xFF, 0xFF];
+
+// an RGBA picture for debug views, rows top to bottom
+pub struct DebugImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl DebugImage {
+    fn new(width: usize, height: usize) -> DebugImage {
+        DebugImage { width, height, pixels: vec![0xFF; width * height * 4] }
+    }
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * self.width + x) * 4;
+        [self.pixels[offset], self.pixels[offset + 1], self.pixels[offset + 2], self.pixels[offset + 3]]
+    }
+    fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let offset = (y * self.width + x) * 4;
+        self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+    }
+    // a rectangle outline that wraps around the edges, like the background does
+    fn outline(&mut self, left: usize, top: usize, width: usize, height: usize, rgba: [u8; 4]) {
+        for x in left..left + width {
+            self.set_pixel(x % self.width, top % self.height, rgba);
+            self.set_pixel(x % self.width, (top + height - 1) % self.height, rgba);
+        }
+        for y in top..top + height {
+            self.set_pixel(left % self.width, y % self.height, rgba);
+            self.set_pixel((left + width - 1) % self.width, y % self.height, rgba);
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TileMap {
+    // 0x9800
+    Low,
+    // 0x9C00
+    High,
+}
+
+// an OAM entry with its fields pulled apart, for tooling
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct OamEntry {
+    pub index: usize,
+    // top left corner on screen, negative when partly off the top or left edge
+    pub x: i16,
+    pub y: i16,
+    pub tile: u8,
+    pub behind_background: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    // OBP0/OBP1 on DMG
+    pub palette: usize,
+    // CGB only
+    pub cgb_palette: usize,
+    pub tile_bank: usize,
+}
+
+impl GPU {
+    // every tile in one vram bank with the raw colour indices shaded in order,
+    // ignoring the palette registers
+    pub fn render_tile_sheet(&self, bank: u8) -> DebugImage {
+        let shades = self.config.palette.colors();
+        let vram = self.vram_bank_data(bank);
+        let mut image = DebugImage::new(TILE_SHEET_COLUMNS * TILE_SIDE, TILE_SHEET_ROWS * TILE_SIDE);
+        for tile in 0..TILE_SHEET_COLUMNS * TILE_SHEET_ROWS {
+            let left = tile % TILE_SHEET_COLUMNS * TILE_SIDE;
+            let top = tile / TILE_SHEET_COLUMNS * TILE_SIDE;
+            for row in 0..TILE_SIDE {
+                for column in 0..TILE_SIDE {
+                    let color = tile_pixel(vram, tile * 16 + row * 2, column);
+                    image.set_pixel(left + column, top + row, shades[color as usize]);
+                }
+            }
+        }
+        image
+    }
+    // the whole 256x256 plane of a tile map, using the current tile data mode and
+    // BGP. with overlays the screen's view through SCX/SCY is outlined, and when
+    // the window uses this map, the part of it that's on screen
+    pub fn render_tile_map(&self, map: TileMap, overlays: bool) -> DebugImage {
+        let shades = self.config.palette.colors();
+        let base = match map { TileMap::Low => TILE_MAP_0, TileMap::High => TILE_MAP_1 };
+        let vram = self.vram_bank_data(0);
+        let mut image = DebugImage::new(BACKGROUND_SIDE, BACKGROUND_SIDE);
+        for y in 0..BACKGROUND_SIDE {
+            for x in 0..BACKGROUND_SIDE {
+                let tile = vram[base + (y / TILE_SIDE) * TILE_MAP_SIDE + x / TILE_SIDE];
+                let row_address = tile_data_address(self.lcdc, tile) + (y % TILE_SIDE) * 2;
+                let color = tile_pixel(vram, row_address, x % TILE_SIDE);
+                image.set_pixel(x, y, shades[self.bg_palette.shade(color) as usize]);
+            }
+        }
+        if overlays {
+            if self.lcdc.bg_tile_map == (map == TileMap::High) {
+                image.outline(self.scroll_x as usize, self.scroll_y as usize, SCREEN_WIDTH, SCREEN_HEIGHT, VIEWPORT_COLOR);
+            }
+            let window_left = (self.window_x as usize).saturating_sub(WINDOW_X_OFFSET);
+            let window_visible = self.lcdc.window_enabled
+                && window_left < SCREEN_WIDTH
+                && (self.window_y as usize) < SCREEN_HEIGHT;
+            if window_visible && self.lcdc.window_tile_map == (map == TileMap::High) {
+                let width = SCREEN_WIDTH - window_left;
+                let height = SCREEN_HEIGHT - self.window_y as usize;
+                image.outline(0, 0, width, height, WINDOW_COLOR);
+            }
+        }
+        image
+    }
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        (0..SPRITE_COUNT).map(|index| {
+            let entry = &self.oam[index * OAM_ENTRY_SIZE..(index + 1) * OAM_ENTRY_SIZE];
+            let sprite = Sprite { y: entry[0], x: entry[1], tile: entry[2], flags: entry[3] };
+            OamEntry {
+                index,
+                x: sprite.x as i16 - SPRITE_X_OFFSET as i16,
+                y: sprite.y as i16 - SPRITE_Y_OFFSET as i16,
+                tile: sprite.tile,
+                behind_background: sprite.behind_background(),
+                y_flip: sprite.y_flip(),
+                x_flip: sprite.x_flip(),
+                palette: sprite.palette(),
+                cgb_palette: sprite.cgb_palette(),
+                tile_bank: sprite.tile_bank(),
+            }
+        }).collect()
+    }
+}
@@ -0,0 +1,193 @@
+// compares frames against stored golden images in regression tests. a golden is
+// either a PNG (any colour type or bit depth, expanded to 8 bit RGBA) or a raw
+// 160x144 RGBA dump like RecordingSink writes, told apart by the extension.
+//
+// a missing golden is written from the frame being checked, so a new test records
+// its own reference on the first run. to re-record after an intended change:
+//     UPDATE_GOLDEN=1 cargo test
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::frame::{Frame, FRAME_BYTES, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+// how a frame differs from its golden
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diff {
+    pub pixels: usize,
+    // (x, y, actual, expected) of the first differing pixel in reading order
+    pub first: (usize, usize, [u8; 4], [u8; 4]),
+    // left, top, right, bottom of the differing area, inclusive
+    pub bounds: (usize, usize, usize, usize),
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y, actual, expected) = self.first;
+        let (left, top, right, bottom) = self.bounds;
+        write!(f, "{} pixels differ inside ({}, {})-({}, {}), first at ({}, {}): got {:02X?}, expected {:02X?}",
+            self.pixels, left, top, right, bottom, x, y, actual, expected)
+    }
+}
+
+// None when every pixel matches
+pub fn compare(frame: &Frame, golden: &[u8]) -> Option<Diff> {
+    let mut diff: Option<Diff> = None;
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            let offset = (y * SCREEN_WIDTH + x) * 4;
+            let actual = frame.pixel(x, y);
+            let expected = [golden[offset], golden[offset + 1], golden[offset + 2], golden[offset + 3]];
+            if actual == expected { continue }
+            match &mut diff {
+                None => diff = Some(Diff { pixels: 1, first: (x, y, actual, expected), bounds: (x, y, x, y) }),
+                Some(diff) => {
+                    diff.pixels += 1;
+                    let (left, top, right, bottom) = diff.bounds;
+                    diff.bounds = (left.min(x), top.min(y), right.max(x), bottom.max(y));
+                }
+            }
+        }
+    }
+    diff
+}
+
+// the frame faded out with the differing pixels in red, to see at a glance
+// what's wrong
+pub fn diff_image(frame: &Frame, golden: &[u8]) -> Frame {
+    let mut image = Frame::new();
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            let offset = (y * SCREEN_WIDTH + x) * 4;
+            let actual = frame.pixel(x, y);
+            let pixel = if actual[..] == golden[offset..offset + 4] {
+                let faded = |channel: u8| 0xC0 + channel / 4;
+                [faded(actual[0]), faded(actual[1]), faded(actual[2]), 0xFF]
+            } else {
+                [0xFF, 0x00, 0x00, 0xFF]
+            };
+            image.set_pixel(x, y, pixel);
+        }
+    }
+    image
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// the golden as FRAME_BYTES of RGBA
+pub fn load(path: &Path) -> io::Result<Vec<u8>> {
+    if !is_png(path) {
+        let data = fs::read(path)?;
+        if data.len() != FRAME_BYTES {
+            return Err(invalid(format!("raw golden is {} bytes, expected {}", data.len(), FRAME_BYTES)));
+        }
+        return Ok(data);
+    }
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|error| invalid(error.to_string()))?;
+    let mut data = vec![0; reader.output_buffer_size().ok_or_else(|| invalid("png too large".to_string()))?];
+    let info = reader.next_frame(&mut data).map_err(|error| invalid(error.to_string()))?;
+    if (info.width as usize, info.height as usize) != (SCREEN_WIDTH, SCREEN_HEIGHT) {
+        return Err(invalid(format!("golden is {}x{}, expected {}x{}", info.width, info.height, SCREEN_WIDTH, SCREEN_HEIGHT)));
+    }
+    let data = &data[..info.buffer_size()];
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => data.to_vec(),
+        png::ColorType::Rgb => data.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF]).collect(),
+        png::ColorType::GrayscaleAlpha => data.chunks_exact(2).flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]]).collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|&g| [g, g, g, 0xFF]).collect(),
+        // expanded to rgb by the transformations above
+        png::ColorType::Indexed => unreachable!(),
+    };
+    Ok(rgba)
+}
+
+pub fn save(path: &Path, frame: &Frame) -> io::Result<()> {
+    if !is_png(path) {
+        return fs::write(path, &frame.pixels[..]);
+    }
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|error| invalid(error.to_string()))?;
+    writer.write_image_data(&frame.pixels[..]).map_err(|error| invalid(error.to_string()))
+}
+
+// next to the golden, e.g. title.png -> title.actual.png
+fn actual_path(path: &Path) -> PathBuf {
+    beside(path, "actual")
+}
+
+fn beside(path: &Path, kind: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{}.{}.{}", stem, kind, extension.to_string_lossy())),
+        None => path.with_file_name(format!("{}.{}", stem, kind)),
+    }
+}
+
+// what failing tests leave next to the golden: the frame as <name>.actual.<extension>
+// and a diff_image as <name>.diff.<extension>, described for the panic message
+pub fn save_failure(path: &Path, frame: &Frame, golden: &[u8]) -> String {
+    let actual = actual_path(path);
+    let diff = beside(path, "diff");
+    match save(&actual, frame).and_then(|()| save(&diff, &diff_image(frame, golden))) {
+        Ok(()) => format!("frame saved to {}, differences in red in {}", actual.display(), diff.display()),
+        Err(error) => format!("couldn't save the frame: {}", error),
+    }
+}
+
+// panics with a diff report when the frame doesn't match the golden at `path`,
+// leaving the frame and a diff image beside it to look at, see save_failure
+pub fn assert_matches_golden(frame: &Frame, path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    if std::env::var_os(UPDATE_VAR).is_some() || !path.exists() {
+        save(path, frame).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+        return;
+    }
+    let golden = load(path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    if let Some(diff) = compare(frame, &golden) {
+        panic!("{} doesn't match: {}, {}", path.display(), diff, save_failure(path, frame, &golden));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_and_raw_goldens_round_trip_and_report_differences() {
+        let dir = std::env::temp_dir().join(format!("gb-emulator-golden-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut frame = Frame::blank([0xE0, 0xF8, 0xD0, 0xFF]);
+        frame.set_pixel(3, 4, [0x08, 0x18, 0x20, 0xFF]);
+        for name in ["frame.png", "frame.rgba"] {
+            let path = dir.join(name);
+            save(&path, &frame).unwrap();
+            assert_eq!(compare(&frame, &load(&path).unwrap()), None);
+        }
+        let golden = load(&dir.join("frame.png")).unwrap();
+        frame.set_pixel(10, 2, [0x00, 0x00, 0x00, 0xFF]);
+        frame.set_pixel(3, 4, [0xE0, 0xF8, 0xD0, 0xFF]);
+        let diff = compare(&frame, &golden).unwrap();
+        assert_eq!(diff.pixels, 2);
+        assert_eq!(diff.first, (10, 2, [0x00, 0x00, 0x00, 0xFF], [0xE0, 0xF8, 0xD0, 0xFF]));
+        assert_eq!(diff.bounds, (3, 2, 10, 4));
+        assert_eq!(actual_path(&dir.join("frame.png")), dir.join("frame.actual.png"));
+        let image = diff_image(&frame, &golden);
+        assert_eq!(image.pixel(10, 2), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(image.pixel(0, 0), [0xF8, 0xFE, 0xF4, 0xFF]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+pub const BCPS: u16 = 0xFF68;
+pub const BCPD: u16 = 0xFF69;
+pub const OCPS: u16 = 0xFF6A;
+pub const OCPD: u16 = 0xFF6B;
+
+pub const PALETTE_COUNT: usize = 8;
+const PALETTE_RAM_SIZE: usize = PALETTE_COUNT * 4 * 2;
+
+// CGB colour palette memory: 8 palettes of 4 colours, each colour a little endian
+// RGB555 word. the cpu only sees it through an index register (BCPS/OCPS, bit 7
+// turns on auto increment after data writes) and a data register (BCPD/OCPD)
+#[derive(Copy, Clone)]
+pub struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+    index: u8,
+    auto_increment: bool,
+}
+
+impl PaletteRam {
+    // the boot rom leaves everything white
+    pub fn new() -> PaletteRam {
+        PaletteRam { data: [0xFF; PALETTE_RAM_SIZE], index: 0, auto_increment: false }
+    }
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.bytes(&self.data);
+        state.u8(self.spec());
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        state.bytes(&mut self.data)?;
+        self.write_spec(state.u8()?);
+        Ok(())
+    }
+    // bit 6 is unused and reads 1
+    pub fn spec(&self) -> u8 {
+        (self.auto_increment as u8) << 7 | 0x40 | self.index
+    }
+    pub fn write_spec(&mut self, value: u8) {
+        self.index = value & 0x3F;
+        self.auto_increment = value & 0x80 != 0;
+    }
+    pub fn data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+    pub fn write_data(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+        self.advance();
+    }
+    // a data write moves the index on even when the write itself was blocked
+    pub fn advance(&mut self) {
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+    pub fn color(&self, palette: usize, color: usize) -> u16 {
+        let offset = (palette * 4 + color) * 2;
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+    pub fn colors(&self) -> [[u16; 4]; PALETTE_COUNT] {
+        std::array::from_fn(|palette| std::array::from_fn(|color| self.color(palette, color)))
+    }
+}
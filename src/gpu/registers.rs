@@ -0,0 +1,161 @@
+// typed views of the LCD registers. each converts to and from the byte the cpu
+// sees, so masking of unused and read only bits lives in one place
+
+// FF40
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Lcdc {
+    pub lcd_enabled: bool,
+    // false: 0x9800, true: 0x9C00
+    pub window_tile_map: bool,
+    pub window_enabled: bool,
+    // false: 0x8800 with signed tile indices, true: 0x8000 with unsigned ones
+    pub tile_data_unsigned: bool,
+    // false: 0x9800, true: 0x9C00
+    pub bg_tile_map: bool,
+    // false: 8x8, true: 8x16
+    pub tall_sprites: bool,
+    pub sprites_enabled: bool,
+    // on CGB this is the background/window master priority instead
+    pub bg_enabled: bool,
+}
+
+impl From<u8> for Lcdc {
+    fn from(byte: u8) -> Self {
+        Lcdc {
+            lcd_enabled: byte & 0x80 != 0,
+            window_tile_map: byte & 0x40 != 0,
+            window_enabled: byte & 0x20 != 0,
+            tile_data_unsigned: byte & 0x10 != 0,
+            bg_tile_map: byte & 0x08 != 0,
+            tall_sprites: byte & 0x04 != 0,
+            sprites_enabled: byte & 0x02 != 0,
+            bg_enabled: byte & 0x01 != 0,
+        }
+    }
+}
+
+impl From<Lcdc> for u8 {
+    fn from(lcdc: Lcdc) -> u8 {
+        (lcdc.lcd_enabled as u8) << 7
+            | (lcdc.window_tile_map as u8) << 6
+            | (lcdc.window_enabled as u8) << 5
+            | (lcdc.tile_data_unsigned as u8) << 4
+            | (lcdc.bg_tile_map as u8) << 3
+            | (lcdc.tall_sprites as u8) << 2
+            | (lcdc.sprites_enabled as u8) << 1
+            | lcdc.bg_enabled as u8
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Mode {
+    #[default]
+    HBlank,
+    VBlank,
+    OamScan,
+    Drawing,
+}
+
+impl From<u8> for Mode {
+    fn from(byte: u8) -> Self {
+        match byte & 0x03 {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            _ => Mode::Drawing,
+        }
+    }
+}
+
+impl From<Mode> for u8 {
+    fn from(mode: Mode) -> u8 {
+        match mode {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Drawing => 3,
+        }
+    }
+}
+
+// FF41. mode and lyc_match are driven by the PPU, the cpu can only change the
+// interrupt selects. bit 7 is unused and reads 1
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Stat {
+    pub lyc_interrupt: bool,
+    pub oam_interrupt: bool,
+    pub vblank_interrupt: bool,
+    pub hblank_interrupt: bool,
+    pub lyc_match: bool,
+    pub mode: Mode,
+}
+
+impl Stat {
+    // a cpu write, leaves the read only bits alone
+    pub fn write(&mut self, byte: u8) {
+        let written = Stat::from(byte);
+        self.lyc_interrupt = written.lyc_interrupt;
+        self.oam_interrupt = written.oam_interrupt;
+        self.vblank_interrupt = written.vblank_interrupt;
+        self.hblank_interrupt = written.hblank_interrupt;
+    }
+    // the enabled sources are ORed into a single line and only its rising edge
+    // requests an interrupt, so a source becoming active while another one already
+    // holds the line high goes unnoticed
+    pub fn interrupt_line(&self) -> bool {
+        (self.lyc_interrupt && self.lyc_match)
+            || (self.oam_interrupt && self.mode == Mode::OamScan)
+            || (self.vblank_interrupt && self.mode == Mode::VBlank)
+            || (self.hblank_interrupt && self.mode == Mode::HBlank)
+    }
+}
+
+impl From<u8> for Stat {
+    fn from(byte: u8) -> Self {
+        Stat {
+            lyc_interrupt: byte & 0x40 != 0,
+            oam_interrupt: byte & 0x20 != 0,
+            vblank_interrupt: byte & 0x10 != 0,
+            hblank_interrupt: byte & 0x08 != 0,
+            lyc_match: byte & 0x04 != 0,
+            mode: Mode::from(byte),
+        }
+    }
+}
+
+impl From<Stat> for u8 {
+    fn from(stat: Stat) -> u8 {
+        0x80
+            | (stat.lyc_interrupt as u8) << 6
+            | (stat.oam_interrupt as u8) << 5
+            | (stat.vblank_interrupt as u8) << 4
+            | (stat.hblank_interrupt as u8) << 3
+            | (stat.lyc_match as u8) << 2
+            | u8::from(stat.mode)
+    }
+}
+
+// FF47-FF49, the shade (0 lightest, 3 darkest) each colour index maps to. object
+// palettes ignore index 0 since it's transparent, but the bits still read back
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Palette {
+    pub shades: [u8; 4],
+}
+
+impl Palette {
+    pub fn shade(&self, color: u8) -> u8 {
+        self.shades[(color & 0x03) as usize]
+    }
+}
+
+impl From<u8> for Palette {
+    fn from(byte: u8) -> Self {
+        Palette { shades: [byte & 0x03, (byte >> 2) & 0x03, (byte >> 4) & 0x03, (byte >> 6) & 0x03] }
+    }
+}
+
+impl From<Palette> for u8 {
+    fn from(palette: Palette) -> u8 {
+        palette.shades.iter().rev().fold(0, |byte, &shade| byte << 2 | (shade & 0x03))
+    }
+}
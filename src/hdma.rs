@@ -0,0 +1,161 @@
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+// CGB VRAM DMA (FF51-FF55). a general purpose transfer copies everything at
+// once, an hblank transfer copies one 16 byte block at the start of each hblank
+pub const HDMA_BEGIN: u16 = 0xFF51;
+pub const HDMA_END: u16 = 0xFF55;
+pub const BLOCK_SIZE: u16 = 0x10;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HdmaMode {
+    General,
+    HBlank,
+}
+
+// a block to copy, addresses are cpu addresses
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HdmaBlock {
+    pub source: u16,
+    pub destination: u16,
+    pub length: u16,
+}
+
+#[derive(Default)]
+pub struct Hdma {
+    source: u16,
+    // offset into VRAM
+    destination: u16,
+    // blocks left in the running hblank transfer
+    remaining: u8,
+    active: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        Hdma::default()
+    }
+    pub fn reset(&mut self) {
+        *self = Hdma::default();
+    }
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.u16(self.source);
+        state.u16(self.destination);
+        state.u8(self.remaining);
+        state.bool(self.active);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.source = state.u16()?;
+        self.destination = state.u16()? & 0x1FF0;
+        self.remaining = state.u8()?;
+        self.active = state.bool()?;
+        Ok(())
+    }
+    pub fn active(&self) -> bool {
+        self.active
+    }
+    // only FF55 can be read back: blocks left minus one, with bit 7 set once idle
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            HDMA_END if self.active => self.remaining.wrapping_sub(1) & 0x7F,
+            HDMA_END => 0x80 | (self.remaining.wrapping_sub(1) & 0x7F),
+            _ => 0xFF,
+        }
+    }
+    // returns the transfer to run right away for a general purpose DMA
+    pub fn write(&mut self, address: u16, value: u8) -> Option<HdmaBlock> {
+        match address {
+            0xFF51 => self.source = (self.source & 0x00FF) | (value as u16) << 8,
+            // the low four bits of both addresses are ignored
+            0xFF52 => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            0xFF53 => self.destination = (self.destination & 0x00FF) | ((value & 0x1F) as u16) << 8,
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16,
+            _ => {
+                let blocks = (value & 0x7F) + 1;
+                let mode = if value & 0x80 != 0 { HdmaMode::HBlank } else { HdmaMode::General };
+                match mode {
+                    // writing bit 7 clear during an hblank transfer stops it instead
+                    HdmaMode::General if self.active => self.active = false,
+                    HdmaMode::General => {
+                        let block = self.next_block(blocks as u16 * BLOCK_SIZE);
+                        self.remaining = 0;
+                        return Some(block);
+                    }
+                    HdmaMode::HBlank => {
+                        self.remaining = blocks;
+                        self.active = true;
+                    }
+                }
+            }
+        }
+        None
+    }
+    // the block to copy as the PPU enters hblank, if a transfer is running
+    pub fn hblank(&mut self) -> Option<HdmaBlock> {
+        if !self.active { return None }
+        let block = self.next_block(BLOCK_SIZE);
+        self.remaining -= 1;
+        self.active = self.remaining > 0;
+        Some(block)
+    }
+    fn next_block(&mut self, length: u16) -> HdmaBlock {
+        let block = HdmaBlock { source: self.source, destination: 0x8000 | self.destination, length };
+        self.source = self.source.wrapping_add(length);
+        self.destination = (self.destination + length) & 0x1FF0;
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(hdma: &mut Hdma, source: u16, destination: u16) {
+        hdma.write(0xFF51, (source >> 8) as u8);
+        hdma.write(0xFF52, source as u8);
+        hdma.write(0xFF53, (destination >> 8) as u8);
+        hdma.write(0xFF54, destination as u8);
+    }
+
+    #[test]
+    fn general_purpose_dma_copies_everything_at_once() {
+        let mut hdma = Hdma::new();
+        // the low nibbles and the top three destination bits are dropped
+        addresses(&mut hdma, 0xC12F, 0xE80F);
+        let block = hdma.write(HDMA_END, 0x03);
+        assert_eq!(block, Some(HdmaBlock { source: 0xC120, destination: 0x8800, length: 0x40 }));
+        assert!(!hdma.active());
+        assert_eq!(hdma.read(HDMA_END), 0xFF);
+        // a second transfer carries on from where the first stopped
+        let block = hdma.write(HDMA_END, 0x00);
+        assert_eq!(block, Some(HdmaBlock { source: 0xC160, destination: 0x8840, length: 0x10 }));
+    }
+
+    #[test]
+    fn hblank_dma_copies_a_block_per_hblank() {
+        let mut hdma = Hdma::new();
+        addresses(&mut hdma, 0x4000, 0x9000);
+        assert_eq!(hdma.write(HDMA_END, 0x82), None);
+        assert!(hdma.active());
+        assert_eq!(hdma.read(HDMA_END), 0x02);
+        assert_eq!(hdma.hblank(), Some(HdmaBlock { source: 0x4000, destination: 0x9000, length: 0x10 }));
+        assert_eq!(hdma.hblank(), Some(HdmaBlock { source: 0x4010, destination: 0x9010, length: 0x10 }));
+        assert_eq!(hdma.read(HDMA_END), 0x00);
+        assert_eq!(hdma.hblank().map(|block| block.source), Some(0x4020));
+        assert_eq!(hdma.hblank(), None);
+        assert_eq!(hdma.read(HDMA_END), 0xFF);
+    }
+
+    #[test]
+    fn cancelling_leaves_the_blocks_left_readable() {
+        let mut hdma = Hdma::new();
+        addresses(&mut hdma, 0x4000, 0x8000);
+        hdma.write(HDMA_END, 0x87);
+        hdma.hblank();
+        hdma.hblank();
+        // bit 7 clear stops it rather than starting a general purpose copy
+        assert_eq!(hdma.write(HDMA_END, 0x00), None);
+        assert!(!hdma.active());
+        assert_eq!(hdma.read(HDMA_END), 0x85);
+        assert_eq!(hdma.hblank(), None);
+    }
+}
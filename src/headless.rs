@@ -0,0 +1,125 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::gameboy::GameBoy;
+use crate::joypad::Button;
+
+#[derive(Debug)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+// inputs for an unattended run, one line per change:
+//   <frame> <button>...
+// holds exactly those buttons from that frame on, no buttons releases everything.
+// frames count from 0 and must go up, # starts a comment
+//   60 start
+//   62
+//   100 a right
+pub struct InputScript {
+    // (frame, buttons held) in frame order
+    changes: Vec<(u32, Vec<Button>)>,
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<InputScript, ScriptError> {
+        let mut changes: Vec<(u32, Vec<Button>)> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| ScriptError { line: index + 1, message };
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(frame) = words.next() else { continue };
+            let frame: u32 = frame.parse().map_err(|_| error(format!("bad frame number {:?}", frame)))?;
+            if let Some(&(last, _)) = changes.last() && frame <= last {
+                return Err(error(format!("frame {} isn't after frame {}", frame, last)));
+            }
+            let buttons = words
+                .map(|word| Button::from_name(word).ok_or_else(|| error(format!("unknown button {:?}", word))))
+                .collect::<Result<_, _>>()?;
+            changes.push((frame, buttons));
+        }
+        Ok(InputScript { changes })
+    }
+    // the buttons that change at `frame`, or None when nothing does
+    pub fn buttons_at(&self, frame: u32) -> Option<&[Button]> {
+        self.changes
+            .binary_search_by_key(&frame, |&(start, _)| start)
+            .ok()
+            .map(|index| self.changes[index].1.as_slice())
+    }
+}
+
+// what --headless does: run a fixed number of frames with no window or sound,
+// then write out what was asked for
+pub struct HeadlessRun {
+    pub frames: u32,
+    pub input: Option<InputScript>,
+    // the last frame, as a png
+    pub screenshot: Option<PathBuf>,
+    // how many times bigger than 160x144 the screenshot is
+    pub screenshot_scale: usize,
+    // every byte the game sent over the link cable
+    pub serial_log: Option<PathBuf>,
+}
+
+impl HeadlessRun {
+    pub fn new(frames: u32) -> HeadlessRun {
+        HeadlessRun { frames, input: None, screenshot: None, screenshot_scale: 1, serial_log: None }
+    }
+    pub fn run(&self, gameboy: &mut GameBoy) -> io::Result<()> {
+        gameboy.mmu_mut().serial.start_capture();
+        for frame in 0..self.frames {
+            if let Some(buttons) = self.input.as_ref().and_then(|input| input.buttons_at(frame)) {
+                for button in Button::ALL {
+                    gameboy.set_button(button, buttons.contains(&button));
+                }
+            }
+            gameboy.run_frame();
+        }
+        if let Some(path) = &self.serial_log {
+            fs::write(path, gameboy.mmu().serial.captured())?;
+        }
+        if let Some(path) = &self.screenshot {
+            save_screenshot(gameboy, path, self.screenshot_scale)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "png")]
+fn save_screenshot(gameboy: &GameBoy, path: &std::path::Path, scale: usize) -> io::Result<()> {
+    gameboy.screenshot(path, crate::scaler::Filter::Nearest(scale))
+}
+
+#[cfg(not(feature = "png"))]
+fn save_screenshot(_gameboy: &GameBoy, _path: &std::path::Path, _scale: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "screenshots need the png feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_list_buttons_held_from_a_frame() {
+        let script = InputScript::parse("# title screen\n60 start\n62\n\n100 A right # walk\n").unwrap();
+        assert_eq!(script.buttons_at(60), Some(&[Button::Start][..]));
+        assert_eq!(script.buttons_at(61), None);
+        assert_eq!(script.buttons_at(62), Some(&[][..]));
+        assert_eq!(script.buttons_at(100), Some(&[Button::A, Button::Right][..]));
+        let error = InputScript::parse("10 a\n5 b").err().unwrap();
+        assert_eq!(error.line, 2);
+        assert!(InputScript::parse("10 turbo").is_err());
+    }
+}
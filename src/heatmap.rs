@@ -0,0 +1,137 @@
+use std::cell::Cell;
+use std::io::{self, Write};
+
+pub const ADDRESS_SPACE_SIZE: usize = 0x10000;
+// heatmap images lay the address space out as a 256x256 grid (one row per high byte)
+pub const HEATMAP_IMAGE_SIDE: usize = 256;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+// per-address access counters, split by access kind
+#[derive(Clone)]
+pub struct HeatmapSnapshot {
+    pub reads: Vec<u32>,
+    pub writes: Vec<u32>,
+    pub executes: Vec<u32>,
+    // number of instructions covered by these counts
+    pub instructions: u64,
+}
+
+impl HeatmapSnapshot {
+    fn empty() -> HeatmapSnapshot {
+        HeatmapSnapshot {
+            reads: vec![0; ADDRESS_SPACE_SIZE],
+            writes: vec![0; ADDRESS_SPACE_SIZE],
+            executes: vec![0; ADDRESS_SPACE_SIZE],
+            instructions: 0,
+        }
+    }
+    pub fn channel(&self, kind: AccessKind) -> &[u32] {
+        match kind {
+            AccessKind::Read => &self.reads,
+            AccessKind::Write => &self.writes,
+            AccessKind::Execute => &self.executes,
+        }
+    }
+    // one line per touched address: address,reads,writes,executes
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "address,reads,writes,executes")?;
+        for address in 0..ADDRESS_SPACE_SIZE {
+            let (r, w, x) = (self.reads[address], self.writes[address], self.executes[address]);
+            if r == 0 && w == 0 && x == 0 { continue }
+            writeln!(writer, "{:04X},{},{},{}", address, r, w, x)?;
+        }
+        Ok(())
+    }
+    // render a single channel as a 256x256 RGBA image, log scaled so rarely touched
+    // addresses are still visible next to hot loops
+    pub fn to_rgba(&self, kind: AccessKind) -> Vec<u8> {
+        let counts = self.channel(kind);
+        let max = counts.iter().copied().max().unwrap_or(0);
+        let max_log = ((max as f32) + 1.0).ln();
+        let mut image = vec![0; ADDRESS_SPACE_SIZE * 4];
+        for (address, &count) in counts.iter().enumerate() {
+            let intensity = if count == 0 || max_log == 0.0 { 0 }
+                else { (((count as f32) + 1.0).ln() / max_log * 255.0) as u8 };
+            let (r, g, b) = match kind {
+                AccessKind::Read => (0, intensity, 0),
+                AccessKind::Write => (intensity, 0, 0),
+                AccessKind::Execute => (0, 0, intensity),
+            };
+            image[address * 4..address * 4 + 4].copy_from_slice(&[r, g, b, 0xFF]);
+        }
+        image
+    }
+}
+
+// accumulates access counts for every address on the bus. counters use Cell so
+// reads (which only borrow the bus immutably) can still be recorded
+pub struct MemoryHeatmap {
+    reads: Vec<Cell<u32>>,
+    writes: Vec<Cell<u32>>,
+    executes: Vec<Cell<u32>>,
+    instructions: u64,
+    // number of instructions per window, None accumulates forever
+    window: Option<u64>,
+    last_window: Option<HeatmapSnapshot>,
+}
+
+impl MemoryHeatmap {
+    pub fn new(window: Option<u64>) -> MemoryHeatmap {
+        MemoryHeatmap {
+            reads: vec![Cell::new(0); ADDRESS_SPACE_SIZE],
+            writes: vec![Cell::new(0); ADDRESS_SPACE_SIZE],
+            executes: vec![Cell::new(0); ADDRESS_SPACE_SIZE],
+            instructions: 0,
+            window,
+            last_window: None,
+        }
+    }
+    pub fn record(&self, kind: AccessKind, address: u16) {
+        let counter = match kind {
+            AccessKind::Read => &self.reads[address as usize],
+            AccessKind::Write => &self.writes[address as usize],
+            AccessKind::Execute => &self.executes[address as usize],
+        };
+        counter.set(counter.get().saturating_add(1));
+    }
+    // called once per executed instruction, rolls the window over when it fills up
+    pub fn end_instruction(&mut self) {
+        self.instructions += 1;
+        if self.window.is_some_and(|window| self.instructions >= window) {
+            self.last_window = Some(self.snapshot());
+            self.clear();
+        }
+    }
+    pub fn set_window(&mut self, window: Option<u64>) {
+        self.window = window;
+        self.clear();
+        self.last_window = None;
+    }
+    pub fn clear(&mut self) {
+        for counter in self.reads.iter().chain(&self.writes).chain(&self.executes) {
+            counter.set(0);
+        }
+        self.instructions = 0;
+    }
+    // counts accumulated in the window currently being filled
+    pub fn snapshot(&self) -> HeatmapSnapshot {
+        let mut snapshot = HeatmapSnapshot::empty();
+        for address in 0..ADDRESS_SPACE_SIZE {
+            snapshot.reads[address] = self.reads[address].get();
+            snapshot.writes[address] = self.writes[address].get();
+            snapshot.executes[address] = self.executes[address].get();
+        }
+        snapshot.instructions = self.instructions;
+        snapshot
+    }
+    // most recently completed window, if a window size is configured
+    pub fn last_window(&self) -> Option<&HeatmapSnapshot> {
+        self.last_window.as_ref()
+    }
+}
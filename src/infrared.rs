@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+pub const RP: u16 = 0xFF56;
+
+// what the CGB's infrared receiver sees
+pub enum IrInput {
+    // nothing shining at it, as with nobody else around
+    Dark,
+    // a constant light source, e.g. a remote held down
+    Light,
+    // another emulator's LED
+    Linked(IrLink),
+}
+
+// one end of a pair of facing IR ports: its own LED is the other end's light.
+// the ends can live in different threads
+pub struct IrLink {
+    led: Arc<AtomicBool>,
+    light: Arc<AtomicBool>,
+}
+
+impl IrLink {
+    pub fn pair() -> (IrLink, IrLink) {
+        let first = Arc::new(AtomicBool::new(false));
+        let second = Arc::new(AtomicBool::new(false));
+        (IrLink { led: first.clone(), light: second.clone() }, IrLink { led: second, light: first })
+    }
+    fn set_led(&self, on: bool) {
+        self.led.store(on, Ordering::Relaxed);
+    }
+    fn light(&self) -> bool {
+        self.light.load(Ordering::Relaxed)
+    }
+}
+
+// RP: bit 0 turns the LED on, bits 6-7 both set enable reading, and bit 1 then
+// reads 0 while light is being received
+pub struct Infrared {
+    rp: u8,
+    input: IrInput,
+}
+
+impl Infrared {
+    pub fn new() -> Infrared {
+        Infrared { rp: 0, input: IrInput::Dark }
+    }
+    // the input stays plugged in
+    pub fn reset(&mut self) {
+        self.write(RP, 0);
+    }
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.u8(self.rp);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        let rp = state.u8()?;
+        self.write(RP, rp);
+        Ok(())
+    }
+    pub fn set_input(&mut self, input: IrInput) {
+        self.input = input;
+        self.write(RP, self.rp);
+    }
+    fn receiving(&self) -> bool {
+        if self.rp & 0xC0 != 0xC0 { return false }
+        match &self.input {
+            IrInput::Dark => false,
+            IrInput::Light => true,
+            IrInput::Linked(link) => link.light(),
+        }
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            RP => 0x3C | self.rp | (!self.receiving() as u8) << 1,
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        if address != RP { return }
+        self.rp = value & 0xC1;
+        if let IrInput::Linked(link) = &self.input {
+            link.set_led(self.rp & 0x01 != 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linked_ports_see_each_others_led() {
+        let (first, second) = IrLink::pair();
+        let mut sender = Infrared::new();
+        let mut receiver = Infrared::new();
+        sender.set_input(IrInput::Linked(first));
+        receiver.set_input(IrInput::Linked(second));
+        sender.write(RP, 0x01);
+        // nothing is read until reading is enabled
+        assert_eq!(receiver.read(RP), 0x3E);
+        receiver.write(RP, 0xC0);
+        assert_eq!(receiver.read(RP), 0xFC);
+        sender.write(RP, 0x00);
+        assert_eq!(receiver.read(RP), 0xFE);
+        receiver.set_input(IrInput::Light);
+        assert_eq!(receiver.read(RP), 0xFC);
+    }
+}
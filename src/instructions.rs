@@ -5,17 +5,18 @@ pub enum JumpTest {
     Carry,
     Always,
 }
+// HighC and HighN8 are the 0xFF00 page offset by C or an immediate byte (LDH)
 pub enum LoadByteTarget {
-    A, B, C, D, E, H, L, BC, DE, HL,
+    A, B, C, D, E, H, L, BC, DE, HL, A16, HighC, HighN8,
 }
 pub enum LoadByteSource {
-    A, B, C, D, E, H, L, BC, DE, HL, N8,
+    A, B, C, D, E, H, L, BC, DE, HL, N8, A16, HighC, HighN8,
 }
 pub enum LoadWordTarget {
     BC, DE, HL, SP, A16,
 }
 pub enum LoadWordSource {
-    N16, SP,
+    N16, SP, HL,
 }
 pub enum LoadIncDecTarget {
     HL, A,
@@ -47,10 +48,22 @@ pub enum PrefixedTarget {
 }
 pub enum Instruction {
     // Standard Instructions
+    NOP(),
+    HALT(),
+    STOP(),
+    DI(),
+    EI(),
     JP(JumpTest),
     JR(JumpTest),
     JPHL(),
+    CALL(JumpTest),
+    RET(JumpTest),
+    RETI(),
+    // call to a fixed address in page 0
+    RST(u8),
     LD(LoadType),
+    // LD HL, SP + signed immediate
+    LDHLSP(),
     POP(StackTarget),
     PUSH(StackTarget),
     INC(PrefixedTarget),
@@ -58,6 +71,8 @@ pub enum Instruction {
     INC16(ArithmeticWordTarget),
     DEC16(ArithmeticWordTarget),
     ADDHL(ArithmeticWordTarget),
+    // ADD SP, signed immediate
+    ADDSP(),
     ADD(ArithmeticByteTarget),
     ADC(ArithmeticByteTarget),
     SUB(ArithmeticByteTarget),
@@ -70,6 +85,7 @@ pub enum Instruction {
     RRCA(),
     RLA(),
     RRA(),
+    DAA(),
     CPL(),
     SCF(),
     CCF(),
@@ -98,6 +114,80 @@ impl Instruction {
 
     fn from_byte_standard(byte: u8) -> Option<Instruction> {
         match byte {
+            // Control
+            0x00 => Some(Instruction::NOP()),
+            0x10 => Some(Instruction::STOP()),
+            0x76 => Some(Instruction::HALT()),
+            0xF3 => Some(Instruction::DI()),
+            0xFB => Some(Instruction::EI()),
+            // JP
+            0xC3 => Some(Instruction::JP(JumpTest::Always)),
+            0xC2 => Some(Instruction::JP(JumpTest::NotZero)),
+            0xCA => Some(Instruction::JP(JumpTest::Zero)),
+            0xD2 => Some(Instruction::JP(JumpTest::NotCarry)),
+            0xDA => Some(Instruction::JP(JumpTest::Carry)),
+            0xE9 => Some(Instruction::JPHL()),
+            // JR
+            0x18 => Some(Instruction::JR(JumpTest::Always)),
+            0x20 => Some(Instruction::JR(JumpTest::NotZero)),
+            0x28 => Some(Instruction::JR(JumpTest::Zero)),
+            0x30 => Some(Instruction::JR(JumpTest::NotCarry)),
+            0x38 => Some(Instruction::JR(JumpTest::Carry)),
+            // CALL
+            0xCD => Some(Instruction::CALL(JumpTest::Always)),
+            0xC4 => Some(Instruction::CALL(JumpTest::NotZero)),
+            0xCC => Some(Instruction::CALL(JumpTest::Zero)),
+            0xD4 => Some(Instruction::CALL(JumpTest::NotCarry)),
+            0xDC => Some(Instruction::CALL(JumpTest::Carry)),
+            // RET
+            0xC9 => Some(Instruction::RET(JumpTest::Always)),
+            0xC0 => Some(Instruction::RET(JumpTest::NotZero)),
+            0xC8 => Some(Instruction::RET(JumpTest::Zero)),
+            0xD0 => Some(Instruction::RET(JumpTest::NotCarry)),
+            0xD8 => Some(Instruction::RET(JumpTest::Carry)),
+            0xD9 => Some(Instruction::RETI()),
+            // RST
+            0xC7 => Some(Instruction::RST(0x00)),
+            0xCF => Some(Instruction::RST(0x08)),
+            0xD7 => Some(Instruction::RST(0x10)),
+            0xDF => Some(Instruction::RST(0x18)),
+            0xE7 => Some(Instruction::RST(0x20)),
+            0xEF => Some(Instruction::RST(0x28)),
+            0xF7 => Some(Instruction::RST(0x30)),
+            0xFF => Some(Instruction::RST(0x38)),
+            // Accumulator rotates and flag operations
+            0x07 => Some(Instruction::RLCA()),
+            0x0F => Some(Instruction::RRCA()),
+            0x17 => Some(Instruction::RLA()),
+            0x1F => Some(Instruction::RRA()),
+            0x27 => Some(Instruction::DAA()),
+            0x2F => Some(Instruction::CPL()),
+            0x37 => Some(Instruction::SCF()),
+            0x3F => Some(Instruction::CCF()),
+            // LD Word
+            0x01 => Some(Instruction::LD(LoadType::Word(LoadWordTarget::BC, LoadWordSource::N16))),
+            0x11 => Some(Instruction::LD(LoadType::Word(LoadWordTarget::DE, LoadWordSource::N16))),
+            0x21 => Some(Instruction::LD(LoadType::Word(LoadWordTarget::HL, LoadWordSource::N16))),
+            0x31 => Some(Instruction::LD(LoadType::Word(LoadWordTarget::SP, LoadWordSource::N16))),
+            0x08 => Some(Instruction::LD(LoadType::Word(LoadWordTarget::A16, LoadWordSource::SP))),
+            0xF9 => Some(Instruction::LD(LoadType::Word(LoadWordTarget::SP, LoadWordSource::HL))),
+            0xF8 => Some(Instruction::LDHLSP()),
+            0xE8 => Some(Instruction::ADDSP()),
+            // LD Byte, indirect through BC/DE, HL+/HL- and absolute addresses
+            0x02 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::BC, LoadByteSource::A))),
+            0x12 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::DE, LoadByteSource::A))),
+            0x0A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::BC))),
+            0x1A => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::DE))),
+            0x22 => Some(Instruction::LD(LoadType::AddressIncDec(LoadIncDecTarget::HL, LoadIncDecSource::A, AddressMode::Inc))),
+            0x32 => Some(Instruction::LD(LoadType::AddressIncDec(LoadIncDecTarget::HL, LoadIncDecSource::A, AddressMode::Dec))),
+            0x2A => Some(Instruction::LD(LoadType::AddressIncDec(LoadIncDecTarget::A, LoadIncDecSource::HL, AddressMode::Inc))),
+            0x3A => Some(Instruction::LD(LoadType::AddressIncDec(LoadIncDecTarget::A, LoadIncDecSource::HL, AddressMode::Dec))),
+            0xEA => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A16, LoadByteSource::A))),
+            0xFA => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::A16))),
+            0xE0 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HighN8, LoadByteSource::A))),
+            0xF0 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::HighN8))),
+            0xE2 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HighC, LoadByteSource::A))),
+            0xF2 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::HighC))),
             // INC
             0x04 => Some(Instruction::INC(PrefixedTarget::B)),
             0x0C => Some(Instruction::INC(PrefixedTarget::C)),
@@ -199,7 +289,6 @@ impl Instruction {
             0x73 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HL, LoadByteSource::E))),
             0x74 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HL, LoadByteSource::H))),
             0x75 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HL, LoadByteSource::L))),
-            // 0x76 => HALT
             0x77 => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::HL, LoadByteSource::A))),
             // LD Byte, Target A
             0x3E => Some(Instruction::LD(LoadType::Byte(LoadByteTarget::A, LoadByteSource::N8))),
@@ -301,7 +390,9 @@ impl Instruction {
             0xD5 => Some(Instruction::PUSH(StackTarget::DE)),
             0xE5 => Some(Instruction::PUSH(StackTarget::HL)),
             0xF5 => Some(Instruction::PUSH(StackTarget::AF)),
-            _ => panic!("Unknown instruction (exited from_byte_standard)"),
+            // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC and 0xFD
+            // don't exist, the real cpu locks up on them
+            _ => None,
         }
     }
 
@@ -0,0 +1,153 @@
+use crate::model::Model;
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+pub const IO_BEGIN: usize = 0xFF00;
+pub const IO_END: usize = 0xFF7F;
+pub const IO_SIZE: usize = IO_END - IO_BEGIN + 1;
+
+// which bits of a register can be read back and which can be written. bits that
+// can't be read return 1, like the undriven lines on hardware
+#[derive(Copy, Clone)]
+struct RegisterBits {
+    readable: u8,
+    writable: u8,
+}
+
+const UNMAPPED: RegisterBits = RegisterBits { readable: 0x00, writable: 0x00 };
+const READ_WRITE: RegisterBits = RegisterBits { readable: 0xFF, writable: 0xFF };
+const WRITE_ONLY: RegisterBits = RegisterBits { readable: 0x00, writable: 0xFF };
+const fn bits(readable: u8, writable: u8) -> RegisterBits {
+    RegisterBits { readable, writable }
+}
+
+fn dmg_register_bits(address: u16) -> RegisterBits {
+    match address {
+        0xFF00 => bits(0x3F, 0x30),        // P1, low nibble comes from the buttons
+        0xFF01 => READ_WRITE,              // SB
+        0xFF02 => bits(0x81, 0x81),        // SC
+        0xFF04..=0xFF06 => READ_WRITE,     // DIV, TIMA, TMA
+        0xFF07 => bits(0x07, 0x07),        // TAC
+        0xFF0F => bits(0x1F, 0x1F),        // IF
+        0xFF10 => bits(0x7F, 0x7F),        // NR10
+        0xFF11 | 0xFF16 => bits(0xC0, 0xFF), // NR11/NR21, length is write only
+        0xFF12 | 0xFF17 | 0xFF21 | 0xFF22 | 0xFF24 | 0xFF25 => READ_WRITE,
+        0xFF13 | 0xFF18 | 0xFF1B | 0xFF1D | 0xFF20 => WRITE_ONLY, // period low bytes and lengths
+        0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => bits(0x40, 0xFF), // only the length enable reads back
+        0xFF1A => bits(0x80, 0x80),        // NR30
+        0xFF1C => bits(0x60, 0x60),        // NR32
+        0xFF26 => bits(0x8F, 0x80),        // NR52, channel status bits are read only
+        0xFF30..=0xFF3F => READ_WRITE,     // wave ram
+        0xFF40 => READ_WRITE,              // LCDC
+        0xFF41 => bits(0x7F, 0x78),        // STAT, mode and coincidence are read only
+        0xFF42 | 0xFF43 => READ_WRITE,     // SCY, SCX
+        0xFF44 => bits(0xFF, 0x00),        // LY
+        0xFF45..=0xFF4B => READ_WRITE,     // LYC, DMA, BGP, OBP0, OBP1, WY, WX
+        0xFF50 => WRITE_ONLY,              // boot rom disable
+        _ => UNMAPPED,
+    }
+}
+
+fn cgb_register_bits(address: u16) -> RegisterBits {
+    match address {
+        0xFF02 => bits(0x83, 0x83),        // SC gains the clock speed bit
+        0xFF4D => bits(0x81, 0x01),        // KEY1
+        0xFF4F => bits(0x01, 0x01),        // VBK
+        0xFF51..=0xFF54 => WRITE_ONLY,     // HDMA source/destination
+        0xFF55 => READ_WRITE,              // HDMA5
+        0xFF56 => bits(0xC3, 0xC1),        // RP
+        0xFF68 | 0xFF6A => bits(0xBF, 0xBF), // BCPS, OCPS
+        0xFF69 | 0xFF6B => READ_WRITE,     // BCPD, OCPD
+        0xFF6C => bits(0x01, 0x01),        // OPRI
+        0xFF70 => bits(0x07, 0x07),        // SVBK
+        0xFF76 | 0xFF77 => bits(0xFF, 0x00), // PCM12, PCM34
+        _ => dmg_register_bits(address),
+    }
+}
+
+// the FF00-FF7F registers no component on the bus answers for (see
+// Mmu::read_io), stored with their read/write masks applied. unmapped ones read 0xFF
+pub struct Io {
+    registers: [u8; IO_SIZE],
+    bits: [RegisterBits; IO_SIZE],
+}
+
+impl Io {
+    pub fn new() -> Io {
+        let mut io = Io {
+            registers: [0; IO_SIZE],
+            bits: [UNMAPPED; IO_SIZE],
+        };
+        io.set_model(Model::default());
+        io
+    }
+    pub fn set_model(&mut self, model: Model) {
+        for (index, bits) in self.bits.iter_mut().enumerate() {
+            let address = (IO_BEGIN + index) as u16;
+            *bits = if model.is_cgb() { cgb_register_bits(address) } else { dmg_register_bits(address) };
+        }
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        let index = address as usize - IO_BEGIN;
+        self.registers[index] | !self.bits[index].readable
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        let index = address as usize - IO_BEGIN;
+        let writable = self.bits[index].writable;
+        self.registers[index] = (self.registers[index] & !writable) | (value & writable);
+    }
+    // for hardware updating its own read only bits (LY, STAT mode, NR52 status, ...)
+    pub fn set_raw(&mut self, address: u16, value: u8) {
+        self.registers[address as usize - IO_BEGIN] = value;
+    }
+    pub fn raw(&self, address: u16) -> u8 {
+        self.registers[address as usize - IO_BEGIN]
+    }
+    pub fn reset(&mut self) {
+        self.registers = [0; IO_SIZE];
+    }
+    // the components behind Mmu::read_io save their own registers
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.bytes(&self.registers);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        state.bytes(&mut self.registers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_apply_to_reads_and_writes() {
+        let mut io = Io::new();
+        // TAC only has three bits, the rest read back as 1
+        io.write(0xFF07, 0xFF);
+        assert_eq!(io.read(0xFF07), 0xFF);
+        io.write(0xFF07, 0x00);
+        assert_eq!(io.read(0xFF07), 0xF8);
+        // period low bytes can be written but never read
+        io.write(0xFF13, 0x12);
+        assert_eq!(io.raw(0xFF13), 0x12);
+        assert_eq!(io.read(0xFF13), 0xFF);
+        // the NR52 status bits only change through set_raw
+        io.write(0xFF26, 0x0F);
+        assert_eq!(io.read(0xFF26), 0x70);
+        io.set_raw(0xFF26, 0x81);
+        assert_eq!(io.read(0xFF26), 0xF1);
+    }
+
+    #[test]
+    fn unmapped_registers_read_open_bus() {
+        let mut io = Io::new();
+        for address in [0xFF03, 0xFF08, 0xFF15, 0xFF27, 0xFF4C, 0xFF4D, 0xFF70, 0xFF7F] {
+            io.write(address, 0x00);
+            assert_eq!(io.read(address), 0xFF, "{:04X}", address);
+        }
+        // the CGB registers only exist on CGB
+        io.set_model(Model::Cgb);
+        io.write(0xFF70, 0x00);
+        assert_eq!(io.read(0xFF70), 0xF8);
+        assert_eq!(io.read(0xFF4C), 0xFF);
+    }
+}
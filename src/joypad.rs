@@ -0,0 +1,151 @@
+use crate::cpu::JOYPAD_INTERRUPT;
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+pub const P1: u16 = 0xFF00;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right, Button::Left, Button::Up, Button::Down,
+        Button::A, Button::B, Button::Select, Button::Start,
+    ];
+    // lowercase, as used in input scripts and config files
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Right => "right",
+            Button::Left => "left",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::A => "a",
+            Button::B => "b",
+            Button::Select => "select",
+            Button::Start => "start",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::ALL.into_iter().find(|button| button.name().eq_ignore_ascii_case(name))
+    }
+    // bit in `pressed`: the direction keys are the low nibble, the buttons the high one,
+    // each in the order P1 reports them
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+// P1 bits 4 and 5 select the direction keys and the buttons (0 selects), and the
+// low nibble reads 0 for every pressed key in the selected groups. a line going
+// low requests the joypad interrupt
+pub struct Joypad {
+    select: u8,
+    pressed: u8,
+    interrupts: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad { select: 0x30, pressed: 0, interrupts: 0 }
+    }
+    // the keys are still held after a reset
+    pub fn reset(&mut self) {
+        self.select = 0x30;
+        self.interrupts = 0;
+    }
+    // the buttons held are the frontend's business and stay as they are
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.bytes(&[self.select, self.interrupts]);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.select = state.u8()?;
+        self.interrupts = state.u8()?;
+        Ok(())
+    }
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let lines = self.lines();
+        if pressed {
+            self.pressed |= button.mask();
+        } else {
+            self.pressed &= !button.mask();
+        }
+        self.update_interrupt(lines);
+    }
+    pub fn pressed(&self, button: Button) -> bool {
+        self.pressed & button.mask() != 0
+    }
+    // the low nibble as the cpu sees it, 1 for released
+    fn lines(&self) -> u8 {
+        let mut low = 0;
+        if self.select & 0x10 == 0 {
+            low |= self.pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            low |= self.pressed >> 4;
+        }
+        !low & 0x0F
+    }
+    fn update_interrupt(&mut self, before: u8) {
+        if before & !self.lines() != 0 {
+            self.interrupts |= JOYPAD_INTERRUPT;
+        }
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            P1 => 0xC0 | self.select | self.lines(),
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        if address != P1 { return }
+        let lines = self.lines();
+        self.select = value & 0x30;
+        self.update_interrupt(lines);
+    }
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selected_groups_read_low_and_presses_interrupt() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::Start, true);
+        joypad.set_button(Button::Left, true);
+        // nothing selected, nothing seen
+        assert_eq!(joypad.read(P1), 0xFF);
+        assert_eq!(joypad.take_interrupts(), 0);
+        joypad.write(P1, 0x10);
+        assert_eq!(joypad.read(P1), 0xD7);
+        // selecting a group with a key held pulls a line low
+        assert_eq!(joypad.take_interrupts(), JOYPAD_INTERRUPT);
+        joypad.write(P1, 0x20);
+        assert_eq!(joypad.read(P1), 0xED);
+        joypad.set_button(Button::Down, true);
+        assert_eq!(joypad.read(P1), 0xE5);
+        assert_eq!(joypad.take_interrupts(), JOYPAD_INTERRUPT);
+        joypad.set_button(Button::A, true);
+        assert_eq!(joypad.take_interrupts(), 0);
+    }
+}
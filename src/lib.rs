@@ -0,0 +1,106 @@
+// components are built with new() and don't all implement Default
+#![allow(clippy::new_without_default)]
+
+pub mod registers;
+
+#[allow(clippy::upper_case_acronyms)]
+pub mod instructions;
+
+#[allow(non_snake_case)]
+#[allow(clippy::upper_case_acronyms)]
+pub mod cpu;
+
+#[allow(clippy::upper_case_acronyms)]
+pub mod gpu;
+
+pub mod address;
+
+pub mod apu;
+
+#[cfg(feature = "archives")]
+pub mod archive;
+
+pub mod cartridge;
+
+pub mod color_correction;
+
+pub mod cdl;
+
+pub mod cheats;
+
+pub mod colorization;
+
+pub mod config;
+
+pub mod cost_model;
+
+pub mod debugger;
+
+pub mod disassembler;
+
+pub mod events;
+
+pub mod frame;
+
+pub mod frontend;
+
+pub mod gameboy;
+
+pub mod hdma;
+
+pub mod headless;
+
+pub mod heatmap;
+
+pub mod infrared;
+
+pub mod io;
+
+pub mod joypad;
+
+pub mod mbc;
+
+pub mod mmu;
+
+pub mod model;
+
+pub mod movie;
+
+pub mod oam_dma;
+
+pub mod osd;
+
+pub mod patch;
+
+pub mod recorder;
+
+pub mod renderer;
+
+pub mod reset;
+
+pub mod savestate;
+
+pub mod scaler;
+
+#[cfg(feature = "scripting")]
+pub mod scripting;
+
+pub mod serial;
+
+pub mod sgb;
+
+pub mod symbols;
+
+pub mod timer;
+
+pub mod timing;
+
+pub mod trace;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use gameboy::{GameBoy, GameBoyBuilder, GameBoyError};
+pub use joypad::Button;
+pub use model::Model;
+pub use savestate::StateError;
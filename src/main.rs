@@ -1,16 +1,199 @@
-#[allow(dead_code)]
-mod registers;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-#[allow(dead_code)]
-mod instructions;
+use gb_emulator::cartridge::Cartridge;
+use gb_emulator::debugger::Repl;
+use gb_emulator::headless::{HeadlessRun, InputScript};
+use gb_emulator::reset::DEFAULT_RAM_SEED;
+use gb_emulator::savestate;
+use gb_emulator::symbols::Symbols;
+use gb_emulator::GameBoy;
 
-#[allow(non_snake_case)]
-#[allow(dead_code)]
-mod cpu;
+// ten seconds
+const DEFAULT_HEADLESS_FRAMES: u32 = 600;
 
-#[allow(dead_code)]
-mod gpu;
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("inspect-state") => {
+            let Some(path) = args.get(1) else {
+                eprintln!("usage: gb-emulator inspect-state <state file>");
+                return ExitCode::FAILURE;
+            };
+            let summary = File::open(path).and_then(|file| savestate::inspect(BufReader::new(file)));
+            match summary {
+                Ok(summary) => print!("{}", summary),
+                Err(error) => {
+                    eprintln!("{}: {}", path, error);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        Some("--headless") => return headless(&args[1..]),
+        Some("--debug") => return debug(&args[1..]),
+        Some(path) => return play(path, &args[1..]),
+        None => {
+            eprintln!(
+                "usage: gb-emulator <rom> [options] | --headless <rom> [options] | --debug <rom> [options] | inspect-state <state file>"
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+const HEADLESS_USAGE: &str = "usage: gb-emulator --headless <rom> [--frames N] [--input script.txt] \
+[--screenshot out.png] [--scale N] [--serial-log serial.txt]";
+
+// runs deterministically, so the same rom and script always give the same output
+fn headless(args: &[String]) -> ExitCode {
+    match run_headless(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_headless(args: &[String]) -> Result<(), String> {
+    let mut rom = None;
+    let mut run = HeadlessRun::new(DEFAULT_HEADLESS_FRAMES);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value\n{}", arg, HEADLESS_USAGE));
+        match arg.as_str() {
+            "--frames" => run.frames = value()?.parse().map_err(|_| format!("bad frame count\n{}", HEADLESS_USAGE))?,
+            "--input" => {
+                let path = value()?;
+                let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+                run.input = Some(InputScript::parse(&text).map_err(|error| format!("{}: {}", path, error))?);
+            }
+            "--screenshot" => run.screenshot = Some(PathBuf::from(value()?)),
+            "--scale" => run.screenshot_scale = value()?.parse().map_err(|_| format!("bad scale\n{}", HEADLESS_USAGE))?,
+            "--serial-log" => run.serial_log = Some(PathBuf::from(value()?)),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => return Err(format!("unexpected {}\n{}", arg, HEADLESS_USAGE)),
+        }
+    }
+    let path = rom.ok_or(HEADLESS_USAGE)?;
+    let cartridge = Cartridge::from_file(path).map_err(|error| format!("{}: {}", path, error))?;
+    let mut gameboy = GameBoy::with_cartridge(cartridge)
+        .deterministic(DEFAULT_RAM_SEED)
+        .build()
+        .map_err(|error| format!("{}: {}", path, error))?;
+    run.run(&mut gameboy).map_err(|error| error.to_string())
+}
+
+const DEBUG_USAGE: &str = "usage: gb-emulator --debug <rom> [--symbols game.sym]";
+
+// a prompt on the terminal, stopped before the first instruction of the game.
+// labels come from the symbol file given, or the one rgblink left next to the rom
+fn debug(args: &[String]) -> ExitCode {
+    let (path, symbols) = match args {
+        [path] => (path, Path::new(path).with_extension("sym")),
+        [path, option, symbols] if option == "--symbols" => (path, PathBuf::from(symbols)),
+        _ => {
+            eprintln!("{}", DEBUG_USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let gameboy = Cartridge::from_file(path)
+        .map_err(|error| error.to_string())
+        .and_then(|cartridge| GameBoy::with_cartridge(cartridge).build().map_err(|error| error.to_string()));
+    let mut gameboy = match gameboy {
+        Ok(gameboy) => gameboy,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return ExitCode::FAILURE;
+        }
+    };
+    let debugger = gameboy.attach_debugger();
+    debugger.pause();
+    if symbols.exists() {
+        match Symbols::load(&symbols) {
+            Ok(symbols) => debugger.set_symbols(symbols),
+            Err(error) => {
+                eprintln!("{}: {}", symbols.display(), error);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    println!("type help for the commands");
+    let stdin = std::io::stdin();
+    match Repl::new().run(&mut gameboy, stdin.lock(), std::io::stdout()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "desktop")]
+const PLAY_USAGE: &str = "usage: gb-emulator <rom> [--keymap keys.toml] [--speed N|uncapped] [--frame-skip N] \
+[--record-movie out.gbm | --play-movie in.gbm] [--cheats list.cht]";
+
+#[cfg(feature = "desktop")]
+fn play(path: &str, options: &[String]) -> ExitCode {
+    let result = open_frontend(path, options)
+        .and_then(|frontend| gb_emulator::frontend::desktop::run(frontend).map_err(|error| error.to_string()));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "desktop")]
+fn open_frontend(path: &str, options: &[String]) -> Result<gb_emulator::frontend::Frontend, String> {
+    use gb_emulator::frontend::Frontend;
+    use gb_emulator::gameboy::UNCAPPED;
+    use gb_emulator::cheats::CheatEngine;
+    use gb_emulator::movie::{Movie, MovieError};
+
+    let cartridge = Cartridge::from_file(path).map_err(|error| format!("{}: {}", path, error))?;
+    let gameboy = GameBoy::with_cartridge(cartridge).build().map_err(|error| format!("{}: {}", path, error))?;
+    let mut frontend = Frontend::new(gameboy);
+    frontend.set_state_slots(savestate::StateSlots::for_rom(path.as_ref()));
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or_else(|| format!("{} needs a value\n{}", option, PLAY_USAGE))?;
+        match option.as_str() {
+            "--keymap" => frontend.load_keymap(value.into()).map_err(|error| format!("{}: {}", value, error))?,
+            "--speed" => {
+                let speed = match value.as_str() {
+                    "uncapped" => UNCAPPED,
+                    value => value.parse().ok().filter(|&speed: &f32| speed > 0.0).ok_or(format!("bad speed\n{}", PLAY_USAGE))?,
+                };
+                frontend.gameboy_mut().set_speed(speed);
+            }
+            "--frame-skip" => {
+                let frames = value.parse().map_err(|_| format!("bad frame skip\n{}", PLAY_USAGE))?;
+                frontend.gameboy_mut().set_frame_skip(frames);
+            }
+            "--cheats" => {
+                let cheats = CheatEngine::load_cht(value).map_err(|error| format!("{}: {}", value, error))?;
+                frontend.gameboy_mut().mmu_mut().cheats = cheats;
+            }
+            "--record-movie" => frontend.record_movie(value.into()).map_err(|error| format!("{}: {}", value, error))?,
+            "--play-movie" => File::open(value)
+                .map_err(MovieError::from)
+                .and_then(|file| Movie::load(BufReader::new(file)))
+                .and_then(|movie| frontend.play_movie(movie, Some(value.into())))
+                .map_err(|error| format!("{}: {}", value, error))?,
+            _ => return Err(format!("unexpected {}\n{}", option, PLAY_USAGE)),
+        }
+    }
+    Ok(frontend)
+}
 
-fn main() {
-    println!("Hello, world!");
+#[cfg(not(feature = "desktop"))]
+fn play(_path: &str, _options: &[String]) -> ExitCode {
+    eprintln!("built without a frontend, rebuild with --features desktop");
+    ExitCode::FAILURE
 }
@@ -0,0 +1,245 @@
+use std::sync::{Arc, Mutex};
+
+use crate::cartridge::{CartridgeHeader, MapperKind, ROM_BANK_SIZE};
+use crate::reset::ResetKind;
+
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc5;
+mod mbc7;
+mod pocket_camera;
+
+pub use huc1::HuC1;
+pub use huc3::HuC3;
+pub use mbc1::Mbc1;
+pub use mbc2::Mbc2;
+pub use mbc5::Mbc5;
+pub use mbc7::Mbc7;
+pub use pocket_camera::PocketCamera;
+
+pub const RAM_BANK_SIZE: usize = 0x2000;
+// the Game Boy Camera's sensor
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+// the banking hardware on a cartridge. it owns the rom and external ram and sees
+// every cpu access to 0x0000-0x7FFF and 0xA000-0xBFFF (ram addresses are relative
+// to 0xA000). besides the built in controllers, anything implementing this can be
+// put on the bus with Cartridge::from_mapper, e.g. flash carts or coprocessors
+pub trait Mapper {
+    fn read_rom(&self, address: u16) -> u8;
+    // writes to rom are how mappers get their commands
+    fn write_rom(&mut self, address: u16, value: u8);
+    fn read_ram(&self, address: u16) -> u8;
+    fn write_ram(&mut self, address: u16, value: u8);
+    // the rom bank read_rom goes to for an address, for debuggers
+    fn rom_bank(&self, address: u16) -> usize {
+        address as usize / ROM_BANK_SIZE
+    }
+    fn rom(&self) -> &[u8];
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+    // only carts with a motor do anything with this
+    fn set_rumble_sink(&mut self, _sink: Box<dyn RumbleSink>) {}
+    // tilt in g for carts with an accelerometer, x is left/right and y is up/down
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+    // where a camera cart gets its pictures
+    fn set_camera_source(&mut self, _source: Box<dyn CameraSource>) {}
+    // advance anything that runs on the system clock, in clock cycles
+    fn tick(&mut self, _cycles: u32) {}
+    // run real time clocks off tick() instead of the host's time, so runs can be
+    // reproduced exactly
+    fn set_emulated_clock(&mut self, _emulated: bool) {}
+    // mapper state that has to survive a save state: banking registers, a real
+    // time clock and so on, but not the ram. load_state gets back whatever
+    // save_state produced
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load_state(&mut self, _data: &[u8]) {}
+    // put the banking registers back to their power on values
+    fn reset(&mut self, _kind: ResetKind) {}
+}
+
+// told whenever a rumble cart switches its motor on or off, so a frontend can
+// drive a gamepad's force feedback
+pub trait RumbleSink {
+    fn set_rumble(&mut self, on: bool);
+}
+
+impl<T: RumbleSink> RumbleSink for Arc<Mutex<T>> {
+    fn set_rumble(&mut self, on: bool) {
+        if let Ok(mut sink) = self.lock() {
+            sink.set_rumble(on);
+        }
+    }
+}
+
+impl<F: FnMut(bool)> RumbleSink for F {
+    fn set_rumble(&mut self, on: bool) {
+        self(on)
+    }
+}
+
+// asked for a picture whenever a camera cart takes one: CAMERA_WIDTH x CAMERA_HEIGHT
+// grey levels, 0 black to 255 white, row by row. a frontend can hand over a still
+// image or the latest webcam frame
+pub trait CameraSource {
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+impl<T: CameraSource> CameraSource for Arc<Mutex<T>> {
+    fn capture(&mut self) -> Vec<u8> {
+        self.lock().map(|mut source| source.capture()).unwrap_or_default()
+    }
+}
+
+impl<F: FnMut() -> Vec<u8>> CameraSource for F {
+    fn capture(&mut self) -> Vec<u8> {
+        self()
+    }
+}
+
+// the same picture every time, e.g. loaded from a file
+pub struct StillImage {
+    pixels: Vec<u8>,
+}
+
+impl StillImage {
+    // grey levels row by row, any size, scaled to the sensor by nearest neighbour
+    pub fn new(width: usize, height: usize, grey: &[u8]) -> StillImage {
+        let mut pixels = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let index = y * height / CAMERA_HEIGHT * width + x * width / CAMERA_WIDTH;
+                pixels.push(grey.get(index).copied().unwrap_or(0));
+            }
+        }
+        StillImage { pixels }
+    }
+    #[cfg(feature = "png")]
+    pub fn from_png(path: &std::path::Path) -> std::io::Result<StillImage> {
+        use std::io::{Error, ErrorKind};
+        let invalid = |error: png::DecodingError| Error::new(ErrorKind::InvalidData, error.to_string());
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(std::fs::File::open(path)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(invalid)?;
+        let size = reader.output_buffer_size().ok_or_else(|| Error::new(ErrorKind::InvalidData, "png too large"))?;
+        let mut data = vec![0; size];
+        let info = reader.next_frame(&mut data).map_err(invalid)?;
+        let channels = info.color_type.samples();
+        // luma from whatever colour channels there are, alpha is ignored
+        let grey: Vec<u8> = data[..info.buffer_size()].chunks_exact(channels).map(|pixel| match pixel {
+            [r, g, b, ..] => ((*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000) as u8,
+            [luma, ..] => *luma,
+            [] => 0,
+        }).collect();
+        Ok(StillImage::new(info.width as usize, info.height as usize, &grey))
+    }
+}
+
+impl CameraSource for StillImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+// picks the mapper named by the header, unsupported ones are treated as a plain rom
+pub fn for_header(header: &CartridgeHeader, rom: Vec<u8>) -> Box<dyn Mapper> {
+    let ram = vec![0; header.ram_size];
+    match header.mapper() {
+        MapperKind::Mbc1 => Box::new(Mbc1::new(rom, ram)),
+        // the header declares no ram for MBC2, it's inside the controller
+        MapperKind::Mbc2 => Box::new(Mbc2::new(rom)),
+        MapperKind::Mbc5 => Box::new(Mbc5::new(rom, ram, header.has_rumble())),
+        // the eeprom isn't described by the header either
+        MapperKind::Mbc7 => Box::new(Mbc7::new(rom)),
+        MapperKind::PocketCamera => Box::new(PocketCamera::new(rom, ram)),
+        MapperKind::HuC1 => Box::new(HuC1::new(rom, ram)),
+        MapperKind::HuC3 => Box::new(HuC3::new(rom, ram)),
+        _ => Box::new(RomOnly { rom, ram }),
+    }
+}
+
+// the banking registers in a state from Mapper::save_state, for looking at a
+// save state without the rom it was made with
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BankState {
+    pub rom_bank: u16,
+    // None for mappers without banked ram
+    pub ram_bank: Option<u8>,
+    pub ram_enabled: bool,
+}
+
+// every built in mapper's state starts with its banking registers, this knows
+// where each one keeps them. None for mappers without any, or a short state
+pub fn bank_state(kind: MapperKind, data: &[u8]) -> Option<BankState> {
+    let state = |rom_bank: u16, ram_bank: Option<u8>, ram_enabled: bool| BankState { rom_bank, ram_bank, ram_enabled };
+    match (kind, data) {
+        // the upper bits only bank ram in mode 1, but always apply to 0x4000
+        (MapperKind::Mbc1, &[ram_enabled, bank1, bank2, mode, ..]) => {
+            Some(state((bank2 as u16) << 5 | bank1 as u16, Some(if mode != 0 { bank2 } else { 0 }), ram_enabled != 0))
+        }
+        (MapperKind::Mbc2, &[ram_enabled, rom_bank, ..]) => Some(state(rom_bank as u16, None, ram_enabled != 0)),
+        (MapperKind::Mbc5, &[ram_enabled, low, high, ram_bank, ..]) => {
+            Some(state(u16::from_le_bytes([low, high]), Some(ram_bank), ram_enabled != 0))
+        }
+        (MapperKind::Mbc7, &[rom_bank, ram_enabled, ram_enabled2, ..]) => {
+            Some(state(rom_bank as u16, None, ram_enabled != 0 && ram_enabled2 != 0))
+        }
+        (MapperKind::PocketCamera, &[ram_enabled, rom_bank, ram_bank, ..]) => {
+            Some(state(rom_bank as u16, Some(ram_bank), ram_enabled != 0))
+        }
+        (MapperKind::HuC1, &[ir_selected, rom_bank, ram_bank, ..]) => {
+            Some(state(rom_bank as u16, Some(ram_bank), ir_selected == 0))
+        }
+        // select 0x0A is read/write ram, the rest are the clock and infrared
+        (MapperKind::HuC3, &[select, rom_bank, ram_bank, ..]) => {
+            Some(state(rom_bank as u16, Some(ram_bank), select == 0x0A))
+        }
+        _ => None,
+    }
+}
+
+// mask selecting a valid bank number for a region of the given size. sizes that
+// aren't a power of two (trimmed or overdumped images) round up, banks past the
+// end of the data read as open bus
+pub fn bank_mask(size: usize, bank_size: usize) -> usize {
+    (size.next_power_of_two() / bank_size).max(1) - 1
+}
+
+pub fn read_banked(data: &[u8], bank: usize, bank_size: usize, offset: usize) -> u8 {
+    data.get(bank * bank_size + offset).copied().unwrap_or(0xFF)
+}
+
+// no banking, the rom is mapped flat and any ram sits at 0xA000
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl Mapper for RomOnly {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked(&self.rom, 0, ROM_BANK_SIZE, address as usize)
+    }
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram.get(address as usize).copied().unwrap_or(0xFF)
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(byte) = self.ram.get_mut(address as usize) {
+            *byte = value;
+        }
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
@@ -0,0 +1,82 @@
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::mbc::{bank_mask, read_banked, Mapper, RAM_BANK_SIZE};
+use crate::reset::ResetKind;
+
+// Hudson's MBC1 lookalike with an infrared LED and sensor. 0x0000-0x1FFF picks
+// whether 0xA000-0xBFFF shows ram or the IR port instead of enabling ram
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ir_selected: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    // LED state written by the game, and whether the sensor currently sees light
+    ir_led: bool,
+    ir_light: bool,
+}
+
+impl HuC1 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> HuC1 {
+        HuC1 { rom, ram, ir_selected: false, rom_bank: 1, ram_bank: 0, ir_led: false, ir_light: false }
+    }
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() { return None }
+        let bank = self.ram_bank as usize & bank_mask(self.ram.len(), RAM_BANK_SIZE);
+        Some((bank * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+}
+
+impl Mapper for HuC1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked(&self.rom, self.rom_bank(address), ROM_BANK_SIZE, address as usize % ROM_BANK_SIZE)
+    }
+    fn rom_bank(&self, address: u16) -> usize {
+        if (address as usize) < ROM_BANK_SIZE { 0 }
+        else { self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE) }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ir_selected = value & 0x0F == 0x0E,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.ir_selected { return 0xC0 | self.ir_light as u8 }
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ir_selected {
+            self.ir_led = value & 0x01 != 0;
+        } else if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+    fn save_state(&self) -> Vec<u8> {
+        vec![self.ir_selected as u8, self.rom_bank, self.ram_bank, self.ir_led as u8]
+    }
+    fn load_state(&mut self, data: &[u8]) {
+        if let [ir_selected, rom_bank, ram_bank, ir_led, ..] = *data {
+            self.ir_selected = ir_selected != 0;
+            self.rom_bank = rom_bank;
+            self.ram_bank = ram_bank;
+            self.ir_led = ir_led != 0;
+        }
+    }
+    fn reset(&mut self, _kind: ResetKind) {
+        self.ir_selected = false;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.ir_led = false;
+    }
+}
@@ -0,0 +1,331 @@
+use crate::apu::CLOCK_RATE;
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::mbc::{bank_mask, read_banked, Mapper, RAM_BANK_SIZE};
+use crate::reset::ResetKind;
+use crate::timing::unix_now;
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const STATE_SIZE: usize = 24;
+// states saved with the emulated clock also carry the cycles into the current second
+const EMULATED_STATE_SIZE: usize = 28;
+
+// the clock counts minutes in the day and days, and is driven by a tiny command
+// protocol: 0xA000 takes a command nibble plus an argument nibble and results are
+// read back a nibble at a time. time is kept against the host clock, so it keeps
+// running while the emulator is closed as long as the state is saved. with the
+// emulated clock it only moves as the game runs, a second every CLOCK_RATE cycles
+struct Rtc {
+    minutes: u16,
+    days: u16,
+    alarm_minutes: u16,
+    alarm_days: u16,
+    alarm_enabled: bool,
+    // index into the clock's nibble registers for the next read or write
+    index: u8,
+    // last nibble read by a read command
+    result: u8,
+    // host time the counters were last brought up to date, and seconds not yet
+    // making a full minute
+    updated_at: u64,
+    seconds: u8,
+    emulated: bool,
+    cycles: u32,
+}
+
+impl Rtc {
+    fn new() -> Rtc {
+        Rtc {
+            minutes: 0,
+            days: 0,
+            alarm_minutes: 0,
+            alarm_days: 0,
+            alarm_enabled: false,
+            index: 0,
+            result: 0,
+            updated_at: unix_now(),
+            seconds: 0,
+            emulated: false,
+            cycles: 0,
+        }
+    }
+    fn update(&mut self) {
+        if self.emulated { return }
+        let now = unix_now();
+        let elapsed = now.saturating_sub(self.updated_at);
+        self.updated_at = now;
+        self.advance(elapsed);
+    }
+    fn tick(&mut self, cycles: u32) {
+        if !self.emulated { return }
+        self.cycles += cycles;
+        if self.cycles >= CLOCK_RATE {
+            self.advance((self.cycles / CLOCK_RATE) as u64);
+            self.cycles %= CLOCK_RATE;
+        }
+    }
+    fn advance(&mut self, seconds: u64) {
+        let elapsed = seconds + self.seconds as u64;
+        self.seconds = (elapsed % 60) as u8;
+        let minutes = self.minutes as u64 + elapsed / 60;
+        self.minutes = (minutes % MINUTES_PER_DAY as u64) as u16;
+        self.days = self.days.wrapping_add((minutes / MINUTES_PER_DAY as u64) as u16);
+    }
+    fn read_nibble(&self) -> u8 {
+        let index = self.index as u32;
+        let nibble = match self.index {
+            0x00..=0x02 => self.minutes >> (index * 4),
+            0x03..=0x06 => self.days >> ((index - 3) * 4),
+            0x58..=0x5A => self.alarm_minutes >> ((index - 0x58) * 4),
+            0x5B..=0x5E => self.alarm_days >> ((index - 0x5B) * 4),
+            0x5F => self.alarm_enabled as u16,
+            _ => 0,
+        };
+        (nibble & 0x0F) as u8
+    }
+    fn write_nibble(&mut self, nibble: u8) {
+        fn set(value: &mut u16, shift: u32, nibble: u8) {
+            *value = (*value & !(0x0F << shift)) | ((nibble as u16) << shift);
+        }
+        let index = self.index as u32;
+        match self.index {
+            0x00..=0x02 => set(&mut self.minutes, index * 4, nibble),
+            0x03..=0x06 => set(&mut self.days, (index - 3) * 4, nibble),
+            0x58..=0x5A => set(&mut self.alarm_minutes, (index - 0x58) * 4, nibble),
+            0x5B..=0x5E => set(&mut self.alarm_days, (index - 0x5B) * 4, nibble),
+            0x5F => self.alarm_enabled = nibble & 0x01 != 0,
+            _ => {}
+        }
+    }
+    fn command(&mut self, value: u8) {
+        self.update();
+        let argument = value & 0x0F;
+        match value >> 4 {
+            // read and advance
+            0x1 => {
+                self.result = self.read_nibble();
+                self.index = self.index.wrapping_add(1);
+            }
+            // write, 0x3 also advances
+            0x2 => self.write_nibble(argument),
+            0x3 => {
+                self.write_nibble(argument);
+                self.index = self.index.wrapping_add(1);
+            }
+            // set the low/high nibble of the index
+            0x4 => self.index = (self.index & 0xF0) | argument,
+            0x5 => self.index = (self.index & 0x0F) | argument << 4,
+            _ => {}
+        }
+    }
+}
+
+// Hudson's controller with a real time clock and IR port. the low nibble written
+// to 0x0000-0x1FFF selects what 0xA000-0xBFFF talks to
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    select: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    rtc: Rtc,
+    ir_led: bool,
+    ir_light: bool,
+}
+
+impl HuC3 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> HuC3 {
+        HuC3 { rom, ram, select: 0, rom_bank: 1, ram_bank: 0, rtc: Rtc::new(), ir_led: false, ir_light: false }
+    }
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() { return None }
+        let bank = self.ram_bank as usize & bank_mask(self.ram.len(), RAM_BANK_SIZE);
+        Some((bank * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+}
+
+impl Mapper for HuC3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked(&self.rom, self.rom_bank(address), ROM_BANK_SIZE, address as usize % ROM_BANK_SIZE)
+    }
+    fn rom_bank(&self, address: u16) -> usize {
+        if (address as usize) < ROM_BANK_SIZE { 0 }
+        else { self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE) }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.select = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.select {
+            // 0x0 maps ram read only, 0xA read/write
+            0x0 | 0xA => self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset]),
+            0xC => 0x80 | self.rtc.result,
+            // the clock is always ready for the next command
+            0xD => 0x01,
+            0xE => 0xC0 | self.ir_light as u8,
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        match self.select {
+            0xA => if let Some(offset) = self.ram_offset(address) {
+                self.ram[offset] = value;
+            },
+            0xB => self.rtc.command(value),
+            0xE => self.ir_led = value & 0x01 != 0,
+            _ => {}
+        }
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+    fn set_emulated_clock(&mut self, emulated: bool) {
+        self.rtc.update();
+        self.rtc.emulated = emulated;
+        self.rtc.updated_at = unix_now();
+    }
+    fn tick(&mut self, cycles: u32) {
+        self.rtc.tick(cycles);
+    }
+    // banking registers and the clock, with the host time it was saved at so
+    // loading it later catches up on the time in between
+    fn save_state(&self) -> Vec<u8> {
+        let rtc = &self.rtc;
+        let mut data = vec![self.select, self.rom_bank, self.ram_bank, self.ir_led as u8];
+        data.extend_from_slice(&rtc.minutes.to_le_bytes());
+        data.extend_from_slice(&rtc.days.to_le_bytes());
+        data.extend_from_slice(&rtc.alarm_minutes.to_le_bytes());
+        data.extend_from_slice(&rtc.alarm_days.to_le_bytes());
+        data.extend_from_slice(&[rtc.alarm_enabled as u8, rtc.index, rtc.result, rtc.seconds]);
+        data.extend_from_slice(&rtc.updated_at.to_le_bytes());
+        if rtc.emulated {
+            data.extend_from_slice(&rtc.cycles.to_le_bytes());
+        }
+        data
+    }
+    fn load_state(&mut self, data: &[u8]) {
+        if data.len() < STATE_SIZE { return }
+        let word = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        self.select = data[0];
+        self.rom_bank = data[1];
+        self.ram_bank = data[2];
+        self.ir_led = data[3] != 0;
+        self.rtc = Rtc {
+            minutes: word(4),
+            days: word(6),
+            alarm_minutes: word(8),
+            alarm_days: word(10),
+            alarm_enabled: data[12] != 0,
+            index: data[13],
+            result: data[14],
+            seconds: data[15],
+            updated_at: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+            emulated: self.rtc.emulated,
+            cycles: 0,
+        };
+        if self.rtc.emulated {
+            // no time passes between saving and loading
+            self.rtc.updated_at = unix_now();
+            if data.len() >= EMULATED_STATE_SIZE {
+                self.rtc.cycles = u32::from_le_bytes(data[24..28].try_into().unwrap());
+            }
+        }
+        self.rtc.update();
+    }
+    // the clock has its own battery, only the banking goes back to defaults
+    fn reset(&mut self, _kind: ResetKind) {
+        self.select = 0;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.ir_led = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn huc3() -> HuC3 {
+        let mut mbc = HuC3::new(vec![0; 4 * ROM_BANK_SIZE], vec![0; 4 * RAM_BANK_SIZE]);
+        mbc.set_emulated_clock(true);
+        mbc
+    }
+
+    fn commands(mbc: &mut HuC3, values: &[u8]) {
+        mbc.write_rom(0x0000, 0x0B);
+        for &value in values {
+            mbc.write_ram(0x0000, value);
+        }
+    }
+
+    // reads the nibbles from the index onward, low nibble first
+    fn read(mbc: &mut HuC3, index: u8, count: u32) -> u16 {
+        commands(mbc, &[0x40 | index & 0x0F, 0x50 | index >> 4]);
+        (0..count).fold(0, |value, nibble| {
+            commands(mbc, &[0x10]);
+            mbc.write_rom(0x0000, 0x0C);
+            let result = mbc.read_ram(0x0000);
+            assert_eq!(result & 0xF0, 0x80);
+            value | ((result & 0x0F) as u16) << (nibble * 4)
+        })
+    }
+
+    #[test]
+    fn the_clock_command_protocol() {
+        let mut mbc = huc3();
+        mbc.write_rom(0x0000, 0x0D);
+        assert_eq!(mbc.read_ram(0x0000), 0x01);
+        // 23:59 on day 2, written a nibble at a time from index 0
+        let minutes = MINUTES_PER_DAY - 1;
+        commands(&mut mbc, &[0x40, 0x50]);
+        for nibble in 0..3 {
+            commands(&mut mbc, &[0x30 | (minutes >> (nibble * 4)) as u8 & 0x0F]);
+        }
+        commands(&mut mbc, &[0x32, 0x30, 0x30, 0x30]);
+        assert_eq!(read(&mut mbc, 0x00, 3), minutes);
+        assert_eq!(read(&mut mbc, 0x03, 4), 2);
+        // 0x2 writes without moving the index
+        commands(&mut mbc, &[0x4F, 0x55, 0x21]);
+        assert_eq!(read(&mut mbc, 0x5F, 1), 1);
+        // a minute later the day rolls over
+        mbc.tick(60 * CLOCK_RATE);
+        assert_eq!(read(&mut mbc, 0x00, 3), 0);
+        assert_eq!(read(&mut mbc, 0x03, 4), 3);
+    }
+
+    #[test]
+    fn state_round_trips_with_the_clock() {
+        let mut mbc = huc3();
+        mbc.write_rom(0x2000, 0x03);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0x0010, 0x42);
+        commands(&mut mbc, &[0x40, 0x50, 0x35, 0x30, 0x30]);
+        mbc.tick(59 * CLOCK_RATE + CLOCK_RATE / 2);
+        let state = mbc.save_state();
+        assert_eq!(state.len(), EMULATED_STATE_SIZE);
+
+        let mut loaded = huc3();
+        loaded.load_state(&state);
+        assert_eq!(loaded.rom_bank(0x4000), 3);
+        assert_eq!(read(&mut loaded, 0x00, 3), 5);
+        // the half second carried over makes the minute
+        loaded.tick(CLOCK_RATE / 2);
+        assert_eq!(read(&mut loaded, 0x00, 3), 6);
+        // ram is saved separately, the bank still selects it
+        loaded.ram_mut().copy_from_slice(mbc.ram());
+        loaded.write_rom(0x0000, 0x0A);
+        assert_eq!(loaded.read_ram(0x0010), 0x42);
+        assert_eq!(loaded.ram()[2 * RAM_BANK_SIZE + 0x10], 0x42);
+    }
+}
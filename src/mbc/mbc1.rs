@@ -0,0 +1,152 @@
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::mbc::{bank_mask, read_banked, Mapper, RAM_BANK_SIZE};
+use crate::reset::ResetKind;
+
+// up to 2 MiB of rom and 32 KiB of ram. the 5 bit bank register can't select bank
+// 0, it's bumped to 1 before the upper two bits are added, which is why banks
+// 0x20/0x40/0x60 can never be mapped at 0x4000 (they read as 0x21/0x41/0x61).
+// in mode 1 the upper bits also bank 0x0000-0x3FFF and the ram
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // 0x2000-0x3FFF, low 5 bits of the rom bank
+    bank1: u8,
+    // 0x4000-0x5FFF, ram bank or upper 2 bits of the rom bank
+    bank2: u8,
+    // 0x6000-0x7FFF, advanced banking mode
+    mode: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Mbc1 {
+        Mbc1 { rom, ram, ram_enabled: false, bank1: 1, bank2: 0, mode: false }
+    }
+    fn rom_mask(&self) -> usize {
+        bank_mask(self.rom.len(), ROM_BANK_SIZE)
+    }
+    fn low_rom_bank(&self) -> usize {
+        if self.mode { ((self.bank2 as usize) << 5) & self.rom_mask() } else { 0 }
+    }
+    fn high_rom_bank(&self) -> usize {
+        (((self.bank2 as usize) << 5) | self.bank1 as usize) & self.rom_mask()
+    }
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() { return None }
+        let bank = if self.mode { self.bank2 as usize & bank_mask(self.ram.len(), RAM_BANK_SIZE) } else { 0 };
+        Some((bank * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked(&self.rom, self.rom_bank(address), ROM_BANK_SIZE, address as usize % ROM_BANK_SIZE)
+    }
+    fn rom_bank(&self, address: u16) -> usize {
+        if (address as usize) < ROM_BANK_SIZE { self.low_rom_bank() } else { self.high_rom_bank() }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.bank1 = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01 != 0,
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+    fn save_state(&self) -> Vec<u8> {
+        vec![self.ram_enabled as u8, self.bank1, self.bank2, self.mode as u8]
+    }
+    fn load_state(&mut self, data: &[u8]) {
+        if let [ram_enabled, bank1, bank2, mode, ..] = *data {
+            self.ram_enabled = ram_enabled != 0;
+            self.bank1 = (bank1 & 0x1F).max(1);
+            self.bank2 = bank2 & 0x03;
+            self.mode = mode != 0;
+        }
+    }
+    fn reset(&mut self, _kind: ResetKind) {
+        self.ram_enabled = false;
+        self.bank1 = 1;
+        self.bank2 = 0;
+        self.mode = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2 MiB of rom with every bank starting with its own number, and 32 KiB of ram
+    fn mbc1() -> Mbc1 {
+        let mut rom = vec![0; 128 * ROM_BANK_SIZE];
+        for (bank, data) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            data[0] = bank as u8;
+        }
+        Mbc1::new(rom, vec![0; 4 * RAM_BANK_SIZE])
+    }
+
+    #[test]
+    fn bank_0_selects_the_next_bank() {
+        let mut mbc = mbc1();
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        for (upper, bank) in [(0, 0x01), (1, 0x21), (2, 0x41), (3, 0x61)] {
+            mbc.write_rom(0x4000, upper);
+            mbc.write_rom(0x2000, 0x00);
+            assert_eq!(mbc.read_rom(0x4000), bank);
+            // the register only has five bits
+            mbc.write_rom(0x2000, 0xE5);
+            assert_eq!(mbc.read_rom(0x4000), bank - 1 + 5);
+        }
+    }
+
+    #[test]
+    fn mode_1_banks_the_bottom_of_rom() {
+        let mut mbc = mbc1();
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.rom_bank(0x0000), 0x40);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn ram_is_banked_in_mode_1_and_off_until_enabled() {
+        let mut mbc = mbc1();
+        mbc.write_ram(0x0000, 0x11);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0x0000, 0x11);
+        // in mode 0 the upper bits don't touch ram
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_ram(0x0000), 0x11);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+        mbc.write_ram(0x0000, 0x22);
+        assert_eq!(mbc.ram()[2 * RAM_BANK_SIZE], 0x22);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0x11);
+        // anything but 0x0A in the low nibble disables it again
+        mbc.write_rom(0x0000, 0x1B);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+    }
+}
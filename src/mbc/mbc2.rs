@@ -0,0 +1,119 @@
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::mbc::{bank_mask, read_banked, Mapper};
+use crate::reset::ResetKind;
+
+// 512 half-byte cells built into the controller, mirrored across 0xA000-0xBFFF
+const RAM_SIZE: usize = 0x200;
+
+// up to 256 KiB of rom. there's a single register range at 0x0000-0x3FFF and
+// address bit 8 picks between ram enable (clear) and rom bank (set)
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 { rom, ram: vec![0; RAM_SIZE], ram_enabled: false, rom_bank: 1 }
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked(&self.rom, self.rom_bank(address), ROM_BANK_SIZE, address as usize % ROM_BANK_SIZE)
+    }
+    fn rom_bank(&self, address: u16) -> usize {
+        if (address as usize) < ROM_BANK_SIZE { 0 }
+        else { self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE) }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0000..=0x3FFF => self.rom_bank = (value & 0x0F).max(1),
+            _ => {}
+        }
+    }
+    // only the low nibble is stored, the upper one floats high
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled { return 0xFF }
+        self.ram[address as usize % RAM_SIZE] | 0xF0
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[address as usize % RAM_SIZE] = value & 0x0F;
+        }
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+    fn save_state(&self) -> Vec<u8> {
+        vec![self.ram_enabled as u8, self.rom_bank]
+    }
+    fn load_state(&mut self, data: &[u8]) {
+        if let [ram_enabled, rom_bank, ..] = *data {
+            self.ram_enabled = ram_enabled != 0;
+            self.rom_bank = (rom_bank & 0x0F).max(1);
+        }
+    }
+    fn reset(&mut self, _kind: ResetKind) {
+        self.ram_enabled = false;
+        self.rom_bank = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 256 KiB of rom with every bank starting with its own number
+    fn mbc2() -> Mbc2 {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        for (bank, data) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            data[0] = bank as u8;
+        }
+        Mbc2::new(rom)
+    }
+
+    #[test]
+    fn address_bit_8_picks_the_register() {
+        let mut mbc = mbc2();
+        // bit 8 clear is ram enable, whatever the value
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+        mbc.write_rom(0x2000, 0x0A);
+        assert_eq!(mbc.read_ram(0x0000), 0xF0);
+        // bit 8 set is the rom bank, anywhere below 0x4000
+        mbc.write_rom(0x0100, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        mbc.write_rom(0x3FFF, 0xF7);
+        assert_eq!(mbc.read_rom(0x4000), 7);
+        mbc.write_rom(0x2100, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_holds_nibbles_and_mirrors() {
+        let mut mbc = mbc2();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0x0000, 0xAB);
+        assert_eq!(mbc.read_ram(0x0000), 0xFB);
+        assert_eq!(mbc.ram()[0], 0x0B);
+        // 512 cells repeat through the whole ram area
+        assert_eq!(mbc.read_ram(0x0200), 0xFB);
+        assert_eq!(mbc.read_ram(0x1E00), 0xFB);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+        mbc.write_ram(0x0000, 0x03);
+        assert_eq!(mbc.ram()[0], 0x0B);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::mbc::{bank_mask, read_banked, Mapper, RumbleSink, RAM_BANK_SIZE};
+use crate::reset::ResetKind;
+
+// up to 8 MiB of rom through a 9 bit bank register and 128 KiB of ram. unlike
+// MBC1, bank 0 can be mapped at 0x4000. rumble carts wire the motor to bit 3 of
+// the ram bank register, leaving them only 3 bank bits
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumbling: bool,
+    rumble_sink: Option<Box<dyn RumbleSink>>,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumbling: false,
+            rumble_sink: None,
+        }
+    }
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() { return None }
+        let bank = self.ram_bank as usize & bank_mask(self.ram.len(), RAM_BANK_SIZE);
+        Some((bank * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+    fn set_rumble(&mut self, rumbling: bool) {
+        if rumbling == self.rumbling { return }
+        self.rumbling = rumbling;
+        if let Some(sink) = &mut self.rumble_sink {
+            sink.set_rumble(rumbling);
+        }
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked(&self.rom, self.rom_bank(address), ROM_BANK_SIZE, address as usize % ROM_BANK_SIZE)
+    }
+    fn rom_bank(&self, address: u16) -> usize {
+        if (address as usize) < ROM_BANK_SIZE { 0 }
+        else { self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE) }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 0x01) << 8),
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.ram_bank = value & 0x07;
+                self.set_rumble(value & 0x08 != 0);
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+    fn set_rumble_sink(&mut self, sink: Box<dyn RumbleSink>) {
+        self.rumble_sink = Some(sink);
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let [low, high] = self.rom_bank.to_le_bytes();
+        vec![self.ram_enabled as u8, low, high, self.ram_bank, self.rumbling as u8]
+    }
+    fn load_state(&mut self, data: &[u8]) {
+        if let [ram_enabled, low, high, ram_bank, rumbling, ..] = *data {
+            self.ram_enabled = ram_enabled != 0;
+            self.rom_bank = u16::from_le_bytes([low, high & 0x01]);
+            self.ram_bank = ram_bank & 0x0F;
+            self.set_rumble(rumbling != 0);
+        }
+    }
+    fn reset(&mut self, _kind: ResetKind) {
+        self.ram_enabled = false;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.set_rumble(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    // 8 MiB of rom with every bank starting with its own number
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 512 * ROM_BANK_SIZE];
+        for (bank, data) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            data[..2].copy_from_slice(&(bank as u16).to_le_bytes());
+        }
+        rom
+    }
+
+    fn bank_at_4000(mbc: &Mbc5) -> u16 {
+        u16::from_le_bytes([mbc.read_rom(0x4000), mbc.read_rom(0x4001)])
+    }
+
+    struct Motor(Rc<RefCell<Vec<bool>>>);
+
+    impl RumbleSink for Motor {
+        fn set_rumble(&mut self, on: bool) {
+            self.0.borrow_mut().push(on);
+        }
+    }
+
+    #[test]
+    fn the_rom_bank_has_nine_bits_and_can_be_0() {
+        let mut mbc = Mbc5::new(rom(), Vec::new(), false);
+        assert_eq!(bank_at_4000(&mbc), 1);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(bank_at_4000(&mbc), 0);
+        mbc.write_rom(0x2000, 0x23);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(bank_at_4000(&mbc), 0x123);
+        assert_eq!(mbc.rom_bank(0x4000), 0x123);
+        // the high bit stays when the low byte changes, and the other way around
+        mbc.write_rom(0x2FFF, 0xFF);
+        assert_eq!(bank_at_4000(&mbc), 0x1FF);
+        mbc.write_rom(0x3FFF, 0xFE);
+        assert_eq!(bank_at_4000(&mbc), 0x0FF);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_banks_without_rumble() {
+        let mut mbc = Mbc5::new(rom(), vec![0; 16 * RAM_BANK_SIZE], false);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.ram()[15 * RAM_BANK_SIZE], 0x42);
+    }
+
+    #[test]
+    fn bit_3_of_the_ram_bank_drives_the_motor() {
+        let motor = Rc::new(RefCell::new(Vec::new()));
+        let mut mbc = Mbc5::new(rom(), vec![0; 8 * RAM_BANK_SIZE], true);
+        mbc.set_rumble_sink(Box::new(Motor(motor.clone())));
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0B);
+        // the sink only hears about changes
+        mbc.write_rom(0x4000, 0x0A);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(*motor.borrow(), [true, false]);
+        // and the motor bit doesn't bank ram
+        mbc.write_rom(0x4000, 0x0B);
+        mbc.write_ram(0x0000, 0x99);
+        assert_eq!(mbc.ram()[3 * RAM_BANK_SIZE], 0x99);
+    }
+}
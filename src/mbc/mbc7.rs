@@ -0,0 +1,380 @@
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::mbc::{bank_mask, read_banked, Mapper};
+use crate::reset::ResetKind;
+
+// 93LC56 in 16 bit mode: 128 words, stored little endian
+const EEPROM_WORDS: usize = 128;
+// accelerometer reading when flat, and how far one g of tilt moves it
+const ACCELEROMETER_CENTER: f32 = 0x81D0 as f32;
+const ACCELEROMETER_PER_G: f32 = 0x70 as f32;
+// what the latches hold after being erased
+const ACCELEROMETER_ERASED: u16 = 0x8000;
+
+// Mbc7::save_state length
+const STATE_SIZE: usize = 14;
+
+// state of the serial eeprom's command decoder, advanced on each rising clock edge
+#[derive(Copy, Clone)]
+enum EepromState {
+    // waiting for a start bit
+    Idle,
+    // 2 opcode bits then 8 address bits (the top one is ignored)
+    Command { bits: u16, count: u8 },
+    // shifting out the word at address, most significant bit first
+    Reading { address: u8, bit: u8 },
+    // shifting in a word, address None writes every word (WRAL)
+    Writing { address: Option<u8>, data: u16, count: u8 },
+}
+
+struct Eeprom {
+    data: Vec<u8>,
+    state: EepromState,
+    write_enabled: bool,
+    chip_select: bool,
+    clock: bool,
+    data_in: bool,
+    data_out: bool,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: vec![0xFF; EEPROM_WORDS * 2],
+            state: EepromState::Idle,
+            write_enabled: false,
+            chip_select: false,
+            clock: false,
+            data_in: false,
+            data_out: true,
+        }
+    }
+    // the pins and the decoder as 6 bytes, the words themselves are cartridge ram.
+    // WRAL's missing address is stored as 0xFF
+    fn save_state(&self) -> [u8; 6] {
+        let pins = (self.write_enabled as u8) << 4 | (self.chip_select as u8) << 3 | (self.clock as u8) << 2
+            | (self.data_in as u8) << 1 | self.data_out as u8;
+        let (kind, address, [low, high], count) = match self.state {
+            EepromState::Idle => (0, 0, [0, 0], 0),
+            EepromState::Command { bits, count } => (1, 0, bits.to_le_bytes(), count),
+            EepromState::Reading { address, bit } => (2, address, [0, 0], bit),
+            EepromState::Writing { address, data, count } => (3, address.unwrap_or(0xFF), data.to_le_bytes(), count),
+        };
+        [pins, kind, address, low, high, count]
+    }
+    fn load_state(&mut self, data: [u8; 6]) {
+        let [pins, kind, address, low, high, count] = data;
+        self.write_enabled = pins & 0x10 != 0;
+        self.chip_select = pins & 0x08 != 0;
+        self.clock = pins & 0x04 != 0;
+        self.data_in = pins & 0x02 != 0;
+        self.data_out = pins & 0x01 != 0;
+        let word = (address != 0xFF).then_some(address % EEPROM_WORDS as u8);
+        let data = u16::from_le_bytes([low, high]);
+        self.state = match kind {
+            1 => EepromState::Command { bits: data, count: count.min(9) },
+            2 => EepromState::Reading { address: address % EEPROM_WORDS as u8, bit: count.min(15) },
+            3 => EepromState::Writing { address: word, data, count: count.min(15) },
+            _ => EepromState::Idle,
+        };
+    }
+    fn word(&self, address: u8) -> u16 {
+        let index = (address as usize % EEPROM_WORDS) * 2;
+        u16::from_le_bytes([self.data[index], self.data[index + 1]])
+    }
+    fn set_word(&mut self, address: u8, word: u16) {
+        if !self.write_enabled { return }
+        let index = (address as usize % EEPROM_WORDS) * 2;
+        self.data[index..index + 2].copy_from_slice(&word.to_le_bytes());
+    }
+    // the pins as seen through 0xA080
+    fn read(&self) -> u8 {
+        (self.chip_select as u8) << 7 | (self.clock as u8) << 6 | (self.data_in as u8) << 1 | self.data_out as u8
+    }
+    fn write(&mut self, value: u8) {
+        let chip_select = value & 0x80 != 0;
+        let clock = value & 0x40 != 0;
+        self.data_in = value & 0x02 != 0;
+        if !chip_select {
+            self.state = EepromState::Idle;
+        } else if clock && !self.clock {
+            self.clock_bit();
+        }
+        self.chip_select = chip_select;
+        self.clock = clock;
+    }
+    fn clock_bit(&mut self) {
+        let bit = self.data_in as u16;
+        self.state = match self.state {
+            EepromState::Idle if bit == 1 => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } if count < 9 => EepromState::Command { bits: bits << 1 | bit, count: count + 1 },
+            EepromState::Command { bits, .. } => self.run_command(bits << 1 | bit),
+            EepromState::Reading { address, bit } => {
+                self.data_out = self.word(address) >> (15 - bit) & 1 != 0;
+                // keeps going into the next word for as long as the clock runs
+                if bit == 15 { EepromState::Reading { address: (address + 1) % EEPROM_WORDS as u8, bit: 0 } }
+                else { EepromState::Reading { address, bit: bit + 1 } }
+            }
+            EepromState::Writing { address, data, count } if count < 15 => EepromState::Writing { address, data: data << 1 | bit, count: count + 1 },
+            EepromState::Writing { address, data, .. } => {
+                let data = data << 1 | bit;
+                match address {
+                    Some(address) => self.set_word(address, data),
+                    None => for address in 0..EEPROM_WORDS as u8 { self.set_word(address, data) },
+                }
+                // ready
+                self.data_out = true;
+                EepromState::Idle
+            }
+        };
+    }
+    fn run_command(&mut self, command: u16) -> EepromState {
+        let address = (command & 0x7F) as u8;
+        match command >> 8 {
+            // READ, a dummy zero comes out before the data
+            0b10 => {
+                self.data_out = false;
+                EepromState::Reading { address, bit: 0 }
+            }
+            // WRITE
+            0b01 => EepromState::Writing { address: Some(address), data: 0, count: 0 },
+            // ERASE
+            0b11 => {
+                self.set_word(address, 0xFFFF);
+                self.data_out = true;
+                EepromState::Idle
+            }
+            _ => match (command >> 6) & 0x03 {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    EepromState::Idle
+                }
+                // WRAL
+                0b01 => EepromState::Writing { address: None, data: 0, count: 0 },
+                // ERAL
+                0b10 => {
+                    for address in 0..EEPROM_WORDS as u8 { self.set_word(address, 0xFFFF) }
+                    self.data_out = true;
+                    EepromState::Idle
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    EepromState::Idle
+                }
+            },
+        }
+    }
+}
+
+// Kirby Tilt 'n' Tumble's controller: rom banking, a two axis accelerometer and
+// a serial eeprom instead of ram. everything sits in 0xA000-0xAFFF once both ram
+// enables are set, one register per 16 bytes
+pub struct Mbc7 {
+    rom: Vec<u8>,
+    rom_bank: u8,
+    ram_enabled: bool,
+    ram_enabled2: bool,
+    eeprom: Eeprom,
+    // latest tilt from the frontend, in g. positive x tilts right, positive y tilts down
+    tilt: (f32, f32),
+    latched: (u16, u16),
+    // a 0x55 write erases the latches and arms them for the 0xAA that latches
+    latch_armed: bool,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>) -> Mbc7 {
+        Mbc7 {
+            rom,
+            rom_bank: 1,
+            ram_enabled: false,
+            ram_enabled2: false,
+            eeprom: Eeprom::new(),
+            tilt: (0.0, 0.0),
+            latched: (ACCELEROMETER_ERASED, ACCELEROMETER_ERASED),
+            latch_armed: false,
+        }
+    }
+    fn registers_enabled(&self) -> bool {
+        self.ram_enabled && self.ram_enabled2
+    }
+}
+
+fn accelerometer_value(g: f32) -> u16 {
+    (ACCELEROMETER_CENTER - g * ACCELEROMETER_PER_G).clamp(0.0, u16::MAX as f32) as u16
+}
+
+impl Mapper for Mbc7 {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked(&self.rom, self.rom_bank(address), ROM_BANK_SIZE, address as usize % ROM_BANK_SIZE)
+    }
+    fn rom_bank(&self, address: u16) -> usize {
+        if (address as usize) < ROM_BANK_SIZE { 0 }
+        else { self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE) }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value,
+            0x4000..=0x5FFF => self.ram_enabled2 = value == 0x40,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.registers_enabled() || address >= 0x1000 { return 0xFF }
+        let (x, y) = self.latched;
+        match (address >> 4) & 0x0F {
+            0x2 => x as u8,
+            0x3 => (x >> 8) as u8,
+            0x4 => y as u8,
+            0x5 => (y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.registers_enabled() || address >= 0x1000 { return }
+        match (address >> 4) & 0x0F {
+            0x0 if value == 0x55 => {
+                self.latched = (ACCELEROMETER_ERASED, ACCELEROMETER_ERASED);
+                self.latch_armed = true;
+            }
+            0x1 if value == 0xAA && self.latch_armed => {
+                self.latched = (accelerometer_value(self.tilt.0), accelerometer_value(self.tilt.1));
+                self.latch_armed = false;
+            }
+            0x8 => self.eeprom.write(value),
+            _ => {}
+        }
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    // the eeprom is what gets saved
+    fn ram(&self) -> &[u8] {
+        &self.eeprom.data
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.eeprom.data
+    }
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = vec![self.rom_bank, self.ram_enabled as u8, self.ram_enabled2 as u8, self.latch_armed as u8];
+        data.extend_from_slice(&self.latched.0.to_le_bytes());
+        data.extend_from_slice(&self.latched.1.to_le_bytes());
+        data.extend_from_slice(&self.eeprom.save_state());
+        data
+    }
+    fn load_state(&mut self, data: &[u8]) {
+        if data.len() < STATE_SIZE { return }
+        self.rom_bank = data[0];
+        self.ram_enabled = data[1] != 0;
+        self.ram_enabled2 = data[2] != 0;
+        self.latch_armed = data[3] != 0;
+        self.latched = (u16::from_le_bytes([data[4], data[5]]), u16::from_le_bytes([data[6], data[7]]));
+        self.eeprom.load_state(data[8..14].try_into().unwrap());
+    }
+    fn reset(&mut self, _kind: ResetKind) {
+        self.rom_bank = 1;
+        self.ram_enabled = false;
+        self.ram_enabled2 = false;
+        self.latched = (ACCELEROMETER_ERASED, ACCELEROMETER_ERASED);
+        self.latch_armed = false;
+        let data = std::mem::take(&mut self.eeprom.data);
+        self.eeprom = Eeprom { data, ..Eeprom::new() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHIP_SELECT: u8 = 0x80;
+    const CLOCK: u8 = 0x40;
+
+    fn mbc7() -> Mbc7 {
+        let mut mbc = Mbc7::new(vec![0; 4 * ROM_BANK_SIZE]);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x40);
+        mbc
+    }
+
+    // clocks `count` bits of `bits` into the eeprom, most significant first
+    fn send(mbc: &mut Mbc7, bits: u32, count: u32) {
+        for index in (0..count).rev() {
+            let data_in = ((bits >> index) & 1) as u8 * 0x02;
+            mbc.write_ram(0x80, CHIP_SELECT | data_in);
+            mbc.write_ram(0x80, CHIP_SELECT | CLOCK | data_in);
+        }
+    }
+
+    // a start bit, two opcode bits and the address
+    fn command(mbc: &mut Mbc7, opcode: u32, address: u32) {
+        send(mbc, 1 << 10 | opcode << 8 | address, 11);
+    }
+
+    fn read_word(mbc: &mut Mbc7, address: u32) -> u16 {
+        command(mbc, 0b10, address);
+        // a dummy zero comes before the data
+        assert_eq!(mbc.read_ram(0x80) & 0x01, 0);
+        let mut word = 0;
+        for _ in 0..16 {
+            send(mbc, 0, 1);
+            word = word << 1 | (mbc.read_ram(0x80) & 0x01) as u16;
+        }
+        mbc.write_ram(0x80, 0x00);
+        word
+    }
+
+    #[test]
+    fn eeprom_commands() {
+        let mut mbc = mbc7();
+        // writes are refused until EWEN
+        command(&mut mbc, 0b01, 3);
+        send(&mut mbc, 0x1234, 16);
+        assert_eq!(read_word(&mut mbc, 3), 0xFFFF);
+        command(&mut mbc, 0b00, 0b1100_0000);
+        command(&mut mbc, 0b01, 3);
+        send(&mut mbc, 0x1234, 16);
+        // ready once it's written
+        assert_eq!(mbc.read_ram(0x80) & 0x01, 1);
+        assert_eq!(mbc.ram()[6..8], [0x34, 0x12]);
+        assert_eq!(read_word(&mut mbc, 3), 0x1234);
+        // dropping chip select abandons a command halfway
+        command(&mut mbc, 0b01, 4);
+        send(&mut mbc, 0xAB, 8);
+        mbc.write_ram(0x80, 0x00);
+        assert_eq!(read_word(&mut mbc, 4), 0xFFFF);
+        // ERAL
+        command(&mut mbc, 0b00, 0b1000_0000);
+        assert!(mbc.ram().iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn the_accelerometer_latches_on_55_then_aa() {
+        let mut mbc = mbc7();
+        let latched = |mbc: &Mbc7| {
+            let byte = |address| mbc.read_ram(address) as u16;
+            (byte(0x20) | byte(0x30) << 8, byte(0x40) | byte(0x50) << 8)
+        };
+        mbc.set_tilt(1.0, -0.5);
+        // 0xAA on its own does nothing
+        mbc.write_ram(0x10, 0xAA);
+        assert_eq!(latched(&mbc), (0x8000, 0x8000));
+        mbc.write_ram(0x00, 0x55);
+        assert_eq!(latched(&mbc), (0x8000, 0x8000));
+        mbc.write_ram(0x10, 0xAA);
+        assert_eq!(latched(&mbc), (0x81D0 - 0x70, 0x81D0 + 0x38));
+        // it holds until latched again
+        mbc.set_tilt(0.0, 0.0);
+        assert_eq!(latched(&mbc), (0x81D0 - 0x70, 0x81D0 + 0x38));
+        // both ram enables are needed to see any of it
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0x20), 0xFF);
+    }
+}
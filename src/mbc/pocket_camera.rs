@@ -0,0 +1,199 @@
+use crate::cartridge::ROM_BANK_SIZE;
+use crate::mbc::{bank_mask, read_banked, CameraSource, Mapper, CAMERA_HEIGHT, CAMERA_WIDTH, RAM_BANK_SIZE};
+use crate::reset::ResetKind;
+
+// 0xA000-0xA035, mirrored every 0x80 bytes
+const REGISTER_COUNT: usize = 0x36;
+const MATRIX: usize = 0x06;
+// the finished picture goes to ram bank 0 as 16x14 tiles
+const IMAGE_ADDRESS: usize = 0x0100;
+// exposure that passes light through unchanged
+const UNITY_EXPOSURE: f32 = 0x1000 as f32;
+// how much the edge enhancement ratio (register 4 bits 4-6) amplifies the difference
+// from the neighbours
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+const STATE_SIZE: usize = 4 + REGISTER_COUNT + 4;
+
+// the Game Boy Camera's MAC-GBD. banks like MBC3 without a clock, but setting bit 4
+// of the ram bank maps the M64282FP sensor's registers over 0xA000. writing 1 to
+// register 0 bit 0 takes a picture, which after the exposure time is processed
+// through the 4x4 dither matrix into 2bpp tiles in ram.
+//
+// the picture comes from the host's camera source. only exposure, edge
+// enhancement, invert and the matrix are modelled, not gain or the voltage offsets
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    registers: [u8; REGISTER_COUNT],
+    // clock cycles until the running capture finishes, 0 when idle
+    capture_cycles: u32,
+    source: Option<Box<dyn CameraSource>>,
+}
+
+impl PocketCamera {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> PocketCamera {
+        PocketCamera {
+            rom,
+            ram,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: [0; REGISTER_COUNT],
+            capture_cycles: 0,
+            source: None,
+        }
+    }
+    fn registers_mapped(&self) -> bool {
+        self.ram_bank & 0x10 != 0
+    }
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() { return None }
+        let bank = self.ram_bank as usize & bank_mask(self.ram.len(), RAM_BANK_SIZE);
+        Some((bank * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+    fn exposure(&self) -> u16 {
+        u16::from_be_bytes([self.registers[2], self.registers[3]])
+    }
+    fn start_capture(&mut self) {
+        // in cpu machine cycles, a 1-D filtering pass (register 1 bit 7 clear) adds 512
+        let filtering = if self.registers[1] & 0x80 != 0 { 0 } else { 512 };
+        let machine_cycles = 32446 + filtering + 16 * self.exposure() as u32;
+        self.capture_cycles = machine_cycles * 4;
+    }
+    fn finish_capture(&mut self) {
+        let mut light = self.source.as_mut().map(|source| source.capture()).unwrap_or_default();
+        // nothing in front of the lens reads as darkness
+        light.resize(CAMERA_WIDTH * CAMERA_HEIGHT, 0);
+        let exposure = self.exposure() as f32 / UNITY_EXPOSURE;
+        let sensed: Vec<f32> = light.iter().map(|&level| level as f32 * exposure).collect();
+        let pixel = |x: isize, y: isize| {
+            let x = x.clamp(0, CAMERA_WIDTH as isize - 1) as usize;
+            let y = y.clamp(0, CAMERA_HEIGHT as isize - 1) as usize;
+            sensed[y * CAMERA_WIDTH + x]
+        };
+        let edge_mode = self.registers[1] >> 5 & 0x03;
+        let edge_ratio = EDGE_RATIOS[(self.registers[4] >> 4 & 0x07) as usize];
+        let invert = self.registers[4] & 0x08 != 0;
+        let matrix = &self.registers[MATRIX..];
+        let Some(image) = self.ram.get_mut(IMAGE_ADDRESS..IMAGE_ADDRESS + CAMERA_WIDTH * CAMERA_HEIGHT / 4) else { return };
+        image.fill(0);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let (sx, sy) = (x as isize, y as isize);
+                let center = pixel(sx, sy);
+                // VH selects which neighbours the enhancement looks at
+                let neighbours = match edge_mode {
+                    0 => None,
+                    1 => Some(2.0 * center - pixel(sx - 1, sy) - pixel(sx + 1, sy)),
+                    2 => Some(2.0 * center - pixel(sx, sy - 1) - pixel(sx, sy + 1)),
+                    _ => Some(4.0 * center - pixel(sx - 1, sy) - pixel(sx + 1, sy) - pixel(sx, sy - 1) - pixel(sx, sy + 1)),
+                };
+                let mut level = center + neighbours.map_or(0.0, |difference| difference * edge_ratio);
+                if invert {
+                    level = 255.0 - level;
+                }
+                let level = level.clamp(0.0, 255.0) as u8;
+                let thresholds = &matrix[(y % 4 * 4 + x % 4) * 3..][..3];
+                let shade = thresholds.iter().filter(|&&threshold| level < threshold).count() as u8;
+                let tile = y / 8 * (CAMERA_WIDTH / 8) + x / 8;
+                let address = tile * 16 + y % 8 * 2;
+                let bit = 7 - x % 8;
+                image[address] |= (shade & 1) << bit;
+                image[address + 1] |= (shade >> 1) << bit;
+            }
+        }
+    }
+}
+
+impl Mapper for PocketCamera {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_banked(&self.rom, self.rom_bank(address), ROM_BANK_SIZE, address as usize % ROM_BANK_SIZE)
+    }
+    fn rom_bank(&self, address: u16) -> usize {
+        // bank 0 can be mapped at 0x4000 too
+        if (address as usize) < ROM_BANK_SIZE { 0 }
+        else { self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE) }
+    }
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x1F,
+            _ => {}
+        }
+    }
+    // ram reads work without the enable, it only guards writes
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.registers_mapped() {
+            // only the capture register reads back, the rest are write only
+            return match address & 0x7F {
+                0 => self.registers[0] & 0x06 | (self.capture_cycles > 0) as u8,
+                _ => 0x00,
+            };
+        }
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.registers_mapped() {
+            let register = (address & 0x7F) as usize;
+            if register >= REGISTER_COUNT { return }
+            if register == 0 {
+                self.registers[0] = value & 0x06;
+                if value & 0x01 != 0 && self.capture_cycles == 0 {
+                    self.start_capture();
+                }
+            } else {
+                self.registers[register] = value;
+            }
+            return;
+        }
+        if !self.ram_enabled { return }
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+    fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.source = Some(source);
+    }
+    fn tick(&mut self, cycles: u32) {
+        if self.capture_cycles == 0 { return }
+        self.capture_cycles = self.capture_cycles.saturating_sub(cycles);
+        if self.capture_cycles == 0 {
+            self.finish_capture();
+        }
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = vec![self.ram_enabled as u8, self.rom_bank, self.ram_bank, 0];
+        data.extend_from_slice(&self.registers);
+        data.extend_from_slice(&self.capture_cycles.to_le_bytes());
+        data
+    }
+    fn load_state(&mut self, data: &[u8]) {
+        if data.len() < STATE_SIZE { return }
+        self.ram_enabled = data[0] != 0;
+        self.rom_bank = data[1];
+        self.ram_bank = data[2];
+        self.registers.copy_from_slice(&data[4..4 + REGISTER_COUNT]);
+        let cycles = &data[4 + REGISTER_COUNT..STATE_SIZE];
+        self.capture_cycles = u32::from_le_bytes([cycles[0], cycles[1], cycles[2], cycles[3]]);
+    }
+    fn reset(&mut self, _kind: ResetKind) {
+        self.ram_enabled = false;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.registers = [0; REGISTER_COUNT];
+        self.capture_cycles = 0;
+    }
+}
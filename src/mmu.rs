@@ -0,0 +1,1063 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+
+use crate::address::BankedAddress;
+use crate::apu::{Apu, NR10, NR24, NR41, NR52, PCM12, PCM34};
+use crate::cartridge::{Cartridge, CgbSupport};
+use crate::cdl::CodeDataLog;
+use crate::cheats::CheatEngine;
+use crate::colorization::{self, ManualPalette};
+use crate::config::Accuracy;
+use crate::cpu::{Bus, INTERRUPT_FLAGS, VBLANK_INTERRUPT};
+use crate::debugger::{MemoryAccess, Watchpoint};
+use crate::events::{EventKind, EventSubscriber, HardwareEvent};
+use crate::gpu::*;
+use crate::hdma::{Hdma, HdmaBlock, HDMA_BEGIN, HDMA_END};
+use crate::heatmap::{AccessKind, MemoryHeatmap};
+use crate::infrared::{Infrared, RP};
+use crate::io::{Io, IO_BEGIN, IO_END};
+use crate::joypad::{Joypad, P1};
+use crate::mbc::{CameraSource, RAM_BANK_SIZE};
+use crate::model::Model;
+use crate::oam_dma::{OamDma, DMA, OAM_DMA_LENGTH};
+use crate::reset::{fill_power_on_pattern, ResetKind, DEFAULT_RAM_SEED};
+use crate::savestate::{
+    find_section, rom_hash, Section, StateDecoder, StateEncoder, StateError, APU_SECTION, CARTRIDGE_RAM_SECTION,
+    MAPPER_SECTION, MEMORY_SECTION, PPU_SECTION, ROM_SECTION, SCREEN_SECTION, SGB_SECTION,
+};
+use crate::serial::{Serial, SB, SC};
+use crate::sgb::Sgb;
+use crate::timer::{Timer, DIV, TAC};
+
+pub const ROM_BEGIN: usize = 0x0000;
+pub const ROM_END: usize = 0x7FFF;
+pub const EXTERNAL_RAM_BEGIN: usize = 0xA000;
+pub const EXTERNAL_RAM_END: usize = 0xBFFF;
+pub const WRAM_BEGIN: usize = 0xC000;
+pub const WRAM_END: usize = 0xDFFF;
+pub const WRAM_SIZE: usize = WRAM_END - WRAM_BEGIN + 1;
+// CGB has eight 4 KiB banks, 0 at C000 and any of 1-7 at D000
+pub const WRAM_BANK_SIZE: usize = 0x1000;
+pub const WRAM_BANKS: usize = 8;
+pub const ECHO_RAM_BEGIN: usize = 0xE000;
+pub const ECHO_RAM_END: usize = 0xFDFF;
+pub const UNUSABLE_BEGIN: usize = 0xFEA0;
+pub const UNUSABLE_END: usize = 0xFEFF;
+pub const HRAM_BEGIN: usize = 0xFF80;
+pub const HRAM_END: usize = 0xFFFE;
+pub const HRAM_SIZE: usize = HRAM_END - HRAM_BEGIN + 1;
+pub const INTERRUPT_ENABLE: usize = 0xFFFF;
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+// the CGB boot rom skips 0x0100-0x01FF so the cartridge header shows through,
+// dumps keep that gap
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+// any write here unmaps the boot rom until the next reset
+const BOOT_ROM_DISABLE: u16 = 0xFF50;
+// VBK and SVBK
+const VRAM_BANK: u16 = 0xFF4F;
+const WRAM_BANK: u16 = 0xFF70;
+// KEY1, CGB only. bit 0 arms a switch between normal and double speed that the
+// next STOP carries out, bit 7 reads back the speed the cpu is running at
+const SPEED_SWITCH: u16 = 0xFF4D;
+
+#[derive(Debug)]
+pub struct BootRomSizeError(pub usize);
+
+impl fmt::Display for BootRomSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "boot rom is {} bytes, expected {} (DMG) or {} (CGB)", self.0, DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE)
+    }
+}
+
+impl std::error::Error for BootRomSizeError {}
+
+// routes every cpu access to the piece of hardware that answers it
+pub struct Mmu {
+    cartridge: Option<Cartridge>,
+    boot_rom: Option<Vec<u8>>,
+    boot_rom_mapped: bool,
+    wram: [u8; WRAM_BANK_SIZE * WRAM_BANKS],
+    // SVBK, only ever changes on CGB
+    wram_bank: u8,
+    // see SPEED_SWITCH
+    double_speed: bool,
+    speed_switch_armed: bool,
+    pub io: Io,
+    hram: [u8; HRAM_SIZE],
+    interrupt_enable: u8,
+    pub gpu: GPU,
+    pub apu: Apu,
+    pub timer: Timer,
+    pub serial: Serial,
+    pub infrared: Infrared,
+    pub joypad: Joypad,
+    // only with an SGB model and a game that asks for it
+    pub sgb: Option<Sgb>,
+    hdma: Hdma,
+    oam_dma: OamDma,
+    pub cheats: CheatEngine,
+    heatmap: Option<MemoryHeatmap>,
+    code_data_log: Option<CodeDataLog>,
+    event_subscriber: Option<EventSubscriber>,
+    watchpoints: Vec<Watchpoint>,
+    // caught since take_watch_hits last ran
+    watch_hits: RefCell<Vec<MemoryAccess>>,
+    model: Model,
+    // the button combination held at boot to colour a DMG game, if any
+    manual_palette: Option<ManualPalette>,
+    // seeds the work ram noise a power cycle leaves behind
+    ram_seed: u32,
+    // clock cycles run since the Mmu was made, a timestamp for debugging tools.
+    // resets and save states leave it alone
+    cycles: u64,
+}
+
+impl Mmu {
+    pub fn new() -> Mmu {
+        Mmu {
+            cartridge: None,
+            boot_rom: None,
+            boot_rom_mapped: false,
+            wram: [0; WRAM_BANK_SIZE * WRAM_BANKS],
+            wram_bank: 1,
+            double_speed: false,
+            speed_switch_armed: false,
+            io: Io::new(),
+            hram: [0; HRAM_SIZE],
+            interrupt_enable: 0,
+            gpu: GPU::new(),
+            apu: Apu::new(),
+            timer: Timer::new(),
+            serial: Serial::new(),
+            infrared: Infrared::new(),
+            joypad: Joypad::new(),
+            sgb: None,
+            hdma: Hdma::new(),
+            oam_dma: OamDma::new(),
+            cheats: CheatEngine::new(),
+            heatmap: None,
+            code_data_log: None,
+            event_subscriber: None,
+            watchpoints: Vec::new(),
+            watch_hits: RefCell::new(Vec::new()),
+            model: Model::default(),
+            manual_palette: None,
+            ram_seed: DEFAULT_RAM_SEED,
+            cycles: 0,
+        }
+    }
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.io.set_model(model);
+        self.serial.set_cgb(model.is_cgb());
+        self.update_compatibility_mode();
+    }
+    pub fn model(&self) -> Model {
+        self.model
+    }
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    // the accuracy preset for every component that has one
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.gpu.set_config(GpuConfig { accuracy, ..self.gpu.config() });
+        self.timer.set_accuracy(accuracy);
+    }
+    // fills work ram with the power-on noise for `seed` right away, rather than
+    // only after the next power cycle
+    pub fn set_ram_seed(&mut self, seed: u32) {
+        self.ram_seed = seed;
+        fill_power_on_pattern(&mut self.wram, seed);
+    }
+    pub fn set_manual_palette(&mut self, palette: Option<ManualPalette>) {
+        self.manual_palette = palette;
+        self.update_compatibility_mode();
+    }
+    // a CGB running a DMG game draws it the DMG way, only coloured in with the
+    // palette the boot rom picked for it
+    fn update_compatibility_mode(&mut self) {
+        let dmg_game = self.cartridge.as_ref().filter(|cartridge| cartridge.header.cgb == CgbSupport::None);
+        match dmg_game {
+            Some(cartridge) if self.model.is_cgb() => {
+                let colors = self.manual_palette.map_or_else(|| colorization::boot_palette(cartridge), |palette| palette.colors());
+                self.gpu.set_cgb_mode(false);
+                self.gpu.set_dmg_colors(Some(colors));
+            }
+            _ => {
+                self.gpu.set_cgb_mode(self.model.is_cgb());
+                self.gpu.set_dmg_colors(None);
+            }
+        }
+        // the SGB only listens to games that declare support for it
+        let sgb_game = self.cartridge.as_ref().is_some_and(|cartridge| cartridge.header.sgb);
+        if self.model != Model::Sgb || !sgb_game {
+            self.sgb = None;
+        } else if self.sgb.is_none() {
+            self.sgb = Some(Sgb::new());
+        }
+    }
+    // offset into the whole of work ram for an offset from C000
+    fn wram_index(&self, offset: usize) -> usize {
+        if offset < WRAM_BANK_SIZE { offset }
+        else { self.wram_bank as usize * WRAM_BANK_SIZE + offset - WRAM_BANK_SIZE }
+    }
+    // FEA0-FEFF isn't backed by anything. writes vanish and what reads return
+    // depends on the model: 0x00 on monochrome hardware, while CGB (rev E) echoes
+    // the upper nibble of the address' low byte, e.g. FEB3 reads 0xBB
+    fn read_unusable(&self, address: usize) -> u8 {
+        match self.model {
+            Model::Cgb => {
+                let nibble = (address as u8) >> 4;
+                nibble << 4 | nibble
+            }
+            Model::Dmg | Model::Mgb | Model::Sgb => 0x00,
+        }
+    }
+    // map a boot rom over the start of the cartridge. it stays visible until the
+    // program writes to FF50, and comes back on every reset
+    pub fn load_boot_rom(&mut self, rom: Vec<u8>) -> Result<(), BootRomSizeError> {
+        if rom.len() != DMG_BOOT_ROM_SIZE && rom.len() != CGB_BOOT_ROM_SIZE {
+            return Err(BootRomSizeError(rom.len()));
+        }
+        self.boot_rom = Some(rom);
+        self.boot_rom_mapped = true;
+        Ok(())
+    }
+    pub fn remove_boot_rom(&mut self) -> Option<Vec<u8>> {
+        self.boot_rom_mapped = false;
+        self.boot_rom.take()
+    }
+    fn read_boot_rom(&self, address: usize) -> Option<u8> {
+        let rom = self.boot_rom.as_ref().filter(|_| self.boot_rom_mapped)?;
+        let header = 0x0100..0x0200;
+        if address < rom.len() && !header.contains(&address) { Some(rom[address]) } else { None }
+    }
+    // the cartridge answers for 0x0000-0x7FFF and 0xA000-0xBFFF, with none
+    // inserted those read as open bus
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+        self.update_compatibility_mode();
+    }
+    pub fn eject_cartridge(&mut self) -> Option<Cartridge> {
+        let cartridge = self.cartridge.take();
+        self.update_compatibility_mode();
+        cartridge
+    }
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+    // battery ram of the inserted cartridge, empty without one
+    pub fn save_ram(&self) -> Vec<u8> {
+        self.cartridge.as_ref().map(Cartridge::save_ram).unwrap_or_default()
+    }
+    pub fn load_ram(&mut self, data: &[u8]) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.load_ram(data);
+        }
+    }
+    // writes battery ram out to the cartridge's save file now, if it has one
+    pub fn flush_save(&self) -> io::Result<()> {
+        self.cartridge.as_ref().map_or(Ok(()), Cartridge::flush_save)
+    }
+    // analog input for accelerometer carts, in g. a frontend can feed this from a
+    // gamepad stick or a device sensor
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.set_tilt(x, y);
+        }
+    }
+    // where a camera cart's pictures come from, see CameraSource
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.set_camera_source(source);
+        }
+    }
+    // start collecting access counts, window is measured in executed instructions
+    pub fn enable_heatmap(&mut self, window: Option<u64>) {
+        self.heatmap = Some(MemoryHeatmap::new(window));
+    }
+    pub fn disable_heatmap(&mut self) -> Option<MemoryHeatmap> {
+        self.heatmap.take()
+    }
+    pub fn heatmap(&self) -> Option<&MemoryHeatmap> {
+        self.heatmap.as_ref()
+    }
+    // start marking rom bytes as code or data, sized for the inserted cartridge
+    pub fn enable_code_data_log(&mut self) {
+        let size = self.cartridge.as_ref().map_or(0, |cartridge| cartridge.rom().len());
+        self.code_data_log = Some(CodeDataLog::new(size));
+    }
+    // carry on with a log from before, e.g. one loaded from an earlier session
+    pub fn set_code_data_log(&mut self, log: Option<CodeDataLog>) -> Option<CodeDataLog> {
+        std::mem::replace(&mut self.code_data_log, log)
+    }
+    pub fn code_data_log(&self) -> Option<&CodeDataLog> {
+        self.code_data_log.as_ref()
+    }
+    // hear about interrupts, dma, lcd modes and so on as they happen
+    pub fn set_event_subscriber<F: FnMut(&HardwareEvent) + 'static>(&mut self, subscriber: F) {
+        self.event_subscriber = Some(Box::new(subscriber));
+    }
+    pub fn clear_event_subscriber(&mut self) {
+        self.event_subscriber = None;
+    }
+    fn emit(&mut self, kind: EventKind) {
+        if let Some(subscriber) = &mut self.event_subscriber {
+            subscriber(&HardwareEvent { cycle: self.cycles, kind });
+        }
+    }
+    fn rom_bank(&self) -> Option<usize> {
+        self.cartridge.as_ref().map(|cartridge| cartridge.mapper().rom_bank(0x4000))
+    }
+    // what a tick did, in the order it happened
+    fn emit_tick_events(&mut self, mode: Mode, timer: u8, serial: u8, requested: u8) {
+        if self.gpu.mode() != mode {
+            self.emit(EventKind::LcdModeChanged(self.gpu.mode()));
+        }
+        if timer != 0 {
+            self.emit(EventKind::TimerOverflow);
+        }
+        if serial != 0 {
+            let incoming = self.serial.read(SB);
+            self.emit(EventKind::SerialTransferFinished { incoming });
+        }
+        for bit in 0..5 {
+            if requested & (1 << bit) != 0 {
+                self.emit(EventKind::InterruptRequested(1 << bit));
+            }
+        }
+    }
+    // catch cpu reads and writes of an address. false if it was already there
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.watchpoints.contains(&watchpoint) { return false }
+        self.watchpoints.push(watchpoint);
+        true
+    }
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|&other| other != watchpoint);
+        self.watchpoints.len() != count
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+    // the accesses watchpoints caught, oldest first. an attached debugger takes
+    // them after every instruction and stops, otherwise they pile up here
+    pub fn take_watch_hits(&self) -> Vec<MemoryAccess> {
+        self.watch_hits.take()
+    }
+    fn watch(&self, kind: AccessKind, address: u16, value: u8) {
+        if !self.watchpoints.iter().any(|watchpoint| watchpoint.address == address) { return }
+        let access = MemoryAccess { kind, address, bank: self.bank_at(address), value };
+        if self.watchpoints.iter().any(|watchpoint| watchpoint.matches(&access)) {
+            self.watch_hits.borrow_mut().push(access);
+        }
+    }
+    // where a cpu address is in the rom file, None outside the cartridge rom or
+    // while the boot rom covers it
+    fn rom_offset(&self, address: u16) -> Option<usize> {
+        if self.read_boot_rom(address as usize).is_some() { return None }
+        let offset = BankedAddress::mapped(self, address).rom_offset()?;
+        let cartridge = self.cartridge.as_ref()?;
+        Some(offset % cartridge.rom().len().max(1))
+    }
+    // a byte from any bank, mapped in or not: rom banks at 4000-7FFF and work
+    // ram banks at D000-DFFF. anywhere else, or with no bank, what peek_byte sees
+    pub fn peek_banked(&self, location: BankedAddress) -> u8 {
+        match (location.bank, location.address as usize) {
+            (Some(_), 0x4000..=ROM_END) => {
+                let rom = self.cartridge.as_ref().map_or(&[][..], |cartridge| cartridge.rom());
+                let offset = location.rom_offset().unwrap_or(0);
+                if rom.is_empty() { OPEN_BUS } else { rom[offset % rom.len()] }
+            }
+            (Some(bank), 0xD000..=WRAM_END) => {
+                let bank = (bank % WRAM_BANKS).max(1);
+                self.wram[bank * WRAM_BANK_SIZE + location.address as usize - 0xD000]
+            }
+            _ => self.peek_memory(location.address),
+        }
+    }
+    // the bus as if `bank` were mapped into the switchable areas, so tools can
+    // look at banks the game hasn't switched in
+    pub fn bank_view(&self, bank: usize) -> BankView<'_> {
+        BankView { mmu: self, bank }
+    }
+    // registers owned by a component on the bus are routed here, the rest go to io
+    fn read_io(&self, address: u16) -> u8 {
+        match address {
+            P1 => {
+                let value = self.joypad.read(address);
+                self.sgb.as_ref().map_or(value, |sgb| sgb.read_p1(value))
+            }
+            SB | SC => self.serial.read(address),
+            DIV..=TAC => self.timer.read(address),
+            0xFF40 => self.gpu.lcdc.into(),
+            0xFF41 => self.gpu.stat().into(),
+            0xFF42 => self.gpu.scroll_y,
+            0xFF43 => self.gpu.scroll_x,
+            0xFF44 => self.gpu.ly(),
+            0xFF45 => self.gpu.lyc,
+            DMA => self.oam_dma.read(),
+            0xFF47 => self.gpu.bg_palette.into(),
+            0xFF48 => self.gpu.obj_palettes[0].into(),
+            0xFF49 => self.gpu.obj_palettes[1].into(),
+            0xFF4A => self.gpu.window_y,
+            0xFF4B => self.gpu.window_x,
+            NR10..=NR24 | NR41..=NR52 => self.apu.read(address),
+            HDMA_BEGIN..=HDMA_END if self.model.is_cgb() => self.hdma.read(address),
+            VRAM_BANK if self.model.is_cgb() => 0xFE | self.gpu.vram_bank(),
+            BCPS..=OCPD if self.model.is_cgb() => self.gpu.read_palette_register(address),
+            WRAM_BANK if self.model.is_cgb() => 0xF8 | self.wram_bank,
+            SPEED_SWITCH if self.model.is_cgb() => 0x7E | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8,
+            PCM12 | PCM34 if self.model.is_cgb() => self.apu.read_pcm(address),
+            RP if self.model.is_cgb() => self.infrared.read(address),
+            _ => self.io.read(address),
+        }
+    }
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            P1 => {
+                self.joypad.write(address, value);
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_p1(value);
+                }
+            }
+            SB | SC => {
+                self.serial.write(address, value);
+                if address == SC && value & 0x80 != 0 && self.serial.read(SC) & 0x80 != 0 {
+                    let outgoing = self.serial.read(SB);
+                    self.emit(EventKind::SerialTransferStarted { outgoing });
+                }
+            }
+            DIV..=TAC => self.timer.write(address, value),
+            0xFF40 => self.gpu.write_lcdc(value),
+            0xFF41 => self.gpu.write_stat(value),
+            0xFF42 => self.gpu.scroll_y = value,
+            0xFF43 => self.gpu.scroll_x = value,
+            // LY is read only
+            0xFF44 => {}
+            0xFF45 => self.gpu.lyc = value,
+            DMA => {
+                self.oam_dma.start(value);
+                let source = self.oam_dma.source();
+                self.emit(EventKind::DmaStarted { source, destination: OAM_BEGIN as u16, length: OAM_DMA_LENGTH });
+            }
+            0xFF47 => self.gpu.bg_palette = value.into(),
+            0xFF48 => self.gpu.obj_palettes[0] = value.into(),
+            0xFF49 => self.gpu.obj_palettes[1] = value.into(),
+            0xFF4A => self.gpu.window_y = value,
+            0xFF4B => self.gpu.window_x = value,
+            NR10..=NR24 | NR41..=NR52 => self.apu.write(address, value),
+            HDMA_BEGIN..=HDMA_END if self.model.is_cgb() => {
+                if let Some(block) = self.hdma.write(address, value) {
+                    self.run_hdma(block);
+                }
+            }
+            VRAM_BANK if self.model.is_cgb() => self.gpu.set_vram_bank(value & 0x01),
+            BCPS..=OCPD if self.model.is_cgb() => self.gpu.write_palette_register(address, value),
+            // bank 0 can't be mapped at D000, asking for it gets bank 1
+            WRAM_BANK if self.model.is_cgb() => {
+                let bank = (value & 0x07).max(1);
+                if bank != self.wram_bank {
+                    self.wram_bank = bank;
+                    self.emit(EventKind::WramBankSwitched(bank));
+                }
+            }
+            SPEED_SWITCH if self.model.is_cgb() => self.speed_switch_armed = value & 0x01 != 0,
+            RP if self.model.is_cgb() => self.infrared.write(address, value),
+            BOOT_ROM_DISABLE => {
+                self.boot_rom_mapped = false;
+                self.io.write(address, value);
+            }
+            _ => self.io.write(address, value),
+        }
+    }
+    // copies straight into VRAM. the cpu is paused while this happens on hardware,
+    // that stall isn't counted
+    fn run_hdma(&mut self, block: HdmaBlock) {
+        let (source, destination, length) = (block.source, block.destination, block.length);
+        self.emit(EventKind::DmaStarted { source, destination, length });
+        for offset in 0..block.length {
+            let value = self.peek_memory(block.source.wrapping_add(offset));
+            let destination = block.destination.wrapping_add(offset) as usize;
+            self.gpu.write_vram((destination - VRAM_BEGIN) % VRAM_SIZE, value);
+        }
+        self.emit(EventKind::DmaFinished);
+    }
+    // copies the bytes of an OAM DMA the cycles just run got through. it reads
+    // past the PPU's locks, and pages from E0 up see work ram like echo ram does
+    fn run_oam_dma(&mut self, cycles: u32) {
+        let source = self.oam_dma.source();
+        let copied = self.oam_dma.tick(cycles);
+        if copied.is_empty() { return }
+        for offset in copied {
+            let address = source + offset;
+            let address = if address as usize >= ECHO_RAM_BEGIN { address - 0x2000 } else { address };
+            let value = self.peek_memory(address);
+            self.gpu.write_oam(offset as usize, value);
+        }
+        if !self.oam_dma.active() {
+            self.emit(EventKind::DmaFinished);
+        }
+    }
+    // GameShark codes, run at the start of every vblank like the real device's
+    // interrupt hook
+    fn apply_cheat_writes(&mut self) {
+        for (bank, address, value) in self.cheats.ram_writes() {
+            match (bank, address as usize) {
+                (0x80..=0x8F, EXTERNAL_RAM_BEGIN..=EXTERNAL_RAM_END) => {
+                    let offset = (bank as usize & 0x0F) * RAM_BANK_SIZE + address as usize - EXTERNAL_RAM_BEGIN;
+                    if let Some(byte) = self.cartridge.as_mut().and_then(|cartridge| cartridge.mapper_mut().ram_mut().get_mut(offset)) {
+                        *byte = value;
+                    }
+                }
+                (0x90..=0x97, 0xD000..=WRAM_END) => {
+                    let bank = (bank as usize & 0x07).max(1);
+                    self.wram[bank * WRAM_BANK_SIZE + address as usize - 0xD000] = value;
+                }
+                // whatever bank is mapped, written behind the cpu's back so heatmaps,
+                // watchpoints and the CDL only see what the game itself does
+                (_, EXTERNAL_RAM_BEGIN..=EXTERNAL_RAM_END) => {
+                    if let Some(cartridge) = &mut self.cartridge {
+                        cartridge.write_ram(address - EXTERNAL_RAM_BEGIN as u16, value);
+                    }
+                }
+                (_, WRAM_BEGIN..=WRAM_END) => self.wram[self.wram_index(address as usize - WRAM_BEGIN)] = value,
+                (_, ECHO_RAM_BEGIN..=ECHO_RAM_END) => {
+                    self.wram[self.wram_index(address as usize - ECHO_RAM_BEGIN)] = value;
+                }
+                (_, HRAM_BEGIN..=HRAM_END) => self.hram[address as usize - HRAM_BEGIN] = value,
+                // codes only ever target ram
+                _ => {}
+            }
+        }
+    }
+    // whether the PPU currently keeps the cpu away from this address
+    fn blocked_by_ppu(&self, address: u16) -> bool {
+        match address as usize {
+            VRAM_BEGIN..=VRAM_END => !self.gpu.vram_accessible(),
+            OAM_BEGIN..=OAM_END => !self.gpu.oam_accessible(),
+            _ => false,
+        }
+    }
+    // OAM DMA has the rest of the bus while it runs, the cpu only gets HRAM
+    fn blocked_by_dma(&self, address: u16) -> bool {
+        self.oam_dma.active() && !(HRAM_BEGIN..=HRAM_END).contains(&(address as usize))
+    }
+    // read without recording the access, for tooling that shouldn't disturb the heatmap
+    fn peek_memory(&self, address: u16) -> u8 {
+        let address = address as usize;
+        match address {
+            ROM_BEGIN..=ROM_END => self.read_boot_rom(address)
+                .or_else(|| self.cartridge.as_ref()
+                    .map(|cartridge| self.cheats.patch_rom(address as u16, cartridge.read_rom(address as u16))))
+                .unwrap_or(OPEN_BUS),
+            VRAM_BEGIN..=VRAM_END => self.gpu.read_vram(address - VRAM_BEGIN),
+            EXTERNAL_RAM_BEGIN..=EXTERNAL_RAM_END => self.cartridge.as_ref()
+                .map_or(OPEN_BUS, |cartridge| cartridge.read_ram((address - EXTERNAL_RAM_BEGIN) as u16)),
+            WRAM_BEGIN..=WRAM_END => self.wram[self.wram_index(address - WRAM_BEGIN)],
+            // echo ram mirrors the first 0x1E00 bytes of work ram, for reads and writes alike
+            ECHO_RAM_BEGIN..=ECHO_RAM_END => self.wram[self.wram_index(address - ECHO_RAM_BEGIN)],
+            OAM_BEGIN..=OAM_END => self.gpu.read_oam(address - OAM_BEGIN),
+            UNUSABLE_BEGIN..=UNUSABLE_END => self.read_unusable(address),
+            IO_BEGIN..=IO_END => self.read_io(address as u16),
+            HRAM_BEGIN..=HRAM_END => self.hram[address - HRAM_BEGIN],
+            _ => self.interrupt_enable,
+        }
+    }
+}
+
+impl Mmu {
+    // a hash of the rom to check states against, and the title to name it by
+    fn rom_identity(&self) -> (u64, String) {
+        match &self.cartridge {
+            Some(cartridge) => (rom_hash(cartridge.rom()), cartridge.header.title.clone()),
+            None => (rom_hash(&[]), String::new()),
+        }
+    }
+    // the cartridge type at the end is only for savestate::inspect, it tells it
+    // how to read the MBC section
+    fn save_rom_identity(&self, state: &mut StateEncoder) {
+        let (hash, title) = self.rom_identity();
+        state.u64(hash);
+        state.vec(title.as_bytes());
+        state.u8(self.cartridge.as_ref().map_or(0x00, |cartridge| cartridge.header.cartridge_type));
+    }
+    // everything on the bus that isn't the PPU, APU or cartridge
+    fn save_memory(&self, state: &mut StateEncoder) {
+        state.bool(self.boot_rom_mapped);
+        state.bytes(&self.wram);
+        state.u8(self.wram_bank);
+        state.bytes(&self.hram);
+        state.u8(self.interrupt_enable);
+        self.io.save_state(state);
+        self.hdma.save_state(state);
+        self.oam_dma.save_state(state);
+        state.bool(self.double_speed);
+        state.bool(self.speed_switch_armed);
+        self.timer.save_state(state);
+        self.serial.save_state(state);
+        self.joypad.save_state(state);
+        self.infrared.save_state(state);
+    }
+    fn load_memory(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        // a state from partway through the boot rom can't go on without one
+        self.boot_rom_mapped = state.bool()? && self.boot_rom.is_some();
+        state.bytes(&mut self.wram)?;
+        self.wram_bank = (state.u8()? & 0x07).max(1);
+        state.bytes(&mut self.hram)?;
+        self.interrupt_enable = state.u8()?;
+        self.io.load_state(state)?;
+        self.hdma.load_state(state)?;
+        self.oam_dma.load_state(state)?;
+        self.double_speed = state.bool()? && self.model.is_cgb();
+        self.speed_switch_armed = state.bool()? && self.model.is_cgb();
+        self.timer.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.infrared.load_state(state)
+    }
+}
+
+// see Mmu::bank_view. writes go nowhere
+pub struct BankView<'a> {
+    mmu: &'a Mmu,
+    bank: usize,
+}
+
+impl Bus for BankView<'_> {
+    fn bank_at(&self, address: u16) -> Option<usize> {
+        match address {
+            0x4000..=0x7FFF | 0xD000..=0xDFFF => Some(self.bank),
+            _ => self.mmu.bank_at(address),
+        }
+    }
+    fn read_byte(&self, address: u16) -> u8 {
+        self.mmu.peek_banked(BankedAddress { bank: self.bank_at(address), address })
+    }
+    fn write_byte(&mut self, _address: u16, _value: u8) {}
+}
+
+impl Bus for Mmu {
+    // the cartridge's rom bank in 0x0000-0x7FFF, the work ram bank in 0xD000-0xDFFF
+    fn bank_at(&self, address: u16) -> Option<usize> {
+        match address {
+            0x0000..=0x7FFF => self.cartridge.as_ref().map(|cartridge| cartridge.mapper().rom_bank(address)),
+            0xD000..=0xDFFF => Some(self.wram_bank as usize),
+            _ => None,
+        }
+    }
+    fn read_byte(&self, address: u16) -> u8 {
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record(AccessKind::Read, address);
+        }
+        if let Some(log) = &self.code_data_log && address as usize <= ROM_END {
+            log.read(address, self.rom_offset(address));
+        }
+        let value = if self.blocked_by_ppu(address) || self.blocked_by_dma(address) { OPEN_BUS } else { self.peek_memory(address) };
+        if !self.watchpoints.is_empty() {
+            self.watch(AccessKind::Read, address, value);
+        }
+        value
+    }
+    fn peek_byte(&self, address: u16) -> u8 {
+        self.peek_memory(address)
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record(AccessKind::Write, address);
+        }
+        if !self.watchpoints.is_empty() {
+            self.watch(AccessKind::Write, address, value);
+        }
+        if self.blocked_by_ppu(address) || self.blocked_by_dma(address) { return }
+        let address = address as usize;
+        match address {
+            ROM_BEGIN..=ROM_END => {
+                let bank = self.rom_bank();
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.write_rom(address as u16, value);
+                }
+                if let Some(switched) = self.rom_bank().filter(|&switched| Some(switched) != bank) {
+                    self.emit(EventKind::RomBankSwitched(switched));
+                }
+            }
+            VRAM_BEGIN..=VRAM_END => self.gpu.write_vram(address - VRAM_BEGIN, value),
+            EXTERNAL_RAM_BEGIN..=EXTERNAL_RAM_END => {
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.write_ram((address - EXTERNAL_RAM_BEGIN) as u16, value);
+                }
+            }
+            WRAM_BEGIN..=WRAM_END => self.wram[self.wram_index(address - WRAM_BEGIN)] = value,
+            ECHO_RAM_BEGIN..=ECHO_RAM_END => self.wram[self.wram_index(address - ECHO_RAM_BEGIN)] = value,
+            OAM_BEGIN..=OAM_END => self.gpu.write_oam(address - OAM_BEGIN, value),
+            UNUSABLE_BEGIN..=UNUSABLE_END => {}
+            IO_BEGIN..=IO_END => self.write_io(address as u16, value),
+            HRAM_BEGIN..=HRAM_END => self.hram[address - HRAM_BEGIN] = value,
+            _ => self.interrupt_enable = value,
+        }
+    }
+    fn instruction_started(&self, address: u16) {
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record(AccessKind::Execute, address);
+        }
+        if let Some(log) = &self.code_data_log {
+            log.instruction_started(address, self.peek_memory(address), |address| self.rom_offset(address));
+        }
+    }
+    fn instruction_finished(&mut self) {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.end_instruction();
+        }
+    }
+    fn interrupt_dispatched(&mut self, interrupt: u8) {
+        self.emit(EventKind::InterruptDispatched(interrupt));
+    }
+    fn state_sections(&self) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut sections = vec![
+            (ROM_SECTION, StateEncoder::encode(|state| self.save_rom_identity(state))),
+            (MEMORY_SECTION, StateEncoder::encode(|state| self.save_memory(state))),
+            (PPU_SECTION, StateEncoder::encode(|state| self.gpu.save_state(state))),
+            (APU_SECTION, StateEncoder::encode(|state| self.apu.save_state(state))),
+        ];
+        if let Some(cartridge) = &self.cartridge {
+            let mapper = cartridge.save_state();
+            if !mapper.is_empty() {
+                sections.push((MAPPER_SECTION, mapper));
+            }
+            let ram = cartridge.save_ram();
+            if !ram.is_empty() {
+                sections.push((CARTRIDGE_RAM_SECTION, ram));
+            }
+        }
+        if let Some(sgb) = &self.sgb {
+            sections.push((SGB_SECTION, StateEncoder::encode(|state| sgb.save_state(state))));
+        }
+        sections.push((SCREEN_SECTION, StateEncoder::encode(|state| self.gpu.save_screen(state))));
+        sections
+    }
+    // states made with another rom are refused before anything is touched.
+    // sections that depend on the cartridge or model are optional
+    fn load_state_sections(&mut self, sections: &[Section]) -> Result<(), StateError> {
+        let mut rom = StateDecoder::new(find_section(sections, ROM_SECTION)?);
+        let (hash, title) = (rom.u64()?, rom.vec()?);
+        let (loaded_hash, loaded_title) = self.rom_identity();
+        if hash != loaded_hash {
+            let state = String::from_utf8_lossy(&title).into_owned();
+            return Err(StateError::WrongRom { state, loaded: loaded_title });
+        }
+        self.load_memory(&mut StateDecoder::new(find_section(sections, MEMORY_SECTION)?))?;
+        self.gpu.load_state(&mut StateDecoder::new(find_section(sections, PPU_SECTION)?))?;
+        self.apu.load_state(&mut StateDecoder::new(find_section(sections, APU_SECTION)?))?;
+        let optional = |tag| sections.iter().find(|section: &&Section| section.tag == tag);
+        if let Some(cartridge) = &mut self.cartridge {
+            if let Some(section) = optional(MAPPER_SECTION) {
+                cartridge.load_state(&section.data);
+            }
+            if let Some(section) = optional(CARTRIDGE_RAM_SECTION) {
+                cartridge.load_ram(&section.data);
+            }
+        }
+        if let Some(sgb) = &mut self.sgb && let Some(section) = optional(SGB_SECTION) {
+            sgb.load_state(&mut StateDecoder::new(section))?;
+        }
+        if let Some(section) = optional(SCREEN_SECTION) {
+            self.gpu.load_screen(&mut StateDecoder::new(section))?;
+        }
+        Ok(())
+    }
+    // `cycles` are the cpu's. in double speed the timer, serial clock and OAM DMA
+    // keep up with it while everything else sees half as many
+    fn tick(&mut self, cycles: u32) {
+        let dots = if self.double_speed { cycles / 2 } else { cycles };
+        self.cycles += dots as u64;
+        self.run_oam_dma(cycles);
+        let mode = self.gpu.mode();
+        self.gpu.tick(dots);
+        self.apu.tick(dots);
+        self.timer.tick(cycles);
+        self.serial.tick(cycles);
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.tick(dots);
+        }
+        let timer = self.timer.take_interrupts();
+        let serial = self.serial.take_interrupts();
+        let requested = self.gpu.take_interrupts() | timer | serial | self.joypad.take_interrupts();
+        if self.event_subscriber.is_some() {
+            self.emit_tick_events(mode, timer, serial, requested);
+        }
+        if requested & VBLANK_INTERRUPT != 0 {
+            if let Some(sgb) = &mut self.sgb {
+                sgb.frame_finished(self.gpu.indexed_frame());
+            }
+            self.apply_cheat_writes();
+        }
+        if requested != 0 {
+            self.io.set_raw(INTERRUPT_FLAGS, self.io.raw(INTERRUPT_FLAGS) | requested);
+        }
+        if self.gpu.take_entered_hblank() && let Some(block) = self.hdma.hblank() {
+            self.run_hdma(block);
+        }
+    }
+    fn reset(&mut self, kind: ResetKind) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.reset(kind);
+        }
+        match kind {
+            ResetKind::Soft => {}
+            ResetKind::PowerCycle => {
+                fill_power_on_pattern(&mut self.wram, self.ram_seed);
+                self.hram = [0; HRAM_SIZE];
+            }
+        }
+        // io registers always come back to their reset values
+        self.io.reset();
+        self.interrupt_enable = 0;
+        self.wram_bank = 1;
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.gpu.reset(kind);
+        self.apu.reset();
+        self.timer.reset();
+        self.serial.reset();
+        self.infrared.reset();
+        self.joypad.reset();
+        if self.sgb.is_some() {
+            self.sgb = Some(Sgb::new());
+        }
+        self.hdma.reset();
+        self.oam_dma.reset();
+        self.boot_rom_mapped = self.boot_rom.is_some();
+    }
+    fn interrupt_flags(&self) -> u8 {
+        self.io.raw(INTERRUPT_FLAGS)
+    }
+    fn set_interrupt_flags(&mut self, value: u8) {
+        self.io.set_raw(INTERRUPT_FLAGS, value);
+    }
+    fn interrupt_enable(&self) -> u8 {
+        self.interrupt_enable
+    }
+    fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+    fn set_divider(&mut self, counter: u16) {
+        self.timer.set_counter(counter);
+    }
+    // the switch resets the divider. the couple of thousand cycles the cpu sits
+    // still for while the clock settles aren't counted
+    fn stop(&mut self) -> bool {
+        if !self.model.is_cgb() || !self.speed_switch_armed { return false }
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.timer.set_counter(0);
+        true
+    }
+    fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    #[test]
+    fn boot_rom_covers_the_cartridge_until_ff50() {
+        let mut mmu = Mmu::new();
+        mmu.insert_cartridge(Cartridge::from_bytes(test_rom(&[0x18, 0xFE], 0x00, 0)).unwrap());
+        assert!(matches!(mmu.load_boot_rom(vec![0; 0x200]), Err(BootRomSizeError(0x200))));
+        mmu.load_boot_rom(vec![0x31; DMG_BOOT_ROM_SIZE]).unwrap();
+        assert!(mmu.boot_rom_mapped());
+        assert_eq!(mmu.read_byte(0x0000), 0x31);
+        assert_eq!(mmu.read_byte(0x00FF), 0x31);
+        // the cartridge header shows through from 0x0100
+        assert_eq!(mmu.read_byte(0x0100), 0x18);
+        // writing anything to FF50 unmaps it for good
+        mmu.write_byte(BOOT_ROM_DISABLE, 0x01);
+        assert!(!mmu.boot_rom_mapped());
+        assert_eq!(mmu.read_byte(0x0000), 0x00);
+        mmu.write_byte(BOOT_ROM_DISABLE, 0x00);
+        assert_eq!(mmu.read_byte(0x0000), 0x00);
+        // until the next reset
+        mmu.reset(ResetKind::Soft);
+        assert_eq!(mmu.read_byte(0x0000), 0x31);
+
+        // a CGB boot rom also covers 0x0200-0x08FF, but not the header
+        let mut cgb_rom = vec![0x3E; CGB_BOOT_ROM_SIZE];
+        cgb_rom[0x0100..0x0200].fill(0x00);
+        mmu.load_boot_rom(cgb_rom).unwrap();
+        assert_eq!(mmu.read_byte(0x0150), 0x00);
+        assert_eq!(mmu.read_byte(0x0200), 0x3E);
+        assert_eq!(mmu.read_byte(0x08FF), 0x3E);
+        assert_eq!(mmu.read_byte(0x0900), 0x00);
+        mmu.remove_boot_rom();
+        assert_eq!(mmu.read_byte(0x0200), 0x00);
+    }
+
+    #[test]
+    fn io_reads_through_the_register_masks() {
+        let mut mmu = Mmu::new();
+        // P1: the top two bits read 1, only the select bits can be written and
+        // the button lines read 1 with nothing pressed
+        mmu.write_byte(P1, 0x00);
+        assert_eq!(mmu.read_byte(P1), 0xCF);
+        mmu.write_byte(P1, 0xEF);
+        assert_eq!(mmu.read_byte(P1), 0xEF);
+        // STAT: bit 7 reads 1 and writes can't touch the mode or the LY=LYC flag
+        let status = mmu.read_byte(0xFF41) & 0x07;
+        mmu.write_byte(0xFF41, 0x00);
+        assert_eq!(mmu.read_byte(0xFF41), 0x80 | status);
+        mmu.write_byte(0xFF41, 0xFF);
+        assert_eq!(mmu.read_byte(0xFF41), 0xF8 | status);
+        // registers nothing drives
+        for address in [0xFF03, 0xFF08, 0xFF27, 0xFF4C, 0xFF4F, 0xFF70] {
+            mmu.write_byte(address, 0x00);
+            assert_eq!(mmu.read_byte(address), 0xFF, "{:04X}", address);
+        }
+    }
+
+    #[test]
+    fn oam_dma_copies_a_page_while_the_cpu_waits_in_hram() {
+        let mut mmu = Mmu::new();
+        for offset in 0..0xA0 {
+            mmu.write_byte(0xC100 + offset, offset as u8 ^ 0x5A);
+        }
+        mmu.write_byte(HRAM_BEGIN as u16, 0x12);
+        mmu.write_byte(DMA, 0xC1);
+        // the rest of the bus belongs to the DMA until it's done
+        assert_eq!(mmu.read_byte(0xC100), 0xFF);
+        assert_eq!(mmu.read_byte(HRAM_BEGIN as u16), 0x12);
+        mmu.write_byte(0xC000, 0x34);
+        mmu.tick(4 * 0x50);
+        assert_eq!(mmu.gpu.read_oam(0x4F), 0x4F ^ 0x5A);
+        assert_eq!(mmu.gpu.read_oam(0x50), 0x00);
+        mmu.tick(4 * 0x50);
+        for offset in 0..0xA0 {
+            assert_eq!(mmu.gpu.read_oam(offset), offset as u8 ^ 0x5A, "{:02X}", offset);
+        }
+        assert_eq!(mmu.read_byte(0xC100), 0x5A);
+        assert_eq!(mmu.read_byte(0xC000), 0x00);
+        assert_eq!(mmu.read_byte(DMA), 0xC1);
+        // pages from E0 up read work ram
+        mmu.write_byte(DMA, 0xE1);
+        mmu.tick(4 * 0xA0);
+        assert_eq!(mmu.gpu.read_oam(0x01), 0x01 ^ 0x5A);
+    }
+
+    fn cgb_mmu() -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.set_model(Model::Cgb);
+        mmu
+    }
+
+    #[test]
+    fn vram_dma_copies_from_the_bus() {
+        let mut mmu = cgb_mmu();
+        for offset in 0..0x40 {
+            mmu.write_byte(0xC000 + offset, offset as u8 + 1);
+        }
+        // general purpose: two blocks to 0x8100 right away
+        for (address, value) in [(0xFF51, 0xC0), (0xFF52, 0x00), (0xFF53, 0x01), (0xFF54, 0x00), (HDMA_END, 0x01)] {
+            mmu.write_byte(address, value);
+        }
+        assert_eq!(mmu.gpu.read_vram(0x100), 0x01);
+        assert_eq!(mmu.gpu.read_vram(0x11F), 0x20);
+        assert_eq!(mmu.gpu.read_vram(0x120), 0x00);
+        assert_eq!(mmu.read_byte(HDMA_END), 0xFF);
+        // hblank: one block each time the PPU enters hblank
+        mmu.write_byte(0xFF40, 0x91);
+        for (address, value) in [(0xFF51, 0xC0), (0xFF52, 0x20), (0xFF53, 0x02), (0xFF54, 0x00), (HDMA_END, 0x81)] {
+            mmu.write_byte(address, value);
+        }
+        mmu.tick(DOTS_PER_LINE);
+        assert_eq!(mmu.gpu.read_vram(0x20F), 0x30);
+        assert_eq!(mmu.gpu.read_vram(0x210), 0x00);
+        assert_eq!(mmu.read_byte(HDMA_END), 0x00);
+        mmu.tick(DOTS_PER_LINE);
+        assert_eq!(mmu.gpu.read_vram(0x21F), 0x40);
+        assert_eq!(mmu.read_byte(HDMA_END), 0xFF);
+    }
+
+    #[test]
+    fn key1_switches_speed_on_stop() {
+        let mut mmu = cgb_mmu();
+        assert_eq!(mmu.read_byte(SPEED_SWITCH), 0x7E);
+        assert!(!mmu.stop());
+        mmu.write_byte(SPEED_SWITCH, 0xFF);
+        assert_eq!(mmu.read_byte(SPEED_SWITCH), 0x7F);
+        assert!(mmu.stop());
+        assert_eq!(mmu.read_byte(SPEED_SWITCH), 0xFE);
+        // the PPU gets half the cpu's cycles, the divider all of them
+        mmu.write_byte(0xFF40, 0x91);
+        mmu.tick(DOTS_PER_LINE * 2);
+        assert_eq!(mmu.gpu.ly(), 1);
+        assert_eq!(mmu.read_byte(DIV), 0x03);
+        // and back
+        mmu.write_byte(SPEED_SWITCH, 0x01);
+        assert!(mmu.stop());
+        assert_eq!(mmu.read_byte(SPEED_SWITCH), 0x7E);
+        // a DMG has no KEY1
+        let mut dmg = Mmu::new();
+        dmg.write_byte(SPEED_SWITCH, 0x01);
+        assert_eq!(dmg.read_byte(SPEED_SWITCH), 0xFF);
+        assert!(!dmg.stop());
+    }
+
+    #[test]
+    fn pcm_registers_are_cgb_only() {
+        let mut cgb = cgb_mmu();
+        let mut dmg = Mmu::new();
+        for mmu in [&mut cgb, &mut dmg] {
+            // square 1 at full volume, high one duty step in
+            for (address, value) in [(NR52, 0x80), (0xFF11, 0xC0), (0xFF12, 0xF0), (0xFF14, 0x87)] {
+                mmu.write_byte(address, value);
+            }
+            mmu.tick(1024);
+        }
+        assert_eq!(cgb.read_byte(PCM12), 0x0F);
+        assert_eq!(cgb.read_byte(PCM34), 0x00);
+        assert_eq!(dmg.read_byte(PCM12), 0xFF);
+        assert_eq!(dmg.read_byte(PCM34), 0xFF);
+    }
+
+    #[test]
+    fn svbk_and_vbk_pick_the_banks_the_cpu_sees() {
+        let mut mmu = cgb_mmu();
+        // work ram bank 0 is always at C000, D000 switches between 1 and 7
+        mmu.write_byte(0xC000, 0x10);
+        for bank in 1..8 {
+            mmu.write_byte(WRAM_BANK, bank);
+            mmu.write_byte(0xD000, bank);
+        }
+        mmu.write_byte(WRAM_BANK, 3);
+        assert_eq!(mmu.read_byte(0xD000), 3);
+        assert_eq!(mmu.read_byte(WRAM_BANK), 0xFB);
+        // asking for bank 0 gets bank 1
+        mmu.write_byte(WRAM_BANK, 0);
+        assert_eq!(mmu.read_byte(0xD000), 1);
+        assert_eq!(mmu.read_byte(WRAM_BANK), 0xF9);
+        assert_eq!(mmu.read_byte(0xC000), 0x10);
+        // VBK picks the vram bank cpu writes land in
+        mmu.write_byte(0x8000, 0xAA);
+        mmu.write_byte(VRAM_BANK, 0x01);
+        assert_eq!(mmu.read_byte(VRAM_BANK), 0xFF);
+        mmu.write_byte(0x8000, 0xBB);
+        assert_eq!(mmu.read_byte(0x8000), 0xBB);
+        assert_eq!(mmu.gpu.vram_bank_data(0)[0], 0xAA);
+        assert_eq!(mmu.gpu.vram_bank_data(1)[0], 0xBB);
+        mmu.write_byte(VRAM_BANK, 0xFE);
+        assert_eq!(mmu.read_byte(0x8000), 0xAA);
+        // a DMG has neither
+        let mut dmg = Mmu::new();
+        dmg.write_byte(WRAM_BANK, 3);
+        dmg.write_byte(0xD000, 0x42);
+        dmg.write_byte(WRAM_BANK, 1);
+        assert_eq!(dmg.read_byte(0xD000), 0x42);
+    }
+}
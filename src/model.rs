@@ -0,0 +1,98 @@
+use crate::cartridge::{CartridgeHeader, CgbSupport};
+use crate::mmu::{CGB_BOOT_ROM_SIZE, DMG_BOOT_ROM_SIZE};
+use crate::registers::{FlagsRegister, Registers};
+
+// the hardware revision being emulated
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Model {
+    #[default]
+    Dmg,
+    // Game Boy Pocket / Light
+    Mgb,
+    Cgb,
+    Sgb,
+}
+
+// register state the boot rom leaves behind when it hands over to the cartridge at 0x0100
+pub struct PostBootState {
+    pub registers: Registers,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+// io register values after the boot rom, common to every model
+const COMMON_IO: [(u16, u8); 36] = [
+    (0xFF00, 0xCF), (0xFF01, 0x00), (0xFF05, 0x00), (0xFF06, 0x00), (0xFF07, 0xF8),
+    (0xFF0F, 0xE1), (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF),
+    (0xFF14, 0xBF), (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0xBF),
+    (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+    (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF), (0xFF24, 0x77),
+    (0xFF25, 0xF3), (0xFF40, 0x91), (0xFF42, 0x00), (0xFF43, 0x00), (0xFF44, 0x00),
+    (0xFF45, 0x00), (0xFF47, 0xFC), (0xFF4A, 0x00), (0xFF4B, 0x00),
+    (0xFF50, 0x01), (0xFFFF, 0x00),
+];
+
+impl Model {
+    pub fn is_cgb(&self) -> bool {
+        *self == Model::Cgb
+    }
+    // what a game would most likely have been played on, from its CGB flag
+    pub fn for_cartridge(header: &CartridgeHeader) -> Model {
+        match header.cgb {
+            CgbSupport::None => Model::Dmg,
+            CgbSupport::Enhanced | CgbSupport::Only => Model::Cgb,
+        }
+    }
+    // the boot rom this model runs, by size
+    pub fn boot_rom_size(&self) -> usize {
+        if self.is_cgb() { CGB_BOOT_ROM_SIZE } else { DMG_BOOT_ROM_SIZE }
+    }
+    // values documented in pan docs' "power up sequence". the DMG/MGB flags depend on
+    // the cartridge header checksum at 0x014D since the boot rom's check leaves them behind
+    pub fn post_boot_state(&self, header_checksum: u8) -> PostBootState {
+        let checksum_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+        let (af, bc, de, hl) = match self {
+            Model::Dmg => (0x0100 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF00 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Cgb => (0x1180, 0x0000, 0xFF56, 0x000D),
+        };
+        let mut registers = Registers::new();
+        registers.a = (af >> 8) as u8;
+        registers.f = FlagsRegister::from(af as u8);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
+        PostBootState { registers, sp: 0xFFFE, pc: 0x0100 }
+    }
+    // the 16 bit counter behind DIV as the boot rom leaves it. it can't go in with
+    // the io registers, any write to DIV clears it
+    pub fn post_boot_divider(&self) -> u16 {
+        match self {
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb | Model::Cgb => 0x0000,
+        }
+    }
+    // io register contents after the boot rom
+    pub fn post_boot_io(&self) -> Vec<(u16, u8)> {
+        let specific: &[(u16, u8)] = match self {
+            Model::Dmg | Model::Mgb => &[
+                (0xFF02, 0x7E), (0xFF26, 0xF1), (0xFF41, 0x85),
+                (0xFF48, 0xFF), (0xFF49, 0xFF),
+            ],
+            Model::Sgb => &[
+                (0xFF02, 0x7E), (0xFF26, 0xF0), (0xFF41, 0x85),
+                (0xFF48, 0xFF), (0xFF49, 0xFF),
+            ],
+            Model::Cgb => &[
+                (0xFF02, 0x7F), (0xFF26, 0xF1), (0xFF41, 0x85),
+                (0xFF4D, 0x7E), (0xFF4F, 0xFE), (0xFF55, 0xFF), (0xFF56, 0x3E),
+                (0xFF68, 0xC0), (0xFF6A, 0xC1), (0xFF70, 0xF8),
+            ],
+        };
+        // NR52 has to come before the other sound registers, they ignore writes while the APU is off
+        let mut io = specific.to_vec();
+        io.extend_from_slice(&COMMON_IO);
+        io
+    }
+}
@@ -0,0 +1,379 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::gameboy::GameBoy;
+use crate::joypad::Button;
+use crate::savestate::{read_sections, rom_hash, StateDecoder, StateEncoder, StateError};
+
+// movie files:
+//   "GBMV" | version: u16 | rom hash: u64 | rerecords: u32 | start | frames
+// the start is a kind byte and its data: 0 for power on, with the cartridge ram
+// as it was, or 1 with a save state. frames are a u32 count then 2 bytes each,
+// the buttons held (a bit per Button::ALL entry) and 1 if the frame starts with
+// a reset
+pub const MAGIC: [u8; 4] = *b"GBMV";
+pub const VERSION: u16 = 1;
+// added to save states made while a movie runs, so they can be loaded back into it
+pub const MOVIE_SECTION: [u8; 4] = *b"MOVI";
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    State(StateError),
+    Format(String),
+    // recorded with another game
+    WrongRom,
+    // in read-only mode, a state from another movie or past the end of this one
+    NotInMovie,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Io(error) => write!(f, "{}", error),
+            MovieError::State(error) => write!(f, "{}", error),
+            MovieError::Format(message) => write!(f, "bad movie file: {}", message),
+            MovieError::WrongRom => write!(f, "the movie was recorded with another rom"),
+            MovieError::NotInMovie => write!(f, "the save state isn't part of this movie"),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(error: io::Error) -> Self {
+        MovieError::Io(error)
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> Self {
+        MovieError::State(error)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MovieStart {
+    // from a power cycle, with the cartridge ram it had
+    PowerOn { ram: Vec<u8> },
+    State(Vec<u8>),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct MovieFrame {
+    // bit n set while Button::ALL[n] is held
+    pub buttons: u8,
+    // reset before the frame runs
+    pub reset: bool,
+}
+
+impl MovieFrame {
+    pub fn held(&self, button: Button) -> bool {
+        let index = Button::ALL.iter().position(|&other| other == button).unwrap();
+        self.buttons & 1 << index != 0
+    }
+}
+
+fn encode_frames(state: &mut StateEncoder, frames: &[MovieFrame]) {
+    state.u32(frames.len() as u32);
+    for frame in frames {
+        state.bytes(&[frame.buttons, frame.reset as u8]);
+    }
+}
+
+fn decode_frames(state: &mut StateDecoder) -> Result<Vec<MovieFrame>, StateError> {
+    let count = state.u32()?;
+    let mut frames = Vec::new();
+    for _ in 0..count {
+        frames.push(MovieFrame { buttons: state.u8()?, reset: state.bool()? });
+    }
+    Ok(frames)
+}
+
+fn held_buttons(gameboy: &GameBoy) -> u8 {
+    Button::ALL
+        .iter()
+        .enumerate()
+        .filter(|&(_, &button)| gameboy.mmu().joypad.pressed(button))
+        .fold(0, |buttons, (index, _)| buttons | 1 << index)
+}
+
+fn loaded_rom_hash(gameboy: &GameBoy) -> u64 {
+    rom_hash(gameboy.mmu().cartridge().map_or(&[], |cartridge| cartridge.rom()))
+}
+
+// the joypad input of a run, frame by frame, from a known start
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+    // how many times the recording was rewound and taken up again
+    pub rerecords: u32,
+}
+
+impl Movie {
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let data = StateEncoder::encode(|state| {
+            state.bytes(&MAGIC);
+            state.u16(VERSION);
+            state.u64(self.rom_hash);
+            state.u32(self.rerecords);
+            match &self.start {
+                MovieStart::PowerOn { ram } => {
+                    state.u8(0);
+                    state.vec(ram);
+                }
+                MovieStart::State(data) => {
+                    state.u8(1);
+                    state.vec(data);
+                }
+            }
+            encode_frames(state, &self.frames);
+        });
+        writer.write_all(&data)?;
+        writer.flush()
+    }
+    pub fn load<R: Read>(mut reader: R) -> Result<Movie, MovieError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let mut state = StateDecoder::from_bytes(MAGIC, &data);
+        let mut magic = [0; 4];
+        state.bytes(&mut magic)?;
+        if magic != MAGIC {
+            return Err(MovieError::Format("not a movie".to_string()));
+        }
+        let version = state.u16()?;
+        if version != VERSION {
+            return Err(MovieError::Format(format!("version {} isn't supported", version)));
+        }
+        let rom_hash = state.u64()?;
+        let rerecords = state.u32()?;
+        let start = match state.u8()? {
+            0 => MovieStart::PowerOn { ram: state.vec()? },
+            1 => MovieStart::State(state.vec()?),
+            kind => return Err(MovieError::Format(format!("unknown start {}", kind))),
+        };
+        Ok(Movie { rom_hash, start, frames: decode_frames(&mut state)?, rerecords })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    // played to the end, the game runs on under the player's control
+    Finished,
+}
+
+// a movie being recorded or played back. before_frame() has to run ahead of every
+// frame: it feeds the recorded buttons in, or writes down the ones held. save
+// states made through save_state() remember where in the movie they were made;
+// loading one read-only jumps there and keeps playing, read-write cuts the movie
+// at that point and records from there (a rerecord)
+pub struct MovieSession {
+    movie: Movie,
+    mode: MovieMode,
+    // frames run since the start
+    position: usize,
+    read_only: bool,
+    reset_requested: bool,
+}
+
+impl MovieSession {
+    // power cycles the machine and starts recording. the cartridge ram goes into
+    // the movie and real time clocks switch to emulated time, so playback matches
+    pub fn record(gameboy: &mut GameBoy) -> MovieSession {
+        if let Some(cartridge) = gameboy.mmu_mut().cartridge_mut() {
+            cartridge.set_emulated_clock(true);
+        }
+        gameboy.reset();
+        let ram = gameboy.save_ram();
+        MovieSession::new(Movie {
+            rom_hash: loaded_rom_hash(gameboy),
+            start: MovieStart::PowerOn { ram },
+            frames: Vec::new(),
+            rerecords: 0,
+        }, MovieMode::Recording)
+    }
+    // records on from where the game is now, starting with a save state
+    pub fn record_from_here(gameboy: &mut GameBoy) -> io::Result<MovieSession> {
+        if let Some(cartridge) = gameboy.mmu_mut().cartridge_mut() {
+            cartridge.set_emulated_clock(true);
+        }
+        Ok(MovieSession::new(Movie {
+            rom_hash: loaded_rom_hash(gameboy),
+            start: MovieStart::State(gameboy.save_state(Vec::new())?),
+            frames: Vec::new(),
+            rerecords: 0,
+        }, MovieMode::Recording))
+    }
+    // puts the machine at the movie's start, read-only
+    pub fn play(gameboy: &mut GameBoy, movie: Movie) -> Result<MovieSession, MovieError> {
+        if movie.rom_hash != loaded_rom_hash(gameboy) {
+            return Err(MovieError::WrongRom);
+        }
+        if let Some(cartridge) = gameboy.mmu_mut().cartridge_mut() {
+            cartridge.set_emulated_clock(true);
+        }
+        match &movie.start {
+            MovieStart::PowerOn { ram } => {
+                let ram = ram.clone();
+                gameboy.reset();
+                gameboy.load_ram(&ram);
+            }
+            MovieStart::State(state) => gameboy.load_state(state.as_slice())?,
+        }
+        let mode = if movie.frames.is_empty() { MovieMode::Finished } else { MovieMode::Playing };
+        let mut session = MovieSession::new(movie, mode);
+        session.read_only = true;
+        Ok(session)
+    }
+    fn new(movie: Movie, mode: MovieMode) -> MovieSession {
+        MovieSession { movie, mode, position: 0, read_only: false, reset_requested: false }
+    }
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+    pub fn into_movie(self) -> Movie {
+        self.movie
+    }
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+    // the frame about to run, counting from 0
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+    // while recording, the next frame starts with a reset. played back resets
+    // come from the movie, so this does nothing then
+    pub fn request_reset(&mut self) {
+        if self.mode == MovieMode::Recording {
+            self.reset_requested = true;
+        }
+    }
+    pub fn before_frame(&mut self, gameboy: &mut GameBoy) {
+        match self.mode {
+            MovieMode::Recording => {
+                let frame = MovieFrame { buttons: held_buttons(gameboy), reset: std::mem::take(&mut self.reset_requested) };
+                if frame.reset {
+                    gameboy.reset();
+                }
+                self.movie.frames.push(frame);
+                self.position += 1;
+            }
+            MovieMode::Playing => {
+                let frame = self.movie.frames[self.position];
+                if frame.reset {
+                    gameboy.reset();
+                }
+                for button in Button::ALL {
+                    gameboy.set_button(button, frame.held(button));
+                }
+                self.position += 1;
+                if self.position == self.movie.frames.len() {
+                    self.mode = MovieMode::Finished;
+                }
+            }
+            MovieMode::Finished => {}
+        }
+    }
+    // the machine's state followed by the movie so far
+    pub fn save_state(&self, gameboy: &GameBoy) -> io::Result<Vec<u8>> {
+        let mut data = gameboy.save_state(Vec::new())?;
+        let section = StateEncoder::encode(|state| {
+            state.u32(self.position as u32);
+            encode_frames(state, &self.movie.frames[..self.position]);
+        });
+        data.extend_from_slice(&MOVIE_SECTION);
+        data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+        data.extend_from_slice(&section);
+        Ok(data)
+    }
+    pub fn load_state(&mut self, gameboy: &mut GameBoy, data: &[u8]) -> Result<(), MovieError> {
+        let (_, sections) = read_sections(data)?;
+        let section = sections.iter().find(|section| section.tag == MOVIE_SECTION).ok_or(MovieError::NotInMovie)?;
+        let mut state = StateDecoder::new(section);
+        let position = state.u32()? as usize;
+        let frames = decode_frames(&mut state)?;
+        if self.read_only && self.movie.frames.get(..position) != Some(&frames[..]) {
+            return Err(MovieError::NotInMovie);
+        }
+        gameboy.load_state(data)?;
+        self.position = position;
+        if self.read_only {
+            self.mode = if position < self.movie.frames.len() { MovieMode::Playing } else { MovieMode::Finished };
+        } else {
+            self.movie.frames = frames;
+            self.movie.rerecords += 1;
+            self.mode = MovieMode::Recording;
+        }
+        self.reset_requested = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    // a rom that keeps copying the joypad into C000
+    fn joypad_rom() -> Vec<u8> {
+        // ld a, 0x91; ldh (0x40), a; ld a, 0x10; ldh (0x00), a; ldh a, (0x00); ld (0xC000), a; jr -7
+        let program = [0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0xEA, 0x00, 0xC0, 0x18, 0xF9];
+        test_rom(&program, 0x00, 0)
+    }
+
+    #[test]
+    fn movies_replay_the_same_run() {
+        let mut gameboy = GameBoy::new(joypad_rom()).unwrap();
+        let mut session = MovieSession::record(&mut gameboy);
+        let mut recorded = Vec::new();
+        let mut state = None;
+        for frame in 0..20 {
+            gameboy.set_button(Button::A, frame % 3 == 0);
+            if frame == 10 {
+                state = Some(session.save_state(&gameboy).unwrap());
+            }
+            if frame == 15 {
+                session.request_reset();
+            }
+            session.before_frame(&mut gameboy);
+            gameboy.run_frame();
+            recorded.push(gameboy.cpu().save_state(Vec::new()).unwrap());
+        }
+        assert_eq!(session.movie().frames.iter().filter(|frame| frame.reset).count(), 1);
+        let mut file = Vec::new();
+        session.into_movie().save(&mut file).unwrap();
+
+        let movie = Movie::load(file.as_slice()).unwrap();
+        let mut gameboy = GameBoy::new(joypad_rom()).unwrap();
+        gameboy.run_frame();
+        let mut session = MovieSession::play(&mut gameboy, movie.clone()).unwrap();
+        for state in &recorded {
+            session.before_frame(&mut gameboy);
+            gameboy.run_frame();
+            assert_eq!(&gameboy.cpu().save_state(Vec::new()).unwrap(), state);
+        }
+        assert_eq!(session.mode(), MovieMode::Finished);
+
+        // read-only jumps back and plays on, read-write takes over from there
+        let state = state.unwrap();
+        session.load_state(&mut gameboy, &state).unwrap();
+        assert_eq!((session.mode(), session.position()), (MovieMode::Playing, 10));
+        session.set_read_only(false);
+        session.load_state(&mut gameboy, &state).unwrap();
+        assert_eq!(session.mode(), MovieMode::Recording);
+        assert_eq!(session.movie().frames.len(), 10);
+        assert_eq!(session.movie().rerecords, 1);
+        assert_eq!(session.movie().frames[..], movie.frames[..10]);
+    }
+}
@@ -0,0 +1,84 @@
+use std::ops::Range;
+
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+// OAM DMA (FF46). writing a page number copies the 160 bytes from page << 8 up
+// into OAM, one byte per machine cycle. while it runs the cpu can only reach HRAM
+pub const DMA: u16 = 0xFF46;
+pub const OAM_DMA_LENGTH: u16 = 0xA0;
+const CYCLES_PER_BYTE: u32 = 4;
+
+pub struct OamDma {
+    // the page last written, FF46 reads it back
+    page: u8,
+    // bytes copied so far, OAM_DMA_LENGTH once the transfer is over
+    copied: u16,
+    // clock cycles towards the next byte
+    cycles: u32,
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma { page: 0xFF, copied: OAM_DMA_LENGTH, cycles: 0 }
+    }
+    pub fn reset(&mut self) {
+        *self = OamDma::new();
+    }
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.u8(self.page);
+        state.u16(self.copied);
+        state.u8(self.cycles as u8);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.page = state.u8()?;
+        self.copied = state.u16()?.min(OAM_DMA_LENGTH);
+        self.cycles = state.u8()? as u32 % CYCLES_PER_BYTE;
+        Ok(())
+    }
+    pub fn active(&self) -> bool {
+        self.copied < OAM_DMA_LENGTH
+    }
+    pub fn read(&self) -> u8 {
+        self.page
+    }
+    // where the running (or last) transfer copies from
+    pub fn source(&self) -> u16 {
+        (self.page as u16) << 8
+    }
+    // writing FF46 starts over, even partway through a transfer
+    pub fn start(&mut self, page: u8) {
+        self.page = page;
+        self.copied = 0;
+        self.cycles = 0;
+    }
+    // the OAM offsets to copy for the clock cycles just run
+    pub fn tick(&mut self, cycles: u32) -> Range<u16> {
+        let start = self.copied;
+        if !self.active() { return start..start }
+        self.cycles += cycles;
+        let bytes = (self.cycles / CYCLES_PER_BYTE).min(OAM_DMA_LENGTH as u32) as u16;
+        self.cycles %= CYCLES_PER_BYTE;
+        self.copied = (start + bytes).min(OAM_DMA_LENGTH);
+        start..self.copied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_byte_per_machine_cycle() {
+        let mut dma = OamDma::new();
+        assert!(!dma.active());
+        assert_eq!(dma.read(), 0xFF);
+        dma.start(0xC1);
+        assert_eq!(dma.source(), 0xC100);
+        assert_eq!(dma.tick(6), 0..1);
+        assert_eq!(dma.tick(2), 1..2);
+        assert_eq!(dma.tick(4 * 200), 2..OAM_DMA_LENGTH);
+        assert!(!dma.active());
+        assert!(dma.tick(4).is_empty());
+        assert_eq!(dma.read(), 0xC1);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+
+use crate::frame::{Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// 5x7 glyphs for ascii 0x20-0x7E, one byte per column with bit 0 as the top row
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00], [0x08, 0x2A, 0x1C, 0x2A, 0x08], [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00], [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3E],
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7F, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40], [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7F, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], [0x38, 0x44, 0x44, 0x48, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7E, 0x09, 0x01, 0x02], [0x0C, 0x52, 0x52, 0x52, 0x3E],
+    [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3D, 0x00],
+    [0x7F, 0x10, 0x28, 0x44, 0x00], [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x18, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], [0x7C, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7C], [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3F, 0x44, 0x40, 0x20], [0x3C, 0x40, 0x40, 0x20, 0x7C], [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], [0x44, 0x28, 0x10, 0x28, 0x44], [0x0C, 0x50, 0x50, 0x50, 0x3C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], [0x00, 0x00, 0x7F, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+// one pixel of spacing around each glyph and line
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+pub const MAX_LINE_CHARS: usize = (SCREEN_WIDTH - 2) / CELL_WIDTH;
+// roughly two seconds at the game boy's ~59.7 fps
+pub const DEFAULT_DURATION_FRAMES: u32 = 120;
+
+const TEXT_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const BACKGROUND_COLOR: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+struct OsdMessage {
+    text: String,
+    frames_left: u32,
+}
+
+// short status messages ("State saved", "Cheat enabled", ...) drawn over the
+// bottom of the picture for a number of frames. frontends that show their own
+// notifications can turn it off and read the messages themselves
+pub struct Osd {
+    messages: VecDeque<OsdMessage>,
+    enabled: bool,
+}
+
+impl Osd {
+    pub fn new() -> Osd {
+        Osd { messages: VecDeque::new(), enabled: true }
+    }
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn push(&mut self, text: &str) {
+        self.push_for(text, DEFAULT_DURATION_FRAMES);
+    }
+    pub fn push_for(&mut self, text: &str, frames: u32) {
+        // only as many messages as fit on screen are ever shown
+        if self.messages.len() == SCREEN_HEIGHT / LINE_HEIGHT {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(OsdMessage { text: text.to_string(), frames_left: frames });
+    }
+    // messages currently on screen, oldest first
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        self.messages.iter().map(|message| message.text.as_str())
+    }
+    pub fn is_visible(&self) -> bool {
+        self.enabled && !self.messages.is_empty()
+    }
+    // advance one frame, dropping messages whose time is up
+    pub fn tick(&mut self) {
+        for message in self.messages.iter_mut() {
+            message.frames_left = message.frames_left.saturating_sub(1);
+        }
+        self.messages.retain(|message| message.frames_left > 0);
+    }
+    // draw the messages bottom up, newest at the bottom
+    pub fn render(&self, frame: &mut Frame) {
+        if !self.enabled { return }
+        for (row, message) in self.messages.iter().rev().enumerate() {
+            let top = SCREEN_HEIGHT - (row + 1) * LINE_HEIGHT;
+            draw_line(frame, top, &message.text);
+        }
+    }
+}
+
+fn draw_line(frame: &mut Frame, top: usize, text: &str) {
+    let chars: Vec<char> = text.chars().take(MAX_LINE_CHARS).collect();
+    let width = chars.len() * CELL_WIDTH + 1;
+    for y in top..top + LINE_HEIGHT {
+        for x in 0..width {
+            frame.set_pixel(x, y, BACKGROUND_COLOR);
+        }
+    }
+    for (index, character) in chars.iter().enumerate() {
+        // anything outside printable ascii shows up as '?'
+        let code = *character as usize;
+        let glyph = if (0x20..0x7F).contains(&code) { FONT[code - 0x20] } else { FONT[b'?' as usize - 0x20] };
+        let left = 1 + index * CELL_WIDTH;
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                if bits & (1 << row) != 0 {
+                    frame.set_pixel(left + column, top + 1 + row, TEXT_COLOR);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_pixels(frame: &Frame) -> usize {
+        frame.pixels.chunks_exact(4).filter(|&pixel| pixel == TEXT_COLOR).count()
+    }
+
+    #[test]
+    fn messages_draw_until_they_expire() {
+        let mut osd = Osd::new();
+        osd.push_for("State 1 saved", 2);
+        let mut frame = Frame::blank([0x80, 0x80, 0x80, 0xFF]);
+        osd.render(&mut frame);
+        // on a dark box along the bottom, starting at the left edge
+        assert!(text_pixels(&frame) > 0);
+        assert_eq!(frame.pixel(0, SCREEN_HEIGHT - 1), BACKGROUND_COLOR);
+        assert_eq!(frame.pixel(0, SCREEN_HEIGHT - LINE_HEIGHT - 1), [0x80, 0x80, 0x80, 0xFF]);
+        osd.tick();
+        assert!(osd.is_visible());
+        osd.tick();
+        assert!(!osd.is_visible());
+        let mut frame = Frame::blank([0x80, 0x80, 0x80, 0xFF]);
+        osd.render(&mut frame);
+        assert_eq!(text_pixels(&frame), 0);
+    }
+
+    #[test]
+    fn only_a_screenful_is_kept_and_hiding_keeps_the_queue() {
+        let mut osd = Osd::new();
+        for index in 0..20 {
+            osd.push(&format!("Cheat enabled: {}", index));
+        }
+        assert_eq!(osd.messages().count(), SCREEN_HEIGHT / LINE_HEIGHT);
+        assert_eq!(osd.messages().last(), Some("Cheat enabled: 19"));
+        osd.set_enabled(false);
+        assert!(!osd.is_visible());
+        let mut frame = Frame::blank([0x80, 0x80, 0x80, 0xFF]);
+        osd.render(&mut frame);
+        assert_eq!(text_pixels(&frame), 0);
+        assert_eq!(osd.messages().count(), SCREEN_HEIGHT / LINE_HEIGHT);
+    }
+}
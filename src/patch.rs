@@ -0,0 +1,317 @@
+use std::fmt;
+
+// ROM patches in the two formats hacks and translations are distributed in.
+// IPS is a list of (offset, bytes) records, BPS describes the target as runs
+// copied from the source, the target so far or the patch, and carries CRC32s of
+// the source, target and patch itself
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// three CRC32s
+const BPS_FOOTER_SIZE: usize = 12;
+// the biggest cartridge there is. a BPS target size is checked against this
+// before anything is allocated for it
+pub const MAX_TARGET_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+    // neither an IPS nor a BPS header
+    UnknownFormat,
+    // the patch ends in the middle of a record
+    Truncated,
+    // a BPS action reads or writes outside the source or target
+    OutOfBounds,
+    // a BPS target bigger than MAX_TARGET_SIZE
+    TargetTooLarge(usize),
+    SourceSize { expected: usize, actual: usize },
+    SourceChecksum { expected: u32, actual: u32 },
+    TargetChecksum { expected: u32, actual: u32 },
+    PatchChecksum { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS or BPS patch"),
+            PatchError::Truncated => write!(f, "patch is truncated"),
+            PatchError::OutOfBounds => write!(f, "patch refers to data outside the rom"),
+            PatchError::TargetTooLarge(size) =>
+                write!(f, "patch makes a {} byte rom, more than any cartridge holds", size),
+            PatchError::SourceSize { expected, actual } =>
+                write!(f, "patch is for a {} byte rom, this one is {} bytes", expected, actual),
+            PatchError::SourceChecksum { expected, actual } =>
+                write!(f, "patch is for a rom with crc {:08X}, this one is {:08X}", expected, actual),
+            PatchError::TargetChecksum { expected, actual } =>
+                write!(f, "patched rom has crc {:08X}, expected {:08X}", actual, expected),
+            PatchError::PatchChecksum { expected, actual } =>
+                write!(f, "patch is corrupt, crc {:08X} instead of {:08X}", actual, expected),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+// picks the format from the patch's header
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+// the usual reflected CRC32 (polynomial 0xEDB88320)
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 })
+    })
+}
+
+// reads through a patch, every read failing with Truncated past the end
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.position.checked_add(length).ok_or(PatchError::Truncated)?;
+        let bytes = self.data.get(self.position..end).ok_or(PatchError::Truncated)?;
+        self.position += length;
+        Ok(bytes)
+    }
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.take(1)?[0])
+    }
+    fn big_endian(&mut self, length: usize) -> Result<usize, PatchError> {
+        Ok(self.take(length)?.iter().fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+    // BPS numbers: 7 bits per byte, least significant first, the top bit ends the
+    // number. each continuation also adds one so there's only one encoding per value
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.byte()?;
+            let digit = ((byte & 0x7F) as usize).checked_mul(shift).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(digit).ok_or(PatchError::OutOfBounds)?;
+            if byte & 0x80 != 0 { return Ok(value) }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+    // relative offsets, bit 0 is the sign
+    fn signed_varint(&mut self) -> Result<isize, PatchError> {
+        let value = self.varint()?;
+        let magnitude = (value >> 1) as isize;
+        Ok(if value & 1 != 0 { -magnitude } else { magnitude })
+    }
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut reader = Reader { data: patch, position: IPS_MAGIC.len() };
+    let mut target = rom.to_vec();
+    loop {
+        let offset_bytes = reader.take(3)?;
+        if offset_bytes == IPS_EOF { break }
+        let offset = offset_bytes.iter().fold(0, |value, &byte| value << 8 | byte as usize);
+        let length = reader.big_endian(2)?;
+        // a zero length record is a run of one repeated byte
+        let (length, run) = if length == 0 { (reader.big_endian(2)?, Some(reader.byte()?)) } else { (length, None) };
+        if target.len() < offset + length {
+            target.resize(offset + length, 0);
+        }
+        match run {
+            Some(value) => target[offset..offset + length].fill(value),
+            None => target[offset..offset + length].copy_from_slice(reader.take(length)?),
+        }
+    }
+    // some patchers append the size the rom should be truncated to
+    if let Ok(size) = reader.big_endian(3) {
+        target.truncate(size);
+    }
+    Ok(target)
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE { return Err(PatchError::Truncated) }
+    let footer = patch.len() - BPS_FOOTER_SIZE;
+    let checksum = |index: usize| u32::from_le_bytes(patch[index..index + 4].try_into().unwrap());
+    let (source_crc, target_crc, patch_crc) = (checksum(footer), checksum(footer + 4), checksum(footer + 8));
+    let actual = crc32(&patch[..footer + 8]);
+    if actual != patch_crc { return Err(PatchError::PatchChecksum { expected: patch_crc, actual }) }
+    let actual = crc32(rom);
+    if actual != source_crc { return Err(PatchError::SourceChecksum { expected: source_crc, actual }) }
+
+    let mut reader = Reader { data: &patch[..footer], position: BPS_MAGIC.len() };
+    let source_size = reader.varint()?;
+    if source_size != rom.len() { return Err(PatchError::SourceSize { expected: source_size, actual: rom.len() }) }
+    let target_size = reader.varint()?;
+    if target_size > MAX_TARGET_SIZE { return Err(PatchError::TargetTooLarge(target_size)) }
+    let metadata_size = reader.varint()?;
+    reader.take(metadata_size)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0isize;
+    let mut target_offset = 0isize;
+    while reader.position < footer {
+        let command = reader.varint()?;
+        let length = (command >> 2) + 1;
+        if target.len() + length > target_size { return Err(PatchError::OutOfBounds) }
+        match command & 0x03 {
+            // source read, the source at the same position as the output
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(rom.get(start..start + length).ok_or(PatchError::OutOfBounds)?);
+            }
+            // target read, bytes straight from the patch
+            1 => target.extend_from_slice(reader.take(length)?),
+            // source copy
+            2 => {
+                source_offset = source_offset.checked_add(reader.signed_varint()?).ok_or(PatchError::OutOfBounds)?;
+                let start = usize::try_from(source_offset).map_err(|_| PatchError::OutOfBounds)?;
+                target.extend_from_slice(rom.get(start..start + length).ok_or(PatchError::OutOfBounds)?);
+                source_offset += length as isize;
+            }
+            // target copy, may overlap what it's writing so goes a byte at a time
+            _ => {
+                target_offset = target_offset.checked_add(reader.signed_varint()?).ok_or(PatchError::OutOfBounds)?;
+                for _ in 0..length {
+                    let index = usize::try_from(target_offset).map_err(|_| PatchError::OutOfBounds)?;
+                    let byte = *target.get(index).ok_or(PatchError::OutOfBounds)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    let actual = crc32(&target);
+    if actual != target_crc { return Err(PatchError::TargetChecksum { expected: target_crc, actual }) }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(out: &mut Vec<u8>, mut value: usize) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn signed_varint(out: &mut Vec<u8>, value: isize) {
+        varint(out, value.unsigned_abs() << 1 | (value < 0) as usize);
+    }
+
+    // a BPS patch with the given actions already encoded, and correct checksums
+    fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        varint(&mut patch, source.len());
+        varint(&mut patch, target.len());
+        varint(&mut patch, 0);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    // a BPS action: the kind in the low two bits, the length above
+    fn action(out: &mut Vec<u8>, kind: usize, length: usize) {
+        varint(out, (length - 1) << 2 | kind);
+    }
+
+    #[test]
+    fn ips_records_overwrite_and_extend() {
+        let rom = vec![0u8; 8];
+        // 3 bytes at 2, then 2 bytes at 7 running past the end
+        let patch = [b"PATCH".as_slice(), &[0, 0, 2, 0, 3, 1, 2, 3], &[0, 0, 7, 0, 2, 4, 5], b"EOF"].concat();
+        assert_eq!(apply(&rom, &patch), Ok(vec![0, 0, 1, 2, 3, 0, 0, 4, 5]));
+        // cut off in the middle of a record
+        assert_eq!(apply(&rom, &patch[..12]), Err(PatchError::Truncated));
+        assert_eq!(apply(&rom, b"NOT A PATCH"), Err(PatchError::UnknownFormat));
+    }
+
+    #[test]
+    fn ips_rle_records_fill_a_run() {
+        let rom = vec![0u8; 4];
+        // a zero length, then the run length and the byte
+        let patch = [b"PATCH".as_slice(), &[0, 0, 1, 0, 0, 0, 5, 0xAB], b"EOF"].concat();
+        assert_eq!(apply(&rom, &patch), Ok(vec![0, 0xAB, 0xAB, 0xAB, 0xAB, 0xAB]));
+    }
+
+    #[test]
+    fn ips_can_truncate_the_rom() {
+        let rom = vec![7u8; 16];
+        let patch = [b"PATCH".as_slice(), &[0, 0, 0, 0, 1, 9], b"EOF", &[0, 0, 4]].concat();
+        assert_eq!(apply(&rom, &patch), Ok(vec![9, 7, 7, 7]));
+    }
+
+    #[test]
+    fn bps_round_trips_every_action() {
+        let source: Vec<u8> = (0..32).collect();
+        let mut target = source[..8].to_vec();
+        target.extend_from_slice(&[0xAA, 0xBB]);
+        target.extend_from_slice(&source[20..24]);
+        // an overlapping target copy repeating the last two bytes
+        target.extend_from_slice(&[22, 23, 22, 23, 22, 23]);
+        let mut actions = Vec::new();
+        action(&mut actions, 0, 8);
+        action(&mut actions, 1, 2);
+        actions.extend_from_slice(&[0xAA, 0xBB]);
+        action(&mut actions, 2, 4);
+        signed_varint(&mut actions, 20);
+        action(&mut actions, 3, 6);
+        signed_varint(&mut actions, 12);
+        let patch = bps(&source, &target, &actions);
+        assert_eq!(apply(&source, &patch), Ok(target));
+    }
+
+    #[test]
+    fn bps_checks_every_checksum() {
+        let source = vec![1u8; 4];
+        let target = vec![1u8, 1, 1, 2];
+        let mut actions = Vec::new();
+        action(&mut actions, 0, 3);
+        action(&mut actions, 1, 1);
+        actions.push(2);
+        let patch = bps(&source, &target, &actions);
+        assert_eq!(apply(&source, &patch), Ok(target.clone()));
+
+        let mut corrupt = patch.clone();
+        corrupt[8] ^= 0x01;
+        assert!(matches!(apply(&source, &corrupt), Err(PatchError::PatchChecksum { .. })));
+        let expected = crc32(&source);
+        let actual = crc32(&[2, 2, 2, 2]);
+        assert_eq!(apply(&[2, 2, 2, 2], &patch), Err(PatchError::SourceChecksum { expected, actual }));
+        // a patch whose own checksum is fine but makes the wrong rom
+        let wrong = bps(&source, &[1, 1, 1, 3], &actions);
+        let (expected, actual) = (crc32(&[1, 1, 1, 3]), crc32(&target));
+        assert_eq!(apply(&source, &wrong), Err(PatchError::TargetChecksum { expected, actual }));
+    }
+
+    #[test]
+    fn bps_refuses_huge_sizes() {
+        let mut patch = BPS_MAGIC.to_vec();
+        varint(&mut patch, 0);
+        varint(&mut patch, usize::MAX >> 8);
+        varint(&mut patch, 0);
+        patch.extend_from_slice(&crc32(&[]).to_le_bytes());
+        patch.extend_from_slice(&[0; 4]);
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        assert_eq!(apply(&[], &patch), Err(PatchError::TargetTooLarge(usize::MAX >> 8)));
+        // a number too long for a usize is out of bounds rather than wrapping
+        let mut reader = Reader { data: &[0x7F; 16], position: 0 };
+        assert_eq!(reader.varint(), Err(PatchError::OutOfBounds));
+    }
+}
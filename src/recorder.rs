@@ -0,0 +1,198 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+#[cfg(feature = "gif")]
+use crate::apu::CLOCK_RATE;
+use crate::frame::{Frame, FrameSink, RecordingSink, SCREEN_HEIGHT, SCREEN_WIDTH};
+#[cfg(feature = "gif")]
+use crate::timing::CYCLES_PER_FRAME;
+
+// the frame rate ffmpeg is told the raw frames come at
+const FRAME_RATE: &str = "59.7275";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RecordingFormat {
+    // an animated gif at half the frame rate, without sound
+    #[cfg(feature = "gif")]
+    Gif,
+    // whatever ffmpeg makes of the file name, e.g. .mp4 or .webm. ffmpeg has to be
+    // on the PATH. with audio, the sound is muxed in when the recording stops
+    Ffmpeg { audio: bool },
+}
+
+// writes every frame the PPU finishes while a recording runs. it stays attached
+// to the GPU as a frame sink and does nothing between recordings
+pub struct Recorder {
+    output: Option<Output>,
+}
+
+enum Output {
+    #[cfg(feature = "gif")]
+    Gif(GifOutput),
+    Ffmpeg(FfmpegOutput),
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder { output: None }
+    }
+    pub fn recording(&self) -> bool {
+        self.output.is_some()
+    }
+    // a recording already running is finished first. for ffmpeg with audio the
+    // sound has to be written to `audio_path` by the caller until stop()
+    pub fn start(&mut self, path: &Path, format: RecordingFormat, audio_path: Option<PathBuf>) -> io::Result<()> {
+        self.stop()?;
+        self.output = Some(match format {
+            #[cfg(feature = "gif")]
+            RecordingFormat::Gif => Output::Gif(GifOutput::new(path)?),
+            RecordingFormat::Ffmpeg { .. } => Output::Ffmpeg(FfmpegOutput::new(path, audio_path)?),
+        });
+        Ok(())
+    }
+    // finishes the file, reporting the first error the recording ran into if any
+    pub fn stop(&mut self) -> io::Result<()> {
+        match self.output.take() {
+            #[cfg(feature = "gif")]
+            Some(Output::Gif(gif)) => gif.finish(),
+            Some(Output::Ffmpeg(ffmpeg)) => ffmpeg.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FrameSink for Recorder {
+    fn push_frame(&mut self, frame: &Frame) {
+        match &mut self.output {
+            #[cfg(feature = "gif")]
+            Some(Output::Gif(gif)) => gif.push_frame(frame),
+            Some(Output::Ffmpeg(ffmpeg)) => ffmpeg.sink.push_frame(frame),
+            None => {}
+        }
+    }
+}
+
+// raw frames piped into ffmpeg. with audio the video goes to a temporary file
+// first and gets muxed with the sound at the end
+struct FfmpegOutput {
+    child: Child,
+    sink: RecordingSink<ChildStdin>,
+    path: PathBuf,
+    // (temporary video, wav) when there's sound to add
+    audio: Option<(PathBuf, PathBuf)>,
+}
+
+impl FfmpegOutput {
+    fn new(path: &Path, audio_path: Option<PathBuf>) -> io::Result<FfmpegOutput> {
+        let audio = audio_path.map(|wav| (path.with_extension("video.mkv"), wav));
+        let video_path = audio.as_ref().map_or(path, |(video, _)| video.as_path());
+        let mut child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", SCREEN_WIDTH, SCREEN_HEIGHT), "-r", FRAME_RATE, "-i", "-"])
+            .args(["-pix_fmt", "yuv420p"])
+            .arg(video_path)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("ffmpeg stdin is piped");
+        Ok(FfmpegOutput { child, sink: RecordingSink::new(stdin), path: path.to_path_buf(), audio })
+    }
+    fn finish(mut self) -> io::Result<()> {
+        // closing stdin tells ffmpeg the video is over
+        let written = self.sink.finish().map(drop);
+        let status = self.child.wait()?;
+        written?;
+        if !status.success() {
+            return Err(io::Error::other(format!("ffmpeg failed: {}", status)));
+        }
+        let Some((video, wav)) = self.audio else { return Ok(()) };
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-i"])
+            .arg(&video)
+            .arg("-i")
+            .arg(&wav)
+            .args(["-c:v", "copy", "-shortest"])
+            .arg(&self.path)
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("ffmpeg couldn't add the audio: {}", status)));
+        }
+        fs::remove_file(video)?;
+        fs::remove_file(wav)
+    }
+}
+
+// gif delays are in hundredths of a second and browsers slow down anything
+// under 2, so every other frame is kept and shown for 3 or 4
+#[cfg(feature = "gif")]
+struct GifOutput {
+    encoder: gif::Encoder<io::BufWriter<fs::File>>,
+    frames: u64,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "gif")]
+impl GifOutput {
+    fn new(path: &Path) -> io::Result<GifOutput> {
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        let mut encoder = gif::Encoder::new(file, SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, &[]).map_err(gif_error)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+        Ok(GifOutput { encoder, frames: 0, error: None })
+    }
+    // hundredths of a second from the first frame to the start of `frame`
+    fn centiseconds(frame: u64) -> u64 {
+        frame * CYCLES_PER_FRAME as u64 * 100 / CLOCK_RATE as u64
+    }
+    fn push_frame(&mut self, frame: &Frame) {
+        let index = self.frames;
+        self.frames += 1;
+        if !index.is_multiple_of(2) || self.error.is_some() { return }
+        let mut pixels = frame.pixels.to_vec();
+        let mut gif_frame = gif::Frame::from_rgba_speed(SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, &mut pixels, 10);
+        gif_frame.delay = (GifOutput::centiseconds(index + 2) - GifOutput::centiseconds(index)) as u16;
+        if let Err(error) = self.encoder.write_frame(&gif_frame) {
+            self.error = Some(gif_error(error));
+        }
+    }
+    fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        let mut file = self.encoder.into_inner()?;
+        io::Write::flush(&mut file)
+    }
+}
+
+#[cfg(feature = "gif")]
+fn gif_error(error: gif::EncodingError) -> io::Error {
+    match error {
+        gif::EncodingError::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+    }
+}
+
+#[cfg(all(test, feature = "gif"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gifs_keep_every_other_frame() {
+        let path = std::env::temp_dir().join(format!("gb-emulator-recorder-{}.gif", std::process::id()));
+        let mut recorder = Recorder::new();
+        recorder.start(&path, RecordingFormat::Gif, None).unwrap();
+        for _ in 0..5 {
+            recorder.push_frame(&Frame::new());
+        }
+        recorder.stop().unwrap();
+        assert!(!recorder.recording());
+        let mut decoder = gif::DecodeOptions::new().read_info(fs::File::open(&path).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        fs::remove_file(path).unwrap();
+        // 2 frames are 3.35 hundredths of a second
+        assert_eq!(delays, [3, 3, 4]);
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Copy, Clone)]
 pub struct Registers {
     pub a: u8,
     pub f: FlagsRegister,
@@ -10,6 +11,18 @@ pub struct Registers {
 }
 
 impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            a: 0,
+            f: FlagsRegister::from(0),
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+        }
+    }
     pub fn get_af(&self) -> u16 {
         let f_u8: u8 = self.f.into();
         (self.a as u16) << 8 | f_u8 as u16
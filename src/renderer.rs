@@ -0,0 +1,375 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::color_correction::to_rgba;
+use crate::colorization::DmgColors;
+use crate::config::ColorCorrection;
+use crate::frame::{Frame, SCREEN_WIDTH};
+use crate::gpu::{Lcdc, Palette, Vram, OAM_SIZE, PALETTE_COUNT, VRAM_SIZE};
+
+// offsets into vram of the two tile maps (0x9800, 0x9C00) and the base of the
+// signed tile data area (0x9000)
+pub const TILE_MAP_0: usize = 0x1800;
+pub const TILE_MAP_1: usize = 0x1C00;
+const SIGNED_TILE_DATA: usize = 0x1000;
+pub const TILE_MAP_SIDE: usize = 32;
+pub const BACKGROUND_SIDE: usize = 256;
+// the PPU stops looking through OAM once it has found this many sprites on a line
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+pub const OAM_ENTRY_SIZE: usize = 4;
+// sprite coordinates are offset so they can sit partly off the top/left edge
+pub const SPRITE_Y_OFFSET: usize = 16;
+pub const SPRITE_X_OFFSET: usize = 8;
+// WX holds the window's screen x plus 7, so 0-166 puts it on screen
+pub const WINDOW_X_OFFSET: usize = 7;
+
+// one OAM entry, as stored
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+}
+
+impl Sprite {
+    pub fn behind_background(&self) -> bool {
+        self.flags & 0x80 != 0
+    }
+    pub fn y_flip(&self) -> bool {
+        self.flags & 0x40 != 0
+    }
+    pub fn x_flip(&self) -> bool {
+        self.flags & 0x20 != 0
+    }
+    // CGB only, which vram bank the tile comes from
+    pub fn tile_bank(&self) -> usize {
+        (self.flags >> 3 & 0x01) as usize
+    }
+    // OBP0 or OBP1
+    pub fn palette(&self) -> usize {
+        (self.flags >> 4 & 0x01) as usize
+    }
+    // one of the eight object palettes in CGB palette ram
+    pub fn cgb_palette(&self) -> usize {
+        (self.flags & 0x07) as usize
+    }
+}
+
+// the sprites OAM scan picked for a line, in OAM order
+#[derive(Copy, Clone, Default)]
+pub struct LineSprites {
+    pub sprites: [Sprite; MAX_SPRITES_PER_LINE],
+    pub count: usize,
+}
+
+impl LineSprites {
+    pub fn as_slice(&self) -> &[Sprite] {
+        &self.sprites[..self.count]
+    }
+}
+
+// mode 2: walk OAM in order and keep the first ten sprites whose rows cover the
+// line. x doesn't matter here, so sprites parked off screen still use up slots,
+// which games rely on to hide sprites or make them flicker
+pub fn scan_oam(oam: &[u8; OAM_SIZE], line: usize, tall_sprites: bool) -> LineSprites {
+    let height = if tall_sprites { 16 } else { 8 };
+    let mut selected = LineSprites::default();
+    for entry in oam.chunks_exact(OAM_ENTRY_SIZE) {
+        let sprite = Sprite { y: entry[0], x: entry[1], tile: entry[2], flags: entry[3] };
+        let top = sprite.y as usize;
+        let covers_line = (top..top + height).contains(&(line + SPRITE_Y_OFFSET));
+        if !covers_line { continue }
+        selected.sprites[selected.count] = sprite;
+        selected.count += 1;
+        if selected.count == MAX_SPRITES_PER_LINE { break }
+    }
+    selected
+}
+
+// CGB palette ram as RGB555 colours, [palette][colour]
+#[derive(Copy, Clone, Default)]
+pub struct CgbPalettes {
+    pub background: [[u16; 4]; PALETTE_COUNT],
+    pub objects: [[u16; 4]; PALETTE_COUNT],
+}
+
+// the registers a scanline is drawn with, captured when that line is rendered
+#[derive(Copy, Clone, Default)]
+pub struct LineRegisters {
+    pub lcdc: Lcdc,
+    pub scx: u8,
+    pub scy: u8,
+    pub wx: u8,
+    // the window's own line counter, None when the window isn't on this line
+    pub window_line: Option<u8>,
+    // in CGB mode the background attribute maps and second tile bank are used too
+    pub cgb: bool,
+    pub bg_palette: Palette,
+    pub obj_palettes: [Palette; 2],
+    // only in CGB mode, where colours come from palette ram instead of BGP/OBP
+    pub cgb_palettes: Option<CgbPalettes>,
+    // a DMG game on CGB hardware, the shades are coloured in afterwards
+    pub dmg_colors: Option<DmgColors>,
+    // RGBA for each DMG shade, from GpuConfig::palette
+    pub shades: [[u8; 4]; 4],
+    // applied to CGB colours, including those given to DMG games
+    pub color_correction: ColorCorrection,
+    pub sprites: LineSprites,
+}
+
+// where the 16 bytes of a tile start. in the unsigned mode tiles 0-255 sit at
+// 0x8000, otherwise the index is signed around 0x9000 so 128-255 land in 0x8800
+pub fn tile_data_address(lcdc: Lcdc, tile_index: u8) -> usize {
+    if lcdc.tile_data_unsigned { tile_index as usize * 16 }
+    else { (SIGNED_TILE_DATA as isize + tile_index as i8 as isize * 16) as usize }
+}
+
+// a tile row is two bytes, bit 7 of each is the leftmost pixel. the second byte
+// holds the high bit of the colour index and the first the low bit
+pub fn tile_pixel(vram: &[u8; VRAM_SIZE], row_address: usize, x_in_tile: usize) -> u8 {
+    let mask = 1 << (7 - x_in_tile);
+    let lsb = (vram[row_address] & mask != 0) as u8;
+    let msb = (vram[row_address + 1] & mask != 0) as u8;
+    msb << 1 | lsb
+}
+
+// draw one line straight from vram into the frame
+pub fn render_line(vram: &Vram, registers: &LineRegisters, line: usize, frame: &mut Frame) {
+    let mut pixels = [BackgroundPixel::default(); SCREEN_WIDTH];
+    // on DMG a cleared LCDC bit 0 blanks the background and window to colour 0,
+    // on CGB they're still drawn but lose any priority over sprites
+    if registers.lcdc.bg_enabled || registers.cgb {
+        render_background(vram, registers, line, &mut pixels);
+        if let Some(window_line) = registers.window_line {
+            render_window(vram, registers, window_line as usize, &mut pixels);
+        }
+    }
+    let mut sprites = [None; SCREEN_WIDTH];
+    if registers.lcdc.sprites_enabled {
+        render_sprites(vram, registers, line, &mut sprites);
+    }
+    let master_priority = registers.lcdc.bg_enabled;
+    for (x, (background, sprite)) in pixels.into_iter().zip(sprites).enumerate() {
+        // the background covers a sprite when either of them asks for it, but only
+        // with a non zero colour. priority looks at the colour index, not at the
+        // shade it maps to
+        let sprite = sprite.filter(|sprite: &SpritePixel| {
+            background.color == 0 || !master_priority || !(sprite.behind_background || background.priority)
+        });
+        let (rgba, shade) = match (&registers.cgb_palettes, sprite) {
+            (Some(palettes), Some(sprite)) => {
+                let color = palettes.objects[sprite.palette][sprite.color as usize];
+                (to_rgba(color, registers.color_correction), sprite.color)
+            }
+            (Some(palettes), None) => {
+                let color = palettes.background[background.palette][background.color as usize];
+                (to_rgba(color, registers.color_correction), background.color)
+            }
+            (None, Some(sprite)) => {
+                let colors = registers.dmg_colors.map(|colors| colors.objects[sprite.palette]);
+                dmg_shade(registers, registers.obj_palettes[sprite.palette], sprite.color, colors)
+            }
+            (None, None) => {
+                let colors = registers.dmg_colors.map(|colors| colors.background);
+                dmg_shade(registers, registers.bg_palette, background.color, colors)
+            }
+        };
+        frame.set_pixel(x, line, rgba);
+        // CGB colours have no shade, the colour index is the closest thing
+        frame.set_shade(x, line, shade);
+    }
+}
+
+fn dmg_shade(registers: &LineRegisters, palette: Palette, color: u8, colors: Option<[u16; 4]>) -> ([u8; 4], u8) {
+    let shade = palette.shade(color);
+    match colors {
+        Some(colors) => (to_rgba(colors[shade as usize], registers.color_correction), shade),
+        None => (registers.shades[shade as usize], shade),
+    }
+}
+
+// a background or window pixel, palette and priority come from the CGB attribute map
+#[derive(Copy, Clone, Default)]
+struct BackgroundPixel {
+    color: u8,
+    palette: usize,
+    priority: bool,
+}
+
+// a pixel in one of the 256x256 tile map planes. on CGB every map entry has an
+// attribute byte at the same offset in bank 1: palette in bits 0-2, tile bank in
+// bit 3, x/y flip in bits 5/6 and priority over sprites in bit 7
+fn map_pixel(vram: &Vram, registers: &LineRegisters, tile_map: usize, x: usize, y: usize) -> BackgroundPixel {
+    let map_index = tile_map + (y / 8) * TILE_MAP_SIDE + x / 8;
+    let attributes = if registers.cgb { vram[1][map_index] } else { 0 };
+    let row = if attributes & 0x40 != 0 { 7 - y % 8 } else { y % 8 };
+    let column = if attributes & 0x20 != 0 { 7 - x % 8 } else { x % 8 };
+    let bank = &vram[(attributes >> 3 & 0x01) as usize];
+    // two bytes per row, 16 bytes per tile
+    let row_address = tile_data_address(registers.lcdc, vram[0][map_index]) + row * 2;
+    BackgroundPixel {
+        color: tile_pixel(bank, row_address, column),
+        palette: (attributes & 0x07) as usize,
+        priority: attributes & 0x80 != 0,
+    }
+}
+
+// the background is a 256x256 plane of tiles that SCX/SCY scroll the screen
+// over, wrapping at the edges
+fn render_background(vram: &Vram, registers: &LineRegisters, line: usize, pixels: &mut [BackgroundPixel; SCREEN_WIDTH]) {
+    let tile_map = if registers.lcdc.bg_tile_map { TILE_MAP_1 } else { TILE_MAP_0 };
+    let y = (line + registers.scy as usize) % BACKGROUND_SIDE;
+    for (x, pixel) in pixels.iter_mut().enumerate() {
+        let background_x = (x + registers.scx as usize) % BACKGROUND_SIDE;
+        *pixel = map_pixel(vram, registers, tile_map, background_x, y);
+    }
+}
+
+// the window isn't scrolled, its top left corner is pinned to (WX-7, WY) and it
+// covers the background from there to the right edge
+fn render_window(vram: &Vram, registers: &LineRegisters, window_line: usize, pixels: &mut [BackgroundPixel; SCREEN_WIDTH]) {
+    let tile_map = if registers.lcdc.window_tile_map { TILE_MAP_1 } else { TILE_MAP_0 };
+    let left = (registers.wx as usize).saturating_sub(WINDOW_X_OFFSET);
+    for (x, pixel) in pixels.iter_mut().enumerate().skip(left) {
+        let window_x = x + WINDOW_X_OFFSET - registers.wx as usize;
+        *pixel = map_pixel(vram, registers, tile_map, window_x, window_line);
+    }
+}
+
+// the sprite pixel that won a screen position
+#[derive(Copy, Clone)]
+struct SpritePixel {
+    x: u8,
+    color: u8,
+    palette: usize,
+    behind_background: bool,
+}
+
+// on DMG the sprite with the smaller x wins where sprites overlap, ties go to the
+// one earlier in OAM. CGB only goes by OAM order. colour 0 is transparent. the
+// winning sprite decides on its own whether the background covers it, it doesn't
+// let sprites under it through
+fn render_sprites(vram: &Vram, registers: &LineRegisters, line: usize, pixels: &mut [Option<SpritePixel>; SCREEN_WIDTH]) {
+    let height = if registers.lcdc.tall_sprites { 16 } else { 8 };
+    for sprite in registers.sprites.as_slice() {
+        let mut row = line + SPRITE_Y_OFFSET - sprite.y as usize;
+        if sprite.y_flip() { row = height - 1 - row }
+        // 8x16 sprites ignore the low bit of the tile index
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let row_address = tile as usize * 16 + row * 2;
+        let (bank, palette) = if registers.cgb { (sprite.tile_bank(), sprite.cgb_palette()) } else { (0, sprite.palette()) };
+        for column in 0..8 {
+            let Some(x) = (sprite.x as usize + column).checked_sub(SPRITE_X_OFFSET) else { continue };
+            if x >= SCREEN_WIDTH { break }
+            if pixels[x].is_some_and(|other| registers.cgb || other.x <= sprite.x) { continue }
+            let color = tile_pixel(&vram[bank], row_address, if sprite.x_flip() { 7 - column } else { column });
+            if color == 0 { continue }
+            pixels[x] = Some(SpritePixel { x: sprite.x, color, palette, behind_background: sprite.behind_background() });
+        }
+    }
+}
+
+// everything needed to redraw a frame away from the emulation thread: vram as it
+// was when the frame started, and per line the registers plus the vram writes
+// that landed before that line was drawn
+struct FrameJob {
+    vram: Box<Vram>,
+    lines: Vec<LineJob>,
+    pending_writes: Vec<VramWrite>,
+}
+
+// (bank, offset, value)
+type VramWrite = (usize, usize, u8);
+
+struct LineJob {
+    line: usize,
+    registers: LineRegisters,
+    vram_writes: Vec<VramWrite>,
+}
+
+impl FrameJob {
+    fn new(vram: &Vram) -> FrameJob {
+        FrameJob { vram: Box::new(*vram), lines: Vec::new(), pending_writes: Vec::new() }
+    }
+    fn render(mut self) -> Frame {
+        let mut frame = Frame::new();
+        for job in &self.lines {
+            for &(bank, index, value) in &job.vram_writes {
+                self.vram[bank][index] = value;
+            }
+            render_line(&self.vram, &job.registers, job.line, &mut frame);
+        }
+        frame
+    }
+}
+
+// there are no threads to spawn in the browser, the fast preset renders on the
+// cpu's thread there like the cycle accurate one
+pub fn parallel_rendering_supported() -> bool {
+    !cfg!(target_arch = "wasm32")
+}
+
+// renders finished frames on a worker thread while the cpu carries on with the
+// next one. frames therefore come out one frame late, which is why this is only
+// used by the fast accuracy preset
+pub struct ParallelRenderer {
+    jobs: Option<Sender<FrameJob>>,
+    frames: Receiver<Frame>,
+    job: FrameJob,
+    in_flight: bool,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ParallelRenderer {
+    pub fn new(vram: &Vram) -> ParallelRenderer {
+        let (job_sender, job_receiver) = mpsc::channel::<FrameJob>();
+        let (frame_sender, frame_receiver) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            for job in job_receiver {
+                if frame_sender.send(job.render()).is_err() { break }
+            }
+        });
+        ParallelRenderer {
+            jobs: Some(job_sender),
+            frames: frame_receiver,
+            job: FrameJob::new(vram),
+            in_flight: false,
+            worker: Some(worker),
+        }
+    }
+    pub fn vram_written(&mut self, bank: usize, index: usize, value: u8) {
+        self.job.pending_writes.push((bank, index, value));
+    }
+    // the line has been drawn, remember what it was drawn with
+    pub fn hblank(&mut self, line: usize, registers: LineRegisters) {
+        let vram_writes = std::mem::take(&mut self.job.pending_writes);
+        self.job.lines.push(LineJob { line, registers, vram_writes });
+    }
+    // hands the just finished frame to the worker and returns the previous frame,
+    // once the worker has produced it
+    pub fn vblank(&mut self, vram: &Vram) -> Option<Frame> {
+        let previous = if self.in_flight { self.frames.recv().ok() } else { None };
+        let job = std::mem::replace(&mut self.job, FrameJob::new(vram));
+        // writes made after the last line still need to reach the worker's copy,
+        // they're already part of the new job's starting vram
+        self.in_flight = self.jobs.as_ref().is_some_and(|jobs| jobs.send(job).is_ok());
+        previous
+    }
+    // wait for the frame in flight, used when switching back to single threaded rendering
+    pub fn finish(mut self) -> Option<Frame> {
+        let frame = if self.in_flight { self.frames.recv().ok() } else { None };
+        self.in_flight = false;
+        frame
+    }
+}
+
+impl Drop for ParallelRenderer {
+    fn drop(&mut self) {
+        // closing the channel ends the worker's loop
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// Soft is the equivalent of a reset line: the cpu and peripherals restart but
+// memory keeps whatever it held. PowerCycle cuts power, so volatile memory comes
+// back up in its power-on pattern and only battery backed state survives
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ResetKind {
+    Soft,
+    PowerCycle,
+}
+
+pub const DEFAULT_RAM_SEED: u32 = 0x1234_5678;
+
+// dmg work ram powers up in a noisy but mostly repeatable state. a seeded xorshift
+// stream stands in for it so runs stay reproducible. xorshift never leaves 0, so
+// that seed means the default one
+pub fn fill_power_on_pattern(memory: &mut [u8], seed: u32) {
+    let mut state = if seed == 0 { DEFAULT_RAM_SEED } else { seed };
+    for byte in memory.iter_mut() {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        *byte = state as u8;
+    }
+}
@@ -0,0 +1,633 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::apu::{Apu, NR52};
+use crate::cartridge::MapperKind;
+use crate::frame::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::gameboy::GameBoy;
+use crate::gpu::{Mode, GPU};
+use crate::io::IO_SIZE;
+use crate::mbc::{bank_state, BankState};
+use crate::mmu::{HRAM_SIZE, WRAM_BANKS, WRAM_BANK_SIZE};
+use crate::registers::{FlagsRegister, Registers};
+
+// save states are a small header followed by tagged sections, so tools can
+// skip (or at least list) sections they don't understand:
+//   "GBST" | version: u16 | { tag: [u8; 4] | length: u32 | data }*
+// all integers are little endian. the version goes up whenever a section's
+// layout changes, states from other versions are refused rather than misread
+pub const MAGIC: [u8; 4] = *b"GBST";
+pub const VERSION: u16 = 3;
+
+// GameBoy::save_state puts this one straight after the header, so save state
+// pickers can read it without going through the rest of the file
+pub const INFO_SECTION: [u8; 4] = *b"INFO";
+
+// which rom the state was made with, as a hash of the whole image and its title
+pub const ROM_SECTION: [u8; 4] = *b"ROM ";
+pub const CPU_SECTION: [u8; 4] = *b"CPU ";
+// work ram, high ram, io registers and the small components behind them
+pub const MEMORY_SECTION: [u8; 4] = *b"MEM ";
+pub const PPU_SECTION: [u8; 4] = *b"PPU ";
+pub const APU_SECTION: [u8; 4] = *b"APU ";
+// banking registers and whatever other hardware the cartridge has (e.g. a clock)
+pub const MAPPER_SECTION: [u8; 4] = *b"MBC ";
+// the cartridge's ram, battery backed or not
+pub const CARTRIDGE_RAM_SECTION: [u8; 4] = *b"SRAM";
+// only with a Super Game Boy active
+pub const SGB_SECTION: [u8; 4] = *b"SGB ";
+// the picture on screen, so it's right straight after loading rather than from
+// the next frame on
+pub const SCREEN_SECTION: [u8; 4] = *b"LCD ";
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    // written in a format version this build can't read
+    Version(u16),
+    // made with another game, by title
+    WrongRom { state: String, loaded: String },
+    // a section is missing or doesn't hold what it should
+    Corrupt(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io(error) => write!(f, "{}", error),
+            StateError::Version(version) => write!(f, "save state version {} isn't supported (expected {})", version, VERSION),
+            StateError::WrongRom { state, loaded } => {
+                write!(f, "save state is for {:?}, not the loaded {:?}", state, loaded)
+            }
+            StateError::Corrupt(message) => write!(f, "corrupt save state: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(error: io::Error) -> Self {
+        StateError::Io(error)
+    }
+}
+
+// FNV-1a, like Frame::hash
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for &byte in rom {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash
+}
+
+// builds a section's data a field at a time
+pub struct StateEncoder {
+    data: Vec<u8>,
+}
+
+impl StateEncoder {
+    pub fn new() -> StateEncoder {
+        StateEncoder { data: Vec::new() }
+    }
+    // a whole section's data from one save_state
+    pub fn encode(save: impl FnOnce(&mut StateEncoder)) -> Vec<u8> {
+        let mut state = StateEncoder::new();
+        save(&mut state);
+        state.finish()
+    }
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+    // a fixed amount, the reader has to know how much
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+    // any amount, prefixed with the length
+    pub fn vec(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+// reads back what a StateEncoder wrote, failing once the data runs out
+pub struct StateDecoder<'a> {
+    tag: [u8; 4],
+    data: &'a [u8],
+}
+
+impl<'a> StateDecoder<'a> {
+    pub fn new(section: &'a Section) -> StateDecoder<'a> {
+        StateDecoder::from_bytes(section.tag, &section.data)
+    }
+    // data from outside a state file, `tag` only names it in errors
+    pub fn from_bytes(tag: [u8; 4], data: &'a [u8]) -> StateDecoder<'a> {
+        StateDecoder { tag, data }
+    }
+    fn take(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < length {
+            return Err(StateError::Corrupt(format!("{} section is cut short", String::from_utf8_lossy(&self.tag))));
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(taken)
+    }
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+    pub fn vec(&mut self) -> Result<Vec<u8>, StateError> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+    // a value out of range for what it's read into
+    pub fn invalid(&self, what: &str) -> StateError {
+        StateError::Corrupt(format!("bad {} in the {} section", what, String::from_utf8_lossy(&self.tag)))
+    }
+}
+
+// the section with `tag`, failing if there's none
+pub fn find_section(sections: &[Section], tag: [u8; 4]) -> Result<&Section, StateError> {
+    sections
+        .iter()
+        .find(|section| section.tag == tag)
+        .ok_or_else(|| StateError::Corrupt(format!("no {} section", String::from_utf8_lossy(&tag))))
+}
+
+pub struct StateWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> StateWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<StateWriter<W>> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(StateWriter { writer })
+    }
+    pub fn section(&mut self, tag: [u8; 4], data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&tag)?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(data)
+    }
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub struct Section {
+    pub tag: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+fn read_header<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC { return Err(invalid_data("not a save state")) }
+    let mut version = [0; 2];
+    reader.read_exact(&mut version)?;
+    Ok(u16::from_le_bytes(version))
+}
+
+// None at the end of the file
+fn read_section<R: Read>(reader: &mut R) -> io::Result<Option<Section>> {
+    let mut tag = [0; 4];
+    match reader.read_exact(&mut tag) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    // a corrupt length mustn't get allocated up front, only what's really there is read
+    let length = u32::from_le_bytes(length) as u64;
+    let mut data = Vec::new();
+    reader.by_ref().take(length).read_to_end(&mut data)?;
+    if data.len() as u64 != length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "save state section is cut short"));
+    }
+    Ok(Some(Section { tag, data }))
+}
+
+// reads the header and every section into memory
+pub fn read_sections<R: Read>(mut reader: R) -> io::Result<(u16, Vec<Section>)> {
+    let version = read_header(&mut reader)?;
+    let mut sections = Vec::new();
+    while let Some(section) = read_section(&mut reader)? {
+        sections.push(section);
+    }
+    Ok((version, sections))
+}
+
+// the thumbnail is the screen at half size
+pub const THUMBNAIL_WIDTH: usize = SCREEN_WIDTH / 2;
+pub const THUMBNAIL_HEIGHT: usize = SCREEN_HEIGHT / 2;
+
+// what a save state picker shows: when the state was saved and what was on screen
+pub struct StateInfo {
+    // seconds since the unix epoch, 0 where the host has no clock
+    pub timestamp: u64,
+    pub thumbnail_width: usize,
+    pub thumbnail_height: usize,
+    // RGBA, row by row
+    pub thumbnail: Vec<u8>,
+}
+
+impl StateInfo {
+    // `frame` is a full size RGBA screen, every 2x2 block of it is averaged
+    pub fn new(timestamp: u64, frame: &[u8]) -> StateInfo {
+        let mut thumbnail = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+        for y in 0..THUMBNAIL_HEIGHT {
+            for x in 0..THUMBNAIL_WIDTH {
+                for channel in 0..4 {
+                    let sum: u32 = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .iter()
+                        .map(|(dx, dy)| frame[((y * 2 + dy) * SCREEN_WIDTH + x * 2 + dx) * 4 + channel] as u32)
+                        .sum();
+                    thumbnail.push((sum / 4) as u8);
+                }
+            }
+        }
+        StateInfo { timestamp, thumbnail_width: THUMBNAIL_WIDTH, thumbnail_height: THUMBNAIL_HEIGHT, thumbnail }
+    }
+    pub fn encode(&self) -> Vec<u8> {
+        StateEncoder::encode(|state| {
+            state.u64(self.timestamp);
+            state.u16(self.thumbnail_width as u16);
+            state.u16(self.thumbnail_height as u16);
+            state.vec(&self.thumbnail);
+        })
+    }
+    pub fn decode(section: &Section) -> Result<StateInfo, StateError> {
+        let mut state = StateDecoder::new(section);
+        let timestamp = state.u64()?;
+        let (thumbnail_width, thumbnail_height) = (state.u16()? as usize, state.u16()? as usize);
+        let thumbnail = state.vec()?;
+        if thumbnail.len() != thumbnail_width * thumbnail_height * 4 {
+            return Err(state.invalid("thumbnail size"));
+        }
+        Ok(StateInfo { timestamp, thumbnail_width, thumbnail_height, thumbnail })
+    }
+}
+
+// just the info section, None for states saved without one
+pub fn read_info<R: Read>(mut reader: R) -> Result<Option<StateInfo>, StateError> {
+    read_header(&mut reader)?;
+    match read_section(&mut reader)? {
+        Some(section) if section.tag == INFO_SECTION => Ok(Some(StateInfo::decode(&section)?)),
+        _ => Ok(None),
+    }
+}
+
+pub const STATE_SLOTS: u8 = 10;
+
+// numbered save states for one game, kept side by side as <name>.ss0 to .ss9
+pub struct StateSlots {
+    dir: PathBuf,
+    name: String,
+}
+
+impl StateSlots {
+    pub fn new(dir: impl Into<PathBuf>, name: &str) -> StateSlots {
+        StateSlots { dir: dir.into(), name: name.to_string() }
+    }
+    // next to the rom and named after it
+    pub fn for_rom(path: &Path) -> StateSlots {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let name = path.file_stem().map_or("state".into(), |stem| stem.to_string_lossy());
+        StateSlots::new(dir, &name)
+    }
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("{}.ss{}", self.name, slot))
+    }
+    // replaces whatever the slot held. both this and load() announce how it went
+    // on the OSD
+    pub fn save(&self, gameboy: &mut GameBoy, slot: u8) -> io::Result<()> {
+        let result = gameboy.save_state(Vec::new()).and_then(|state| fs::write(self.path(slot), state));
+        gameboy.show_message(&match &result {
+            Ok(()) => format!("State {} saved", slot),
+            Err(error) => format!("Couldn't save state {}: {}", slot, error),
+        });
+        result
+    }
+    pub fn load(&self, gameboy: &mut GameBoy, slot: u8) -> Result<(), StateError> {
+        let result = fs::File::open(self.path(slot))
+            .map_err(StateError::from)
+            .and_then(|file| gameboy.load_state(io::BufReader::new(file)));
+        gameboy.show_message(&match &result {
+            Ok(()) => format!("State {} loaded", slot),
+            Err(error) => format!("Couldn't load state {}: {}", slot, error),
+        });
+        result
+    }
+    pub fn delete(&self, slot: u8) -> io::Result<()> {
+        fs::remove_file(self.path(slot))
+    }
+    // None for an empty slot, or a state saved without info
+    pub fn info(&self, slot: u8) -> Result<Option<StateInfo>, StateError> {
+        match fs::File::open(self.path(slot)) {
+            Ok(file) => read_info(io::BufReader::new(file)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+    // the slots holding a readable state, in order
+    pub fn list(&self) -> Vec<(u8, StateInfo)> {
+        (0..STATE_SLOTS).filter_map(|slot| Some((slot, self.info(slot).ok()??))).collect()
+    }
+}
+
+pub struct CpuState {
+    pub registers: Registers,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+    pub ime_pending: bool,
+    pub halted: bool,
+    pub locked_up: Option<u8>,
+}
+
+impl CpuState {
+    pub fn encode(&self) -> Vec<u8> {
+        let r = &self.registers;
+        let mut data = vec![r.a, r.f.into(), r.b, r.c, r.d, r.e, r.h, r.l];
+        data.extend_from_slice(&self.sp.to_le_bytes());
+        data.extend_from_slice(&self.pc.to_le_bytes());
+        data.extend_from_slice(&[self.ime as u8, self.ime_pending as u8, self.halted as u8]);
+        data.extend_from_slice(&[self.locked_up.is_some() as u8, self.locked_up.unwrap_or(0)]);
+        data
+    }
+    pub fn decode(data: &[u8]) -> io::Result<CpuState> {
+        if data.len() < 12 { return Err(invalid_data("truncated cpu section")) }
+        let mut registers = Registers::new();
+        registers.a = data[0];
+        registers.f = FlagsRegister::from(data[1]);
+        registers.b = data[2];
+        registers.c = data[3];
+        registers.d = data[4];
+        registers.e = data[5];
+        registers.h = data[6];
+        registers.l = data[7];
+        // version 1 states stop at the registers
+        let flag = |index: usize| data.get(index).is_some_and(|&byte| byte != 0);
+        Ok(CpuState {
+            registers,
+            sp: u16::from_le_bytes([data[8], data[9]]),
+            pc: u16::from_le_bytes([data[10], data[11]]),
+            ime: flag(12),
+            ime_pending: flag(13),
+            halted: flag(14),
+            locked_up: if flag(15) { data.get(16).copied() } else { None },
+        })
+    }
+}
+
+// the bus' own registers, from the MEM section
+pub struct MemorySummary {
+    pub boot_rom_mapped: bool,
+    pub wram_bank: u8,
+    pub interrupt_enable: u8,
+    pub interrupt_flags: u8,
+}
+
+pub struct PpuSummary {
+    pub lcd_enabled: bool,
+    pub mode: Mode,
+    pub ly: u8,
+    pub lyc: u8,
+}
+
+pub struct ApuSummary {
+    pub powered: bool,
+    // whether each of the four channels is playing, as NR52 reports them
+    pub channels: [bool; 4],
+}
+
+// everything that can be learned from a save state without loading it into a machine
+pub struct StateSummary {
+    pub version: u16,
+    pub info: Option<StateInfo>,
+    pub title: Option<String>,
+    pub cpu: Option<CpuState>,
+    pub memory: Option<MemorySummary>,
+    // only for states that name their mapper, and mappers with banks
+    pub mapper: Option<(MapperKind, BankState)>,
+    pub ppu: Option<PpuSummary>,
+    pub apu: Option<ApuSummary>,
+    // (tag, length) of every section, including the decoded ones
+    pub sections: Vec<([u8; 4], usize)>,
+}
+
+// the layout Mmu::save_memory writes, up to the io registers
+fn decode_memory(section: &Section) -> Result<MemorySummary, StateError> {
+    let mut state = StateDecoder::new(section);
+    let boot_rom_mapped = state.bool()?;
+    state.bytes(&mut vec![0; WRAM_BANK_SIZE * WRAM_BANKS])?;
+    let wram_bank = state.u8()?;
+    state.bytes(&mut [0; HRAM_SIZE])?;
+    let interrupt_enable = state.u8()?;
+    let mut io = [0; IO_SIZE];
+    state.bytes(&mut io)?;
+    Ok(MemorySummary { boot_rom_mapped, wram_bank, interrupt_enable, interrupt_flags: io[0x0F] })
+}
+
+// the PPU and APU decode their own sections, the summaries are read off them
+fn decode_ppu(section: &Section) -> Result<PpuSummary, StateError> {
+    let mut gpu = GPU::new();
+    gpu.load_state(&mut StateDecoder::new(section))?;
+    Ok(PpuSummary { lcd_enabled: gpu.lcdc.lcd_enabled, mode: gpu.mode(), ly: gpu.ly(), lyc: gpu.lyc })
+}
+
+fn decode_apu(section: &Section) -> Result<ApuSummary, StateError> {
+    let mut apu = Apu::new();
+    apu.load_state(&mut StateDecoder::new(section))?;
+    let status = apu.read(NR52);
+    Ok(ApuSummary { powered: status & 0x80 != 0, channels: [0, 1, 2, 3].map(|bit| status >> bit & 1 != 0) })
+}
+
+fn corrupt(error: StateError) -> io::Error {
+    match error {
+        StateError::Io(error) => error,
+        other => invalid_data(&other.to_string()),
+    }
+}
+
+pub fn inspect<R: Read>(reader: R) -> io::Result<StateSummary> {
+    let (version, sections) = read_sections(reader)?;
+    let optional = |tag| sections.iter().find(|section: &&Section| section.tag == tag);
+    let mut title = None;
+    let mut mapper_kind = None;
+    if let Some(section) = optional(ROM_SECTION) {
+        let mut rom = StateDecoder::new(section);
+        rom.u64().map_err(corrupt)?;
+        title = Some(String::from_utf8_lossy(&rom.vec().map_err(corrupt)?).into_owned());
+        // older states don't say which mapper they were made with
+        mapper_kind = rom.u8().ok().map(MapperKind::from_cartridge_type);
+    }
+    let mapper = mapper_kind
+        .zip(optional(MAPPER_SECTION))
+        .and_then(|(kind, section)| Some((kind, bank_state(kind, &section.data)?)));
+    Ok(StateSummary {
+        version,
+        info: optional(INFO_SECTION).and_then(|section| StateInfo::decode(section).ok()),
+        title,
+        cpu: optional(CPU_SECTION).map(|section| CpuState::decode(&section.data)).transpose()?,
+        memory: optional(MEMORY_SECTION).map(decode_memory).transpose().map_err(corrupt)?,
+        mapper,
+        ppu: optional(PPU_SECTION).map(decode_ppu).transpose().map_err(corrupt)?,
+        apu: optional(APU_SECTION).map(decode_apu).transpose().map_err(corrupt)?,
+        sections: sections.iter().map(|section| (section.tag, section.data.len())).collect(),
+    })
+}
+
+impl fmt::Display for StateSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "save state version {}", self.version)?;
+        if let Some(info) = &self.info {
+            writeln!(f, "saved at {} (unix time), {}x{} thumbnail", info.timestamp, info.thumbnail_width, info.thumbnail_height)?;
+        }
+        if let Some(title) = &self.title {
+            writeln!(f, "rom: {:?}", title)?;
+        }
+        match &self.cpu {
+            Some(cpu) => {
+                let r = &cpu.registers;
+                let flags = r.f;
+                writeln!(f, "cpu:")?;
+                writeln!(f, "  AF={:04X} BC={:04X} DE={:04X} HL={:04X}", r.get_af(), r.get_bc(), r.get_de(), r.get_hl())?;
+                writeln!(f, "  SP={:04X} PC={:04X}", cpu.sp, cpu.pc)?;
+                writeln!(
+                    f, "  flags: {}{}{}{}",
+                    if flags.zero { 'Z' } else { '-' },
+                    if flags.subtract { 'N' } else { '-' },
+                    if flags.half_carry { 'H' } else { '-' },
+                    if flags.carry { 'C' } else { '-' },
+                )?;
+                writeln!(f, "  IME={} halted={}", cpu.ime as u8, cpu.halted as u8)?;
+                if let Some(opcode) = cpu.locked_up {
+                    writeln!(f, "  locked up on illegal opcode {:02X}", opcode)?;
+                }
+            }
+            None => writeln!(f, "cpu: missing")?,
+        }
+        if let Some(memory) = &self.memory {
+            writeln!(f, "memory:")?;
+            writeln!(f, "  IE={:02X} IF={:02X}", memory.interrupt_enable, memory.interrupt_flags)?;
+            writeln!(f, "  wram bank {}, boot rom {}", memory.wram_bank, if memory.boot_rom_mapped { "mapped" } else { "off" })?;
+        }
+        if let Some((kind, banks)) = &self.mapper {
+            writeln!(f, "mapper: {:?}", kind)?;
+            write!(f, "  rom bank {}", banks.rom_bank)?;
+            if let Some(ram_bank) = banks.ram_bank {
+                write!(f, ", ram bank {}", ram_bank)?;
+            }
+            writeln!(f, ", ram {}", if banks.ram_enabled { "enabled" } else { "disabled" })?;
+        }
+        if let Some(ppu) = &self.ppu {
+            writeln!(f, "ppu:")?;
+            if ppu.lcd_enabled {
+                writeln!(f, "  {:?} on LY={} (LYC={})", ppu.mode, ppu.ly, ppu.lyc)?;
+            } else {
+                writeln!(f, "  LCD off")?;
+            }
+        }
+        if let Some(apu) = &self.apu {
+            writeln!(f, "apu:")?;
+            if apu.powered {
+                let channel = |number: usize| if apu.channels[number - 1] { (b'0' + number as u8) as char } else { '-' };
+                writeln!(f, "  channels playing: {}{}{}{}", channel(1), channel(2), channel(3), channel(4))?;
+            } else {
+                writeln!(f, "  powered off")?;
+            }
+        }
+        writeln!(f, "sections:")?;
+        for (tag, length) in &self.sections {
+            writeln!(f, "  {} ({} bytes)", String::from_utf8_lossy(tag), length)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    #[test]
+    fn inspect_decodes_a_machine_state() {
+        // MBC5. ld a, 0x91; ldh (0x40), a; rom bank 3, enable ram, ram bank 2; jr -2
+        let program = [
+            0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x03, 0xEA, 0x00, 0x20, 0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0x02, 0xEA,
+            0x00, 0x40, 0x18, 0xFE,
+        ];
+        let mut gameboy = GameBoy::new(test_rom(&program, 0x1B, 0x03)).unwrap();
+        gameboy.run_frame();
+        let state = gameboy.save_state(Vec::new()).unwrap();
+        let summary = inspect(state.as_slice()).unwrap();
+
+        assert_eq!(summary.version, VERSION);
+        assert_eq!(summary.cpu.as_ref().unwrap().pc, 0x0113);
+        let memory = summary.memory.as_ref().unwrap();
+        assert!(!memory.boot_rom_mapped);
+        assert_eq!(memory.wram_bank, 1);
+        let (kind, banks) = summary.mapper.unwrap();
+        assert_eq!(kind, MapperKind::Mbc5);
+        assert_eq!(banks, BankState { rom_bank: 3, ram_bank: Some(2), ram_enabled: true });
+        // run_frame() stops as vblank starts
+        let ppu = summary.ppu.as_ref().unwrap();
+        assert!(ppu.lcd_enabled);
+        assert_eq!((ppu.mode, ppu.ly), (Mode::VBlank, 144));
+        assert!(summary.apu.as_ref().unwrap().powered);
+
+        let text = summary.to_string();
+        assert!(text.contains("mapper: Mbc5\n  rom bank 3, ram bank 2, ram enabled"), "{}", text);
+        assert!(text.contains("VBlank on LY=144"), "{}", text);
+    }
+
+    #[test]
+    fn a_corrupt_section_length_is_an_error() {
+        let mut state = StateWriter::new(Vec::new()).unwrap().finish().unwrap();
+        state.extend_from_slice(b"MEM ");
+        state.extend_from_slice(&u32::MAX.to_le_bytes());
+        state.extend_from_slice(&[0; 16]);
+        let error = read_sections(state.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::frame::{Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Filter {
+    // every pixel becomes a factor x factor block
+    Nearest(usize),
+    // doubles the size, rounding off diagonal edges (AdvMAME2x)
+    Scale2x,
+}
+
+impl Filter {
+    pub fn factor(&self) -> usize {
+        match self {
+            Filter::Nearest(factor) => (*factor).max(1),
+            Filter::Scale2x => 2,
+        }
+    }
+}
+
+// upscales frames for display. the output buffer is kept between frames so a
+// frontend can call scale() every frame without allocating
+pub struct Scaler {
+    filter: Filter,
+    output: Vec<u8>,
+}
+
+impl Scaler {
+    pub fn new(filter: Filter) -> Scaler {
+        let mut scaler = Scaler { filter, output: Vec::new() };
+        scaler.set_filter(filter);
+        scaler
+    }
+    // nearest neighbour at the biggest whole factor that fits in the window
+    pub fn for_window(width: usize, height: usize) -> Scaler {
+        let factor = (width / SCREEN_WIDTH).min(height / SCREEN_HEIGHT).max(1);
+        Scaler::new(Filter::Nearest(factor))
+    }
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.output.resize(self.width() * self.height() * 4, 0);
+    }
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH * self.filter.factor()
+    }
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT * self.filter.factor()
+    }
+    // the scaled frame as RGBA, width() x height()
+    pub fn scale(&mut self, frame: &Frame) -> &[u8] {
+        match self.filter {
+            Filter::Nearest(_) => self.nearest(frame),
+            Filter::Scale2x => self.scale2x(frame),
+        }
+        &self.output
+    }
+    fn set_output_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let offset = (y * self.width() + x) * 4;
+        self.output[offset..offset + 4].copy_from_slice(&rgba);
+    }
+    fn nearest(&mut self, frame: &Frame) {
+        let factor = self.filter.factor();
+        let row_bytes = self.width() * 4;
+        for y in 0..SCREEN_HEIGHT {
+            // build the first row of the block, then copy it down
+            let first_row = y * factor;
+            for x in 0..SCREEN_WIDTH {
+                let rgba = frame.pixel(x, y);
+                for column in 0..factor {
+                    self.set_output_pixel(x * factor + column, first_row, rgba);
+                }
+            }
+            let start = first_row * row_bytes;
+            for row in 1..factor {
+                self.output.copy_within(start..start + row_bytes, start + row * row_bytes);
+            }
+        }
+    }
+    // each pixel P looks at its neighbours above (A), right (B), left (C) and
+    // below (D). a corner takes a neighbour's colour where the two neighbours
+    // next to it agree and the other two don't, which follows diagonal lines
+    fn scale2x(&mut self, frame: &Frame) {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let p = frame.pixel(x, y);
+                let a = frame.pixel(x, y.saturating_sub(1));
+                let b = frame.pixel((x + 1).min(SCREEN_WIDTH - 1), y);
+                let c = frame.pixel(x.saturating_sub(1), y);
+                let d = frame.pixel(x, (y + 1).min(SCREEN_HEIGHT - 1));
+                let corners = if b != c && a != d {
+                    [
+                        if c == a { a } else { p },
+                        if a == b { b } else { p },
+                        if c == d { c } else { p },
+                        if b == d { d } else { p },
+                    ]
+                } else {
+                    [p; 4]
+                };
+                self.set_output_pixel(x * 2, y * 2, corners[0]);
+                self.set_output_pixel(x * 2 + 1, y * 2, corners[1]);
+                self.set_output_pixel(x * 2, y * 2 + 1, corners[2]);
+                self.set_output_pixel(x * 2 + 1, y * 2 + 1, corners[3]);
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST, INT};
+
+use crate::address::BankedAddress;
+use crate::cpu::Bus;
+use crate::debugger::{Breakpoint, Debugger, StopReason, Watchpoint};
+use crate::gameboy::GameBoy;
+use crate::heatmap::AccessKind;
+use crate::joypad::Button;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScriptingError(pub String);
+
+impl fmt::Display for ScriptingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ScriptingError {}
+
+impl From<Box<EvalAltResult>> for ScriptingError {
+    fn from(error: Box<EvalAltResult>) -> ScriptingError {
+        ScriptingError(error.to_string())
+    }
+}
+
+fn button(name: &str) -> Result<Button, Box<EvalAltResult>> {
+    Button::from_name(name).ok_or_else(|| format!("no button {:?}", name).into())
+}
+
+// the functions scripts get on top of rhai's own
+fn register_api(engine: &mut Engine, gameboy: &Rc<RefCell<GameBoy>>) {
+    let shared = gameboy.clone();
+    engine.register_fn("read", move |address: INT| shared.borrow().mmu().peek_byte(address as u16) as INT);
+    let shared = gameboy.clone();
+    engine.register_fn("write", move |address: INT, value: INT| {
+        shared.borrow_mut().mmu_mut().write_byte(address as u16, value as u8);
+    });
+    let shared = gameboy.clone();
+    engine.register_fn("press", move |name: &str| {
+        shared.borrow_mut().press(button(name)?);
+        Ok::<(), Box<EvalAltResult>>(())
+    });
+    let shared = gameboy.clone();
+    engine.register_fn("release", move |name: &str| {
+        shared.borrow_mut().release(button(name)?);
+        Ok::<(), Box<EvalAltResult>>(())
+    });
+    // each with a bank in front as well, for the switchable areas
+    let shared = gameboy.clone();
+    let break_at = move |location: BankedAddress| {
+        shared.borrow_mut().attach_debugger().add_breakpoint(Breakpoint::from(location));
+    };
+    let shared = gameboy.clone();
+    let watch = move |watchpoint: Watchpoint| {
+        shared.borrow_mut().mmu_mut().add_watchpoint(watchpoint);
+    };
+    let banked = |bank: INT, address: INT| BankedAddress::banked(bank as usize, address as u16);
+    let unbanked = |address: INT| BankedAddress::new(address as u16);
+    let function = break_at.clone();
+    engine.register_fn("break_at", move |address: INT| function(unbanked(address)));
+    engine.register_fn("break_at", move |bank: INT, address: INT| break_at(banked(bank, address)));
+    let function = watch.clone();
+    engine.register_fn("watch_read", move |address: INT| function(Watchpoint::read(unbanked(address))));
+    let function = watch.clone();
+    engine.register_fn("watch_read", move |bank: INT, address: INT| function(Watchpoint::read(banked(bank, address))));
+    let function = watch.clone();
+    engine.register_fn("watch_write", move |address: INT| function(Watchpoint::write(unbanked(address))));
+    engine.register_fn("watch_write", move |bank: INT, address: INT| watch(Watchpoint::write(banked(bank, address))));
+}
+
+// a rhai script driving a GameBoy, for HUDs, bots and glitch hunting. the top
+// level runs once when loaded, after that run_frame() calls whichever of these
+// the script defines:
+//   fn init()                         once, after the top level
+//   fn on_frame()                     after every frame
+//   fn on_read(address, value)        after an instruction read a watched address
+//   fn on_write(address, value)       after one wrote to it
+//   fn on_breakpoint(address)         before the instruction at a breakpoint
+// with these to call:
+//   read(address), write(address, value)
+//   press(button), release(button)    buttons by name, "a", "start", "up"...
+//   break_at([bank,] address)
+//   watch_read([bank,] address), watch_write([bank,] address)
+// hooks share an object map as `this`, to keep things between calls. the
+// GameBoy is shared with the script's functions, so it can't be borrowed by
+// anyone else while the script runs
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    hooks: HashSet<String>,
+    state: Dynamic,
+    gameboy: Rc<RefCell<GameBoy>>,
+}
+
+impl Script {
+    pub fn new(source: &str, gameboy: Rc<RefCell<GameBoy>>) -> Result<Script, ScriptingError> {
+        let mut engine = Engine::new();
+        register_api(&mut engine, &gameboy);
+        let ast = engine.compile(source).map_err(|error| ScriptingError(error.to_string()))?;
+        let hooks = ast.iter_functions().map(|function| function.name.to_string()).collect();
+        gameboy.borrow_mut().attach_debugger();
+        let mut script =
+            Script { engine, ast, scope: Scope::new(), hooks, state: Dynamic::from_map(Map::new()), gameboy };
+        script.engine.run_ast_with_scope(&mut script.scope, &script.ast)?;
+        script.gameboy.borrow().mmu().take_watch_hits();
+        script.call("init", ())?;
+        Ok(script)
+    }
+    pub fn load(path: impl AsRef<Path>, gameboy: Rc<RefCell<GameBoy>>) -> Result<Script, ScriptingError> {
+        let source = fs::read_to_string(path).map_err(|error| ScriptingError(error.to_string()))?;
+        Script::new(&source, gameboy)
+    }
+    // run_frame() with the hooks called along the way. the script keeps a
+    // debugger attached for its breakpoints and watchpoints, and gives up the
+    // frame if something else pauses it
+    pub fn run_frame(&mut self) -> Result<(), ScriptingError> {
+        loop {
+            let reason = {
+                let mut gameboy = self.gameboy.borrow_mut();
+                gameboy.run_frame();
+                gameboy.debugger().and_then(Debugger::stop_reason)
+            };
+            match reason {
+                None => break,
+                Some(StopReason::Breakpoint(breakpoint)) => {
+                    self.call("on_breakpoint", (breakpoint.address as INT,))?;
+                }
+                Some(StopReason::Watchpoint(_)) => {
+                    let hits = self.gameboy.borrow_mut().attach_debugger().watch_hits().to_vec();
+                    for access in hits {
+                        let hook = if access.kind == AccessKind::Write { "on_write" } else { "on_read" };
+                        self.call(hook, (access.address as INT, access.value as INT))?;
+                    }
+                }
+                Some(_) => return Ok(()),
+            }
+            self.gameboy.borrow_mut().attach_debugger().run();
+        }
+        self.call("on_frame", ())
+    }
+    fn call(&mut self, hook: &str, arguments: impl FuncArgs) -> Result<(), ScriptingError> {
+        if !self.hooks.contains(hook) { return Ok(()) }
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        let _: Dynamic = self.engine.call_fn_with_options(options, &mut self.scope, &self.ast, hook, arguments)?;
+        // the script's own writes aren't the game's, watchpoints shouldn't see them
+        self.gameboy.borrow().mmu().take_watch_hits();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    #[test]
+    fn hooks_see_and_change_the_machine() {
+        // ld hl, 0xC000; ld a, [$FF00]; inc [hl]; call 0x0150; jr -8. 0x0150 is ret
+        let mut rom = test_rom(&[0x21, 0x00, 0xC0, 0xF0, 0x00, 0x34, 0xCD, 0x50, 0x01, 0x18, 0xF8], 0x00, 0);
+        rom[0x150] = 0xC9;
+        let gameboy = Rc::new(RefCell::new(GameBoy::new(rom).unwrap()));
+        let source = r#"
+            break_at(0x0150);
+            watch_write(0xC000);
+            fn init() { this.frames = 0; this.calls = 0; }
+            fn on_breakpoint(address) { this.calls += 1; }
+            // keep the counter from going past 2
+            fn on_write(address, value) { if value > 2 { write(address, 0); } }
+            fn on_frame() {
+                this.frames += 1;
+                write(0xC100, this.frames);
+                write(0xC101, if this.calls > 255 { 255 } else { this.calls });
+                press("start");
+            }
+        "#;
+        let mut script = Script::new(source, gameboy.clone()).unwrap();
+        script.run_frame().unwrap();
+        script.run_frame().unwrap();
+        {
+            let gameboy = gameboy.borrow();
+            assert_eq!(gameboy.mmu().peek_byte(0xC100), 2);
+            assert!(gameboy.mmu().peek_byte(0xC101) > 0);
+            assert!(gameboy.mmu().peek_byte(0xC000) <= 2);
+            assert!(gameboy.mmu().joypad.pressed(Button::Start));
+        }
+        let error = Script::new("press(\"turbo\");", gameboy.clone()).err().unwrap();
+        assert!(error.0.contains("no button \"turbo\""));
+        assert!(Script::new("fn on_frame( {", gameboy).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::sync::{Arc, Mutex};
+
+use crate::cpu::SERIAL_INTERRUPT;
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+pub mod printer;
+pub mod tcp;
+
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+
+// clock cycles per byte with the internal clock: 8192 Hz, or 262144 Hz with the
+// CGB's fast clock (SC bit 1)
+const BYTE_CYCLES: u32 = 8 * 512;
+const FAST_BYTE_CYCLES: u32 = 8 * 16;
+// what the data line reads with nothing plugged in
+const DISCONNECTED: u8 = 0xFF;
+
+// whatever is on the other end of the link cable
+pub trait SerialDevice {
+    // a transfer clocked by this side finished: `outgoing` was shifted out, the
+    // returned byte was shifted in
+    fn exchange(&mut self, outgoing: u8) -> u8;
+    // asked while this side waits on an external clock. a device that drives the
+    // clock returns the byte it sent once a transfer happened, taking `outgoing`
+    fn poll(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
+
+// lets the host keep a handle on a plugged in device, e.g. to collect printed pages
+impl<T: SerialDevice> SerialDevice for Arc<Mutex<T>> {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.lock().map_or(DISCONNECTED, |mut device| device.exchange(outgoing))
+    }
+    fn poll(&mut self, outgoing: u8) -> Option<u8> {
+        self.lock().ok().and_then(|mut device| device.poll(outgoing))
+    }
+}
+
+// hears every byte this side starts sending, test roms print their results this way
+pub type SerialListener = Box<dyn FnMut(u8)>;
+
+// SB holds the byte being shifted out and in, SC bit 7 starts a transfer and
+// bit 0 picks the internal clock. the interrupt is requested when a byte is done.
+// the byte is swapped whole at the end rather than a bit at a time
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cgb: bool,
+    // clock cycles left in the running internal clock transfer
+    remaining: u32,
+    device: Option<Box<dyn SerialDevice>>,
+    listener: Option<SerialListener>,
+    // everything sent since capturing started, when it has
+    captured: Option<Vec<u8>>,
+    interrupts: u8,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            cgb: false,
+            remaining: 0,
+            device: None,
+            listener: None,
+            captured: None,
+            interrupts: 0,
+        }
+    }
+    // the plugged in device, listener and capture all stay
+    pub fn reset(&mut self) {
+        self.sb = 0;
+        self.sc = 0;
+        self.remaining = 0;
+        self.interrupts = 0;
+    }
+    // the registers and a transfer in progress, not what's plugged in
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.bytes(&[self.sb, self.sc, self.interrupts]);
+        state.u32(self.remaining);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.sb = state.u8()?;
+        self.sc = state.u8()?;
+        self.interrupts = state.u8()?;
+        self.remaining = state.u32()?;
+        Ok(())
+    }
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+    }
+    pub fn disconnect(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.device.take()
+    }
+    pub fn set_output_listener<F: FnMut(u8) + 'static>(&mut self, listener: F) {
+        self.listener = Some(Box::new(listener));
+    }
+    pub fn clear_output_listener(&mut self) {
+        self.listener = None;
+    }
+    // keep every byte sent from now on, see captured()
+    pub fn start_capture(&mut self) {
+        self.captured.get_or_insert_with(Vec::new);
+    }
+    pub fn stop_capture(&mut self) -> Option<Vec<u8>> {
+        self.captured.take()
+    }
+    pub fn captured(&self) -> &[u8] {
+        self.captured.as_deref().unwrap_or_default()
+    }
+    // the capture as text, for test roms that print e.g. "Passed"
+    pub fn captured_text(&self) -> String {
+        String::from_utf8_lossy(self.captured()).into_owned()
+    }
+    fn transferring(&self) -> bool {
+        self.sc & 0x80 != 0
+    }
+    fn internal_clock(&self) -> bool {
+        self.sc & 0x01 != 0
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            SB => self.sb,
+            SC if self.cgb => self.sc | 0x7C,
+            SC => self.sc | 0x7E,
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            SB => self.sb = value,
+            SC => {
+                self.sc = value & if self.cgb { 0x83 } else { 0x81 };
+                if self.transferring() && self.internal_clock() {
+                    let fast = self.cgb && self.sc & 0x02 != 0;
+                    self.remaining = if fast { FAST_BYTE_CYCLES } else { BYTE_CYCLES };
+                    self.sent(self.sb);
+                }
+            }
+            _ => {}
+        }
+    }
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.transferring() { return }
+        if !self.internal_clock() {
+            // nothing happens until the other side clocks a byte over
+            let incoming = self.device.as_mut().and_then(|device| device.poll(self.sb));
+            if let Some(incoming) = incoming {
+                self.finish(incoming);
+            }
+            return;
+        }
+        self.remaining = self.remaining.saturating_sub(cycles);
+        if self.remaining > 0 { return }
+        let incoming = match &mut self.device {
+            Some(device) => device.exchange(self.sb),
+            None => DISCONNECTED,
+        };
+        self.finish(incoming);
+    }
+    fn sent(&mut self, byte: u8) {
+        if let Some(listener) = &mut self.listener {
+            listener(byte);
+        }
+        if let Some(captured) = &mut self.captured {
+            captured.push(byte);
+        }
+    }
+    fn finish(&mut self, incoming: u8) {
+        self.sb = incoming;
+        self.sc &= 0x7F;
+        self.interrupts |= SERIAL_INTERRUPT;
+    }
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    struct Echo;
+
+    impl SerialDevice for Echo {
+        fn exchange(&mut self, outgoing: u8) -> u8 {
+            !outgoing
+        }
+    }
+
+    #[test]
+    fn internal_clock_transfers_take_a_byte_time() {
+        let mut serial = Serial::new();
+        serial.write(SB, 0x42);
+        serial.write(SC, 0x81);
+        assert_eq!(serial.read(SC), 0xFF);
+        serial.tick(BYTE_CYCLES - 4);
+        assert_eq!(serial.take_interrupts(), 0);
+        serial.tick(4);
+        // nobody on the other end
+        assert_eq!(serial.read(SB), 0xFF);
+        assert_eq!(serial.read(SC), 0x7F);
+        assert_eq!(serial.take_interrupts(), SERIAL_INTERRUPT);
+        serial.connect(Box::new(Echo));
+        serial.write(SB, 0x0F);
+        serial.write(SC, 0x81);
+        serial.tick(BYTE_CYCLES);
+        assert_eq!(serial.read(SB), 0xF0);
+        assert_eq!(serial.take_interrupts(), SERIAL_INTERRUPT);
+        // an external clock transfer waits for the other side forever
+        serial.write(SC, 0x80);
+        serial.tick(BYTE_CYCLES * 4);
+        assert_eq!(serial.read(SC), 0xFE);
+        assert_eq!(serial.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn sent_bytes_reach_the_listener_and_capture() {
+        let heard = Rc::new(RefCell::new(Vec::new()));
+        let mut serial = Serial::new();
+        let sink = heard.clone();
+        serial.set_output_listener(move |byte| sink.borrow_mut().push(byte));
+        serial.start_capture();
+        for &byte in b"Passed" {
+            serial.write(SB, byte);
+            serial.write(SC, 0x81);
+            serial.tick(BYTE_CYCLES);
+        }
+        // external clock transfers only go out when the other side clocks them
+        serial.write(SC, 0x80);
+        assert_eq!(serial.captured_text(), "Passed");
+        assert_eq!(*heard.borrow(), b"Passed");
+        assert_eq!(serial.stop_capture().unwrap(), b"Passed");
+        assert!(serial.captured().is_empty());
+    }
+}
@@ -0,0 +1,284 @@
+use super::SerialDevice;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const INITIALIZE: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+// answered during the first byte after the checksum
+const ALIVE: u8 = 0x81;
+// status bits
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const IMAGE_FULL: u8 = 0x04;
+const UNPROCESSED: u8 = 0x08;
+// the printer's memory holds 9 data packets of two tile rows each
+const PACKET_SIZE: usize = 0x280;
+const BUFFER_SIZE: usize = PACKET_SIZE * 9;
+pub const PAPER_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PAPER_WIDTH / 8;
+
+// where the printer is in a packet:
+//   88 33 | command | compression | length (LE u16) | data | checksum (LE u16) | 00 00
+// the last two bytes are the printer's turn, it answers 0x81 then its status
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum State {
+    Magic(usize),
+    Command,
+    Compression,
+    Length(usize),
+    Data,
+    Checksum(usize),
+    Alive,
+    Status,
+}
+
+// a printed picture as shades 0 (white) to 3 (black), one byte per pixel
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PrintedPage {
+    pub width: usize,
+    pub height: usize,
+    pub shades: Vec<u8>,
+    // blank lines to feed before (upper nibble) and after (lower nibble)
+    pub margins: u8,
+}
+
+// thermal paper greys, white to black
+pub const PAPER_COLORS: [[u8; 4]; 4] = [
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF],
+];
+
+impl PrintedPage {
+    // RGBA with the given colour for each shade
+    pub fn to_rgba(&self, colors: [[u8; 4]; 4]) -> Vec<u8> {
+        self.shades.iter().flat_map(|&shade| colors[shade as usize]).collect()
+    }
+    #[cfg(feature = "png")]
+    pub fn save_png(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        crate::frame::write_png(file, self.width, self.height, &self.to_rgba(PAPER_COLORS))
+    }
+}
+
+// the Game Boy Printer as a serial device. pictures arrive as data packets of
+// 2bpp tiles, 20 to a row, optionally run length compressed, and a print
+// command turns everything received so far into a page
+pub struct Printer {
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    buffer: Vec<u8>,
+    status: u8,
+    // status polls left that report printing after a print command
+    busy_polls: u8,
+    pages: Vec<PrintedPage>,
+}
+
+impl Printer {
+    pub fn new() -> Printer {
+        Printer {
+            state: State::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            buffer: Vec::new(),
+            status: 0,
+            busy_polls: 0,
+            pages: Vec::new(),
+        }
+    }
+    // pages printed since the last call, oldest first
+    pub fn take_pages(&mut self) -> Vec<PrintedPage> {
+        std::mem::take(&mut self.pages)
+    }
+    fn receive(&mut self, byte: u8) {
+        self.state = match self.state {
+            State::Magic(index) if byte == MAGIC[index] => {
+                if index + 1 == MAGIC.len() { State::Command } else { State::Magic(index + 1) }
+            }
+            // anything else while waiting for a packet is noise
+            State::Magic(_) => State::Magic((byte == MAGIC[0]) as usize),
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::Length(0)
+            }
+            State::Length(index) => {
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if index == 0 {
+                    self.length = byte as u16;
+                    State::Length(1)
+                } else {
+                    self.length |= (byte as u16) << 8;
+                    self.packet.clear();
+                    if self.length == 0 { State::Checksum(0) } else { State::Data }
+                }
+            }
+            State::Data => {
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.push(byte);
+                if self.packet.len() == self.length as usize { State::Checksum(0) } else { State::Data }
+            }
+            State::Checksum(0) => {
+                self.received_checksum = byte as u16;
+                State::Checksum(1)
+            }
+            State::Checksum(_) => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.run_command();
+                State::Alive
+            }
+            State::Alive => State::Status,
+            State::Status => State::Magic(0),
+        }
+    }
+    fn run_command(&mut self) {
+        if self.received_checksum != self.checksum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+        match self.command {
+            INITIALIZE => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            DATA => {
+                let data = if self.compressed { decompress(&self.packet) } else { self.packet.clone() };
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend_from_slice(&data[..data.len().min(room)]);
+                if !self.buffer.is_empty() {
+                    self.status |= UNPROCESSED;
+                }
+                if self.buffer.len() == BUFFER_SIZE {
+                    self.status |= IMAGE_FULL;
+                }
+            }
+            PRINT => {
+                if let [_sheets, margins, palette, _exposure, ..] = self.packet[..] {
+                    self.print(margins, palette);
+                }
+            }
+            // only asks for the reply
+            STATUS => {}
+            _ => {}
+        }
+    }
+    fn print(&mut self, margins: u8, palette: u8) {
+        // a palette of 0 means the usual mapping
+        let palette = if palette == 0 { 0xE4 } else { palette };
+        let rows = self.buffer.len() / (TILES_PER_ROW * 16);
+        let height = rows * 8;
+        let mut shades = vec![0; PAPER_WIDTH * height];
+        for (y, line) in shades.chunks_exact_mut(PAPER_WIDTH).enumerate() {
+            for (x, shade) in line.iter_mut().enumerate() {
+                let tile = y / 8 * TILES_PER_ROW + x / 8;
+                let address = tile * 16 + y % 8 * 2;
+                let bit = 7 - x % 8;
+                let color = (self.buffer[address] >> bit & 1) | (self.buffer[address + 1] >> bit & 1) << 1;
+                *shade = palette >> (color * 2) & 0x03;
+            }
+        }
+        self.pages.push(PrintedPage { width: PAPER_WIDTH, height, shades, margins });
+        self.buffer.clear();
+        self.status = (self.status & !(UNPROCESSED | IMAGE_FULL)) | PRINTING;
+        self.busy_polls = 2;
+    }
+    fn status(&mut self) -> u8 {
+        let status = self.status;
+        if self.busy_polls > 0 {
+            self.busy_polls -= 1;
+            if self.busy_polls == 0 {
+                self.status &= !PRINTING;
+            }
+        }
+        status
+    }
+}
+
+// each control byte is either n + 1 literal bytes (bit 7 clear) or a run of
+// (n & 0x7F) + 2 copies of the next byte
+fn decompress(packet: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut bytes = packet.iter().copied();
+    while let Some(control) = bytes.next() {
+        if control & 0x80 != 0 {
+            let Some(value) = bytes.next() else { break };
+            data.extend(std::iter::repeat_n(value, (control & 0x7F) as usize + 2));
+        } else {
+            data.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+    data
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        let reply = match self.state {
+            State::Alive => ALIVE,
+            State::Status => self.status(),
+            _ => 0x00,
+        };
+        self.receive(outgoing);
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![0x88, 0x33, command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..].iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        for byte in packet {
+            assert_eq!(printer.exchange(byte), 0x00);
+        }
+        (printer.exchange(0x00), printer.exchange(0x00))
+    }
+
+    #[test]
+    fn data_packets_print_as_a_page() {
+        let mut printer = Printer::new();
+        assert_eq!(send_packet(&mut printer, INITIALIZE, false, &[]), (ALIVE, 0x00));
+        // one packet of solid colour 3 tiles, compressed as runs of 0xFF
+        let mut compressed = Vec::new();
+        for _ in 0..PACKET_SIZE / 128 {
+            compressed.extend_from_slice(&[0xFE, 0xFF]);
+        }
+        assert_eq!(send_packet(&mut printer, DATA, true, &compressed), (ALIVE, UNPROCESSED));
+        assert_eq!(send_packet(&mut printer, DATA, false, &[]), (ALIVE, UNPROCESSED));
+        // BGP-style palette mapping colour 3 to shade 1
+        let (_, status) = send_packet(&mut printer, PRINT, false, &[1, 0x13, 0x64, 0x40]);
+        assert_eq!(status, PRINTING);
+        assert_eq!(send_packet(&mut printer, STATUS, false, &[]), (ALIVE, PRINTING));
+        assert_eq!(send_packet(&mut printer, STATUS, false, &[]), (ALIVE, 0x00));
+        let pages = printer.take_pages();
+        assert_eq!(pages.len(), 1);
+        assert_eq!((pages[0].width, pages[0].height, pages[0].margins), (160, 16, 0x13));
+        assert!(pages[0].shades.iter().all(|&shade| shade == 1));
+        // a bad checksum is reported and the packet dropped
+        let mut packet = vec![0x88, 0x33, DATA, 0, 1, 0, 0xAA, 0x00, 0x00];
+        packet.extend_from_slice(&[0x00, 0x00]);
+        let replies: Vec<u8> = packet.into_iter().map(|byte| printer.exchange(byte)).collect();
+        assert_eq!(replies[replies.len() - 1], CHECKSUM_ERROR);
+    }
+}
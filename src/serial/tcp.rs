@@ -0,0 +1,132 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use super::SerialDevice;
+
+// every message is a kind byte and the data byte
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+// how long the clocking side waits for the other's byte before reading 0xFF
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+const DISCONNECTED: u8 = 0xFF;
+
+// a link cable to another emulator over TCP. the side whose game uses the
+// internal clock sends TRANSFER with its byte and waits for the REPLY carrying
+// the other side's SB, the other side answers as soon as its game is waiting on
+// an external clock. a transfer costs one round trip of emulation time at most,
+// the waiting side doesn't slow down at all. when both sides clock at once each
+// takes the other's TRANSFER as its reply
+pub struct TcpLink {
+    stream: TcpStream,
+    timeout: Duration,
+    // a message that has only partly arrived
+    partial: Vec<u8>,
+    connected: bool,
+}
+
+impl TcpLink {
+    // waits for the other instance to connect
+    pub fn listen(address: impl ToSocketAddrs) -> io::Result<TcpLink> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        TcpLink::from_stream(stream)
+    }
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<TcpLink> {
+        TcpLink::from_stream(TcpStream::connect(address)?)
+    }
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpLink> {
+        // single bytes have to go out straight away
+        stream.set_nodelay(true)?;
+        Ok(TcpLink { stream, timeout: DEFAULT_TIMEOUT, partial: Vec::new(), connected: true })
+    }
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+    // false once the connection has failed, transfers read 0xFF from then on
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+    fn send(&mut self, kind: u8, byte: u8) {
+        if self.stream.write_all(&[kind, byte]).is_err() {
+            self.connected = false;
+        }
+    }
+    // the next whole message, waiting up to `wait` for it (not at all with None)
+    fn receive(&mut self, wait: Option<Duration>) -> Option<(u8, u8)> {
+        let deadline = wait.map(|wait| Instant::now() + wait);
+        while self.connected && self.partial.len() < 2 {
+            let configured = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() { return None }
+                    self.stream.set_nonblocking(false).and_then(|_| self.stream.set_read_timeout(Some(left)))
+                }
+                None => self.stream.set_nonblocking(true),
+            };
+            if configured.is_err() {
+                self.connected = false;
+                return None;
+            }
+            let mut buffer = [0; 2];
+            match self.stream.read(&mut buffer[..2 - self.partial.len()]) {
+                Ok(0) => self.connected = false,
+                Ok(count) => self.partial.extend_from_slice(&buffer[..count]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock && deadline.is_none() => return None,
+                // the deadline check above ends the wait
+                Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(_) => self.connected = false,
+            }
+        }
+        if self.partial.len() < 2 { return None }
+        let message = (self.partial[0], self.partial[1]);
+        self.partial.clear();
+        Some(message)
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.send(TRANSFER, outgoing);
+        match self.receive(Some(self.timeout)) {
+            Some((REPLY | TRANSFER, incoming)) => incoming,
+            _ => DISCONNECTED,
+        }
+    }
+    fn poll(&mut self, outgoing: u8) -> Option<u8> {
+        match self.receive(None)? {
+            (TRANSFER, incoming) => {
+                self.send(REPLY, outgoing);
+                Some(incoming)
+            }
+            // a late reply to a transfer that already timed out
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn bytes_cross_between_two_links() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let waiting = thread::spawn(move || {
+            let mut link = TcpLink::from_stream(listener.accept().unwrap().0).unwrap();
+            loop {
+                if let Some(incoming) = link.poll(0x34) { return incoming }
+                thread::yield_now();
+            }
+        });
+        let mut clocking = TcpLink::connect(address).unwrap();
+        clocking.set_timeout(Duration::from_secs(5));
+        assert_eq!(clocking.exchange(0x12), 0x34);
+        assert_eq!(waiting.join().unwrap(), 0x12);
+        // with the other side gone transfers read nothing
+        assert_eq!(clocking.exchange(0x56), DISCONNECTED);
+        assert!(!clocking.connected());
+    }
+}
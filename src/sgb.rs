@@ -0,0 +1,534 @@
+use crate::color_correction::rgb555_to_rgba;
+use crate::frame::{SCREEN_HEIGHT, SCREEN_PIXELS, SCREEN_WIDTH};
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+// the picture the SNES puts out, border included
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+pub const SGB_FRAME_BYTES: usize = SGB_WIDTH * SGB_HEIGHT * 4;
+// where the game screen sits inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+const PACKET_BYTES: usize = 16;
+const PACKET_BITS: usize = PACKET_BYTES * 8;
+// the palettes cover the screen in 8x8 tiles
+const TILES_WIDE: usize = SCREEN_WIDTH / 8;
+const TILES_HIGH: usize = SCREEN_HEIGHT / 8;
+const ATTRIBUTE_FILES: usize = 45;
+// 2 bits per tile, 4 tiles per byte
+const ATTRIBUTE_FILE_BYTES: usize = TILES_WIDE * TILES_HIGH / 4;
+const SYSTEM_PALETTES: usize = 512;
+// VRAM transfers copy this much of what's on screen, as 2bpp tiles
+const TRANSFER_BYTES: usize = 0x1000;
+const BORDER_TILES: usize = 256;
+// 4bpp SNES tiles
+const BORDER_TILE_BYTES: usize = 32;
+// 32x32 entries are transferred, the picture uses the top 28 rows
+const MAP_WIDTH: usize = 32;
+const BORDER_PALETTES_OFFSET: usize = 0x800;
+// white to black, until the game sends its own
+const DEFAULT_COLORS: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+// MASK_EN, what the game screen shows while the game sets up the next picture
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mask {
+    None,
+    // keep showing the last picture
+    Freeze,
+    Black,
+    // everything in colour 0
+    Color0,
+}
+
+// what the next frame's screen contents get copied into
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Transfer {
+    Palettes,
+    // tiles 0x00-0x7F or 0x80-0xFF
+    BorderTiles(usize),
+    BorderMap,
+    Attributes,
+}
+
+// the Super Game Boy's side of things. the game talks to it by pulsing P14/P15:
+// both low starts a packet, then each of its 128 bits is P15 low for a 0 or P14
+// low for a 1, with both high in between. a command is 1-7 packets, the first
+// byte holding the command number (bits 3-7) and packet count (bits 0-2).
+//
+// the game screen is coloured in with 4 palettes picked per 8x8 tile and framed
+// with a 256x224 border, both of which bigger data sets get to by way of VRAM
+// transfers: the game puts the data on screen and the SGB reads it back
+pub struct Sgb {
+    // packet receiver
+    receiving: bool,
+    bit: usize,
+    packet: [u8; PACKET_BYTES],
+    previous_p1: u8,
+    command: Vec<u8>,
+    // packets left to complete the command in progress
+    remaining_packets: usize,
+    // colour 0 of palette 0 is shared by all of them
+    palettes: [[u16; 4]; 4],
+    attributes: [u8; TILES_WIDE * TILES_HIGH],
+    system_palettes: Vec<[u16; 4]>,
+    attribute_files: Vec<u8>,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u16>,
+    // palettes 4-7, 16 colours each with colour 0 transparent
+    border_palettes: [[u16; 16]; 4],
+    mask: Mask,
+    transfer: Option<Transfer>,
+    // shades of the last frame, or the one frozen by MASK_EN
+    screen: Box<[u8; SCREEN_PIXELS]>,
+    // MLT_REQ, reading P1 with neither group selected gives the current player
+    players: u8,
+    player: u8,
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        Sgb {
+            receiving: false,
+            bit: 0,
+            packet: [0; PACKET_BYTES],
+            previous_p1: 0x30,
+            command: Vec::new(),
+            remaining_packets: 0,
+            palettes: [DEFAULT_COLORS; 4],
+            attributes: [0; TILES_WIDE * TILES_HIGH],
+            system_palettes: vec![DEFAULT_COLORS; SYSTEM_PALETTES],
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_BYTES],
+            border_tiles: vec![0; BORDER_TILES * BORDER_TILE_BYTES],
+            border_map: vec![0; MAP_WIDTH * MAP_WIDTH],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::None,
+            transfer: None,
+            screen: Box::new([0; SCREEN_PIXELS]),
+            players: 1,
+            player: 0,
+        }
+    }
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.bool(self.receiving);
+        state.u8(self.bit as u8);
+        state.bytes(&self.packet);
+        state.u8(self.previous_p1);
+        state.vec(&self.command);
+        state.u8(self.remaining_packets as u8);
+        let colors = self.palettes.iter().flatten()
+            .chain(self.system_palettes.iter().flatten())
+            .chain(self.border_palettes.iter().flatten());
+        for &color in colors {
+            state.u16(color);
+        }
+        state.bytes(&self.attributes);
+        state.bytes(&self.attribute_files);
+        state.bytes(&self.border_tiles);
+        for &entry in &self.border_map {
+            state.u16(entry);
+        }
+        state.u8(self.mask as u8);
+        let transfer = match self.transfer {
+            None => [0, 0],
+            Some(Transfer::Palettes) => [1, 0],
+            Some(Transfer::BorderTiles(half)) => [2, half as u8],
+            Some(Transfer::BorderMap) => [3, 0],
+            Some(Transfer::Attributes) => [4, 0],
+        };
+        state.bytes(&transfer);
+        state.bytes(&self.screen[..]);
+        state.bytes(&[self.players, self.player]);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.receiving = state.bool()?;
+        self.bit = state.u8()? as usize % PACKET_BITS;
+        state.bytes(&mut self.packet)?;
+        self.previous_p1 = state.u8()?;
+        self.command = state.vec()?;
+        self.remaining_packets = state.u8()? as usize;
+        let colors = self.palettes.iter_mut().flatten()
+            .chain(self.system_palettes.iter_mut().flatten())
+            .chain(self.border_palettes.iter_mut().flatten());
+        for color in colors {
+            *color = state.u16()?;
+        }
+        state.bytes(&mut self.attributes)?;
+        state.bytes(&mut self.attribute_files)?;
+        state.bytes(&mut self.border_tiles)?;
+        for entry in self.border_map.iter_mut() {
+            *entry = state.u16()?;
+        }
+        self.mask = match state.u8()? {
+            0 => Mask::None,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(state.invalid("mask")),
+        };
+        self.transfer = match [state.u8()?, state.u8()?] {
+            [0, _] => None,
+            [1, _] => Some(Transfer::Palettes),
+            [2, half] => Some(Transfer::BorderTiles(half as usize & 0x01)),
+            [3, _] => Some(Transfer::BorderMap),
+            [4, _] => Some(Transfer::Attributes),
+            _ => return Err(state.invalid("transfer")),
+        };
+        state.bytes(&mut self.screen[..])?;
+        self.players = state.u8()?.clamp(1, 4);
+        self.player = state.u8()? % self.players;
+        Ok(())
+    }
+    // what the cpu reads from P1, given what the joypad alone would answer
+    pub fn read_p1(&self, value: u8) -> u8 {
+        if self.players > 1 && value & 0x30 == 0x30 {
+            return value & 0xF0 | (0x0F - self.player);
+        }
+        // only player 1 has a controller
+        if self.player != 0 { value | 0x0F } else { value }
+    }
+    pub fn write_p1(&mut self, value: u8) {
+        let lines = value & 0x30;
+        match lines {
+            0x00 => {
+                self.receiving = true;
+                self.bit = 0;
+                self.packet = [0; PACKET_BYTES];
+            }
+            0x10 | 0x20 if self.receiving && self.previous_p1 == 0x30 => {
+                if lines == 0x10 {
+                    self.packet[self.bit / 8] |= 1 << (self.bit % 8);
+                }
+                self.bit += 1;
+                // the stop bit after the last one is ignored
+                if self.bit == PACKET_BITS {
+                    self.receiving = false;
+                    self.packet_received();
+                }
+            }
+            // deselecting after reading the buttons moves on to the next controller
+            0x30 if !self.receiving && self.previous_p1 == 0x10 && self.players > 1 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => {}
+        }
+        self.previous_p1 = lines;
+    }
+    fn packet_received(&mut self) {
+        if self.remaining_packets == 0 {
+            self.command.clear();
+            self.remaining_packets = (self.packet[0] & 0x07).max(1) as usize;
+        }
+        self.command.extend_from_slice(&self.packet);
+        self.remaining_packets -= 1;
+        if self.remaining_packets == 0 {
+            let command = std::mem::take(&mut self.command);
+            self.run_command(&command);
+        }
+    }
+    fn run_command(&mut self, data: &[u8]) {
+        let word = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        match data[0] >> 3 {
+            command @ (PAL01 | PAL23 | PAL03 | PAL12) => {
+                let (first, second) = [(0, 1), (2, 3), (0, 3), (1, 2)][command as usize];
+                for palette in self.palettes.iter_mut() {
+                    palette[0] = word(1);
+                }
+                for color in 1..4 {
+                    self.palettes[first][color] = word(1 + color * 2);
+                    self.palettes[second][color] = word(7 + color * 2);
+                }
+            }
+            ATTR_BLK => {
+                let sets = (data[1] & 0x1F) as usize;
+                for set in data[2..].chunks_exact(6).take(sets) {
+                    self.attribute_block(set);
+                }
+            }
+            ATTR_LIN => {
+                let lines = data[1] as usize;
+                for &line in data[2..].iter().take(lines) {
+                    let (index, palette) = ((line & 0x1F) as usize, line >> 5 & 0x03);
+                    for y in 0..TILES_HIGH {
+                        for x in 0..TILES_WIDE {
+                            let on_line = if line & 0x80 != 0 { y == index } else { x == index };
+                            if on_line {
+                                self.attributes[y * TILES_WIDE + x] = palette;
+                            }
+                        }
+                    }
+                }
+            }
+            ATTR_DIV => {
+                let (settings, divide) = (data[1], data[2] as usize);
+                let horizontal = settings & 0x40 != 0;
+                for y in 0..TILES_HIGH {
+                    for x in 0..TILES_WIDE {
+                        let position = if horizontal { y } else { x };
+                        let shift = match position.cmp(&divide) {
+                            std::cmp::Ordering::Less => 2,
+                            std::cmp::Ordering::Equal => 4,
+                            std::cmp::Ordering::Greater => 0,
+                        };
+                        self.attributes[y * TILES_WIDE + x] = settings >> shift & 0x03;
+                    }
+                }
+            }
+            ATTR_CHR => {
+                let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+                let count = word(3) as usize;
+                let vertical = data[5] & 0x01 != 0;
+                let palettes = data[6..].iter().flat_map(|&byte| [6, 4, 2, 0].map(|shift| byte >> shift & 0x03));
+                for palette in palettes.take(count) {
+                    if x >= TILES_WIDE || y >= TILES_HIGH { break }
+                    self.attributes[y * TILES_WIDE + x] = palette;
+                    if vertical {
+                        y += 1;
+                        if y == TILES_HIGH { y = 0; x += 1 }
+                    } else {
+                        x += 1;
+                        if x == TILES_WIDE { x = 0; y += 1 }
+                    }
+                }
+            }
+            PAL_SET => {
+                for (index, palette) in self.palettes.iter_mut().enumerate() {
+                    *palette = self.system_palettes[word(1 + index * 2) as usize % SYSTEM_PALETTES];
+                }
+                let flags = data[9];
+                if flags & 0x80 != 0 {
+                    self.apply_attribute_file(flags & 0x3F);
+                }
+                if flags & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            ATTR_SET => {
+                self.apply_attribute_file(data[1] & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    0 => Mask::None,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                };
+            }
+            MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            PAL_TRN => self.transfer = Some(Transfer::Palettes),
+            CHR_TRN => self.transfer = Some(Transfer::BorderTiles((data[1] & 0x01) as usize)),
+            PCT_TRN => self.transfer = Some(Transfer::BorderMap),
+            ATTR_TRN => self.transfer = Some(Transfer::Attributes),
+            // sound, SNES code uploads and the rest have nothing to show
+            _ => {}
+        }
+    }
+    // one ATTR_BLK data set: which parts to colour, their palettes and the rectangle
+    fn attribute_block(&mut self, set: &[u8]) {
+        let (control, palettes) = (set[0] & 0x07, set[1]);
+        let (left, top, right, bottom) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+        let inside = palettes & 0x03;
+        let outside = palettes >> 4 & 0x03;
+        // with only the inside or the outside asked for, the border goes with it
+        let border = match control {
+            0x01 => Some(inside),
+            0x04 => Some(outside),
+            _ => (control & 0x02 != 0).then_some(palettes >> 2 & 0x03),
+        };
+        for y in 0..TILES_HIGH {
+            for x in 0..TILES_WIDE {
+                let within = (left..=right).contains(&x) && (top..=bottom).contains(&y);
+                let on_edge = within && (x == left || x == right || y == top || y == bottom);
+                let palette = if on_edge { border }
+                    else if within { (control & 0x01 != 0).then_some(inside) }
+                    else { (control & 0x04 != 0).then_some(outside) };
+                if let Some(palette) = palette {
+                    self.attributes[y * TILES_WIDE + x] = palette;
+                }
+            }
+        }
+    }
+    fn apply_attribute_file(&mut self, file: u8) {
+        let file = file as usize;
+        if file >= ATTRIBUTE_FILES { return }
+        let bytes = &self.attribute_files[file * ATTRIBUTE_FILE_BYTES..][..ATTRIBUTE_FILE_BYTES];
+        for (index, attribute) in self.attributes.iter_mut().enumerate() {
+            *attribute = bytes[index / 4] >> (6 - index % 4 * 2) & 0x03;
+        }
+    }
+    // the LCD finished a frame, given as shades. a pending VRAM transfer reads it
+    pub fn frame_finished(&mut self, shades: &[u8; SCREEN_PIXELS]) {
+        if let Some(transfer) = self.transfer.take() {
+            let data = transfer_data(shades);
+            match transfer {
+                Transfer::Palettes => {
+                    for (palette, colors) in self.system_palettes.iter_mut().zip(data.chunks_exact(8)) {
+                        *palette = [0, 1, 2, 3].map(|color| u16::from_le_bytes([colors[color * 2], colors[color * 2 + 1]]));
+                    }
+                }
+                Transfer::BorderTiles(half) => {
+                    self.border_tiles[half * TRANSFER_BYTES..][..TRANSFER_BYTES].copy_from_slice(&data);
+                }
+                Transfer::BorderMap => {
+                    for (entry, bytes) in self.border_map.iter_mut().zip(data[..BORDER_PALETTES_OFFSET].chunks_exact(2)) {
+                        *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+                    }
+                    let colors = data[BORDER_PALETTES_OFFSET..].chunks_exact(2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+                    for (index, color) in colors.take(4 * 16).enumerate() {
+                        self.border_palettes[index / 16][index % 16] = color;
+                    }
+                }
+                Transfer::Attributes => {
+                    self.attribute_files.copy_from_slice(&data[..ATTRIBUTE_FILES * ATTRIBUTE_FILE_BYTES]);
+                }
+            }
+        }
+        if self.mask != Mask::Freeze {
+            self.screen.copy_from_slice(shades);
+        }
+    }
+    // the full SNES picture as RGBA: border, with the coloured game screen in the middle
+    pub fn render(&self) -> Vec<u8> {
+        let mut pixels = vec![0; SGB_FRAME_BYTES];
+        let backdrop = rgb555_to_rgba(self.palettes[0][0]);
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&backdrop);
+        }
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let shade = self.screen[y * SCREEN_WIDTH + x] as usize;
+                let palette = self.attributes[y / 8 * TILES_WIDE + x / 8] as usize;
+                let color = match self.mask {
+                    Mask::Black => 0x0000,
+                    Mask::Color0 => self.palettes[0][0],
+                    _ if shade == 0 => self.palettes[0][0],
+                    _ => self.palettes[palette][shade],
+                };
+                let offset = ((SCREEN_Y + y) * SGB_WIDTH + SCREEN_X + x) * 4;
+                pixels[offset..offset + 4].copy_from_slice(&rgb555_to_rgba(color));
+            }
+        }
+        // the border goes over the game screen, colour 0 is see through
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let entry = self.border_map[y / 8 * MAP_WIDTH + x / 8];
+                let tile = (entry & 0xFF) as usize * BORDER_TILE_BYTES;
+                let row = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+                let column = if entry & 0x4000 != 0 { 7 - x % 8 } else { x % 8 };
+                let bit = 7 - column;
+                let planes = [tile + row * 2, tile + row * 2 + 1, tile + 16 + row * 2, tile + 16 + row * 2 + 1];
+                let color = planes.iter().enumerate()
+                    .fold(0, |color, (plane, &address)| color | (self.border_tiles[address] >> bit & 1) << plane);
+                if color == 0 { continue }
+                let palette = (entry >> 10 & 0x03) as usize;
+                let offset = (y * SGB_WIDTH + x) * 4;
+                pixels[offset..offset + 4].copy_from_slice(&rgb555_to_rgba(self.border_palettes[palette][color as usize]));
+            }
+        }
+        pixels
+    }
+}
+
+// the screen read back the way the SGB sees a VRAM transfer: 2bpp tiles, 20 to a row
+fn transfer_data(shades: &[u8; SCREEN_PIXELS]) -> Vec<u8> {
+    let mut data = vec![0; TRANSFER_BYTES];
+    for (tile, bytes) in data.chunks_exact_mut(16).enumerate() {
+        let (tile_x, tile_y) = (tile % TILES_WIDE * 8, tile / TILES_WIDE * 8);
+        for row in 0..8 {
+            for column in 0..8 {
+                let shade = shades[(tile_y + row) * SCREEN_WIDTH + tile_x + column];
+                let bit = 7 - column;
+                bytes[row * 2] |= (shade & 1) << bit;
+                bytes[row * 2 + 1] |= (shade >> 1 & 1) << bit;
+            }
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(sgb: &mut Sgb, packet: [u8; PACKET_BYTES]) {
+        sgb.write_p1(0x00);
+        sgb.write_p1(0x30);
+        for bit in 0..PACKET_BITS {
+            let one = packet[bit / 8] >> (bit % 8) & 1 != 0;
+            sgb.write_p1(if one { 0x10 } else { 0x20 });
+            sgb.write_p1(0x30);
+        }
+        sgb.write_p1(0x20);
+        sgb.write_p1(0x30);
+    }
+
+    #[test]
+    fn packets_colour_the_screen() {
+        let mut sgb = Sgb::new();
+        // PAL01: shared colour 0 red, palette 1 colour 3 blue
+        let mut packet = [0; PACKET_BYTES];
+        packet[0] = PAL01 << 3 | 1;
+        packet[1..3].copy_from_slice(&0x001Fu16.to_le_bytes());
+        packet[13..15].copy_from_slice(&0x7C00u16.to_le_bytes());
+        send(&mut sgb, packet);
+        // ATTR_DIV: palette 1 right of column 10, palette 0 left and on it
+        let mut packet = [0; PACKET_BYTES];
+        packet[0] = ATTR_DIV << 3 | 1;
+        packet[1] = 0x01;
+        packet[2] = 10;
+        send(&mut sgb, packet);
+        let mut shades = [3; SCREEN_PIXELS];
+        shades[0] = 0;
+        sgb.frame_finished(&shades);
+        let frame = sgb.render();
+        let pixel = |x: usize, y: usize| {
+            let offset = ((SCREEN_Y + y) * SGB_WIDTH + SCREEN_X + x) * 4;
+            [frame[offset], frame[offset + 1], frame[offset + 2]]
+        };
+        assert_eq!(pixel(0, 0), [0xFF, 0, 0]);
+        assert_eq!(pixel(1, 0), [0, 0, 0]);
+        assert_eq!(pixel(100, 50), [0, 0, 0xFF]);
+        // the empty border shows the backdrop
+        assert_eq!(frame[..3], [0xFF, 0, 0]);
+        // MLT_REQ with two players
+        let mut packet = [0; PACKET_BYTES];
+        packet[0] = MLT_REQ << 3 | 1;
+        packet[1] = 0x01;
+        send(&mut sgb, packet);
+        assert_eq!(sgb.read_p1(0xFF), 0xFF);
+        sgb.write_p1(0x10);
+        sgb.write_p1(0x30);
+        assert_eq!(sgb.read_p1(0xFF), 0xFE);
+        assert_eq!(sgb.read_p1(0xE7), 0xEF);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::address::BankedAddress;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SymbolError(pub String);
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+// the part of the memory map an address is in, labels don't reach past one
+fn region(address: u16) -> u8 {
+    match address {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xCFFF => 4,
+        0xD000..=0xDFFF => 5,
+        _ => 6,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub bank: usize,
+    pub address: u16,
+}
+
+impl Symbol {
+    // whether this is the symbol for an address with `bank` mapped there. bank 0
+    // is what rgblink gives everything outside the switchable areas, so it's
+    // taken to be in any bank
+    pub fn matches(&self, address: u16, bank: Option<usize>) -> bool {
+        self.address == address && self.in_bank(bank)
+    }
+    fn in_bank(&self, bank: Option<usize>) -> bool {
+        self.bank == 0 || bank.is_none_or(|bank| bank == self.bank)
+    }
+    // where the symbol is. bank 0 is left off, being anywhere (see matches)
+    pub fn location(&self) -> BankedAddress {
+        match self.bank {
+            0 => BankedAddress::new(self.address),
+            bank => BankedAddress::banked(bank, self.address),
+        }
+    }
+    // local labels (Main.loop) belong to the routine before them
+    pub fn is_local(&self) -> bool {
+        self.name.contains('.')
+    }
+}
+
+// labels from an RGBDS symbol file, one per line as bank:address and name:
+//   ; File generated by rgblink
+//   00:0150 Main
+//   00:0153 Main.loop
+//   01:4000 LoadTiles
+pub struct Symbols {
+    // in file order, so the first of several labels at an address wins
+    symbols: Vec<Symbol>,
+    by_name: HashMap<String, usize>,
+    by_address: HashMap<u16, Vec<usize>>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols { symbols: Vec::new(), by_name: HashMap::new(), by_address: HashMap::new() }
+    }
+    pub fn parse(text: &str) -> Result<Symbols, SymbolError> {
+        let mut symbols = Symbols::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() { continue }
+            let bad = || SymbolError(format!("line {}: expected bank:address name", index + 1));
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(bad)?;
+            let (bank, address) = location.split_once(':').ok_or_else(bad)?;
+            let bank = usize::from_str_radix(bank, 16).map_err(|_| bad())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| bad())?;
+            symbols.add(Symbol { name: name.trim().to_string(), bank, address });
+        }
+        Ok(symbols)
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Symbols, SymbolError> {
+        let text = fs::read_to_string(path).map_err(|error| SymbolError(error.to_string()))?;
+        Symbols::parse(&text)
+    }
+    pub fn add(&mut self, symbol: Symbol) {
+        let index = self.symbols.len();
+        self.by_name.entry(symbol.name.clone()).or_insert(index);
+        self.by_address.entry(symbol.address).or_default().push(index);
+        self.symbols.push(symbol);
+    }
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name).map(|&index| &self.symbols[index])
+    }
+    // a label, or bank:address as BankedAddress::parse takes it
+    pub fn resolve(&self, text: &str) -> Option<BankedAddress> {
+        self.lookup(text).map(Symbol::location).or_else(|| BankedAddress::parse(text))
+    }
+    // the routine an address is in: the closest global label at or before it
+    // in the same bank and part of the memory map
+    pub fn routine(&self, address: u16, bank: Option<usize>) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .filter(|symbol| !symbol.is_local() && symbol.in_bank(bank))
+            .filter(|symbol| symbol.address <= address && region(symbol.address) == region(address))
+            .max_by_key(|symbol| symbol.address)
+    }
+    // the label at an address, with `bank` mapped there if that's known
+    pub fn label(&self, address: u16, bank: Option<usize>) -> Option<&str> {
+        let indices = self.by_address.get(&address)?;
+        indices
+            .iter()
+            .map(|&index| &self.symbols[index])
+            .find(|symbol| symbol.matches(address, bank))
+            .map(|symbol| symbol.name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_files_give_labels_by_bank() {
+        let symbols = Symbols::parse(
+            "; File generated by rgblink\n\
+             00:0150 Main\n\
+             00:0153 Main.loop ; a comment\n\
+             01:4000 LoadTiles\n\
+             02:4000 PlayMusic\n\
+             00:C000 wScore\n",
+        )
+        .unwrap();
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(symbols.lookup("Main.loop"), Some(&Symbol { name: "Main.loop".into(), bank: 0, address: 0x0153 }));
+        assert_eq!(symbols.label(0x4000, Some(2)), Some("PlayMusic"));
+        assert_eq!(symbols.label(0x4000, Some(3)), None);
+        assert_eq!(symbols.label(0x4000, None), Some("LoadTiles"));
+        assert_eq!(symbols.label(0xC000, None), Some("wScore"));
+        assert_eq!(symbols.routine(0x0160, None).map(|symbol| symbol.name.as_str()), Some("Main"));
+        assert_eq!(symbols.routine(0x4010, Some(2)).map(|symbol| symbol.name.as_str()), Some("PlayMusic"));
+        assert_eq!(symbols.routine(0x0100, None), None);
+        assert_eq!(symbols.resolve("PlayMusic"), Some(BankedAddress::banked(2, 0x4000)));
+        assert_eq!(symbols.resolve("Main"), Some(BankedAddress::new(0x0150)));
+        assert_eq!(symbols.resolve("03:4100"), Some(BankedAddress::banked(3, 0x4100)));
+        assert_eq!(Symbols::parse("0150 Main\n").err(), Some(SymbolError("line 1: expected bank:address name".into())));
+    }
+}
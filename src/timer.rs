@@ -0,0 +1,262 @@
+use crate::config::Accuracy;
+use crate::cpu::TIMER_INTERRUPT;
+use crate::savestate::{StateDecoder, StateEncoder, StateError};
+
+pub const DIV: u16 = 0xFF04;
+pub const TIMA: u16 = 0xFF05;
+pub const TMA: u16 = 0xFF06;
+pub const TAC: u16 = 0xFF07;
+
+// the divider bit whose period each TAC clock select gives TIMA:
+// 4096 Hz, 262144 Hz, 65536 Hz and 16384 Hz
+const TAC_BITS: [u32; 4] = [9, 3, 5, 7];
+// the cycle accurate timer works a machine cycle at a time, the cpu only ticks whole ones
+const M_CYCLE: u32 = 4;
+
+// what happens after TIMA overflows. it reads 0 for a machine cycle before TMA is
+// loaded, and in the cycle of the reload TIMA ignores writes
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+enum Overflow {
+    #[default]
+    None,
+    Pending,
+    Reloaded,
+}
+
+// DIV is the upper byte of a 16 bit counter that goes up every clock cycle.
+// while TAC bit 2 is set TIMA counts at the selected rate, and when it overflows
+// it's reloaded from TMA and the timer interrupt is requested.
+//
+// on hardware TIMA is clocked by a falling edge detector on (enable AND the
+// selected divider bit), so anything that drops that signal counts too: writing
+// DIV with the bit set, or changing TAC. the cycle accurate preset models that
+// and the delayed reload, the fast preset counts whole periods
+#[derive(Default)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    overflow: Overflow,
+    accuracy: Accuracy,
+    // interrupts requested since the bus last collected them, as IF bits
+    interrupts: u8,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer::default()
+    }
+    pub fn reset(&mut self) {
+        *self = Timer { accuracy: self.accuracy, ..Timer::default() };
+    }
+    pub fn save_state(&self, state: &mut StateEncoder) {
+        state.u16(self.counter);
+        state.bytes(&[self.tima, self.tma, self.tac, self.overflow as u8, self.interrupts]);
+    }
+    pub fn load_state(&mut self, state: &mut StateDecoder) -> Result<(), StateError> {
+        self.counter = state.u16()?;
+        self.tima = state.u8()?;
+        self.tma = state.u8()?;
+        self.tac = state.u8()? & 0x07;
+        self.overflow = match state.u8()? {
+            0 => Overflow::None,
+            1 => Overflow::Pending,
+            2 => Overflow::Reloaded,
+            _ => return Err(state.invalid("timer overflow")),
+        };
+        self.interrupts = state.u8()?;
+        Ok(())
+    }
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy;
+    }
+    // for starting where a boot rom would have left the counter, DIV writes only clear it
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+    fn cycle_accurate(&self) -> bool {
+        self.accuracy == Accuracy::CycleAccurate
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        if !self.cycle_accurate() {
+            match address {
+                // any write clears the whole counter
+                DIV => self.counter = 0,
+                TIMA => self.tima = value,
+                TMA => self.tma = value,
+                TAC => self.tac = value & 0x07,
+                _ => {}
+            }
+            return;
+        }
+        let signal = self.signal();
+        match address {
+            DIV => self.counter = 0,
+            // a write while the reload is pending cancels it, in the reload cycle it's lost
+            TIMA => match self.overflow {
+                Overflow::Reloaded => {}
+                _ => {
+                    self.tima = value;
+                    self.overflow = Overflow::None;
+                }
+            },
+            TMA => {
+                self.tma = value;
+                // the reload cycle copies TMA as it's being written
+                if self.overflow == Overflow::Reloaded {
+                    self.tima = value;
+                }
+            }
+            TAC => self.tac = value & 0x07,
+            _ => {}
+        }
+        if signal && !self.signal() {
+            self.increment_tima();
+        }
+    }
+    fn enabled(&self) -> bool {
+        self.tac & 0x04 != 0
+    }
+    // the input of the falling edge detector
+    fn signal(&self) -> bool {
+        self.enabled() && self.counter >> TAC_BITS[(self.tac & 0x03) as usize] & 1 != 0
+    }
+    pub fn tick(&mut self, cycles: u32) {
+        if self.cycle_accurate() {
+            for _ in 0..cycles / M_CYCLE {
+                self.step();
+            }
+            return;
+        }
+        let old = self.counter as u32;
+        let new = old + cycles;
+        self.counter = new as u16;
+        if !self.enabled() { return }
+        // TIMA counts every time the selected bit goes from 1 to 0
+        let period_shift = TAC_BITS[(self.tac & 0x03) as usize] + 1;
+        let increments = (new >> period_shift) - (old >> period_shift);
+        for _ in 0..increments {
+            self.increment_tima();
+        }
+    }
+    // one machine cycle of the cycle accurate timer
+    fn step(&mut self) {
+        self.overflow = match self.overflow {
+            Overflow::Pending => {
+                self.tima = self.tma;
+                self.interrupts |= TIMER_INTERRUPT;
+                Overflow::Reloaded
+            }
+            _ => Overflow::None,
+        };
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(M_CYCLE as u16);
+        if signal && !self.signal() {
+            self.increment_tima();
+        }
+    }
+    fn increment_tima(&mut self) {
+        let (tima, overflowed) = self.tima.overflowing_add(1);
+        if !overflowed {
+            self.tima = tima;
+        } else if self.cycle_accurate() {
+            self.tima = 0;
+            self.overflow = Overflow::Pending;
+        } else {
+            self.tima = self.tma;
+            self.interrupts |= TIMER_INTERRUPT;
+        }
+    }
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tima_counts_at_the_selected_rate_and_reloads_from_tma() {
+        let mut timer = Timer::new();
+        timer.set_accuracy(Accuracy::Fast);
+        timer.tick(256 * 3);
+        assert_eq!(timer.read(DIV), 3);
+        assert_eq!(timer.read(TIMA), 0);
+        // 262144 Hz, every 16 cycles
+        timer.write(TMA, 0xF0);
+        timer.write(TIMA, 0xFE);
+        timer.write(TAC, 0x05);
+        timer.tick(16);
+        assert_eq!(timer.read(TIMA), 0xFF);
+        assert_eq!(timer.take_interrupts(), 0);
+        timer.tick(16);
+        assert_eq!(timer.read(TIMA), 0xF0);
+        assert_eq!(timer.take_interrupts(), TIMER_INTERRUPT);
+        // 4096 Hz, every 1024 cycles. writing DIV restarts the count
+        timer.write(TAC, 0x04);
+        timer.write(DIV, 0x12);
+        timer.tick(1023);
+        assert_eq!(timer.read(TIMA), 0xF0);
+        timer.tick(1);
+        assert_eq!(timer.read(TIMA), 0xF1);
+        assert_eq!(timer.read(TAC), 0xFC);
+    }
+
+    #[test]
+    fn falling_edges_from_div_and_tac_writes_count() {
+        let mut timer = Timer::new();
+        // 262144 Hz watches bit 3, which is set 8 cycles in
+        timer.write(TAC, 0x05);
+        timer.tick(8);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+        // and switching to a rate whose bit is clear drops the signal too
+        timer.tick(8);
+        timer.write(TAC, 0x04);
+        assert_eq!(timer.read(TIMA), 2);
+        // nothing like that with the fast preset
+        timer.set_accuracy(Accuracy::Fast);
+        timer.write(TAC, 0x05);
+        timer.write(DIV, 0);
+        timer.tick(8);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 2);
+    }
+
+    #[test]
+    fn overflow_reloads_a_cycle_late_and_can_be_cancelled() {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0x80);
+        timer.write(TIMA, 0xFF);
+        timer.write(TAC, 0x05);
+        timer.tick(16);
+        assert_eq!(timer.read(TIMA), 0x00);
+        assert_eq!(timer.take_interrupts(), 0);
+        timer.tick(4);
+        assert_eq!(timer.read(TIMA), 0x80);
+        assert_eq!(timer.take_interrupts(), TIMER_INTERRUPT);
+        // writes in the reload cycle are lost, except through TMA
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.read(TIMA), 0x80);
+        timer.write(TMA, 0x90);
+        assert_eq!(timer.read(TIMA), 0x90);
+        // a TIMA write before the reload cancels it and the interrupt
+        timer.write(TIMA, 0xFF);
+        timer.tick(32);
+        timer.write(TIMA, 0x42);
+        timer.tick(4);
+        assert_eq!(timer.read(TIMA), 0x42);
+        assert_eq!(timer.take_interrupts(), 0);
+    }
+}
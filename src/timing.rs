@@ -0,0 +1,54 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// clock cycles (T-cycles, 4 per machine cycle) per instruction. conditional
+// instructions list their not-taken time, see BRANCH_TAKEN_EXTRA for the rest.
+// the 0 entries are opcodes that don't exist on the SM83
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+const CYCLES: [u8; 256] = [
+    4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4,
+    4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4,
+    8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+    8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+    8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4,
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+    8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  4, 12, 24,  8, 16,
+    8, 12, 12,  0, 12, 16,  8, 16,  8, 16, 12,  0, 12,  0,  8, 16,
+   12, 12,  8,  0,  0, 16,  8, 16, 16,  4, 16,  0,  0,  0,  8, 16,
+   12, 12,  8,  4,  0, 16,  8, 16, 12,  8, 16,  4,  0,  0,  8, 16,
+];
+
+// opcode cycles, including the CB prefix fetch for prefixed instructions
+pub fn instruction_cycles(opcode: u8, prefixed: bool) -> u8 {
+    if !prefixed { return CYCLES[opcode as usize] }
+    // (HL) operands take extra memory accesses, BIT only reads it back
+    match (opcode & 0x07, opcode & 0xC0) {
+        (6, 0x40) => 12,
+        (6, _) => 16,
+        _ => 8,
+    }
+}
+
+// extra cycles a conditional jump, call or return costs when its condition holds
+pub fn branch_taken_extra(opcode: u8) -> u8 {
+    match opcode {
+        0x20 | 0x28 | 0x30 | 0x38 => 4,   // JR cc
+        0xC2 | 0xCA | 0xD2 | 0xDA => 4,   // JP cc
+        0xC4 | 0xCC | 0xD4 | 0xDC => 12,  // CALL cc
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => 12,  // RET cc
+        _ => 0,
+    }
+}
+
+// the host's time in seconds since the unix epoch. the browser has no clock std
+// can reach, so there it's always 0 and real time clocks only run emulated
+pub fn unix_now() -> u64 {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) { return 0 }
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
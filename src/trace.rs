@@ -0,0 +1,190 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::{Bus, CpuSnapshot};
+use crate::debugger::Breakpoint;
+use crate::disassembler::disassemble;
+use crate::mmu::Mmu;
+use crate::registers::Registers;
+use crate::symbols::Symbols;
+
+// writes one line per instruction in the format used by Game Boy Doctor
+// (https://github.com/robert/gameboy-doctor) so logs can be diffed against reference traces:
+// A:00 F:11 B:22 C:33 D:44 E:55 H:66 L:77 SP:8888 PC:9999 PCMEM:AA,BB,CC,DD
+pub struct DoctorTracer {
+    writer: Box<dyn Write>,
+}
+
+impl DoctorTracer {
+    pub fn new<W: Write + 'static>(writer: W) -> DoctorTracer {
+        DoctorTracer { writer: Box::new(writer) }
+    }
+    // pcmem holds the 4 bytes starting at pc, before the instruction executes
+    pub fn trace(&mut self, registers: &Registers, sp: u16, pc: u16, pcmem: [u8; 4]) -> io::Result<()> {
+        let f: u8 = registers.f.into();
+        writeln!(
+            self.writer,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            registers.a, f, registers.b, registers.c, registers.d, registers.e, registers.h, registers.l,
+            sp, pc, pcmem[0], pcmem[1], pcmem[2], pcmem[3],
+        )
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// a readable log for diffing runs against other emulators, one line per
+// instruction before it runs:
+//       1234567 01:4003  CD 50 01  call Init           A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE PC:4003
+// that's clock cycles so far, bank:address (just the address where nothing is
+// banked), the instruction and the registers, with labels from the debugger's
+// symbols on lines of their own. everything is traced unless narrowed down to
+// address ranges, banks, or what runs between a start and a stop breakpoint.
+// attached with Debugger::set_trace_log
+pub struct TraceLog {
+    writer: Box<dyn Write>,
+    ranges: Vec<RangeInclusive<u16>>,
+    banks: Vec<usize>,
+    start: Option<Breakpoint>,
+    stop: Option<Breakpoint>,
+    // between start and stop
+    triggered: bool,
+    // keeps only the last lines, written out by finish()
+    ring: Option<(usize, VecDeque<String>)>,
+}
+
+impl TraceLog {
+    pub fn new<W: Write + 'static>(writer: W) -> TraceLog {
+        TraceLog {
+            writer: Box::new(writer),
+            ranges: Vec::new(),
+            banks: Vec::new(),
+            start: None,
+            stop: None,
+            triggered: false,
+            ring: None,
+        }
+    }
+    // only instructions in one of the ranges added
+    pub fn add_range(&mut self, range: RangeInclusive<u16>) {
+        self.ranges.push(range);
+    }
+    // only instructions in one of the banks added, see Bus::bank_at
+    pub fn add_bank(&mut self, bank: usize) {
+        self.banks.push(bank);
+    }
+    // nothing until `start` is reached, then up to and including `stop`. it
+    // starts again the next time `start` comes round
+    pub fn set_triggers(&mut self, start: Breakpoint, stop: Option<Breakpoint>) {
+        self.start = Some(start);
+        self.stop = stop;
+        self.triggered = false;
+    }
+    // hold on to the last `lines` lines instead of writing everything, for
+    // finding out what led up to a crash without a huge file
+    pub fn set_ring_buffer(&mut self, lines: usize) {
+        self.ring = Some((lines, VecDeque::with_capacity(lines)));
+    }
+    fn wanted(&mut self, pc: u16, bank: Option<usize>) -> bool {
+        if let Some(start) = self.start {
+            if start.matches(pc, bank) {
+                self.triggered = true;
+            } else if !self.triggered {
+                return false;
+            }
+            if self.stop.is_some_and(|stop| stop.matches(pc, bank)) {
+                self.triggered = false;
+            }
+        }
+        let in_range = self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc));
+        let in_bank = self.banks.is_empty() || bank.is_some_and(|bank| self.banks.contains(&bank));
+        in_range && in_bank
+    }
+    pub fn trace(&mut self, cpu: &CpuSnapshot, mmu: &Mmu, symbols: &Symbols) -> io::Result<()> {
+        if !self.wanted(cpu.pc, mmu.bank_at(cpu.pc)) { return Ok(()) }
+        let instruction = disassemble(mmu, cpu.pc, Some(symbols));
+        let line = format!(
+            "{:>12} {:>7}  {:<9} {:<20} {}",
+            mmu.cycles(),
+            instruction.location(),
+            instruction.hex(),
+            instruction.text,
+            cpu
+        );
+        if let Some(label) = instruction.label {
+            self.write(format!("{}:", label))?;
+        }
+        self.write(line)
+    }
+    fn write(&mut self, line: String) -> io::Result<()> {
+        match &mut self.ring {
+            Some((capacity, lines)) => {
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                if *capacity > 0 {
+                    lines.push_back(line);
+                }
+                Ok(())
+            }
+            None => writeln!(self.writer, "{}", line),
+        }
+    }
+    // writes out what the ring buffer holds, and flushes
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some((_, lines)) = self.ring.take() {
+            for line in lines {
+                writeln!(self.writer, "{}", line)?;
+            }
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+    use crate::gameboy::GameBoy;
+
+    fn traced(configure: impl FnOnce(&mut TraceLog), name: &str) -> Vec<String> {
+        // ld b, 3; dec b; jr nz, -3; ld a, 0x42; jr -2
+        let rom = test_rom(&[0x06, 0x03, 0x05, 0x20, 0xFD, 0x3E, 0x42, 0x18, 0xFE], 0x00, 0);
+        let mut gameboy = GameBoy::new(rom).unwrap();
+        let path = std::env::temp_dir().join(format!("gb-emulator-trace-{}-{}.txt", name, std::process::id()));
+        let mut log = TraceLog::new(std::fs::File::create(&path).unwrap());
+        configure(&mut log);
+        let debugger = gameboy.attach_debugger();
+        debugger.set_symbols(Symbols::parse("00:0102 Countdown\n").unwrap());
+        debugger.set_trace_log(Some(log));
+        gameboy.run_cycles(120);
+        gameboy.attach_debugger().set_trace_log(None).unwrap().finish().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn trace_logs_narrow_down_to_what_matters() {
+        let all = traced(|_| {}, "all");
+        assert!(all[0].ends_with(" 00:0100  06 03     ld b, $03            A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE PC:0100"));
+        assert_eq!(all[1], "Countdown:");
+        assert!(all[2].contains("00:0102  05        dec b"));
+        // the loop runs three times, each with its label line
+        assert_eq!(all.iter().filter(|line| line.contains("jr nz, Countdown")).count(), 3);
+        let ranged = traced(|log| log.add_range(0x0105..=0x0108), "range");
+        assert!(ranged[0].contains("ld a, $42"));
+        assert!(ranged.iter().all(|line| !line.contains("dec b")));
+        // the jr at the stop trigger loops to itself, only its first run is traced
+        let triggered = traced(|log| log.set_triggers(Breakpoint::new(0x0105), Some(Breakpoint::new(0x0107))), "triggers");
+        assert_eq!(triggered.len(), 2);
+        assert!(triggered[0].contains("ld a, $42"));
+        assert!(triggered[1].contains("jr $0107"));
+        let last = traced(|log| log.set_ring_buffer(2), "ring");
+        assert_eq!(last.len(), 2);
+        assert_eq!(last[1].split_whitespace().nth(1), all.last().unwrap().split_whitespace().nth(1));
+        assert!(traced(|log| log.add_bank(1), "bank").is_empty());
+    }
+}
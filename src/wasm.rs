@@ -0,0 +1,95 @@
+use wasm_bindgen::prelude::*;
+
+use crate::cartridge::Cartridge;
+use crate::frontend::{Action, Frontend};
+use crate::gameboy::GameBoy;
+use crate::joypad::Button;
+
+// the browser's KeyboardEvent.key names, on top of the usual bindings
+const BROWSER_KEYS: [(&str, Button); 6] = [
+    ("arrowup", Button::Up),
+    ("arrowdown", Button::Down),
+    ("arrowleft", Button::Left),
+    ("arrowright", Button::Right),
+    ("enter", Button::Start),
+    ("backspace", Button::Select),
+];
+
+// the emulator as javascript sees it. the page calls tick() once per animation
+// frame, draws framebuffer() into a 160x144 canvas and plays audio()
+#[wasm_bindgen]
+pub struct WebGameBoy {
+    frontend: Frontend,
+}
+
+#[wasm_bindgen]
+impl WebGameBoy {
+    // sample_rate is the AudioContext's, 0 for no sound
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: Vec<u8>, sample_rate: u32) -> Result<WebGameBoy, JsValue> {
+        let error = |error: &dyn std::fmt::Display| JsValue::from_str(&error.to_string());
+        let mut cartridge = Cartridge::from_bytes(rom).map_err(|e| error(&e))?;
+        // there's no host clock to follow in here
+        cartridge.set_emulated_clock(true);
+        let gameboy = GameBoy::with_cartridge(cartridge).build().map_err(|e| error(&e))?;
+        let mut frontend = Frontend::new(gameboy);
+        for (key, button) in BROWSER_KEYS {
+            frontend.keymap_mut().bind(key, Action::Button(button));
+        }
+        frontend.set_sample_rate((sample_rate > 0).then_some(sample_rate));
+        Ok(WebGameBoy { frontend })
+    }
+    // run one frame
+    pub fn tick(&mut self) {
+        self.frontend.run_frame();
+    }
+    // RGBA, row by row
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.frontend.gameboy().frame().to_vec()
+    }
+    // interleaved stereo samples made since the last call
+    pub fn audio(&mut self) -> Vec<f32> {
+        self.frontend.take_audio()
+    }
+    // takes KeyboardEvent.key, true if the key is bound so the page can
+    // preventDefault() it
+    pub fn key_down(&mut self, key: &str) -> bool {
+        self.key(key, true)
+    }
+    pub fn key_up(&mut self, key: &str) -> bool {
+        self.key(key, false)
+    }
+    fn key(&mut self, key: &str, pressed: bool) -> bool {
+        let bound = self.frontend.keymap().action(key).is_some();
+        self.frontend.key(key, pressed);
+        bound
+    }
+    // battery ram for the page to keep in local storage
+    pub fn save_ram(&self) -> Vec<u8> {
+        self.frontend.gameboy().save_ram()
+    }
+    pub fn load_ram(&mut self, data: &[u8]) {
+        self.frontend.gameboy_mut().load_ram(data);
+    }
+    // 1 is real time, Infinity as fast as the page can go. tick() keeps being
+    // called once per frame either way
+    pub fn set_speed(&mut self, speed: f32) {
+        self.frontend.gameboy_mut().set_speed(speed);
+    }
+    // tick() shows the same frame while paused, advance_frame() steps one
+    pub fn pause(&mut self) {
+        self.frontend.gameboy_mut().pause();
+    }
+    pub fn resume(&mut self) {
+        self.frontend.gameboy_mut().resume();
+    }
+    pub fn paused(&self) -> bool {
+        self.frontend.gameboy().paused()
+    }
+    pub fn advance_frame(&mut self) {
+        self.frontend.handle(Action::FrameAdvance, true);
+    }
+    pub fn reset(&mut self) {
+        self.frontend.gameboy_mut().reset();
+    }
+}
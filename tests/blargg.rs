@@ -0,0 +1,79 @@
+// runs Blargg's test roms (https://github.com/retrio/gb-test-roms) headlessly and
+// checks what they print over the link cable. each rom reports "Passed" or
+// "Failed" there once it's done, the same text it puts on screen.
+//
+// the roms aren't vendored, point BLARGG_ROMS_DIR at a checkout of the repo:
+//     BLARGG_ROMS_DIR=../gb-test-roms cargo test --release --test blargg
+// without it the suite is skipped, and so is any rom missing from the checkout.
+
+use std::path::Path;
+
+use gb_emulator::cartridge::Cartridge;
+use gb_emulator::reset::DEFAULT_RAM_SEED;
+use gb_emulator::GameBoy;
+
+const ROMS_DIR_VAR: &str = "BLARGG_ROMS_DIR";
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+// each rom with how long it gets to finish, in emulated seconds. generous,
+// cpu_instrs takes under a minute on hardware
+const ROMS: &[(&str, u32)] = &[
+    ("cpu_instrs/individual/01-special.gb", 10),
+    ("cpu_instrs/individual/02-interrupts.gb", 10),
+    ("cpu_instrs/individual/03-op sp,hl.gb", 10),
+    ("cpu_instrs/individual/04-op r,imm.gb", 10),
+    ("cpu_instrs/individual/05-op rp.gb", 10),
+    ("cpu_instrs/individual/06-ld r,r.gb", 10),
+    ("cpu_instrs/individual/07-jr,jp,call,ret,rst.gb", 10),
+    ("cpu_instrs/individual/08-misc instrs.gb", 10),
+    ("cpu_instrs/individual/09-op r,r.gb", 20),
+    ("cpu_instrs/individual/10-bit ops.gb", 20),
+    ("cpu_instrs/individual/11-op a,(hl).gb", 30),
+    ("cpu_instrs/cpu_instrs.gb", 70),
+    ("instr_timing/instr_timing.gb", 10),
+    ("mem_timing/individual/01-read_timing.gb", 10),
+    ("mem_timing/individual/02-write_timing.gb", 10),
+    ("mem_timing/individual/03-modify_timing.gb", 10),
+    ("mem_timing/mem_timing.gb", 20),
+];
+
+// what the rom printed, once it's said whether it passed or the time's up
+fn run(path: &Path, seconds: u32) -> Result<String, String> {
+    let cartridge = Cartridge::from_file(path).map_err(|error| error.to_string())?;
+    let mut gameboy = GameBoy::with_cartridge(cartridge)
+        .deterministic(DEFAULT_RAM_SEED)
+        .build()
+        .map_err(|error| error.to_string())?;
+    gameboy.mmu_mut().serial.start_capture();
+    for _ in 0..seconds * FRAMES_PER_SECOND {
+        gameboy.run_frame();
+        let output = gameboy.mmu().serial.captured_text();
+        if output.contains("Passed") || output.contains("Failed") {
+            return Ok(output);
+        }
+    }
+    Err(format!("no verdict after {} seconds, printed {:?}", seconds, gameboy.mmu().serial.captured_text()))
+}
+
+#[test]
+fn blargg_test_roms() {
+    let Ok(dir) = std::env::var(ROMS_DIR_VAR) else {
+        eprintln!("{} not set, skipping blargg test roms", ROMS_DIR_VAR);
+        return;
+    };
+    let mut failures = Vec::new();
+    for &(rom, seconds) in ROMS {
+        let path = Path::new(&dir).join(rom);
+        if !path.exists() {
+            eprintln!("{}: not found, skipped", rom);
+            continue;
+        }
+        match run(&path, seconds) {
+            Ok(output) if output.contains("Passed") => eprintln!("{}: passed", rom),
+            Ok(output) => failures.push(format!("{}: {}", rom, output.trim())),
+            Err(error) => failures.push(format!("{}: {}", rom, error)),
+        }
+    }
+    assert!(failures.is_empty(), "failing roms:\n{}", failures.join("\n"));
+}